@@ -0,0 +1,218 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::connect::{NetEvent, Wake};
+
+pub const CAPTURE_EXTENSION: &str = "pvpgncap";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// One line of a `.pvpgncap` file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Milliseconds since the capture started.
+    pub t_ms: u64,
+    pub dir: Direction,
+    pub line: String,
+}
+
+/// The body of a whisper we send is replaced in the capture; the target is
+/// kept because it matters for reproducing routing bugs. Inbound lines,
+/// including whispers to us and the server's echoes of ours, are recorded
+/// as received.
+pub fn redact_outbound(line: &str) -> String {
+    match crate::whisper_command(line) {
+        Some((target, _)) => {
+            let command = line.split(' ').next().unwrap_or("/w");
+            format!("{} {} [redacted]", command, crate::protocol::quote_name(target))
+        }
+        None => line.to_string(),
+    }
+}
+
+/// Records protocol traffic to a JSONL file while enabled from Diagnostics.
+#[derive(Debug)]
+pub struct Capture {
+    pub path: PathBuf,
+    pub include_outbound: bool,
+    started: Instant,
+    file: BufWriter<File>,
+}
+
+impl Capture {
+    pub fn start(dir: &Path, include_outbound: bool) -> io::Result<Capture> {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = dir.join(format!("capture-{}.{}", stamp, CAPTURE_EXTENSION));
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(Capture { path, include_outbound, started: Instant::now(), file })
+    }
+
+    fn write(&mut self, dir: Direction, line: String) -> io::Result<()> {
+        let record = CaptureRecord {
+            t_ms: self.started.elapsed().as_millis() as u64,
+            dir,
+            line,
+        };
+        let text = serde_json::to_string(&record).map_err(io::Error::other)?;
+        writeln!(self.file, "{}", text)?;
+        self.file.flush()
+    }
+
+    pub fn record_inbound(&mut self, line: &str) -> io::Result<()> {
+        self.write(Direction::In, line.to_string())
+    }
+
+    pub fn record_outbound(&mut self, line: &str) -> io::Result<()> {
+        if !self.include_outbound {
+            return Ok(());
+        }
+        self.write(Direction::Out, redact_outbound(line))
+    }
+}
+
+pub fn load(path: &Path) -> io::Result<Vec<CaptureRecord>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = vec![];
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, err))
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// When each inbound line is due, relative to the start of the replay.
+/// `speed` 2.0 plays twice as fast; 0 or less delivers everything at once.
+pub fn replay_schedule(records: &[CaptureRecord], speed: f64) -> Vec<(Duration, String)> {
+    records
+        .iter()
+        .filter(|record| record.dir == Direction::In)
+        .map(|record| {
+            let due = if speed > 0.0 {
+                Duration::from_secs_f64(record.t_ms as f64 / 1000.0 / speed)
+            } else {
+                Duration::ZERO
+            };
+            (due, record.line.clone())
+        })
+        .collect()
+}
+
+/// Feeds a capture into the same channel the socket reader would use.
+pub fn spawn_replay(records: Vec<CaptureRecord>, speed: f64, tx: Sender<NetEvent>, wake: Wake) {
+    let schedule = replay_schedule(&records, speed);
+    std::thread::spawn(move || {
+        let started = Instant::now();
+        for (due, line) in schedule {
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+            if tx.send(NetEvent::Line(line)).is_err() {
+                return;
+            }
+            wake();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn record(t_ms: u64, dir: Direction, line: &str) -> CaptureRecord {
+        CaptureRecord { t_ms, dir, line: line.to_string() }
+    }
+
+    fn scratch_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bnetchat-capture-{}-{}.{}", std::process::id(), name, CAPTURE_EXTENSION));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn whisper_bodies_are_redacted() {
+        assert_eq!(redact_outbound("/w Bob meet me at 5"), "/w Bob [redacted]");
+        assert_eq!(redact_outbound("/msg Bob secret"), "/msg Bob [redacted]");
+        assert_eq!(redact_outbound("/whisper \"[Clan] Bob\" secret"), "/whisper \"[Clan] Bob\" [redacted]");
+    }
+
+    #[test]
+    fn everything_else_is_kept() {
+        assert_eq!(redact_outbound("hello channel"), "hello channel");
+        assert_eq!(redact_outbound("/join Clan X"), "/join Clan X");
+        // Without text there is nothing to hide.
+        assert_eq!(redact_outbound("/w Bob"), "/w Bob");
+    }
+
+    #[test]
+    fn records_are_one_json_object_per_line() {
+        let text = serde_json::to_string(&record(1500, Direction::In, "1018 INFO \"hi\"")).unwrap();
+        assert_eq!(text, r#"{"t_ms":1500,"dir":"in","line":"1018 INFO \"hi\""}"#);
+        let back: CaptureRecord = serde_json::from_str(r#"{"t_ms":2,"dir":"out","line":"/who Chat"}"#).unwrap();
+        assert_eq!(back, record(2, Direction::Out, "/who Chat"));
+    }
+
+    #[test]
+    fn load_skips_blank_lines() {
+        let path = scratch_file(
+            "good",
+            "{\"t_ms\":0,\"dir\":\"in\",\"line\":\"2010 NAME bob\"}\n\n{\"t_ms\":40,\"dir\":\"out\",\"line\":\"hi\"}\n",
+        );
+        let records = load(&path).unwrap();
+        assert_eq!(records, vec![record(0, Direction::In, "2010 NAME bob"), record(40, Direction::Out, "hi")]);
+    }
+
+    #[test]
+    fn load_names_the_bad_line() {
+        let path = scratch_file("bad", "{\"t_ms\":0,\"dir\":\"in\",\"line\":\"ok\"}\nnot json\n");
+        let err = load(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"), "{}", err);
+    }
+
+    #[test]
+    fn schedule_plays_inbound_lines_at_the_given_speed() {
+        let records = [
+            record(0, Direction::In, "a"),
+            record(500, Direction::Out, "typed"),
+            record(1000, Direction::In, "b"),
+            record(3000, Direction::In, "c"),
+        ];
+        let at = |speed| -> Vec<(u64, String)> {
+            replay_schedule(&records, speed).into_iter().map(|(due, line)| (due.as_millis() as u64, line)).collect()
+        };
+        assert_eq!(at(1.0), vec![(0, "a".into()), (1000, "b".into()), (3000, "c".into())]);
+        assert_eq!(at(2.0), vec![(0, "a".into()), (500, "b".into()), (1500, "c".into())]);
+        assert_eq!(at(0.0), vec![(0, "a".into()), (0, "b".into()), (0, "c".into())]);
+    }
+
+    #[test]
+    fn replay_feeds_the_reader_channel_in_order() {
+        let (tx, rx) = channel();
+        let wake: Wake = Arc::new(|| {});
+        spawn_replay(vec![record(0, Direction::In, "one"), record(10, Direction::In, "two")], 0.0, tx, wake);
+        let lines: Vec<NetEvent> = rx.iter().collect();
+        assert_eq!(lines, vec![NetEvent::Line("one".into()), NetEvent::Line("two".into())]);
+    }
+}
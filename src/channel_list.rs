@@ -0,0 +1,27 @@
+//! The channel browser: the server's `/channels` listing in a window.
+
+use std::time::{Duration, Instant};
+
+/// Stop showing "asking the server" after this long without an answer.
+pub const LISTING_TIMEOUT: Duration = Duration::from_secs(6);
+
+#[derive(Debug, Default)]
+pub struct ChannelBrowser {
+    /// The latest listing, in the server's order.
+    pub channels: Vec<(String, u32)>,
+    pub filter: String,
+    /// When the listing we're waiting for was asked for.
+    pub requested: Option<Instant>,
+}
+
+impl ChannelBrowser {
+    pub fn waiting(&self, now: Instant) -> bool {
+        self.requested.is_some_and(|at| now.duration_since(at) < LISTING_TIMEOUT)
+    }
+
+    /// Channels whose name contains the filter, ignoring case.
+    pub fn visible(&self) -> impl Iterator<Item = &(String, u32)> + '_ {
+        let filter = self.filter.trim().to_lowercase();
+        self.channels.iter().filter(move |(name, _)| name.to_lowercase().contains(&filter))
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use crate::clock;
+use crate::paths;
+
+/// Keeps a path component out of other directories and valid on every OS:
+/// `host:6112` becomes `host_6112`.
+fn path_component(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || "-_.#".contains(c) { c } else { '_' })
+        .collect();
+    match cleaned.trim_matches('.') {
+        "" => "_".to_string(),
+        cleaned => cleaned.to_string(),
+    }
+}
+
+/// The other side of a whisper row: `Bob whispers: …` or `To Bob: …`.
+pub fn whisper_peer(content: &str) -> Option<&str> {
+    let content = content.trim_start_matches("🔒 ");
+    let peer = match content.strip_prefix("To ") {
+        Some(rest) => rest.split_once(": ")?.0,
+        None => content.split_once(" whispers: ")?.0,
+    };
+    // Drop a nickname suffix like "Bob (Bobby)".
+    peer.split(' ').next().filter(|peer| !peer.is_empty())
+}
+
+/// Local calendar day, so a file holds one evening's chat rather than
+/// splitting at UTC midnight.
+fn day(unix: u64) -> String {
+    match clock::local_date(unix) {
+        Some(date) => format!("{}-{:02}-{:02}", date.year(), date.month() as u8, date.day()),
+        None => "unknown-date".to_string(),
+    }
+}
+
+/// `<logs>/<server>/<channel>/YYYY-MM-DD.log`, or `<logs>/<server>/whispers/<peer>/…`
+/// for private messages, under [`paths::logs_dir`].
+pub fn log_path(server: &str, channel: &str, message_type: &str, content: &str, timestamp: u64) -> PathBuf {
+    let mut path = paths::logs_dir().join(path_component(server));
+    match whisper_peer(content).filter(|_| message_type == "whisper") {
+        Some(peer) => path.push(Path::new("whispers").join(path_component(&peer.to_lowercase()))),
+        None if channel.is_empty() => path.push("_server"),
+        None => path.push(path_component(channel)),
+    }
+    path.join(format!("{}.log", day(timestamp)))
+}
+
+struct Line {
+    path: PathBuf,
+    text: String,
+}
+
+/// Appends every chat row to daily log files on a writer thread, so a slow
+/// disk never stalls a frame. Dropping it flushes and closes the files.
+#[derive(Debug)]
+pub struct ChatLog {
+    server: String,
+    tx: Option<Sender<Line>>,
+    errors: Receiver<io::Error>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl ChatLog {
+    pub fn start(server: &str) -> ChatLog {
+        let (tx, rx) = channel::<Line>();
+        let (error_tx, errors) = channel();
+        let writer = std::thread::spawn(move || {
+            let mut files: HashMap<PathBuf, LineWriter<File>> = HashMap::new();
+            for line in rx {
+                if let Err(err) = append(&mut files, line) {
+                    let _ = error_tx.send(err);
+                    return;
+                }
+            }
+        });
+        ChatLog {
+            server: server.to_string(),
+            tx: Some(tx),
+            errors,
+            writer: Some(writer),
+        }
+    }
+
+    pub fn record(&self, channel: &str, message_type: &str, content: &str, timestamp: u64) {
+        let line = Line {
+            path: log_path(&self.server, channel, message_type, content, timestamp),
+            text: format!("[{}] [{}] {}", clock::iso(timestamp), message_type, content),
+        };
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(line);
+        }
+    }
+
+    /// The error that stopped the writer, if it has stopped.
+    pub fn failure(&self) -> Option<io::Error> {
+        self.errors.try_recv().ok()
+    }
+}
+
+impl Drop for ChatLog {
+    fn drop(&mut self) {
+        // Closing the channel lets the writer finish the queue and exit.
+        self.tx = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn append(files: &mut HashMap<PathBuf, LineWriter<File>>, line: Line) -> io::Result<()> {
+    if !files.contains_key(&line.path) {
+        if let Some(dir) = line.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&line.path)?;
+        // Yesterday's files are done once a new day starts.
+        let today = line.path.file_name().map(|name| name.to_os_string());
+        files.retain(|path, _| path.file_name().map(|name| name.to_os_string()) == today);
+        files.insert(line.path.clone(), LineWriter::new(file));
+    }
+    let file = files.get_mut(&line.path).expect("opened above");
+    writeln!(file, "{}", line.text)
+}
@@ -0,0 +1,67 @@
+use std::sync::OnceLock;
+
+use time::{Date, OffsetDateTime, UtcOffset};
+
+static LOCAL_OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+
+/// Reads the local UTC offset once. Call before spawning any thread: on Unix
+/// `time` refuses to read it from a multi-threaded process, and we fall back
+/// to UTC. A daylight-saving change while running is not picked up.
+pub fn init() {
+    LOCAL_OFFSET.get_or_init(|| UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC));
+}
+
+fn local(unix: u64) -> Option<OffsetDateTime> {
+    let offset = *LOCAL_OFFSET.get_or_init(|| UtcOffset::UTC);
+    Some(OffsetDateTime::from_unix_timestamp(unix as i64).ok()?.to_offset(offset))
+}
+
+/// The local calendar day a message arrived on.
+pub fn local_date(unix: u64) -> Option<Date> {
+    local(unix).map(|at| at.date())
+}
+
+/// `March 4, 2025`, for the separator rows between days.
+pub fn date_label(date: Date) -> String {
+    format!("{} {}, {}", date.month(), date.day(), date.year())
+}
+
+/// `14:05`, `2:05 PM`, with seconds when asked for.
+pub fn time_of_day(unix: u64, seconds: bool, twelve_hour: bool) -> Option<String> {
+    let at = local(unix)?;
+    let (hour, suffix) = match (twelve_hour, at.hour()) {
+        (false, hour) => (hour, ""),
+        (true, 0) => (12, " AM"),
+        (true, hour @ 1..=11) => (hour, " AM"),
+        (true, 12) => (12, " PM"),
+        (true, hour) => (hour - 12, " PM"),
+    };
+    let mut text = if twelve_hour { format!("{}:{:02}", hour, at.minute()) } else { format!("{:02}:{:02}", hour, at.minute()) };
+    if seconds {
+        text.push_str(&format!(":{:02}", at.second()));
+    }
+    text.push_str(suffix);
+    Some(text)
+}
+
+/// ISO 8601 in local time with its offset, e.g. `2024-03-01T12:04:05+01:00`.
+pub fn iso(unix: u64) -> String {
+    let at = match local(unix) {
+        Some(at) => at,
+        None => return "????-??-??T??:??:??".to_string(),
+    };
+    let offset = at.offset();
+    let sign = if offset.is_negative() { '-' } else { '+' };
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        at.year(),
+        at.month() as u8,
+        at.day(),
+        at.hour(),
+        at.minute(),
+        at.second(),
+        sign,
+        offset.whole_hours().unsigned_abs(),
+        offset.minutes_past_hour().unsigned_abs(),
+    )
+}
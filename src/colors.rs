@@ -0,0 +1,25 @@
+use eframe::egui::{ecolor::Hsva, Color32};
+
+/// FNV-1a over the lowercased name, so `Bob` and `bob` share a color and it
+/// never changes between runs.
+fn name_hash(name: &str) -> u32 {
+    name.to_lowercase().bytes().fold(0x811c_9dc5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Up to two capitals for an avatar: the first letter of each of the first
+/// two words, ignoring clan brackets and the like.
+pub fn initials(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter_map(|word| word.chars().next())
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+/// A stable color for `name`'s messages, bright on dark backgrounds and deep
+/// on light ones so it stays readable as text on either.
+pub fn name_color(name: &str, dark_mode: bool) -> Color32 {
+    let hue = (name_hash(name) % 360) as f32 / 360.0;
+    let (saturation, value) = if dark_mode { (0.5, 0.95) } else { (0.85, 0.55) };
+    Hsva::new(hue, saturation, value, 1.0).into()
+}
@@ -0,0 +1,64 @@
+/// One row of the `/help` window.
+#[derive(Debug)]
+pub struct CommandHelp {
+    pub command: &'static str,
+    pub arguments: &'static str,
+    pub description: &'static str,
+}
+
+/// Common PvPGN telnet commands, plus the ones the client handles itself.
+pub const HELP: &[CommandHelp] = &[
+    CommandHelp { command: "/w", arguments: "<user> <message>", description: "Whisper a private message (also /whisper, /msg, /m)" },
+    CommandHelp { command: "/r", arguments: "<message>", description: "Reply to the last whisper" },
+    CommandHelp { command: "/join", arguments: "<channel>", description: "Switch channels (also /j)" },
+    CommandHelp { command: "/channels", arguments: "", description: "Browse the public channels (also /chs)" },
+    CommandHelp { command: "/rejoin", arguments: "", description: "Leave and rejoin the current channel" },
+    CommandHelp { command: "/who", arguments: "<channel>", description: "List the users in a channel" },
+    CommandHelp { command: "/whois", arguments: "<user>", description: "Where a user is (also /whereis)" },
+    CommandHelp { command: "/stats", arguments: "<user> [client]", description: "Show a user's ladder record" },
+    CommandHelp { command: "/away", arguments: "[message]", description: "Toggle away; whispers get the message as a reply" },
+    CommandHelp { command: "/dnd", arguments: "[message]", description: "Toggle do-not-disturb; whispers are refused" },
+    CommandHelp { command: "/squelch", arguments: "<user>", description: "Hide a user's messages on the server (also /ignore)" },
+    CommandHelp { command: "/unsquelch", arguments: "<user>", description: "Show a squelched user again (also /unignore)" },
+    CommandHelp { command: "/f", arguments: "add|remove|list <user>", description: "Manage your friends list" },
+    CommandHelp { command: "/watch", arguments: "<user>", description: "Be told when a user logs on or off" },
+    CommandHelp { command: "/me", arguments: "<action>", description: "Emote an action (also /emote)" },
+    CommandHelp { command: "/users", arguments: "", description: "How many users are online" },
+    CommandHelp { command: "/time", arguments: "", description: "Server time" },
+    CommandHelp { command: "/help", arguments: "", description: "This window (handled by the client)" },
+    CommandHelp { command: "/clear", arguments: "", description: "Clear the message list (handled by the client)" },
+];
+
+/// Commands the client answers itself instead of sending them.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Local {
+    Help,
+    Clear,
+}
+
+pub fn local_command(input: &str) -> Option<Local> {
+    match input.trim().to_lowercase().as_str() {
+        "/help" => Some(Local::Help),
+        "/clear" => Some(Local::Clear),
+        _ => None,
+    }
+}
+
+/// Rewrites client-side aliases to the command the server knows best:
+/// `/msg bob hi` goes out as `/w bob hi`.
+pub fn expand_alias(input: &str) -> String {
+    match input.split_once(' ') {
+        Some((command, rest)) if command.eq_ignore_ascii_case("/msg") => format!("/w {}", rest),
+        _ => input.to_string(),
+    }
+}
+
+/// Help rows whose command starts with what has been typed so far, while
+/// the input is still just a bare `/command`.
+pub fn completions(input: &str) -> Vec<&'static CommandHelp> {
+    if !input.starts_with('/') || input.contains(' ') {
+        return vec![];
+    }
+    let typed = input.to_lowercase();
+    HELP.iter().filter(|help| help.command.starts_with(&typed) && help.command != typed).collect()
+}
@@ -1,47 +1,1481 @@
-use std::net::{TcpStream};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::{Proxy, ProxyError};
+use crate::tls::TlsTransport;
+
+/// PvPGN's standard port, used when the server field has none.
+pub const DEFAULT_PORT: u16 = 6112;
+
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The name didn't resolve to any address.
+    Resolve(String, io::Error),
+    /// Every resolved address refused the connection.
+    Refused(String),
+    /// Addresses resolved but none answered in time (or failed otherwise).
+    Unreachable(String, io::Error),
+    /// Connected, but logging in failed or stalled.
+    Handshake(String, io::Error),
+    /// Several addresses were tried and all failed; how many, and the last error.
+    Exhausted(String, usize, io::Error),
+    /// The proxy, not the server, failed; the proxy's address and why.
+    Proxy(String, ProxyError),
+    /// TCP connected but the TLS handshake failed (bad certificate, not a
+    /// TLS port, ...).
+    Tls(String, String),
+    /// The server turned down a new account.
+    Register(RegisterError),
+    /// The server turned down the name or password.
+    Login(LoginError),
+}
+
+/// Where a login has got to, reported as it moves on so the splash can say
+/// more than "connecting" and a failure can say where it happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectPhase {
+    Connect,
+    /// Only when the form asked for a new account.
+    Register,
+    Username,
+    Password,
+    Authenticate,
+    Join,
+}
+
+impl ConnectPhase {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConnectPhase::Connect => "connecting",
+            ConnectPhase::Register => "creating the account",
+            ConnectPhase::Username => "sending the name",
+            ConnectPhase::Password => "sending the password",
+            ConnectPhase::Authenticate => "authenticating",
+            ConnectPhase::Join => "joining the channel",
+        }
+    }
+
+    /// This phase's step number and the number of steps, which counts
+    /// account creation only when `register` is set.
+    pub fn step(&self, register: bool) -> (usize, usize) {
+        let skipped = usize::from(!register && *self as usize > ConnectPhase::Register as usize);
+        (*self as usize + 1 - skipped, 5 + usize::from(register))
+    }
+}
+
+/// Where [`ConnectPhase`]s go, waking the UI for each.
+#[derive(Clone)]
+pub struct Progress {
+    pub sender: Sender<ConnectPhase>,
+    pub wake: Wake,
+}
+
+impl Progress {
+    pub fn report(&self, phase: ConnectPhase) {
+        if self.sender.send(phase).is_ok() {
+            (self.wake)();
+        }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// Why creating an account did not work, split by the form field to blame.
+#[derive(Debug)]
+pub enum RegisterError {
+    /// The server does not allow new accounts.
+    Disabled(String),
+    /// Name taken or not allowed; the server's words.
+    Name(String),
+    /// Password too short, mismatched or otherwise refused; the server's words.
+    Password(String),
+    /// The server stopped answering partway through.
+    Io(io::Error),
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegisterError::Disabled(text) => write!(f, "This server does not allow new accounts: {}", text),
+            RegisterError::Name(text) | RegisterError::Password(text) => write!(f, "{}", text),
+            RegisterError::Io(err) => write!(f, "Account creation stopped: {}", err),
+        }
+    }
+}
+
+/// Why the server turned down a login, split by the form field to blame.
+#[derive(Debug)]
+pub enum LoginError {
+    /// Wrong password, or a plain "Login failed." that doesn't say.
+    BadPassword,
+    /// No account by that name.
+    NoAccount,
+    /// The account exists but is locked or closed.
+    Locked,
+    /// Some other error code; the server's words.
+    Refused(String),
+    /// The server stopped answering partway through.
+    Io(io::Error),
+}
+
+impl LoginError {
+    /// Whether the name rather than the password is to blame.
+    pub fn blames_name(&self) -> bool {
+        matches!(self, LoginError::NoAccount | LoginError::Locked)
+    }
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::BadPassword => write!(f, "Incorrect password"),
+            LoginError::NoAccount => write!(f, "Account does not exist"),
+            LoginError::Locked => write!(f, "Account locked"),
+            LoginError::Refused(text) => write!(f, "Login refused: {}", text),
+            LoginError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// What a line read right after the password says about the login: `Ok`
+/// once the server talks chat protocol (any 1xxx line, or 2010 NAME), the
+/// reason when it refuses, `None` for banner chatter.
+fn login_outcome(line: &str) -> Option<Result<(), LoginError>> {
+    let line = line.trim();
+    let (code, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let coded = line_code(line).is_some();
+    if coded && (code.starts_with('1') || code == "2010") {
+        return Some(Ok(()));
+    }
+    let text = line.to_lowercase();
+    if text.contains("locked") || text.contains("account closed") {
+        return Some(Err(LoginError::Locked));
+    }
+    if ["does not exist", "doesn't exist", "no such account", "no such user", "unknown account", "account not found"].iter().any(|words| text.contains(words)) {
+        return Some(Err(LoginError::NoAccount));
+    }
+    if text.contains("login failed")
+        || ["bad", "incorrect", "wrong", "invalid"].iter().any(|word| text.contains(&format!("{} password", word)))
+    {
+        return Some(Err(LoginError::BadPassword));
+    }
+    if coded && code.starts_with('2') {
+        // Past the type word, as in `protocol::parse`.
+        let text = rest.split_once(' ').map_or(rest, |(_, text)| text);
+        return Some(Err(LoginError::Refused(text.trim_matches('"').to_string())));
+    }
+    None
+}
+
+/// The four-digit code leading a chat protocol line, if it has one.
+fn line_code(line: &str) -> Option<&str> {
+    let code = line.trim().split(' ').next()?;
+    Some(code).filter(|code| code.len() == 4 && code.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Follows what the server sends after the password to the login's outcome.
+#[derive(Debug, Default)]
+struct LoginReply {
+    /// A refusal in words only, held until the server asks for the name
+    /// again, hangs up or goes quiet: a banner can mention failed logins too.
+    refused: Option<LoginError>,
+}
+
+impl LoginReply {
+    /// The outcome once `lines` and the unfinished `partial` came in, or
+    /// `None` to keep reading. A success line wins over any refusal in words.
+    fn read(&mut self, lines: &[String], partial: &str) -> Option<Result<(), LoginError>> {
+        for line in lines {
+            match login_outcome(line) {
+                Some(Ok(())) => return Some(Ok(())),
+                Some(Err(err)) if line_code(line).is_some() => return Some(Err(err)),
+                Some(Err(err)) => {
+                    self.refused.get_or_insert(err);
+                }
+                None => {}
+            }
+        }
+        // Asked for the name again: refused, for the reason given if any.
+        if partial.trim_end().ends_with(':') {
+            return Some(Err(self.refused.take().unwrap_or(LoginError::BadPassword)));
+        }
+        None
+    }
+
+    /// Why the login failed when the server hung up or went quiet: the
+    /// refusal read so far, or else `ended`.
+    fn end(self, ended: io::Error) -> LoginError {
+        self.refused.unwrap_or(LoginError::Io(ended))
+    }
+}
+
+pub const MIN_TIMEOUT_SECS: u32 = 1;
+pub const MAX_TIMEOUT_SECS: u32 = 120;
+
+/// How long each stage of connecting may take, in seconds. Slow links
+/// (satellite, congested proxies) need more than the defaults.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Opening the TCP connection, for all addresses together.
+    pub connect_secs: u32,
+    /// Waiting for each prompt or reply during login.
+    pub prompt_secs: u32,
+    /// The whole login or account creation, however many prompts it takes.
+    pub handshake_secs: u32,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig { connect_secs: 5, prompt_secs: 10, handshake_secs: 30 }
+    }
+}
+
+impl TimeoutConfig {
+    /// Pulls hand-edited values back into 1–120 s.
+    pub fn clamp(&mut self) {
+        for secs in [&mut self.connect_secs, &mut self.prompt_secs, &mut self.handshake_secs] {
+            *secs = (*secs).clamp(MIN_TIMEOUT_SECS, MAX_TIMEOUT_SECS);
+        }
+    }
+
+    pub fn connect(&self) -> Duration {
+        Duration::from_secs(u64::from(self.connect_secs))
+    }
+
+    fn prompt(&self) -> Duration {
+        Duration::from_secs(u64::from(self.prompt_secs))
+    }
+
+    fn handshake(&self) -> Duration {
+        Duration::from_secs(u64::from(self.handshake_secs))
+    }
+}
+
+/// Lines the server sends when it refuses part of an account creation.
+fn is_refusal(text: &str) -> bool {
+    let text = text.to_lowercase();
+    ["not allowed", "already exists", "invalid", "too short", "too long", "do not match", "don't match", "failed", "error"]
+        .iter()
+        .any(|word| text.contains(word))
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Resolve(server, err) => write!(f, "Could not resolve host {}: {}", server, err),
+            ConnectError::Refused(server) => write!(f, "Connection refused by {}", server),
+            ConnectError::Unreachable(server, err) => write!(f, "Could not reach {}: {}", server, err),
+            ConnectError::Handshake(server, err) => write!(f, "Login to {} failed: {}", server, err),
+            ConnectError::Exhausted(server, tried, err) => {
+                write!(f, "Could not reach {}: tried {} addresses; last error: {}", server, tried, err)
+            }
+            ConnectError::Proxy(proxy, err) => write!(f, "Proxy {}: {}", proxy, err),
+            ConnectError::Tls(server, err) => write!(f, "TLS handshake with {} failed: {}", server, err),
+            ConnectError::Register(err) => write!(f, "{}", err),
+            ConnectError::Login(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// `host`, `host:port`, `1.2.3.4`, `[::1]` or `[::1]:port`, with the
+/// default port filled in when missing.
+pub fn with_default_port(server: &str) -> String {
+    let server = server.trim();
+    if server.parse::<SocketAddr>().is_ok() {
+        return server.to_string();
+    }
+    let bare = server.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return SocketAddr::new(ip, DEFAULT_PORT).to_string();
+    }
+    match server.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => server.to_string(),
+        _ => format!("{}:{}", server, DEFAULT_PORT),
+    }
+}
+
+/// Splits a server string into host and port, without brackets around an
+/// IPv6 literal. The port defaults to 6112.
+pub fn split_host_port(server: &str) -> (&str, u16) {
+    let server = server.trim();
+    let (host, port) = match server.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => match port.parse() {
+            Ok(port) => (host, port),
+            Err(_) => (server, DEFAULT_PORT),
+        },
+        _ => (server, DEFAULT_PORT),
+    };
+    (host.trim_start_matches('[').trim_end_matches(']'), port)
+}
+
+/// Shortest time one address gets, however many are left to try.
+const MIN_ATTEMPT: Duration = Duration::from_millis(500);
+
+/// Every address `server` resolves to, alternating between IPv6 and IPv4
+/// starting with whichever the resolver put first, so one broken family
+/// only costs every other attempt.
+pub fn resolve(server: &str) -> Result<Vec<SocketAddr>, ConnectError> {
+    let server = with_default_port(server);
+    let resolved: Vec<SocketAddr> = server
+        .to_socket_addrs()
+        .map_err(|err| ConnectError::Resolve(server.clone(), err))?
+        .collect();
+    if resolved.is_empty() {
+        let err = io::Error::new(io::ErrorKind::NotFound, "no addresses found");
+        return Err(ConnectError::Resolve(server, err));
+    }
+    Ok(interleave(resolved))
+}
+
+/// `resolved` alternating between the families, starting with the first
+/// address's; the rest of the larger family goes last, in order.
+fn interleave(resolved: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = resolved[0].is_ipv6();
+    let (mut first, mut second): (Vec<_>, Vec<_>) = resolved.into_iter().partition(|address| address.is_ipv6() == first_v6);
+    first.reverse();
+    second.reverse();
+    let mut addresses = Vec::new();
+    while let Some(address) = first.pop() {
+        addresses.push(address);
+        addresses.extend(second.pop());
+    }
+    addresses.extend(second.into_iter().rev());
+    addresses
+}
+
+/// Resolves `server` and connects to the first address (IPv4 or IPv6)
+/// that answers. `timeout` is the budget for all attempts together, shared
+/// out evenly among the addresses still left to try.
+pub fn open(server: &str, timeout: Duration) -> Result<TcpStream, ConnectError> {
+    let addresses = resolve(server)?;
+    let server = with_default_port(server);
+    let deadline = Instant::now() + timeout;
+    let timed_out = || io::Error::new(io::ErrorKind::TimedOut, format!("timed out after {} s connecting", timeout.as_secs()));
+    let mut last_error = timed_out();
+    let mut all_refused = true;
+    let mut tried = 0;
+    for (index, address) in addresses.iter().enumerate() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            all_refused = false;
+            break;
+        }
+        let left = (addresses.len() - index) as u32;
+        let attempt = (remaining / left).max(MIN_ATTEMPT).min(remaining);
+        tried += 1;
+        match TcpStream::connect_timeout(address, attempt) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => {
+                all_refused &= err.kind() == io::ErrorKind::ConnectionRefused;
+                last_error = match err.kind() {
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => timed_out(),
+                    _ => err,
+                };
+            }
+        }
+    }
+    if all_refused {
+        Err(ConnectError::Refused(server))
+    } else if tried > 1 {
+        Err(ConnectError::Exhausted(server, tried, last_error))
+    } else {
+        Err(ConnectError::Unreachable(server, last_error))
+    }
+}
+
+/// Opens `server`, through `proxy` when one is set, and when `tls` is set
+/// runs the TLS handshake on top. `verify` off accepts self-signed
+/// certificates.
+pub fn open_transport(
+    server: &str,
+    timeout: Duration,
+    proxy: &Proxy,
+    tls: bool,
+    verify: bool,
+) -> Result<Box<dyn Transport>, ConnectError> {
+    let socket = if proxy.enabled() {
+        proxy
+            .open(&with_default_port(server), timeout)
+            .map_err(|err| ConnectError::Proxy(proxy.address(), err))?
+    } else {
+        open(server, timeout)?
+    };
+    if !tls {
+        return Ok(Box::new(socket));
+    }
+    match TlsTransport::wrap(socket, server, verify, timeout) {
+        Ok(transport) => Ok(Box::new(transport)),
+        Err(err) => Err(ConnectError::Tls(with_default_port(server), err)),
+    }
+}
+
+/// A byte connection to the server: plain TCP or a TLS session. Anything
+/// else (a proxy tunnel, a scripted fake) only has to provide these.
+pub trait Transport: Read + Write + Send + fmt::Debug {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// A second handle on the same connection, for the read thread.
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+
+    /// Closes both directions; a read blocked on another handle returns.
+    fn shutdown(&self) -> io::Result<()>;
+
+    /// Sends one line with the `\r\n` the server expects.
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.flush()
+    }
+}
+
+impl Transport for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, std::net::Shutdown::Both)
+    }
+}
+
+/// How often the read thread looks up from the socket to check whether it
+/// has been told to stop.
+const READ_POLL: Duration = Duration::from_millis(250);
+
+/// How long closing the connection waits for the read thread to exit.
+const READER_JOIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The session being used plus one being replaced by a reconnect. More
+/// than this means threads are leaking across logins.
+const MAX_LIVE_READERS: usize = 2;
+
+static LIVE_READERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Read threads currently running, for diagnostics.
+pub fn live_readers() -> usize {
+    LIVE_READERS.load(Ordering::SeqCst)
+}
+
+/// Called after the read thread hands something over, so the UI repaints
+/// right away instead of polling on a timer.
+pub type Wake = Arc<dyn Fn() + Send + Sync>;
+
+/// Counts a read thread for as long as it runs, however it exits.
+struct LiveReader;
+
+impl LiveReader {
+    fn start() -> LiveReader {
+        let live = LIVE_READERS.fetch_add(1, Ordering::SeqCst) + 1;
+        debug_assert!(live <= MAX_LIVE_READERS, "{} read threads running; old ones were not stopped", live);
+        LiveReader
+    }
+}
+
+impl Drop for LiveReader {
+    fn drop(&mut self) {
+        LIVE_READERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The thread turning socket bytes into lines for `Main`.
+#[derive(Debug)]
+struct ReadThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl ReadThread {
+    fn spawn(stream: Box<dyn Transport>, lines: LineBuffer, req_tx: Sender<NetEvent>, wake: Wake) -> io::Result<ReadThread> {
+        stream.set_read_timeout(Some(READ_POLL))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let live = LiveReader::start();
+        let handle = std::thread::spawn(move || {
+            let _live = live;
+            read(stream, lines, req_tx, &stopped, &*wake);
+        });
+        Ok(ReadThread { stop, handle })
+    }
+
+    /// Tells the thread to stop and waits for it a little. Returns false
+    /// (and the thread is left to finish on its own) if it did not.
+    fn stop(self, timeout: Duration) -> bool {
+        self.stop.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        while !self.handle.is_finished() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.handle.join();
+        true
+    }
+}
+
+/// What the read thread hands to the UI: server lines, or the end of the
+/// connection, kept apart so a transport failure never looks like a
+/// protocol message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetEvent {
+    Line(String),
+    /// The server closed the connection.
+    Disconnected(String),
+    /// Reading failed; the connection is gone.
+    IoError(String),
+}
+
+/// Up to `max` events waiting on `events`, oldest first, and whether the
+/// read thread is gone so no more will come.
+pub fn take_events(events: &Receiver<NetEvent>, max: usize) -> (Vec<NetEvent>, bool) {
+    let mut taken = vec![];
+    while taken.len() < max {
+        match events.try_recv() {
+            Ok(event) => taken.push(event),
+            Err(TryRecvError::Empty) => return (taken, false),
+            Err(TryRecvError::Disconnected) => return (taken, true),
+        }
+    }
+    (taken, false)
+}
+
+/// Forwards complete lines until the server hangs up, reading fails or
+/// `stop` is set. Only the first two are reported. `lines` may hold the
+/// start of a line the login read.
+fn read(mut stream: Box<dyn Transport>, mut lines: LineBuffer, req_tx: Sender<NetEvent>, stop: &AtomicBool, wake: &dyn Fn()) {
+    let mut buffer = [0; 1024];
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let n = match stream.read(&mut buffer) {
+            Ok(0) if stop.load(Ordering::SeqCst) => return,
+            Ok(0) => {
+                let _ = req_tx.send(NetEvent::Disconnected("Connection closed by server".to_string()));
+                wake();
+                return;
+            }
+            Ok(n) => n,
+            Err(err) if matches!(err.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                continue
+            }
+            Err(_) if stop.load(Ordering::SeqCst) => return,
+            Err(err) => {
+                let _ = req_tx.send(NetEvent::IoError(err.to_string()));
+                wake();
+                return;
+            }
+        };
+        let complete = lines.push(&buffer[..n]);
+        if complete.is_empty() {
+            continue;
+        }
+        for line in complete {
+            // println!("{}", line);
+            if req_tx.send(NetEvent::Line(line)).is_err() {
+                // Nobody is listening any more.
+                return;
+            }
+        }
+        wake();
+    }
+}
 
 #[derive(Debug)]
 pub struct Connect {
-    stream: TcpStream,
+    /// `None` when replaying a capture: there is no server to talk to.
+    stream: Option<Box<dyn Transport>>,
+    /// Running once the handshake is done.
+    read_thread: Option<ReadThread>,
+    timeouts: TimeoutConfig,
+    /// When the running login or account creation runs out of time.
+    deadline: Option<Instant>,
+    /// What the login read past the password, handed to the read thread
+    /// so the first chat lines aren't lost.
+    early: Vec<String>,
+    lines: LineBuffer,
+    progress: Option<Progress>,
 }
 
 impl Connect {
-    pub(crate) fn new(stream: TcpStream) -> Connect {
-        Connect {stream}
+    pub(crate) fn new(stream: Box<dyn Transport>, timeouts: TimeoutConfig) -> Connect {
+        Connect {
+            stream: Some(stream),
+            read_thread: None,
+            timeouts,
+            deadline: None,
+            early: vec![],
+            lines: LineBuffer::default(),
+            progress: None,
+        }
+    }
+
+    pub(crate) fn offline() -> Connect {
+        Connect {
+            stream: None,
+            read_thread: None,
+            timeouts: TimeoutConfig::default(),
+            deadline: None,
+            early: vec![],
+            lines: LineBuffer::default(),
+            progress: None,
+        }
+    }
+
+    /// Reports each phase of the login or account creation from here on.
+    pub(crate) fn report_to(&mut self, progress: Option<Progress>) {
+        self.progress = progress;
+    }
+
+    fn phase(&self, phase: ConnectPhase) {
+        if let Some(progress) = &self.progress {
+            progress.report(phase);
+        }
+    }
+
+    fn stream(&mut self) -> &mut dyn Transport {
+        self.stream.as_deref_mut().expect("handshake needs a live connection")
     }
 
-    fn waitfor(&mut self, wait_s: &str) {
+    /// Sets the read timeout for the next wait: the prompt timeout, cut
+    /// short by whatever is left of the handshake budget.
+    fn arm(&mut self, waiting_for: &str) -> io::Result<()> {
+        let mut timeout = self.timeouts.prompt();
+        if let Some(deadline) = self.deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(self.timed_out(waiting_for));
+            }
+            timeout = timeout.min(left);
+        }
+        self.stream().set_read_timeout(Some(timeout))
+    }
+
+    /// Says which limit ran out, so the user knows which one to raise.
+    fn timed_out(&self, waiting_for: &str) -> io::Error {
+        let text = match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                format!("login took longer than {} s; gave up waiting for {}", self.timeouts.handshake_secs, waiting_for)
+            }
+            _ => format!("timed out after {} s waiting for {}", self.timeouts.prompt_secs, waiting_for),
+        };
+        io::Error::new(io::ErrorKind::TimedOut, text)
+    }
+
+    /// Runs one handshake under the overall budget.
+    fn with_deadline<T>(&mut self, handshake: impl FnOnce(&mut Connect) -> T) -> T {
+        self.deadline = Some(Instant::now() + self.timeouts.handshake());
+        let result = handshake(self);
+        self.deadline = None;
+        if let Some(stream) = self.stream.as_deref() {
+            let _ = stream.set_read_timeout(None);
+        }
+        result
+    }
+
+    /// Reads until the text so far ends in a prompt (an unfinished line ending
+    /// in `:`, whatever its wording or language) and returns that text. `Ok(None)`
+    /// when nothing like a prompt came in time.
+    fn wait_for_prompt(&mut self, waiting_for: &str) -> io::Result<Option<String>> {
+        self.arm(waiting_for)?;
+        let mut buffer = [0; 1024];
+        let mut text = String::new();
+        let prompt = loop {
+            match self.stream().read(&mut buffer) {
+                Ok(0) => break Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during login")),
+                Ok(n) => {
+                    text.push_str(&String::from_utf8_lossy(&buffer[..n]));
+                    // Prompts wait on the same line; banner lines end in a newline.
+                    if !text.ends_with('\n') && text.trim_end().ends_with(':') {
+                        break Ok(Some(text.trim().to_string()));
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break Ok(None),
+                Err(err) => break Err(err),
+            }
+        };
+        prompt
+    }
+
+    /// Reads until the accumulated text ends in a prompt or a sentence, or
+    /// holds a refusal.
+    fn read_reply(&mut self, waiting_for: &str) -> io::Result<String> {
+        self.arm(waiting_for)?;
         let mut buffer = [0; 1024];
-        let mut s: &str = "";
-        while !s.contains(wait_s) {
-            let n = self.stream.read(&mut buffer).unwrap();
-            s = std::str::from_utf8(&buffer[..n]).expect("Found invalid utf-8");
+        let mut text = String::new();
+        loop {
+            let n = match self.stream().read(&mut buffer) {
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Err(self.timed_out(waiting_for));
+                }
+                Err(err) => return Err(err),
+            };
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+            text.push_str(&String::from_utf8_lossy(&buffer[..n]));
+            let trimmed = text.trim_end();
+            if trimmed.ends_with(':') || trimmed.ends_with('.') || trimmed.ends_with('!') || is_refusal(trimmed) {
+                return Ok(text.trim().to_string());
+            }
         }
     }
-    pub(crate) fn connect(&mut self, username: &str, password: &str) -> i32 {
+
+    fn answer(&mut self, text: &str) -> io::Result<()> {
+        self.stream().write_line(text)
+    }
+
+    /// Starts the thread that reads server lines from here on, after the
+    /// handshake. Its events come out of the returned receiver.
+    pub(crate) fn start_reading(&mut self, wake: Wake) -> io::Result<Receiver<NetEvent>> {
+        let reader = match &self.stream {
+            Some(stream) => stream.try_clone()?,
+            None => return Err(io::Error::new(io::ErrorKind::NotConnected, "no connection to read from")),
+        };
+        let (req_tx, req_rx) = channel();
+        for line in self.early.drain(..) {
+            let _ = req_tx.send(NetEvent::Line(line));
+        }
+        self.read_thread = Some(ReadThread::spawn(reader, std::mem::take(&mut self.lines), req_tx, wake)?);
+        Ok(req_rx)
+    }
+
+    /// Closes the connection and stops the read thread.
+    pub(crate) fn shutdown(&mut self) -> io::Result<()> {
+        let result = match &self.stream {
+            Some(stream) => stream.shutdown(),
+            None => Ok(()),
+        };
+        if let Some(read_thread) = self.read_thread.take() {
+            if !read_thread.stop(READER_JOIN_TIMEOUT) {
+                println!("The read thread did not stop within {:?}", READER_JOIN_TIMEOUT);
+            }
+        }
+        result
+    }
+
+    /// Creates `username` through the telnet "new" account prompts: answering
+    /// `new` at the Username prompt, then the name, the password and its
+    /// confirmation. The caller then logs in on a fresh connection.
+    pub(crate) fn create_account(&mut self, username: &str, password: &str) -> Result<(), RegisterError> {
+        self.with_deadline(|connection| connection.create_account_steps(username, password))
+    }
+
+    fn create_account_steps(&mut self, username: &str, password: &str) -> Result<(), RegisterError> {
+        self.phase(ConnectPhase::Register);
+        self.stream().write_all(&[3]).map_err(RegisterError::Io)?;
+        self.wait_for_prompt("the login prompt").map_err(RegisterError::Io)?;
+        self.answer("new").map_err(RegisterError::Io)?;
+        let reply = self.read_reply("the new account's name prompt").map_err(RegisterError::Io)?;
+        if is_refusal(&reply) {
+            return Err(RegisterError::Disabled(reply));
+        }
+        self.answer(username).map_err(RegisterError::Io)?;
+        let reply = self.read_reply("the new account's password prompt").map_err(RegisterError::Io)?;
+        if is_refusal(&reply) || !reply.to_lowercase().contains("password") {
+            return Err(RegisterError::Name(reply));
+        }
+        self.answer(password).map_err(RegisterError::Io)?;
+        let reply = self.read_reply("the password confirmation prompt").map_err(RegisterError::Io)?;
+        if is_refusal(&reply) {
+            return Err(RegisterError::Password(reply));
+        }
+        // The confirmation prompt ("Retype password:").
+        self.answer(password).map_err(RegisterError::Io)?;
+        let reply = self.read_reply("the account creation confirmation").map_err(RegisterError::Io)?;
+        if is_refusal(&reply) {
+            return Err(RegisterError::Password(reply));
+        }
+        Ok(())
+    }
+
+    /// Logs in and joins `channel`. The first prompt is taken as the name
+    /// request and the next as the password request, whatever they say.
+    /// Servers that ask for both at once, or never prompt, get
+    /// `name password` on one line.
+    pub(crate) fn connect(&mut self, username: &str, password: &str, channel: &str) -> Result<(), LoginError> {
         if username.is_empty() || password.is_empty() {
-            return -1;
+            return Err(LoginError::Io(io::Error::new(io::ErrorKind::InvalidInput, "username and password are required")));
         }
+        self.with_deadline(|connection| connection.log_in(username, password, channel))
+    }
 
-        self.stream.write_all(&[3]).unwrap();
+    fn log_in(&mut self, username: &str, password: &str, channel: &str) -> Result<(), LoginError> {
+        self.phase(ConnectPhase::Username);
+        self.stream().write_all(&[3]).map_err(LoginError::Io)?;
+
+        let both_at_once = match self.wait_for_prompt("the login prompt").map_err(LoginError::Io)? {
+            Some(prompt) => prompt.to_lowercase().contains("password"),
+            None => true,
+        };
+        if both_at_once {
+            self.phase(ConnectPhase::Password);
+            self.answer(&format!("{} {}", username, password)).map_err(LoginError::Io)?;
+        } else {
+            self.answer(username).map_err(LoginError::Io)?;
+            self.phase(ConnectPhase::Password);
+            if self.wait_for_prompt("the password prompt").map_err(LoginError::Io)?.is_none() {
+                return Err(LoginError::Io(self.timed_out("the password prompt")));
+            }
+            self.answer(password).map_err(LoginError::Io)?;
+        }
+        self.phase(ConnectPhase::Authenticate);
+        self.wait_for_login()?;
 
-        println!("Sending username... {}", username);
-        self.waitfor("Username:");
-        self.stream.write_all(format!("{}\r\n", username).as_bytes()).unwrap();
+        self.phase(ConnectPhase::Join);
+        if !channel.is_empty() {
+            self.answer(&format!("/join {}", channel)).map_err(LoginError::Io)?;
+        }
+        Ok(())
+    }
 
-        println!("Sending password...");
-        self.waitfor("Password:");
-        self.stream.write_all(format!("{}\r\n", password).as_bytes()).unwrap();
+    /// Reads past the password until the server says how the login went
+    /// (see [`LoginReply`]). Being asked for the name again means the login
+    /// was refused. A server that says nothing in time has not let us in.
+    fn wait_for_login(&mut self) -> Result<(), LoginError> {
+        self.arm("the login response").map_err(LoginError::Io)?;
+        let mut reply = LoginReply::default();
+        let mut buffer = [0; 1024];
+        loop {
+            let n = match self.stream().read(&mut buffer) {
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Err(reply.end(self.timed_out("the login response")));
+                }
+                Err(err) => return Err(LoginError::Io(err)),
+            };
+            if n == 0 {
+                return Err(reply.end(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during login")));
+            }
+            let lines = self.lines.push(&buffer[..n]);
+            let outcome = reply.read(&lines, &self.lines.partial());
+            self.early.extend(lines);
+            if let Some(outcome) = outcome {
+                return outcome;
+            }
+        }
+    }
 
-        self.stream.write_all(format!("{}\r\n", "/join w3").as_bytes()).unwrap();
-        return 0;
+    /// Asks the server to end the session, so it logs a normal logoff rather
+    /// than a dropped connection. False when there is no server to tell.
+    pub(crate) fn quit(&mut self) -> bool {
+        match self.stream.as_mut() {
+            Some(stream) => stream.write_line("/quit").is_ok(),
+            None => false,
+        }
     }
 
-    pub fn send(&mut self, msg: String) {
+    pub fn send(&mut self, msg: String) -> io::Result<()> {
         // println!("Sending: {}", msg);
-        self.stream.write_all(format!("{}\r\n", msg).as_bytes()).unwrap();
+        match self.stream.as_mut() {
+            Some(stream) => stream.write_line(&msg),
+            None => Ok(()),
+        }
+    }
+}
+
+
+/// Collects raw bytes from the socket and hands out complete lines. A line
+/// (or a UTF-8 sequence) split across two reads waits here for its tail.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Adds `bytes` and returns every line they complete, without the `\r\n`.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let end = match self.pending.iter().rposition(|byte| *byte == b'\n') {
+            Some(end) => end,
+            None => return vec![],
+        };
+        let complete: Vec<u8> = self.pending.drain(..=end).collect();
+        complete
+            .split(|byte| *byte == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty())
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect()
+    }
+
+    /// The unfinished line waiting for its tail, such as a prompt.
+    pub fn partial(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.pending)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::mock_server::{MockServer, Step};
+    use super::*;
+
+    /// Read threads are counted process-wide, so tests take turns running one.
+    static READ_THREADS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// Lines the read thread hands over until the server hangs up.
+    fn session_lines(connection: &mut Connect) -> Vec<NetEvent> {
+        let _turn = READ_THREADS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let events = connection.start_reading(Arc::new(|| {})).unwrap();
+        let mut received = vec![];
+        while let Ok(event) = events.recv_timeout(Duration::from_secs(5)) {
+            let done = !matches!(event, NetEvent::Line(_));
+            received.push(event);
+            if done {
+                break;
+            }
+        }
+        connection.shutdown().unwrap();
+        received
+    }
+
+    fn line(text: &str) -> NetEvent {
+        NetEvent::Line(text.to_string())
+    }
+
+    #[test]
+    fn logs_in_and_keeps_the_lines_after_the_password() {
+        let server = MockServer::start(vec![
+            Step::Send("Username: "),
+            Step::Expect("Bob"),
+            Step::Send("Password: "),
+            Step::Expect("secret"),
+            // The success line and the first chat lines arrive in one write.
+            Step::Send("\r\n2010 NAME Bob\r\n1018 INFO \"Welcome\"\r\n1007 CHA"),
+            Step::Expect("/join Chat"),
+            Step::Send("NNEL \"Chat\"\r\n"),
+        ]);
+        let mut connection = server.connect();
+        connection.connect("Bob", "secret", "Chat").unwrap();
+        assert_eq!(
+            session_lines(&mut connection),
+            vec![
+                line("2010 NAME Bob"),
+                line("1018 INFO \"Welcome\""),
+                line("1007 CHANNEL \"Chat\""),
+                NetEvent::Disconnected("Connection closed by server".into()),
+            ]
+        );
+        assert_eq!(server.finish(), vec!["Bob", "secret", "/join Chat"]);
+    }
+
+    #[test]
+    fn prompts_split_across_slow_writes() {
+        let server = MockServer::start(vec![
+            Step::Send("Welcome to PvPGN\r\nUser"),
+            Step::Pause(50),
+            Step::Send("name"),
+            Step::Pause(50),
+            Step::Send(": "),
+            Step::Expect("Bob"),
+            Step::Send("Pass"),
+            Step::Pause(50),
+            Step::Send("word: "),
+            Step::Expect("secret"),
+            Step::Send("\r\n2010 "),
+            Step::Pause(50),
+            Step::Send("NAME Bob\r\n"),
+            Step::Expect("/join Chat"),
+        ]);
+        let mut connection = server.connect();
+        connection.connect("Bob", "secret", "Chat").unwrap();
+        assert_eq!(session_lines(&mut connection)[0], line("2010 NAME Bob"));
+        server.finish();
+    }
+
+    /// Logs in as Bob against `script` and returns what the client sent.
+    fn log_in_against(script: Vec<Step>) -> Vec<String> {
+        let server = MockServer::start(script);
+        let mut connection = server.connect();
+        connection.connect("Bob", "secret", "").unwrap();
+        drop(connection);
+        server.finish()
+    }
+
+    #[test]
+    fn prompts_in_another_language() {
+        let sent = log_in_against(vec![
+            Step::Send("Willkommen!\r\nHinweis: bitte freundlich sein:\r\nBenutzername: "),
+            Step::Expect("Bob"),
+            Step::Send("Passwort: "),
+            Step::Expect("secret"),
+            Step::Send("2010 NAME Bob\r\n"),
+        ]);
+        assert_eq!(sent, vec!["Bob", "secret"]);
+    }
+
+    #[test]
+    fn one_prompt_for_name_and_password() {
+        let sent = log_in_against(vec![
+            Step::Send("Enter name and password: "),
+            Step::Expect("Bob secret"),
+            Step::Send("2010 NAME Bob\r\n"),
+        ]);
+        assert_eq!(sent, vec!["Bob secret"]);
+    }
+
+    #[test]
+    fn no_prompt_at_all() {
+        let sent = log_in_against(vec![Step::Expect("Bob secret"), Step::Send("2010 NAME Bob\r\n")]);
+        assert_eq!(sent, vec!["Bob secret"]);
+    }
+
+    /// An in-memory [`Transport`]: reads hand out `incoming` one chunk at a
+    /// time, writes pile up in `written`, and clones share both.
+    #[derive(Debug, Clone, Default)]
+    struct Scripted {
+        incoming: Arc<std::sync::Mutex<std::collections::VecDeque<&'static str>>>,
+        written: Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl Scripted {
+        fn new(chunks: &[&'static str]) -> Scripted {
+            let scripted = Scripted::default();
+            scripted.incoming.lock().unwrap().extend(chunks);
+            scripted
+        }
+
+        fn written(&self) -> String {
+            String::from_utf8_lossy(&self.written.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            // An empty script is a closed connection.
+            let chunk = self.incoming.lock().unwrap().pop_front().unwrap_or_default();
+            buffer[..chunk.len()].copy_from_slice(chunk.as_bytes());
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.written.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Scripted {
+        fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+            Ok(Box::new(self.clone()))
+        }
+
+        fn shutdown(&self) -> io::Result<()> {
+            self.incoming.lock().unwrap().clear();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_handshake_runs_over_any_transport() {
+        let transport = Scripted::new(&["Username: ", "Password: ", "2010 NAME Bob\r\n1007 CHANNEL \"Chat\"\r\n"]);
+        let mut connection = Connect::new(Box::new(transport.clone()), TimeoutConfig::default());
+        connection.connect("Bob", "secret", "Chat").unwrap();
+        assert_eq!(transport.written(), "\u{3}Bob\r\nsecret\r\n/join Chat\r\n");
+        assert_eq!(
+            session_lines(&mut connection),
+            vec![line("2010 NAME Bob"), line("1007 CHANNEL \"Chat\""), NetEvent::Disconnected("Connection closed by server".into())]
+        );
+    }
+
+    #[test]
+    fn lines_go_out_with_crlf() {
+        let transport = Scripted::default();
+        let mut connection = Connect::new(Box::new(transport.clone()), TimeoutConfig::default());
+        connection.send("hello".to_string()).unwrap();
+        assert!(connection.quit());
+        assert_eq!(transport.written(), "hello\r\n/quit\r\n");
+        // Replays have no server: sending does nothing.
+        let mut offline = Connect::offline();
+        assert!(offline.send("hello".to_string()).is_ok());
+        assert!(!offline.quit());
+    }
+
+    fn addresses(list: &[&str]) -> Vec<SocketAddr> {
+        list.iter().map(|address| address.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn default_port_is_added_only_when_missing() {
+        assert_eq!(with_default_port("pvpgn.example"), "pvpgn.example:6112");
+        assert_eq!(with_default_port(" pvpgn.example:7000 "), "pvpgn.example:7000");
+        assert_eq!(with_default_port("10.0.0.1"), "10.0.0.1:6112");
+        assert_eq!(with_default_port("::1"), "[::1]:6112");
+        assert_eq!(with_default_port("[::1]"), "[::1]:6112");
+        assert_eq!(with_default_port("[::1]:7000"), "[::1]:7000");
+    }
+
+    #[test]
+    fn host_and_port_split_around_ipv6_brackets() {
+        assert_eq!(split_host_port("pvpgn.example"), ("pvpgn.example", 6112));
+        assert_eq!(split_host_port("pvpgn.example:7000"), ("pvpgn.example", 7000));
+        assert_eq!(split_host_port("[::1]:7000"), ("::1", 7000));
+        assert_eq!(split_host_port("[::1]"), ("::1", 6112));
+        assert_eq!(split_host_port("::1"), ("::1", 6112));
+    }
+
+    #[test]
+    fn families_alternate_starting_with_the_resolvers_first() {
+        let resolved = addresses(&["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"]);
+        assert_eq!(
+            interleave(resolved),
+            addresses(&["[::1]:1", "10.0.0.1:1", "[::2]:1", "10.0.0.2:1", "[::3]:1"])
+        );
+        let resolved = addresses(&["10.0.0.1:1", "[::1]:1", "[::2]:1", "[::3]:1"]);
+        assert_eq!(
+            interleave(resolved),
+            addresses(&["10.0.0.1:1", "[::1]:1", "[::2]:1", "[::3]:1"])
+        );
+        assert_eq!(resolve("127.0.0.1:7000").unwrap(), addresses(&["127.0.0.1:7000"]));
+    }
+
+    #[test]
+    fn opens_the_address_that_answers() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        assert!(open(&address, Duration::from_secs(1)).is_ok());
+        drop(listener);
+        // Nothing listens there any more.
+        assert!(matches!(open(&address, Duration::from_secs(1)), Err(ConnectError::Refused(server)) if server == address));
+    }
+
+    #[test]
+    fn an_address_that_cannot_resolve_is_not_a_refusal() {
+        // A NUL can never be looked up, so this fails without asking DNS.
+        let err = open("bad\0host", Duration::from_secs(1)).unwrap_err();
+        assert!(matches!(err, ConnectError::Resolve(ref server, _) if server == "bad\0host:6112"));
+        assert!(err.to_string().starts_with("Could not resolve host bad\0host:6112: "), "{}", err);
+        assert_eq!(ConnectError::Refused("h:1".into()).to_string(), "Connection refused by h:1");
+    }
+
+    #[test]
+    fn shutting_down_stops_the_read_thread_quietly() {
+        let _turn = READ_THREADS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (_server, _) = listener.accept().unwrap();
+        let mut connection = Connect::new(Box::new(client), TimeoutConfig::default());
+        let before = live_readers();
+        let events = connection.start_reading(Arc::new(|| {})).unwrap();
+        assert_eq!(live_readers(), before + 1);
+        connection.shutdown().unwrap();
+        assert_eq!(live_readers(), before);
+        // Hanging up on purpose is not reported as a lost connection.
+        assert_eq!(events.recv_timeout(Duration::from_millis(100)), Err(std::sync::mpsc::RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn events_are_taken_in_order_up_to_the_cap() {
+        let (sender, events) = channel();
+        for n in 0..250 {
+            sender.send(line(&n.to_string())).unwrap();
+        }
+        let (first, closed) = take_events(&events, 200);
+        assert_eq!(first.len(), 200);
+        assert_eq!(first[0], line("0"));
+        assert_eq!(first[199], line("199"));
+        assert!(!closed);
+        let (rest, closed) = take_events(&events, 200);
+        assert_eq!(rest.first(), Some(&line("200")));
+        assert_eq!(rest.len(), 50);
+        assert!(!closed);
+        // What was sent before the read thread exited still comes out first.
+        sender.send(line("last")).unwrap();
+        drop(sender);
+        assert_eq!(take_events(&events, 200), (vec![line("last")], true));
+    }
+
+    #[test]
+    fn a_refused_login_blames_the_password() {
+        let server = MockServer::start(vec![
+            Step::Send("Username: "),
+            Step::Expect("Bob"),
+            Step::Send("Password: "),
+            Step::Expect("wrong"),
+            Step::Send("\r\nLogin failed.\r\n"),
+            Step::Pause(50),
+            Step::Send("Username: "),
+        ]);
+        let outcome = server.connect().connect("Bob", "wrong", "Chat");
+        assert!(matches!(outcome, Err(LoginError::BadPassword)), "{:?}", outcome);
+        server.finish();
+    }
+
+    /// Logs in as Bob against a server that answers the password with `reply`.
+    fn refused_with(reply: Vec<Step>) -> LoginError {
+        let mut script = vec![Step::Send("Username: "), Step::Expect("Bob"), Step::Send("Password: "), Step::Expect("secret")];
+        script.extend(reply);
+        let server = MockServer::start(script);
+        let outcome = server.connect().connect("Bob", "secret", "Chat");
+        server.finish();
+        outcome.expect_err("the login should be refused")
+    }
+
+    #[test]
+    fn a_coded_bad_password_blames_the_password() {
+        let err = refused_with(vec![Step::Send("2002 BAD PASSWORD\r\n"), Step::Pause(50)]);
+        assert!(matches!(err, LoginError::BadPassword), "{:?}", err);
+        assert!(!err.blames_name());
+    }
+
+    #[test]
+    fn a_missing_account_blames_the_name() {
+        let err = refused_with(vec![Step::Send("\r\nAccount does not exist.\r\n"), Step::Pause(50), Step::Send("Username: ")]);
+        assert!(matches!(err, LoginError::NoAccount), "{:?}", err);
+        assert!(err.blames_name());
+    }
+
+    #[test]
+    fn a_locked_account_blames_the_name() {
+        // Some servers hang up right after saying so.
+        let err = refused_with(vec![Step::Send("\r\nThis account is locked.\r\n")]);
+        assert!(matches!(err, LoginError::Locked), "{:?}", err);
+        assert!(err.blames_name());
+    }
+
+    #[test]
+    fn other_coded_refusals_keep_the_servers_words() {
+        let err = refused_with(vec![Step::Send("2019 ERROR \"Too many logins\"\r\n"), Step::Pause(50)]);
+        assert!(matches!(&err, LoginError::Refused(text) if text == "Too many logins"), "{:?}", err);
+        assert!(!err.blames_name());
+        assert_eq!(err.to_string(), "Login refused: Too many logins");
+    }
+
+    #[test]
+    fn a_drop_mid_handshake_is_an_error() {
+        let server = MockServer::start(vec![Step::Send("Username: "), Step::Expect("Bob")]);
+        let outcome = server.connect().connect("Bob", "secret", "Chat");
+        assert!(
+            matches!(&outcome, Err(LoginError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof),
+            "{:?}",
+            outcome
+        );
+        server.finish();
+    }
+
+    #[test]
+    fn a_server_that_goes_quiet_after_the_password_times_out() {
+        let server = MockServer::start(vec![
+            Step::Send("Username: "),
+            Step::Expect("Bob"),
+            Step::Send("Password: "),
+            Step::Expect("secret"),
+            Step::Pause(1500),
+        ]);
+        let outcome = server.connect().connect("Bob", "secret", "Chat");
+        assert!(
+            matches!(&outcome, Err(LoginError::Io(err)) if err.kind() == io::ErrorKind::TimedOut),
+            "{:?}",
+            outcome
+        );
+        server.finish();
+    }
+
+    /// Runs `reads` (each one socket read) through a [`LoginReply`], then
+    /// ends it as a timeout if nothing was decided.
+    fn login_transcript(reads: &[&str]) -> Result<(), LoginError> {
+        let mut lines = LineBuffer::default();
+        let mut reply = LoginReply::default();
+        for read in reads {
+            let complete = lines.push(read.as_bytes());
+            if let Some(outcome) = reply.read(&complete, &lines.partial()) {
+                return outcome;
+            }
+        }
+        Err(reply.end(io::Error::new(io::ErrorKind::TimedOut, "timed out")))
+    }
+
+    #[test]
+    fn login_outcome_of_single_lines() {
+        assert!(matches!(login_outcome("2010 NAME Bob"), Some(Ok(()))));
+        assert!(matches!(login_outcome("1007 CHANNEL \"Chat\""), Some(Ok(()))));
+        assert!(matches!(login_outcome("Login failed."), Some(Err(LoginError::BadPassword))));
+        assert!(matches!(login_outcome("Incorrect password."), Some(Err(LoginError::BadPassword))));
+        assert!(matches!(login_outcome("Account does not exist."), Some(Err(LoginError::NoAccount))));
+        assert!(matches!(login_outcome("This account is locked."), Some(Err(LoginError::Locked))));
+        assert!(matches!(
+            login_outcome("2019 ERROR \"Too many logins\""),
+            Some(Err(LoginError::Refused(text))) if text == "Too many logins"
+        ));
+        assert!(login_outcome("Welcome to PvPGN!").is_none());
+        assert!(login_outcome("").is_none());
+    }
+
+    #[test]
+    fn refused_password_transcript() {
+        let outcome = login_transcript(&["\r\nLogin failed.\r\n", "Username: "]);
+        assert!(matches!(outcome, Err(LoginError::BadPassword)));
+        // A bare re-prompt says the same without words.
+        assert!(matches!(login_transcript(&["\r\nUsername: "]), Err(LoginError::BadPassword)));
+    }
+
+    #[test]
+    fn unknown_account_transcript() {
+        let outcome = login_transcript(&["\r\nAccount does not exist.\r\nUsername: "]);
+        assert!(matches!(outcome, Err(LoginError::NoAccount)));
+        let err = outcome.unwrap_err();
+        assert!(err.blames_name());
+        // Hanging up after saying so keeps the reason.
+        assert!(matches!(login_transcript(&["Account does not exist.\r\n"]), Err(LoginError::NoAccount)));
+    }
+
+    #[test]
+    fn success_transcript_with_failed_in_the_banner() {
+        let outcome = login_transcript(&[
+            "\r\nWelcome! If your login failed, visit example.org.\r\n",
+            "Last login failed: never\r\n2010 NAME Bob\r\n",
+            "1007 CHANNEL \"Chat\"\r\n",
+        ]);
+        assert!(outcome.is_ok());
+    }
+
+    #[test]
+    fn silence_is_not_a_login() {
+        let outcome = login_transcript(&["\r\n"]);
+        assert!(matches!(outcome, Err(LoginError::Io(err)) if err.kind() == io::ErrorKind::TimedOut));
+        assert!(matches!(login_transcript(&[]), Err(LoginError::Io(_))));
+    }
+
+    #[test]
+    fn a_line_split_across_reads_comes_out_once_complete() {
+        let mut lines = LineBuffer::default();
+        assert!(lines.push(b"1005 TALK Bob 00").is_empty());
+        assert_eq!(lines.partial(), "1005 TALK Bob 00");
+        assert_eq!(lines.push(b"10 \"hi\"\r\n"), vec!["1005 TALK Bob 0010 \"hi\""]);
+        assert_eq!(lines.partial(), "");
+    }
+
+    #[test]
+    fn a_character_split_across_reads_is_not_mangled() {
+        let mut lines = LineBuffer::default();
+        let text = "1005 TALK Bob 0010 \"café\"\r\n".as_bytes();
+        let split = text.iter().position(|byte| *byte == 0xC3).unwrap() + 1;
+        assert!(lines.push(&text[..split]).is_empty());
+        assert_eq!(lines.push(&text[split..]), vec!["1005 TALK Bob 0010 \"café\""]);
+    }
+
+    #[test]
+    fn crlf_and_bare_newlines_both_end_lines() {
+        let mut lines = LineBuffer::default();
+        assert_eq!(lines.push(b"one\r\ntwo\nthree\r"), vec!["one", "two"]);
+        assert_eq!(lines.push(b"\n"), vec!["three"]);
+    }
+
+    #[test]
+    fn several_lines_in_one_chunk_keep_the_tail() {
+        let mut lines = LineBuffer::default();
+        assert_eq!(lines.push(b"a\r\n\r\nb\r\nUsername: "), vec!["a", "b"]);
+        assert_eq!(lines.partial(), "Username: ");
+    }
+}
+
+/// A scripted PvPGN server on a local port, for driving [`Connect`] through
+/// real sockets without any outside network.
+#[cfg(test)]
+mod mock_server {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use super::{Connect, TimeoutConfig};
+
+    /// One step of a server transcript. The connection drops when the
+    /// script runs out.
+    pub enum Step {
+        /// Writes these bytes as they are, prompts without a line break.
+        Send(&'static str),
+        /// Reads one line from the client and checks it.
+        Expect(&'static str),
+        /// Waits before the next step, to split writes across reads.
+        Pause(u64),
+    }
+
+    /// Short enough for tests; the handshake budget still covers slow steps.
+    pub const TIMEOUTS: TimeoutConfig = TimeoutConfig { connect_secs: 1, prompt_secs: 1, handshake_secs: 5 };
+
+    pub struct MockServer {
+        pub address: SocketAddr,
+        handle: JoinHandle<Vec<String>>,
+    }
+
+    impl MockServer {
+        /// Listens on a free local port and plays `script` to the first client.
+        pub fn start(script: Vec<Step>) -> MockServer {
+            let listener = TcpListener::bind("127.0.0.1:0").expect("bind a local port");
+            let address = listener.local_addr().unwrap();
+            let handle = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().expect("accept the client");
+                stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                let mut received = vec![];
+                for step in script {
+                    match step {
+                        Step::Send(bytes) => stream.write_all(bytes.as_bytes()).unwrap(),
+                        Step::Expect(expected) => {
+                            let line = read_line(&mut stream);
+                            assert_eq!(line, expected, "the client sent something else");
+                            received.push(line);
+                        }
+                        Step::Pause(ms) => std::thread::sleep(Duration::from_millis(ms)),
+                    }
+                }
+                received
+            });
+            MockServer { address, handle }
+        }
+
+        /// A [`Connect`] on a fresh connection to this server, as a boxed transport.
+        pub fn connect(&self) -> Connect {
+            let stream = TcpStream::connect(self.address).expect("connect to the mock server");
+            Connect::new(Box::new(stream), TIMEOUTS)
+        }
+
+        /// Waits for the script to finish and returns the lines the client sent.
+        pub fn finish(self) -> Vec<String> {
+            self.handle.join().expect("the script panicked")
+        }
+    }
+
+    /// One line from the client, without the Ctrl-C that opens the chat
+    /// gateway or the `\r\n`.
+    fn read_line(stream: &mut TcpStream) -> String {
+        let mut line = vec![];
+        let mut byte = [0];
+        while stream.read(&mut byte).expect("read from the client") == 1 && byte[0] != b'\n' {
+            if byte[0] != 3 {
+                line.push(byte[0]);
+            }
+        }
+        String::from_utf8_lossy(&line).trim_end_matches('\r').to_string()
+    }
+}
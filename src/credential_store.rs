@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+/// Small key/value store for secret material (encryption keys and the like).
+/// Kept apart from `credentials.json` so it can be locked down on its own.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CredentialStore {
+    entries: HashMap<String, String>,
+}
+
+impl CredentialStore {
+    /// Loads the store, treating a missing file as an empty store.
+    pub fn load() -> io::Result<CredentialStore> {
+        match std::fs::read_to_string(paths::config_file(paths::SECRETS)) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(CredentialStore::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(|value| value.as_str())
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.entries.iter()
+    }
+
+    pub fn set(&mut self, key: &str, value: String) -> io::Result<()> {
+        self.entries.insert(key.to_string(), value);
+        self.save()
+    }
+
+    pub fn remove(&mut self, key: &str) -> io::Result<()> {
+        if self.entries.remove(key).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(paths::config_file(paths::SECRETS))?.write_all(text.as_bytes())
+    }
+}
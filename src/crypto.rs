@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::credential_store::CredentialStore;
+use crate::protocol;
+
+/// Prefix of a public key offer sent inside a whisper.
+pub const KEY_TAG: &str = "~e2e:k:";
+/// Prefix of an encrypted whisper chunk.
+pub const MESSAGE_TAG: &str = "~e2e:m:";
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const IDENTITY_KEY: &str = "e2e/identity";
+const PEER_KEY_PREFIX: &str = "e2e/peer/";
+
+const WORDS: [&str; 64] = [
+    "acid", "amber", "anvil", "arrow", "ash", "aspen", "atlas", "axe",
+    "badge", "barn", "beacon", "birch", "blade", "bolt", "brook", "cabin",
+    "canyon", "cedar", "chalk", "cliff", "cobalt", "comet", "coral", "crane",
+    "dagger", "delta", "dune", "eagle", "ember", "fable", "falcon", "fern",
+    "flint", "forge", "frost", "garnet", "glade", "golem", "granite", "harbor",
+    "hawk", "helm", "ivory", "jade", "lantern", "lotus", "marble", "meadow",
+    "mint", "nova", "oak", "onyx", "orbit", "pebble", "quartz", "raven",
+    "reef", "rune", "sable", "slate", "summit", "thorn", "tundra", "willow",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CryptoError {
+    /// The payload is not valid armor (bad base64, too short, wrong size).
+    Malformed,
+    /// Authentication failed: the payload was altered or sealed with another key.
+    Tampered,
+    /// We don't have a verified key for this correspondent.
+    NoSession,
+    /// The line limit leaves no room for even one byte of ciphertext.
+    NoRoom,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::Malformed => write!(f, "malformed encrypted payload"),
+            CryptoError::Tampered => write!(f, "message failed verification (tampered or wrong key)"),
+            CryptoError::NoSession => write!(f, "no verified key for this user"),
+            CryptoError::NoRoom => write!(f, "the line limit is too low for encrypted whispers"),
+        }
+    }
+}
+
+/// A whisper body that carries one of our tagged payloads.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Armored {
+    Key([u8; 32]),
+    Message(Vec<u8>),
+}
+
+pub fn armor_key(public: &[u8; 32]) -> String {
+    format!("{}{}", KEY_TAG, STANDARD_NO_PAD.encode(public))
+}
+
+/// Recognises our tagged payloads. Anything else is a plain whisper.
+pub fn parse_armor(text: &str) -> Option<Result<Armored, CryptoError>> {
+    let text = text.trim();
+    if let Some(rest) = text.strip_prefix(KEY_TAG) {
+        let key = STANDARD_NO_PAD
+            .decode(rest)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+        return Some(key.map(Armored::Key).ok_or(CryptoError::Malformed));
+    }
+    if let Some(rest) = text.strip_prefix(MESSAGE_TAG) {
+        return Some(match STANDARD_NO_PAD.decode(rest) {
+            Ok(bytes) if bytes.len() >= NONCE_LEN + TAG_LEN => Ok(Armored::Message(bytes)),
+            _ => Err(CryptoError::Malformed),
+        });
+    }
+    None
+}
+
+/// Largest plaintext (in bytes) whose armored form fits in `room` bytes.
+/// Unpadded base64 turns each 3 bytes into 4 characters; a short last
+/// group is never longer than its full-size bound.
+fn max_chunk_len(room: usize) -> Option<usize> {
+    let payload = room.checked_sub(MESSAGE_TAG.len())? / 4 * 3;
+    payload.checked_sub(NONCE_LEN + TAG_LEN).filter(|len| *len > 0)
+}
+
+/// Encrypts `text` into armored lines of at most `room` bytes each, every
+/// one readable on its own. The text is split as plain messages are, so
+/// each chunk is valid UTF-8 and breaks at a space where it can.
+pub fn seal(key: &[u8; 32], text: &str, room: usize) -> Result<Vec<String>, CryptoError> {
+    let max = max_chunk_len(room).ok_or(CryptoError::NoRoom)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let chunks = protocol::split_message(text, max)
+        .into_iter()
+        .map(|chunk| {
+            let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let sealed = cipher
+                .encrypt(&nonce, chunk.as_bytes())
+                .expect("encrypting into a Vec cannot fail");
+            let mut payload = nonce.to_vec();
+            payload.extend(sealed);
+            format!("{}{}", MESSAGE_TAG, STANDARD_NO_PAD.encode(payload))
+        })
+        .collect();
+    Ok(chunks)
+}
+
+pub fn open(key: &[u8; 32], payload: &[u8]) -> Result<String, CryptoError> {
+    if payload.len() < NONCE_LEN + TAG_LEN {
+        return Err(CryptoError::Malformed);
+    }
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let (nonce, sealed) = payload.split_at(NONCE_LEN);
+    let plain = cipher
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| CryptoError::Tampered)?;
+    String::from_utf8(plain).map_err(|_| CryptoError::Malformed)
+}
+
+/// Both public keys in a fixed order so each side derives the same values.
+fn ordered<'a>(a: &'a [u8; 32], b: &'a [u8; 32]) -> (&'a [u8; 32], &'a [u8; 32]) {
+    if a <= b { (a, b) } else { (b, a) }
+}
+
+pub fn session_key(secret: &StaticSecret, theirs: &[u8; 32]) -> [u8; 32] {
+    let ours = PublicKey::from(secret);
+    let shared = secret.diffie_hellman(&PublicKey::from(*theirs));
+    let (first, second) = ordered(ours.as_bytes(), theirs);
+    let mut hasher = Sha256::new();
+    hasher.update(b"pvpgn-chat e2e v1");
+    hasher.update(shared.as_bytes());
+    hasher.update(first);
+    hasher.update(second);
+    hasher.finalize().into()
+}
+
+/// Six words both sides compare out of band to rule out a swapped key.
+pub fn verification_phrase(a: &[u8; 32], b: &[u8; 32]) -> String {
+    let (first, second) = ordered(a, b);
+    let mut hasher = Sha256::new();
+    hasher.update(b"pvpgn-chat verify v1");
+    hasher.update(first);
+    hasher.update(second);
+    let digest = hasher.finalize();
+    digest[..6]
+        .iter()
+        .map(|byte| WORDS[(*byte & 63) as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Peer {
+    public_key: Option<String>,
+    offered: bool,
+    verified: bool,
+}
+
+impl Peer {
+    fn key(&self) -> Option<[u8; 32]> {
+        let encoded = self.public_key.as_ref()?;
+        STANDARD_NO_PAD.decode(encoded).ok()?.try_into().ok()
+    }
+}
+
+/// What the UI should show for an incoming whisper.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received {
+    Plain,
+    Decrypted(String),
+    Notice(String),
+    Rejected(CryptoError),
+}
+
+/// Where encryption stands with one correspondent, used to build the context menu.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PeerState {
+    Off,
+    WaitingForKey,
+    NeedsVerification,
+    Enabled,
+}
+
+/// Identity key plus per-correspondent state, persisted in the credential store.
+pub struct E2e {
+    secret: StaticSecret,
+    peers: HashMap<String, Peer>,
+    store: CredentialStore,
+}
+
+impl fmt::Debug for E2e {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("E2e").field("peers", &self.peers.len()).finish()
+    }
+}
+
+impl E2e {
+    pub fn load(mut store: CredentialStore) -> E2e {
+        let existing = store
+            .get(IDENTITY_KEY)
+            .and_then(|encoded| STANDARD_NO_PAD.decode(encoded).ok())
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+        let secret = match existing {
+            Some(bytes) => StaticSecret::from(bytes),
+            None => {
+                let secret = StaticSecret::random_from_rng(OsRng);
+                if let Err(err) = store.set(IDENTITY_KEY, STANDARD_NO_PAD.encode(secret.to_bytes())) {
+                    println!("Could not store encryption identity: {}", err);
+                }
+                secret
+            }
+        };
+        let peers = E2e::load_peers(&store);
+        E2e { secret, peers, store }
+    }
+
+    fn load_peers(store: &CredentialStore) -> HashMap<String, Peer> {
+        let mut peers = HashMap::new();
+        for (key, value) in store.entries() {
+            if let Some(name) = key.strip_prefix(PEER_KEY_PREFIX) {
+                if let Ok(peer) = serde_json::from_str::<Peer>(value) {
+                    peers.insert(name.to_string(), peer);
+                }
+            }
+        }
+        peers
+    }
+
+    fn public(&self) -> [u8; 32] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+
+    fn save_peer(&mut self, name: &str) {
+        let key = format!("{}{}", PEER_KEY_PREFIX, name);
+        let result = match self.peers.get(name) {
+            Some(peer) => self.store.set(&key, serde_json::to_string(peer).unwrap()),
+            None => self.store.remove(&key),
+        };
+        if let Err(err) = result {
+            println!("Could not store encryption state for {}: {}", name, err);
+        }
+    }
+
+    pub fn state(&self, user: &str) -> PeerState {
+        match self.peers.get(&user.to_lowercase()) {
+            None => PeerState::Off,
+            Some(peer) if peer.verified && peer.key().is_some() => PeerState::Enabled,
+            Some(peer) if peer.key().is_some() && peer.offered => PeerState::NeedsVerification,
+            Some(_) => PeerState::WaitingForKey,
+        }
+    }
+
+    /// Marks our side of the exchange and returns the offer to whisper to `user`.
+    pub fn start(&mut self, user: &str) -> String {
+        let name = user.to_lowercase();
+        self.peers.entry(name.clone()).or_default().offered = true;
+        self.save_peer(&name);
+        armor_key(&self.public())
+    }
+
+    pub fn phrase(&self, user: &str) -> Option<String> {
+        let theirs = self.peers.get(&user.to_lowercase())?.key()?;
+        Some(verification_phrase(&self.public(), &theirs))
+    }
+
+    pub fn verify(&mut self, user: &str) {
+        let name = user.to_lowercase();
+        if let Some(peer) = self.peers.get_mut(&name) {
+            peer.verified = true;
+            self.save_peer(&name);
+        }
+    }
+
+    pub fn disable(&mut self, user: &str) {
+        let name = user.to_lowercase();
+        self.peers.remove(&name);
+        self.save_peer(&name);
+    }
+
+    fn key_for(&self, user: &str) -> Result<[u8; 32], CryptoError> {
+        match self.peers.get(&user.to_lowercase()) {
+            Some(peer) if peer.verified => peer
+                .key()
+                .map(|theirs| session_key(&self.secret, &theirs))
+                .ok_or(CryptoError::NoSession),
+            _ => Err(CryptoError::NoSession),
+        }
+    }
+
+    /// Armored lines of at most `room` bytes for an outgoing whisper, or
+    /// `None` if encryption is off for `user`.
+    pub fn seal_for(&self, user: &str, text: &str, room: usize) -> Option<Result<Vec<String>, CryptoError>> {
+        self.key_for(user).ok().map(|key| seal(&key, text, room))
+    }
+
+    /// Decrypts the server's echo of a whisper we sent to `user`.
+    pub fn open_sent(&self, user: &str, text: &str) -> Option<String> {
+        match parse_armor(text)? {
+            Ok(Armored::Message(payload)) => open(&self.key_for(user).ok()?, &payload).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn receive(&mut self, from: &str, text: &str) -> Received {
+        let armored = match parse_armor(text) {
+            None => return Received::Plain,
+            Some(Err(err)) => return Received::Rejected(err),
+            Some(Ok(armored)) => armored,
+        };
+        match armored {
+            Armored::Message(payload) => match self.key_for(from) {
+                Ok(key) => match open(&key, &payload) {
+                    Ok(text) => Received::Decrypted(text),
+                    Err(err) => Received::Rejected(err),
+                },
+                Err(err) => Received::Rejected(err),
+            },
+            Armored::Key(theirs) => {
+                let name = from.to_lowercase();
+                let encoded = STANDARD_NO_PAD.encode(theirs);
+                let peer = self.peers.entry(name.clone()).or_default();
+                let changed = peer.public_key.as_ref().is_some_and(|old| *old != encoded);
+                let was_verified = peer.verified;
+                peer.public_key = Some(encoded);
+                if changed {
+                    peer.verified = false;
+                }
+                let offered = peer.offered;
+                self.save_peer(&name);
+                let notice = if changed && was_verified {
+                    format!("⚠ {} sent a NEW encryption key. Encryption is paused until you verify the phrase again.", from)
+                } else if offered {
+                    format!("{} sent their encryption key. Compare the verification phrase before enabling.", from)
+                } else {
+                    format!("{} wants to encrypt whispers with you. Choose \"Encrypt whispers\" on their name to reply.", from)
+                };
+                Received::Notice(notice)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn open_line(key: &[u8; 32], line: &str) -> Result<String, CryptoError> {
+        match parse_armor(line) {
+            Some(Ok(Armored::Message(payload))) => open(key, &payload),
+            Some(Ok(Armored::Key(_))) => panic!("a message came back as a key"),
+            Some(Err(err)) => Err(err),
+            None => panic!("not armored: {}", line),
+        }
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let lines = seal(&KEY, "meet at the usual place", 200).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with(MESSAGE_TAG));
+        assert_eq!(open_line(&KEY, &lines[0]).unwrap(), "meet at the usual place");
+    }
+
+    #[test]
+    fn chunks_fit_the_room_and_reassemble() {
+        let text = "héllo wörld ".repeat(40);
+        let name = "[Clan] Bob";
+        let room = 200 - protocol::whisper_prefix(name).len();
+        let lines = seal(&KEY, &text, room).unwrap();
+        assert!(lines.len() > 1);
+        for line in &lines {
+            assert!(line.len() <= room, "{} bytes in {} of room", line.len(), room);
+            assert!(protocol::whisper_prefix(name).len() + line.len() <= 200);
+        }
+        let opened: Vec<String> = lines.iter().map(|line| open_line(&KEY, line).unwrap()).collect();
+        assert_eq!(opened.join(" "), text.trim());
+    }
+
+    #[test]
+    fn a_lower_limit_makes_more_chunks() {
+        let text = "word ".repeat(60);
+        let wide = seal(&KEY, &text, 200).unwrap();
+        let narrow = seal(&KEY, &text, 90).unwrap();
+        assert!(narrow.len() > wide.len());
+        assert!(narrow.iter().all(|line| line.len() <= 90));
+    }
+
+    #[test]
+    fn no_room_is_an_error() {
+        assert_eq!(seal(&KEY, "hi", 40), Err(CryptoError::NoRoom));
+        assert_eq!(seal(&KEY, "hi", 0), Err(CryptoError::NoRoom));
+    }
+
+    #[test]
+    fn tampered_payload_is_rejected() {
+        let line = seal(&KEY, "secret", 200).unwrap().remove(0);
+        let mut payload = match parse_armor(&line) {
+            Some(Ok(Armored::Message(payload))) => payload,
+            other => panic!("{:?}", other),
+        };
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        assert_eq!(open(&KEY, &payload), Err(CryptoError::Tampered));
+    }
+
+    #[test]
+    fn another_key_is_rejected() {
+        let line = seal(&KEY, "secret", 200).unwrap().remove(0);
+        assert_eq!(open_line(&[8; 32], &line), Err(CryptoError::Tampered));
+    }
+
+    #[test]
+    fn malformed_armor() {
+        assert_eq!(parse_armor(&format!("{}not base64!", MESSAGE_TAG)), Some(Err(CryptoError::Malformed)));
+        assert_eq!(parse_armor(&format!("{}AAAA", MESSAGE_TAG)), Some(Err(CryptoError::Malformed)));
+        assert_eq!(parse_armor(&format!("{}AAAA", KEY_TAG)), Some(Err(CryptoError::Malformed)));
+        assert_eq!(parse_armor("just a whisper"), None);
+        assert_eq!(open(&KEY, &[0; 10]), Err(CryptoError::Malformed));
+    }
+
+    #[test]
+    fn key_armor_round_trip() {
+        let public = [42; 32];
+        assert_eq!(parse_armor(&armor_key(&public)), Some(Ok(Armored::Key(public))));
+    }
+
+    #[test]
+    fn both_sides_derive_the_same_session_and_phrase() {
+        let alice = StaticSecret::from([1; 32]);
+        let bob = StaticSecret::from([2; 32]);
+        let alice_public = PublicKey::from(&alice).to_bytes();
+        let bob_public = PublicKey::from(&bob).to_bytes();
+        assert_eq!(session_key(&alice, &bob_public), session_key(&bob, &alice_public));
+        assert_eq!(verification_phrase(&alice_public, &bob_public), verification_phrase(&bob_public, &alice_public));
+        assert_eq!(verification_phrase(&alice_public, &bob_public).split(' ').count(), 6);
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long a whisper waits for the server's echo or error before we stop
+/// pairing errors with it.
+pub const WHISPER_REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why the server refused a whisper, from its 1019 error text.
+pub fn whisper_failure(error: &str) -> Option<&'static str> {
+    let error = error.to_lowercase();
+    if error.contains("not logged on") || error.contains("not logged in") || error.contains("not online") {
+        Some("user is not logged on")
+    } else if error.contains("does not exist") || error.contains("no such user") || error.contains("unknown user") {
+        Some("no such user")
+    } else {
+        None
+    }
+}
+
+/// Whispers sent but not yet echoed back by the server, oldest first. The
+/// server answers in order, so an error belongs to the oldest one.
+#[derive(Debug, Default)]
+pub struct PendingWhispers {
+    queue: VecDeque<(String, Instant)>,
+}
+
+impl PendingWhispers {
+    pub fn sent(&mut self, target: &str, now: Instant) {
+        self.queue.push_back((target.to_string(), now));
+    }
+
+    /// The server echoed a whisper to `target`, so it was delivered.
+    pub fn delivered(&mut self, target: &str) {
+        if let Some(index) = self.queue.iter().position(|(pending, _)| pending.eq_ignore_ascii_case(target)) {
+            self.queue.remove(index);
+        }
+    }
+
+    /// Who a whisper the server echoed back (1010) went to. PvPGN puts our
+    /// own name in the echo rather than the recipient's; the whisper it
+    /// confirms is then the oldest one out.
+    pub fn echoed(&mut self, to: String, own_name: &str, now: Instant) -> String {
+        if to.eq_ignore_ascii_case(own_name) {
+            self.take_oldest(now).unwrap_or(to)
+        } else {
+            self.delivered(&to);
+            to
+        }
+    }
+
+    /// Takes the oldest whisper still waiting: the one an error just arrived
+    /// for, or an echo that doesn't say who it went to.
+    pub fn take_oldest(&mut self, now: Instant) -> Option<String> {
+        while let Some((target, sent)) = self.queue.pop_front() {
+            if now.duration_since(sent) <= WHISPER_REPLY_TIMEOUT {
+                return Some(target);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_echo_naming_us_goes_to_the_oldest_whisper() {
+        let now = Instant::now();
+        let mut pending = PendingWhispers::default();
+        pending.sent("[Clan] Bob", now);
+        pending.sent("Carol", now);
+        assert_eq!(pending.echoed("me".into(), "Me", now), "[Clan] Bob");
+        assert_eq!(pending.echoed("Me".into(), "Me", now), "Carol");
+        // Nothing left to pair it with: all we know is our own name.
+        assert_eq!(pending.echoed("Me".into(), "Me", now), "Me");
+    }
+
+    #[test]
+    fn an_echo_naming_the_recipient_settles_that_whisper() {
+        let now = Instant::now();
+        let mut pending = PendingWhispers::default();
+        pending.sent("Bob", now);
+        pending.sent("Carol", now);
+        assert_eq!(pending.echoed("carol".into(), "Me", now), "carol");
+        assert_eq!(pending.take_oldest(now).as_deref(), Some("Bob"));
+        assert_eq!(pending.take_oldest(now), None);
+    }
+
+    #[test]
+    fn stale_whispers_are_skipped() {
+        let now = Instant::now();
+        let mut pending = PendingWhispers::default();
+        pending.sent("Bob", now);
+        pending.sent("Carol", now + WHISPER_REPLY_TIMEOUT);
+        let later = now + WHISPER_REPLY_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(pending.echoed("Me".into(), "Me", later), "Carol");
+    }
+}
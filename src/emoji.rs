@@ -0,0 +1,103 @@
+/// Text smileys shown as pictures in received messages, unless turned off
+/// in Settings. The first one that matches is taken.
+pub const EMOTICONS: [(&str, &str); 25] = [
+    (":')", "🥲"),
+    (":'(", "😢"),
+    (":-)", "🙂"),
+    (":)", "🙂"),
+    (":-(", "☹"),
+    (":(", "☹"),
+    (":-D", "😀"),
+    (":D", "😀"),
+    ("xD", "😆"),
+    ("XD", "😆"),
+    (";-)", "😉"),
+    (";)", "😉"),
+    (":-P", "😛"),
+    (":P", "😛"),
+    (":p", "😛"),
+    (":-O", "😮"),
+    (":O", "😮"),
+    (":o", "😮"),
+    (":-/", "😕"),
+    (":|", "😐"),
+    (":*", "😘"),
+    (">:(", "😠"),
+    ("o/", "👋"),
+    ("</3", "💔"),
+    ("<3", "❤"),
+];
+
+/// One entry in the picker.
+pub struct Emoji {
+    pub character: &'static str,
+    /// Space-separated words the picker's search matches against.
+    pub name: &'static str,
+}
+
+const fn emoji(character: &'static str, name: &'static str) -> Emoji {
+    Emoji { character, name }
+}
+
+/// What the picker offers, faces first, then hands, hearts and game things.
+pub const PICKER: [Emoji; 48] = [
+    emoji("😀", "grin smile happy"),
+    emoji("😂", "joy laugh tears"),
+    emoji("🤣", "rofl laugh rolling"),
+    emoji("😆", "laugh xd"),
+    emoji("🙂", "smile slight"),
+    emoji("😉", "wink"),
+    emoji("😊", "blush smile"),
+    emoji("😍", "love heart eyes"),
+    emoji("😘", "kiss"),
+    emoji("😛", "tongue"),
+    emoji("😎", "cool sunglasses"),
+    emoji("🤔", "thinking hmm"),
+    emoji("😐", "neutral meh"),
+    emoji("🙄", "eye roll"),
+    emoji("😏", "smirk"),
+    emoji("😮", "surprised wow"),
+    emoji("😴", "sleep tired zzz"),
+    emoji("😕", "confused"),
+    emoji("☹", "frown sad"),
+    emoji("😢", "cry sad tear"),
+    emoji("😭", "sob cry"),
+    emoji("😠", "angry mad"),
+    emoji("😡", "rage angry"),
+    emoji("😱", "scream fear"),
+    emoji("🤯", "mind blown"),
+    emoji("🥲", "smile tear"),
+    emoji("👍", "thumbs up yes ok"),
+    emoji("👎", "thumbs down no"),
+    emoji("👋", "wave hi bye"),
+    emoji("👏", "clap applause"),
+    emoji("🙏", "pray please thanks"),
+    emoji("💪", "strong muscle"),
+    emoji("🤝", "handshake deal"),
+    emoji("✌", "peace victory"),
+    emoji("❤", "heart love"),
+    emoji("💔", "broken heart"),
+    emoji("🔥", "fire hot"),
+    emoji("⭐", "star"),
+    emoji("✨", "sparkles"),
+    emoji("🎉", "party tada"),
+    emoji("🏆", "trophy win"),
+    emoji("🎮", "game controller"),
+    emoji("⚔", "swords fight"),
+    emoji("🛡", "shield"),
+    emoji("💀", "skull dead"),
+    emoji("👑", "crown king"),
+    emoji("💯", "hundred perfect"),
+    emoji("☕", "coffee"),
+];
+
+/// Picker entries whose name has a word starting with `query`, all of them
+/// when it's empty.
+pub fn search(query: &str) -> impl Iterator<Item = &'static Emoji> + '_ {
+    let query = query.trim();
+    PICKER.iter().filter(move |emoji| {
+        query.is_empty()
+            || emoji.character == query
+            || emoji.name.split(' ').any(|word| word.get(..query.len()).is_some_and(|start| start.eq_ignore_ascii_case(query)))
+    })
+}
@@ -0,0 +1,50 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::clock;
+use crate::message::ChatMessage;
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    #[serde(rename = "type")]
+    message_type: &'a str,
+    content: Cow<'a, str>,
+    timestamp: String,
+}
+
+/// One `[date time] content` line per message under a header naming the
+/// channel and when the log was exported.
+pub fn to_text(messages: &[ChatMessage], channel: &str, exported_at: u64) -> String {
+    let mut text = format!("# #{} exported {}\n", channel, clock::iso(exported_at));
+    for message in messages {
+        text.push_str(&format!("[{}] {}\n", clock::iso(message.timestamp), message.text()));
+    }
+    text
+}
+
+pub fn to_json(messages: &[ChatMessage]) -> serde_json::Result<String> {
+    let entries: Vec<Entry> = messages
+        .iter()
+        .map(|message| Entry {
+            message_type: message.kind.as_str(),
+            content: message.text(),
+            timestamp: clock::iso(message.timestamp),
+        })
+        .collect();
+    serde_json::to_string_pretty(&entries)
+}
+
+/// Writes JSON when `path` ends in `.json`, plain text otherwise.
+pub fn write(path: &Path, messages: &[ChatMessage], channel: &str, exported_at: u64) -> std::io::Result<()> {
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let contents = if is_json {
+        to_json(messages).map_err(std::io::Error::other)?
+    } else {
+        to_text(messages, channel, exported_at)
+    };
+    std::fs::write(path, contents)
+}
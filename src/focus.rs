@@ -0,0 +1,159 @@
+use crate::message::MessageKind;
+
+/// Whisper-only focus mode. Nothing is dropped: channel rows still land in the
+/// buffer, they are just dimmed or hidden while focus is on.
+#[derive(Debug, Default)]
+pub struct FocusMode {
+    pub active: bool,
+    /// Hide channel chatter completely instead of dimming it.
+    pub hide: bool,
+    /// Channel rows that arrived while focused.
+    pub suppressed: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Full,
+    Dimmed,
+    Hidden,
+}
+
+/// Channel talk and join/leave noise is what focus mode quiets down.
+/// Whispers, mentions, errors and system notices always stay visible.
+pub fn is_channel_noise(kind: MessageKind, mention: bool) -> bool {
+    !mention && matches!(kind, MessageKind::Chat | MessageKind::Emote | MessageKind::JoinLeave | MessageKind::JoinFold)
+}
+
+impl FocusMode {
+    /// Returns the summary to show when focus mode is switched off.
+    pub fn toggle(&mut self) -> Option<String> {
+        self.active = !self.active;
+        if self.active {
+            self.suppressed = 0;
+            return None;
+        }
+        let count = std::mem::take(&mut self.suppressed);
+        Some(match count {
+            0 => "Focus mode off".to_string(),
+            1 => "Focus mode off: 1 channel message while focused".to_string(),
+            n => format!("Focus mode off: {} channel messages while focused", n),
+        })
+    }
+
+    pub fn note_incoming(&mut self, kind: MessageKind, mention: bool) {
+        if self.active && is_channel_noise(kind, mention) {
+            self.suppressed += 1;
+        }
+    }
+
+    pub fn visibility(&self, kind: MessageKind, mention: bool) -> Visibility {
+        if !self.active || !is_channel_noise(kind, mention) {
+            Visibility::Full
+        } else if self.hide {
+            Visibility::Hidden
+        } else {
+            Visibility::Dimmed
+        }
+    }
+}
+
+/// True when `text` names us, or says one of the comma-separated extra
+/// highlight words, as a whole word.
+pub fn is_highlight(text: &str, own_name: &str, extra_words: &str) -> bool {
+    mentions(text, own_name) || extra_words.split(',').any(|word| mentions(text, word.trim()))
+}
+
+/// True when `text` contains `name`, ignoring case, with no name character
+/// right before or after it. `name` itself may hold anything, so `Bob#2` and
+/// `[Clan] Bob` are found too.
+pub fn mentions(text: &str, name: &str) -> bool {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() {
+        return false;
+    }
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let text = text.to_lowercase();
+    text.match_indices(&name).any(|(at, _)| {
+        let before = text[..at].chars().next_back();
+        let after = text[at + name.len()..].chars().next();
+        !before.is_some_and(is_name_char) && !after.is_some_and(is_name_char)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_channel_noise_only_while_focused() {
+        let mut focus = FocusMode::default();
+        focus.note_incoming(MessageKind::Chat, false);
+        assert_eq!(focus.toggle(), None);
+        focus.note_incoming(MessageKind::Chat, false);
+        focus.note_incoming(MessageKind::Emote, false);
+        focus.note_incoming(MessageKind::JoinLeave, false);
+        focus.note_incoming(MessageKind::Whisper, false);
+        focus.note_incoming(MessageKind::Chat, true);
+        focus.note_incoming(MessageKind::Error, false);
+        assert_eq!(focus.suppressed, 3);
+        assert_eq!(focus.toggle().as_deref(), Some("Focus mode off: 3 channel messages while focused"));
+        assert_eq!(focus.suppressed, 0);
+    }
+
+    #[test]
+    fn summary_wording() {
+        let mut focus = FocusMode::default();
+        focus.toggle();
+        assert_eq!(focus.toggle().as_deref(), Some("Focus mode off"));
+        focus.toggle();
+        focus.note_incoming(MessageKind::Chat, false);
+        assert_eq!(focus.toggle().as_deref(), Some("Focus mode off: 1 channel message while focused"));
+    }
+
+    #[test]
+    fn turning_focus_on_starts_the_count_over() {
+        let mut focus = FocusMode { active: false, hide: false, suppressed: 7 };
+        focus.toggle();
+        assert_eq!(focus.suppressed, 0);
+    }
+
+    #[test]
+    fn visibility_restores_as_soon_as_focus_is_off() {
+        let mut focus = FocusMode::default();
+        assert_eq!(focus.visibility(MessageKind::Chat, false), Visibility::Full);
+        focus.toggle();
+        assert_eq!(focus.visibility(MessageKind::Chat, false), Visibility::Dimmed);
+        assert_eq!(focus.visibility(MessageKind::Chat, true), Visibility::Full);
+        assert_eq!(focus.visibility(MessageKind::Whisper, false), Visibility::Full);
+        assert_eq!(focus.visibility(MessageKind::System, false), Visibility::Full);
+        focus.hide = true;
+        assert_eq!(focus.visibility(MessageKind::JoinFold, false), Visibility::Hidden);
+        focus.toggle();
+        assert_eq!(focus.visibility(MessageKind::JoinFold, false), Visibility::Full);
+    }
+
+    #[test]
+    fn mentions_are_whole_words_ignoring_case() {
+        assert!(mentions("hey BOB, you there?", "bob"));
+        assert!(mentions("ping dark-elf", "Dark-Elf"));
+        assert!(!mentions("bobby is here", "bob"));
+        assert!(!mentions("anything", ""));
+    }
+
+    #[test]
+    fn names_with_symbols_are_found() {
+        assert!(mentions("gg bob#2!", "Bob#2"));
+        assert!(mentions("thanks [clan] bob.", "[Clan] Bob"));
+        assert!(mentions("is Bob.W3 around", "bob.w3"));
+        assert!(!mentions("gg bob#23", "Bob#2"));
+        assert!(!mentions("bobby, bob_x and xbob", "bob"));
+        assert!(mentions("bobby, then bob", "bob"));
+    }
+
+    #[test]
+    fn highlight_words_count_as_mentions() {
+        assert!(is_highlight("raid tonight?", "bob", "raid, loot"));
+        assert!(is_highlight("any LOOT left", "bob", "raid, loot"));
+        assert!(!is_highlight("nothing here", "bob", "raid, , loot"));
+    }
+}
@@ -0,0 +1,212 @@
+//! Per-user idle times, for servers whose `/who` answer includes them.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Parses the idle field variants seen in `/who` output: `idle 5m30s`,
+/// `idle: 330s`, `idle for 5 minutes`, `idle 00:05:30`, `idle 2h 3m`.
+pub fn parse_idle(text: &str) -> Option<Duration> {
+    let text = text.trim().trim_start_matches('(').trim_end_matches(')').trim();
+    let lower = text.to_lowercase();
+    let rest = lower.strip_prefix("idle")?;
+    let rest = rest.trim_start_matches(':').trim();
+    let rest = rest.strip_prefix("for ").unwrap_or(rest).trim();
+    if rest.contains(':') {
+        return parse_clock(rest);
+    }
+    parse_units(rest)
+}
+
+/// `hh:mm:ss` or `mm:ss`.
+fn parse_clock(text: &str) -> Option<Duration> {
+    let fields: Vec<u64> = text
+        .split(':')
+        .map(|field| field.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    let secs = match fields.as_slice() {
+        [m, s] => m * 60 + s,
+        [h, m, s] => h * 3600 + m * 60 + s,
+        _ => return None,
+    };
+    Some(Duration::from_secs(secs))
+}
+
+/// `5m30s`, `5 minutes 30 seconds`, `2h 3m`, or a bare number of seconds.
+fn parse_units(text: &str) -> Option<Duration> {
+    if let Ok(secs) = text.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let mut total = 0;
+    let mut number = String::new();
+    let mut unit = String::new();
+    let mut parsed_any = false;
+    let mut flush = |number: &mut String, unit: &mut String| -> Option<()> {
+        if number.is_empty() {
+            return if unit.is_empty() { Some(()) } else { None };
+        }
+        let value: u64 = number.parse().ok()?;
+        let scale = match unit.trim_end_matches(',') {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+            "d" | "day" | "days" => 86400,
+            _ => return None,
+        };
+        total += value * scale;
+        parsed_any = true;
+        number.clear();
+        unit.clear();
+        Some(())
+    };
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            if !unit.is_empty() {
+                flush(&mut number, &mut unit)?;
+            }
+            number.push(c);
+        } else if c.is_alphabetic() || c == ',' {
+            unit.push(c);
+        } else if !c.is_whitespace() {
+            return None;
+        }
+    }
+    flush(&mut number, &mut unit)?;
+    if parsed_any {
+        Some(Duration::from_secs(total))
+    } else {
+        None
+    }
+}
+
+/// `45s`, `5m`, `2h 3m`.
+pub fn format_idle(idle: Duration) -> String {
+    let secs = idle.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}h {}m", secs / 3600, secs / 60 % 60)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleSupport {
+    /// No refresh has come back yet.
+    Unknown,
+    Supported,
+    /// The server's `/who` answer had no idle fields: hide the idle UI.
+    Unsupported,
+}
+
+#[derive(Debug)]
+pub struct IdleTimes {
+    pub support: IdleSupport,
+    /// Idle time per lowercased user and when we were told it.
+    reported: HashMap<String, (Duration, Instant)>,
+}
+
+impl Default for IdleTimes {
+    fn default() -> Self {
+        IdleTimes { support: IdleSupport::Unknown, reported: HashMap::new() }
+    }
+}
+
+impl IdleTimes {
+    /// Takes the idle fields from a refreshed `/who` answer. An answer with
+    /// users but no idle fields at all means the server doesn't report them.
+    pub fn record(&mut self, users: usize, idle: &HashMap<String, Duration>, now: Instant) {
+        if idle.is_empty() {
+            if users > 0 {
+                self.support = IdleSupport::Unsupported;
+            }
+            return;
+        }
+        self.support = IdleSupport::Supported;
+        for (user, idle) in idle {
+            self.reported.insert(user.to_lowercase(), (*idle, now));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.reported.clear();
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.support == IdleSupport::Supported
+    }
+
+    /// Reported idle time aged to `now`, cut short if we saw them talk since.
+    pub fn idle(&self, user: &str, now: Instant, last_spoke: Option<Instant>) -> Option<Duration> {
+        let (reported, at) = self.reported.get(&user.to_lowercase())?;
+        let aged = *reported + now.duration_since(*at);
+        Some(match last_spoke {
+            Some(spoke) => aged.min(now.duration_since(spoke)),
+            None => aged,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Option<Duration> {
+        Some(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn parses_every_idle_variant() {
+        assert_eq!(parse_idle("idle 5m30s"), secs(330));
+        assert_eq!(parse_idle("idle: 330s"), secs(330));
+        assert_eq!(parse_idle("(idle 330)"), secs(330));
+        assert_eq!(parse_idle("Idle for 5 minutes"), secs(300));
+        assert_eq!(parse_idle("idle for 5 minutes, 30 seconds"), secs(330));
+        assert_eq!(parse_idle("idle 00:05:30"), secs(330));
+        assert_eq!(parse_idle("idle 05:30"), secs(330));
+        assert_eq!(parse_idle("idle 2h 3m"), secs(7380));
+        assert_eq!(parse_idle("idle 1 day"), secs(86400));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        assert_eq!(parse_idle("away 5m"), None);
+        assert_eq!(parse_idle("idle"), None);
+        assert_eq!(parse_idle("idle soon"), None);
+        assert_eq!(parse_idle("idle 5 fortnights"), None);
+        assert_eq!(parse_idle("idle 1:2:3:4"), None);
+        assert_eq!(parse_idle("idle 5m!"), None);
+    }
+
+    #[test]
+    fn formats_coarsely() {
+        assert_eq!(format_idle(Duration::from_secs(45)), "45s");
+        assert_eq!(format_idle(Duration::from_secs(330)), "5m");
+        assert_eq!(format_idle(Duration::from_secs(7380)), "2h 3m");
+    }
+
+    #[test]
+    fn a_who_answer_without_idle_fields_turns_the_feature_off() {
+        let now = Instant::now();
+        let mut times = IdleTimes::default();
+        times.record(0, &HashMap::new(), now);
+        assert_eq!(times.support, IdleSupport::Unknown);
+        times.record(3, &HashMap::new(), now);
+        assert_eq!(times.support, IdleSupport::Unsupported);
+        times.record(1, &HashMap::from([("Bob".to_string(), Duration::from_secs(60))]), now);
+        assert!(times.is_supported());
+    }
+
+    #[test]
+    fn idle_times_age_and_reset_when_the_user_talks() {
+        let start = Instant::now();
+        let mut times = IdleTimes::default();
+        times.record(1, &HashMap::from([("Bob".to_string(), Duration::from_secs(60))]), start);
+        let later = start + Duration::from_secs(30);
+        assert_eq!(times.idle("bob", later, None), secs(90));
+        assert_eq!(times.idle("Bob", later, Some(start + Duration::from_secs(20))), secs(10));
+        assert_eq!(times.idle("Alice", later, None), None);
+        times.clear();
+        assert_eq!(times.idle("Bob", later, None), None);
+    }
+}
@@ -0,0 +1,277 @@
+//! Reading buddy and ban lists exported by other chat clients.
+
+use std::collections::HashSet;
+
+use crate::lists::{ContactLists, ListKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One account name per line.
+    NameList,
+    /// `name,reason` rows, optionally with a header row.
+    Csv,
+    /// banlist.txt layout: `name reason words...`, `;` or `#` comment lines.
+    Banlist,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::NameList, Format::Csv, Format::Banlist];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Format::NameList => "Name per line",
+            Format::Csv => "CSV (name, reason)",
+            Format::Banlist => "banlist.txt",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportRow {
+    pub name: String,
+    pub reason: Option<String>,
+    pub include: bool,
+    /// Already on the target list or earlier in the file.
+    pub duplicate: bool,
+}
+
+/// A line we couldn't make sense of, with its 1-based line number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowError {
+    pub line: usize,
+    pub text: String,
+    pub reason: &'static str,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Parsed {
+    pub rows: Vec<ImportRow>,
+    pub errors: Vec<RowError>,
+}
+
+/// PvPGN account names: 2-15 characters from a small punctuation set.
+pub fn is_account_name(name: &str) -> bool {
+    (2..=15).contains(&name.chars().count())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_[].()`".contains(c))
+}
+
+fn is_comment(line: &str) -> bool {
+    line.starts_with(';') || line.starts_with('#') || line.starts_with("//")
+}
+
+/// Content lines with their line numbers, skipping blanks and comments.
+fn content_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !is_comment(line))
+}
+
+pub fn detect_format(text: &str) -> Format {
+    let lines: Vec<&str> = content_lines(text).map(|(_, line)| line).collect();
+    if lines.is_empty() {
+        return Format::NameList;
+    }
+    let with_commas = lines.iter().filter(|line| line.contains(',')).count();
+    if with_commas * 2 > lines.len() {
+        return Format::Csv;
+    }
+    let has_comments = text.lines().any(|line| is_comment(line.trim()));
+    let with_reasons = lines.iter().filter(|line| line.contains(char::is_whitespace)).count();
+    if has_comments || with_reasons > 0 {
+        Format::Banlist
+    } else {
+        Format::NameList
+    }
+}
+
+fn push_row(parsed: &mut Parsed, line: usize, raw: &str, name: &str, reason: Option<&str>) {
+    if !is_account_name(name) {
+        parsed.errors.push(RowError {
+            line,
+            text: raw.to_string(),
+            reason: "not a valid account name",
+        });
+        return;
+    }
+    parsed.rows.push(ImportRow {
+        name: name.to_string(),
+        reason: reason.map(str::trim).filter(|reason| !reason.is_empty()).map(str::to_string),
+        include: true,
+        duplicate: false,
+    });
+}
+
+pub fn parse_name_list(text: &str) -> Parsed {
+    let mut parsed = Parsed::default();
+    for (line, raw) in content_lines(text) {
+        push_row(&mut parsed, line, raw, raw, None);
+    }
+    parsed
+}
+
+pub fn parse_csv(text: &str) -> Parsed {
+    let mut parsed = Parsed::default();
+    for (line, raw) in content_lines(text) {
+        let mut fields = raw.splitn(2, ',').map(|field| field.trim().trim_matches('"'));
+        let name = fields.next().unwrap_or_default();
+        if line == 1 && matches!(name.to_lowercase().as_str(), "name" | "account" | "user" | "username") {
+            continue;
+        }
+        push_row(&mut parsed, line, raw, name, fields.next());
+    }
+    parsed
+}
+
+pub fn parse_banlist(text: &str) -> Parsed {
+    let mut parsed = Parsed::default();
+    for (line, raw) in content_lines(text) {
+        let (name, reason) = match raw.split_once(char::is_whitespace) {
+            Some((name, reason)) => (name, Some(reason)),
+            None => (raw, None),
+        };
+        push_row(&mut parsed, line, raw, name, reason);
+    }
+    parsed
+}
+
+pub fn parse(text: &str, format: Format) -> Parsed {
+    match format {
+        Format::NameList => parse_name_list(text),
+        Format::Csv => parse_csv(text),
+        Format::Banlist => parse_banlist(text),
+    }
+}
+
+/// Flags rows already on the target list or repeated in the file, and leaves
+/// them unticked so a merge never adds the same name twice.
+pub fn mark_duplicates(rows: &mut [ImportRow], already_listed: impl Fn(&str) -> bool) {
+    let mut seen = HashSet::new();
+    for row in rows {
+        let first_time = seen.insert(row.name.to_lowercase());
+        row.duplicate = !first_time || already_listed(&row.name);
+        row.include = !row.duplicate;
+    }
+}
+
+/// State of the import window between frames.
+#[derive(Debug)]
+pub struct ImportWizard {
+    pub path: String,
+    pub text: Option<String>,
+    pub load_error: Option<String>,
+    pub format: Format,
+    pub target: ListKind,
+    pub parsed: Parsed,
+}
+
+impl Default for ImportWizard {
+    fn default() -> Self {
+        ImportWizard {
+            path: String::new(),
+            text: None,
+            load_error: None,
+            format: Format::NameList,
+            target: ListKind::Friends,
+            parsed: Parsed::default(),
+        }
+    }
+}
+
+impl ImportWizard {
+    /// Reads the file at `path` and detects its format.
+    pub fn load(&mut self, lists: &ContactLists) {
+        match std::fs::read_to_string(self.path.trim()) {
+            Ok(text) => {
+                self.format = detect_format(&text);
+                self.text = Some(text);
+                self.load_error = None;
+            }
+            Err(err) => {
+                self.text = None;
+                self.load_error = Some(err.to_string());
+            }
+        }
+        self.reparse(lists);
+    }
+
+    /// Re-runs the parser after the format or target changed.
+    pub fn reparse(&mut self, lists: &ContactLists) {
+        self.parsed = match &self.text {
+            Some(text) => parse(text, self.format),
+            None => Parsed::default(),
+        };
+        let target = self.target;
+        mark_duplicates(&mut self.parsed.rows, |name| lists.contains(target, name));
+    }
+
+    pub fn selected(&self) -> impl Iterator<Item = &ImportRow> {
+        self.parsed.rows.iter().filter(|row| row.include)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(parsed: &Parsed) -> Vec<&str> {
+        parsed.rows.iter().map(|row| row.name.as_str()).collect()
+    }
+
+    #[test]
+    fn detects_each_format() {
+        assert_eq!(detect_format("Bob\nAlice\n\nCarol\n"), Format::NameList);
+        assert_eq!(detect_format("name,reason\nBob,spam\nAlice,\n"), Format::Csv);
+        assert_eq!(detect_format("; banned users\nBob flooding the channel\nAlice\n"), Format::Banlist);
+        assert_eq!(detect_format("# just a comment\nBob\n"), Format::Banlist);
+        assert_eq!(detect_format(""), Format::NameList);
+    }
+
+    #[test]
+    fn account_names() {
+        assert!(is_account_name("[Clan]Bob"));
+        assert!(is_account_name("a_b-c.(d)`"));
+        assert!(!is_account_name("B"));
+        assert!(!is_account_name("sixteen_chars_xx"));
+        assert!(!is_account_name("Bob Smith"));
+        assert!(!is_account_name("Bøb"));
+    }
+
+    #[test]
+    fn name_lists_report_bad_lines_by_number() {
+        let parsed = parse_name_list("Bob\n\nnot a name\n// note\nAlice");
+        assert_eq!(names(&parsed), vec!["Bob", "Alice"]);
+        assert_eq!(
+            parsed.errors,
+            vec![RowError { line: 3, text: "not a name".into(), reason: "not a valid account name" }]
+        );
+    }
+
+    #[test]
+    fn csv_skips_the_header_and_keeps_reasons() {
+        let parsed = parse_csv("Name,Reason\n\"Bob\", spam, and more\nAlice,\n");
+        assert_eq!(names(&parsed), vec!["Bob", "Alice"]);
+        assert_eq!(parsed.rows[0].reason.as_deref(), Some("spam, and more"));
+        assert_eq!(parsed.rows[1].reason, None);
+        // A header-looking name further down is just a name.
+        assert_eq!(names(&parse_csv("Bob\nuser,x")), vec!["Bob", "user"]);
+    }
+
+    #[test]
+    fn banlists_split_the_name_from_the_reason() {
+        let parsed = parse_banlist("; header\nBob flooding the channel\n# more\nAlice\n");
+        assert_eq!(names(&parsed), vec!["Bob", "Alice"]);
+        assert_eq!(parsed.rows[0].reason.as_deref(), Some("flooding the channel"));
+        assert!(parsed.errors.is_empty());
+    }
+
+    #[test]
+    fn duplicates_are_left_unticked() {
+        let mut parsed = parse_name_list("Bob\nAlice\nbob\nCarol");
+        mark_duplicates(&mut parsed.rows, |name| name == "Carol");
+        let flags: Vec<(bool, bool)> = parsed.rows.iter().map(|row| (row.include, row.duplicate)).collect();
+        assert_eq!(flags, vec![(true, false), (true, false), (false, true), (false, true)]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long the "typing…" hint stays up after the last keystroke.
+pub const TYPING_HINT_DURATION: Duration = Duration::from_secs(3);
+/// Sent messages kept for Up/Down recall.
+pub const HISTORY_LIMIT: usize = 100;
+
+/// Shows the typing hint only for a short while after real keystrokes.
+#[derive(Debug, Default)]
+pub struct TypingIndicator {
+    last_keystroke: Option<Instant>,
+}
+
+impl TypingIndicator {
+    pub fn keystroke(&mut self, now: Instant) {
+        self.last_keystroke = Some(now);
+    }
+
+    pub fn clear(&mut self) {
+        self.last_keystroke = None;
+    }
+
+    /// Time left before the hint expires, or `None` if it should not be shown.
+    /// The caller schedules one repaint after the returned duration instead of
+    /// repainting every frame.
+    pub fn remaining(&self, now: Instant, modal_open: bool) -> Option<Duration> {
+        if modal_open {
+            return None;
+        }
+        let elapsed = now.duration_since(self.last_keystroke?);
+        TYPING_HINT_DURATION
+            .checked_sub(elapsed)
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
+
+/// What a press of Escape does in the main view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscapeAction {
+    CloseDialog,
+    ClearUserFilter,
+    CloseSearch,
+    DeselectRows,
+    ClearSelection,
+    ClearInput,
+    Nothing,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EscapeContext {
+    pub dialog_open: bool,
+    /// The sidebar filter box has focus and text in it.
+    pub user_filter_active: bool,
+    pub search_open: bool,
+    /// Message rows are selected for copying.
+    pub rows_selected: bool,
+    pub input_has_selection: bool,
+    pub input_empty: bool,
+}
+
+/// Escape handles exactly one thing per press, in this order:
+/// 1. close an open dialog,
+/// 2. clear the user filter while typing in it,
+/// 3. close the search bar,
+/// 4. deselect message rows,
+/// 5. collapse a selection in the input,
+/// 6. clear the input text.
+pub fn resolve_escape(context: EscapeContext) -> EscapeAction {
+    if context.dialog_open {
+        EscapeAction::CloseDialog
+    } else if context.user_filter_active {
+        EscapeAction::ClearUserFilter
+    } else if context.search_open {
+        EscapeAction::CloseSearch
+    } else if context.rows_selected {
+        EscapeAction::DeselectRows
+    } else if context.input_has_selection {
+        EscapeAction::ClearSelection
+    } else if !context.input_empty {
+        EscapeAction::ClearInput
+    } else {
+        EscapeAction::Nothing
+    }
+}
+
+/// Previously sent input, browsed with Up/Down like a shell.
+#[derive(Debug, Default)]
+pub struct InputHistory {
+    entries: VecDeque<String>,
+    /// Index into `entries` while browsing; `None` when editing fresh input.
+    position: Option<usize>,
+    /// What was in the box before browsing started.
+    draft: String,
+}
+
+impl InputHistory {
+    /// Records a sent message and stops browsing. Repeats of the last entry collapse.
+    pub fn push(&mut self, sent: &str) {
+        self.position = None;
+        self.draft.clear();
+        if sent.trim().is_empty() || self.entries.back().is_some_and(|last| last == sent) {
+            return;
+        }
+        if self.entries.len() == HISTORY_LIMIT {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(sent.to_string());
+    }
+
+    /// One entry back. `current` is saved as the draft when browsing starts.
+    pub fn previous(&mut self, current: &str) -> Option<String> {
+        let position = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+            Some(0) => return None,
+            Some(position) => position - 1,
+        };
+        self.position = Some(position);
+        Some(self.entries[position].clone())
+    }
+
+    /// One entry forward, ending back at the saved draft.
+    pub fn next(&mut self) -> Option<String> {
+        let position = self.position?;
+        if position + 1 < self.entries.len() {
+            self.position = Some(position + 1);
+            Some(self.entries[position + 1].clone())
+        } else {
+            self.position = None;
+            Some(std::mem::take(&mut self.draft))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_hint_expires_after_the_last_keystroke() {
+        let start = Instant::now();
+        let mut typing = TypingIndicator::default();
+        assert_eq!(typing.remaining(start, false), None);
+        typing.keystroke(start);
+        assert_eq!(typing.remaining(start, false), Some(TYPING_HINT_DURATION));
+        assert_eq!(typing.remaining(start + Duration::from_secs(1), false), Some(Duration::from_secs(2)));
+        assert_eq!(typing.remaining(start + TYPING_HINT_DURATION, false), None);
+        // Another keystroke starts it over.
+        typing.keystroke(start + Duration::from_secs(5));
+        assert_eq!(typing.remaining(start + Duration::from_secs(6), false), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn typing_hint_hides_behind_a_modal_and_after_sending() {
+        let start = Instant::now();
+        let mut typing = TypingIndicator::default();
+        typing.keystroke(start);
+        assert_eq!(typing.remaining(start, true), None);
+        typing.clear();
+        assert_eq!(typing.remaining(start, false), None);
+    }
+
+    #[test]
+    fn escape_handles_one_thing_in_priority_order() {
+        let mut context = EscapeContext {
+            dialog_open: true,
+            user_filter_active: true,
+            search_open: true,
+            rows_selected: true,
+            input_has_selection: true,
+            input_empty: false,
+        };
+        assert_eq!(resolve_escape(context), EscapeAction::CloseDialog);
+        context.dialog_open = false;
+        assert_eq!(resolve_escape(context), EscapeAction::ClearUserFilter);
+        context.user_filter_active = false;
+        assert_eq!(resolve_escape(context), EscapeAction::CloseSearch);
+        context.search_open = false;
+        assert_eq!(resolve_escape(context), EscapeAction::DeselectRows);
+        context.rows_selected = false;
+        assert_eq!(resolve_escape(context), EscapeAction::ClearSelection);
+        context.input_has_selection = false;
+        assert_eq!(resolve_escape(context), EscapeAction::ClearInput);
+        context.input_empty = true;
+        assert_eq!(resolve_escape(context), EscapeAction::Nothing);
+    }
+
+    #[test]
+    fn history_browses_back_and_returns_to_the_draft() {
+        let mut history = InputHistory::default();
+        history.push("first");
+        history.push("second");
+        history.push("second");
+        history.push("   ");
+        assert_eq!(history.previous("draft").as_deref(), Some("second"));
+        assert_eq!(history.previous("ignored").as_deref(), Some("first"));
+        assert_eq!(history.previous("ignored"), None);
+        assert_eq!(history.next().as_deref(), Some("second"));
+        assert_eq!(history.next().as_deref(), Some("draft"));
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn history_keeps_the_newest_entries() {
+        let mut history = InputHistory::default();
+        for index in 0..HISTORY_LIMIT + 5 {
+            history.push(&index.to_string());
+        }
+        let mut oldest = None;
+        while let Some(entry) = history.previous("") {
+            oldest = Some(entry);
+        }
+        assert_eq!(oldest, Some("5".to_string()));
+    }
+}
@@ -0,0 +1,30 @@
+use std::time::{Duration, Instant};
+
+/// Sent when we have been quiet too long. The reply (user and game totals)
+/// goes to the query router, which folds it into the status bar quietly.
+pub const KEEPALIVE_COMMAND: &str = "/users";
+
+/// Some servers drop telnet clients that stay silent, and NAT routers forget
+/// idle TCP connections, so we say something harmless every so often.
+#[derive(Debug)]
+pub struct Keepalive {
+    last_sent: Instant,
+}
+
+impl Keepalive {
+    pub fn new(now: Instant) -> Keepalive {
+        Keepalive { last_sent: now }
+    }
+
+    /// Any line we send counts, keepalives included.
+    pub fn sent(&mut self, now: Instant) {
+        self.last_sent = now;
+    }
+
+    /// Time left before the next keepalive is due, or `None` when it is due now.
+    pub fn remaining(&self, now: Instant, interval: Duration) -> Option<Duration> {
+        interval
+            .checked_sub(now.duration_since(self.last_sent))
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
@@ -0,0 +1,26 @@
+//! Saved login passwords live in the OS keyring, never in credentials.json.
+
+use keyring::Entry;
+
+const SERVICE: &str = "bnetchat";
+
+fn entry(server: &str, user: &str) -> keyring::Result<Entry> {
+    Entry::new(SERVICE, &format!("{}@{}", user.to_lowercase(), server.to_lowercase()))
+}
+
+pub fn load_password(server: &str, user: &str) -> Option<String> {
+    entry(server, user).and_then(|entry| entry.get_password()).ok()
+}
+
+pub fn save_password(server: &str, user: &str, password: &str) -> keyring::Result<()> {
+    entry(server, user)?.set_password(password)
+}
+
+/// Removes a saved password. Having none saved is not an error.
+pub fn forget_password(server: &str, user: &str) {
+    let result = entry(server, user).and_then(|entry| entry.delete_credential());
+    match result {
+        Ok(()) | Err(keyring::Error::NoEntry) => {}
+        Err(err) => println!("Could not remove saved password: {}", err),
+    }
+}
@@ -0,0 +1,54 @@
+//! The channel each saved profile was last in, joined again on the next login.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ops::VOID_CHANNEL;
+use crate::paths;
+
+/// Joined when neither the profile nor a previous session names a channel.
+pub const FALLBACK_CHANNEL: &str = "w3";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LastChannels {
+    /// Keyed by lowercased `user@server`.
+    channels: HashMap<String, String>,
+}
+
+fn key(server: &str, user: &str) -> String {
+    format!("{}@{}", user.trim().to_lowercase(), server.trim().to_lowercase())
+}
+
+impl LastChannels {
+    pub fn load() -> LastChannels {
+        std::fs::read_to_string(paths::config_file(paths::LAST_CHANNELS))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)
+            .and_then(|text| std::fs::write(paths::config_file(paths::LAST_CHANNELS), text));
+        if let Err(err) = result {
+            println!("Could not save the last channels: {}", err);
+        }
+    }
+
+    pub fn get(&self, server: &str, user: &str) -> Option<&str> {
+        self.channels.get(&key(server, user)).map(String::as_str)
+    }
+
+    /// Records a join, writing the file only when the channel changed. Being
+    /// kicked to The Void is not a channel anyone wants to come back to.
+    pub fn record(&mut self, server: &str, user: &str, channel: &str) {
+        if channel.is_empty() || channel.eq_ignore_ascii_case(VOID_CHANNEL) || self.get(server, user) == Some(channel) {
+            return;
+        }
+        self.channels.insert(key(server, user), channel.to_string());
+        self.save();
+    }
+}
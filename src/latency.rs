@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Round trips kept for the min/avg/max tooltip.
+const SAMPLES: usize = 20;
+/// A probe unanswered for this long is given up on.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Round-trip time to the server, measured by timing a request whose answer
+/// the query router already recognizes.
+#[derive(Debug, Default)]
+pub struct Latency {
+    probe_sent: Option<Instant>,
+    last_probe: Option<Instant>,
+    samples: VecDeque<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quality {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl Latency {
+    /// Time left before the next probe is due, or `None` when it is due now.
+    /// Waits while a probe is still out.
+    pub fn remaining(&self, now: Instant, interval: Duration) -> Option<Duration> {
+        if let Some(sent) = self.probe_sent {
+            return PROBE_TIMEOUT.checked_sub(now.duration_since(sent)).filter(|left| !left.is_zero());
+        }
+        // The first probe goes out right away.
+        let last = self.last_probe?;
+        interval.checked_sub(now.duration_since(last)).filter(|left| !left.is_zero())
+    }
+
+    pub fn probe_sent(&mut self, now: Instant) {
+        self.probe_sent = Some(now);
+        self.last_probe = Some(now);
+    }
+
+    /// An answer arrived. Without a probe out, someone typed the command
+    /// themselves and there is nothing to time.
+    pub fn answered(&mut self, now: Instant) {
+        let sent = match self.probe_sent.take() {
+            Some(sent) => sent,
+            None => return,
+        };
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(now.duration_since(sent));
+    }
+
+    /// Forgets the measurements, e.g. after reconnecting.
+    pub fn reset(&mut self) {
+        *self = Latency::default();
+    }
+
+    pub fn last(&self) -> Option<Duration> {
+        self.samples.back().copied()
+    }
+
+    /// `min 31 ms · avg 42 ms · max 80 ms` over the recent samples.
+    pub fn summary(&self) -> Option<String> {
+        let min = self.samples.iter().min()?;
+        let max = self.samples.iter().max()?;
+        let avg = self.samples.iter().sum::<Duration>() / self.samples.len() as u32;
+        Some(format!("min {} ms · avg {} ms · max {} ms", min.as_millis(), avg.as_millis(), max.as_millis()))
+    }
+}
+
+pub fn quality(round_trip: Duration) -> Quality {
+    match round_trip.as_millis() {
+        0..=149 => Quality::Good,
+        150..=399 => Quality::Fair,
+        _ => Quality::Poor,
+    }
+}
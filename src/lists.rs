@@ -0,0 +1,94 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListKind {
+    Friends,
+    Ignore,
+    Watch,
+}
+
+impl ListKind {
+    pub const ALL: [ListKind; 3] = [ListKind::Friends, ListKind::Ignore, ListKind::Watch];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ListKind::Friends => "Friends",
+            ListKind::Ignore => "Ignore",
+            ListKind::Watch => "Watch",
+        }
+    }
+
+    /// Server command that adds `name` to this list.
+    pub fn add_command(&self, name: &str) -> String {
+        match self {
+            ListKind::Friends => format!("/f add {}", name),
+            ListKind::Ignore => format!("/ignore {}", name),
+            ListKind::Watch => format!("/watch {}", name),
+        }
+    }
+}
+
+/// Names this client has put on the server-side lists. The server stays the
+/// source of truth; this copy is what imports check for duplicates against.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContactLists {
+    friends: BTreeSet<String>,
+    ignore: BTreeSet<String>,
+    watch: BTreeSet<String>,
+}
+
+impl ContactLists {
+    pub fn load() -> ContactLists {
+        std::fs::read_to_string(paths::config_file(paths::LISTS))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)
+            .and_then(|text| std::fs::write(paths::config_file(paths::LISTS), text));
+        if let Err(err) = result {
+            println!("Could not save lists: {}", err);
+        }
+    }
+
+    fn list(&self, kind: ListKind) -> &BTreeSet<String> {
+        match kind {
+            ListKind::Friends => &self.friends,
+            ListKind::Ignore => &self.ignore,
+            ListKind::Watch => &self.watch,
+        }
+    }
+
+    fn list_mut(&mut self, kind: ListKind) -> &mut BTreeSet<String> {
+        match kind {
+            ListKind::Friends => &mut self.friends,
+            ListKind::Ignore => &mut self.ignore,
+            ListKind::Watch => &mut self.watch,
+        }
+    }
+
+    pub fn names(&self, kind: ListKind) -> Vec<String> {
+        self.list(kind).iter().cloned().collect()
+    }
+
+    pub fn contains(&self, kind: ListKind, name: &str) -> bool {
+        self.list(kind).contains(&name.to_lowercase())
+    }
+
+    /// Returns false if the name was already listed.
+    pub fn insert(&mut self, kind: ListKind, name: &str) -> bool {
+        self.list_mut(kind).insert(name.to_lowercase())
+    }
+
+    pub fn remove(&mut self, kind: ListKind, name: &str) -> bool {
+        self.list_mut(kind).remove(&name.to_lowercase())
+    }
+}
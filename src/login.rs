@@ -1,7 +1,28 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
 use eframe::egui::{self, Color32, TextEdit};
 use serde::{Deserialize, Serialize};
+use crate::connect::{ConnectPhase, TimeoutConfig, MAX_TIMEOUT_SECS, MIN_TIMEOUT_SECS};
+use crate::keychain;
+use crate::last_channel::{LastChannels, FALLBACK_CHANNEL};
+use crate::paths;
+use crate::protocol::{MAX_LINE_LIMIT, MAX_MESSAGE_LEN, MIN_LINE_LIMIT};
+use crate::proxy::{Proxy, ProxyKind};
+use crate::reconnect;
+use crate::settings::Settings;
 use crate::Credentials;
 
+/// What the login form asked for this frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginAction {
+    None,
+    LogIn,
+    /// Stop waiting for a connection attempt that is under way.
+    Cancel,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Login {
@@ -9,34 +30,273 @@ pub struct Login {
     pub user: String,
     #[serde(skip)]
     pub password: String,
+    pub remember: bool,
+    /// The profile's own channel, used until it has a last channel.
+    pub channel: String,
+    /// Joined this time instead of the usual channel; never saved.
+    #[serde(skip)]
+    pub join_override: String,
+    #[serde(skip)]
+    pub last_channels: LastChannels,
+    /// Wrap the connection in TLS, for servers behind a TLS terminator.
+    pub tls: bool,
+    /// Accept any certificate; for self-signed setups only.
+    pub tls_insecure: bool,
+    pub proxy: Proxy,
+    /// Longest line this server passes on whole, in bytes.
+    pub line_limit: usize,
+    /// Mirrors the settings file; copied back when logging in.
+    #[serde(skip)]
+    pub timeouts: TimeoutConfig,
+    /// Mirrors the settings file; no spinner while connecting.
+    #[serde(skip)]
+    pub reduced_motion: bool,
+    /// Mirrors the settings file; copied back when logging in.
+    #[serde(skip)]
+    pub auto_retry: bool,
     pub error: Option<String>,
+    /// Create the account before logging in.
+    #[serde(skip)]
+    pub create_account: bool,
+    #[serde(skip)]
+    pub confirm_password: String,
+    /// Problems with one field, shown under it.
+    #[serde(skip)]
+    pub name_error: Option<String>,
+    #[serde(skip)]
+    pub password_error: Option<String>,
+    /// A connection attempt is running in the background.
+    #[serde(skip)]
+    pub connecting: bool,
+    /// How far that attempt has got, once it says.
+    #[serde(skip)]
+    pub phase: Option<ConnectPhase>,
+    /// Failed attempts in a row with the form as it is. Editing anything
+    /// starts the count over; logging in replaces the form altogether.
+    #[serde(skip)]
+    pub failed_attempts: usize,
+    /// [`Login::form_key`] when the last attempt failed.
+    #[serde(skip)]
+    failed_form: u64,
+    /// When the automatic retry goes, while one is counting down.
+    #[serde(skip)]
+    pub retry_at: Option<Instant>,
 }
 
 impl Default for Login {
     fn default() -> Self {
+        let settings = Settings::load();
         let mut login = Login {
             server: "".to_string(),
             user: "".to_string(),
             password: "".to_string(),
+            remember: false,
+            channel: settings.default_channel,
+            join_override: "".to_string(),
+            last_channels: LastChannels::load(),
+            tls: false,
+            tls_insecure: false,
+            proxy: Proxy::default(),
+            line_limit: MAX_MESSAGE_LEN,
+            timeouts: settings.timeouts,
+            reduced_motion: settings.reduced_motion,
+            auto_retry: settings.auto_retry_login,
             error: None,
+            create_account: false,
+            confirm_password: "".to_string(),
+            name_error: None,
+            password_error: None,
+            connecting: false,
+            phase: None,
+            failed_attempts: 0,
+            failed_form: 0,
+            retry_at: None,
         };
-        if let Ok(text) = std::fs::read_to_string(&"credentials.json") {
+        if let Ok(text) = std::fs::read_to_string(paths::config_file(paths::CREDENTIALS)) {
 
-                // Parse the string into a dynamically-typed JSON structure.
-            let credentials = serde_json::from_str::<Credentials>(&text).unwrap();
-            login.user.push_str(&credentials.user);
-            login.server.push_str(&credentials.server);
-            login.password.push_str(&credentials.password);
+                // A broken file is reported by the startup checks, so just start empty.
+            if let Ok(mut credentials) = serde_json::from_str::<Credentials>(&text) {
+                if !credentials.password.is_empty() {
+                    // Older versions stored the password here in plain text:
+                    // move it to the keyring and rewrite the file without it.
+                    credentials.remember = true;
+                    if let Err(err) = credentials.save() {
+                        login.error = Some(err);
+                    }
+                } else if credentials.remember {
+                    credentials.password = keychain::load_password(&credentials.server, &credentials.user)
+                        .unwrap_or_default();
+                    if !credentials.proxy.user.is_empty() {
+                        let proxy_key = format!("proxy:{}", credentials.proxy.address());
+                        credentials.proxy.password =
+                            keychain::load_password(&proxy_key, &credentials.proxy.user).unwrap_or_default();
+                    }
+                }
+                login.user.push_str(&credentials.user);
+                login.server.push_str(&credentials.server);
+                login.password.push_str(&credentials.password);
+                login.remember = credentials.remember;
+                login.channel = credentials.channel;
+                login.tls = credentials.tls;
+                login.tls_insecure = credentials.tls_insecure;
+                login.proxy = credentials.proxy;
+                login.line_limit = credentials.line_limit.clamp(MIN_LINE_LIMIT, MAX_LINE_LIMIT);
+            }
         };
 
         login
     }
 }
 
+fn field_error(ui: &mut egui::Ui, error: &Option<String>) {
+    if let Some(error) = error {
+        ui.colored_label(Color32::from_rgb(255, 0, 0), error);
+    }
+}
+
 impl Login {
-    pub fn update(&mut self, ctx: &egui::Context) -> bool {
+    pub fn clear_errors(&mut self) {
+        self.error = None;
+        self.name_error = None;
+        self.password_error = None;
+    }
+
+    /// Counts a failed attempt, whose errors are already set. Only network
+    /// trouble is retried automatically: a refused password stays refused,
+    /// and retrying it could get the account locked.
+    pub fn failed(&mut self, retryable: bool) {
+        self.connecting = false;
+        self.failed_attempts += 1;
+        self.failed_form = self.form_key();
+        self.retry_at = (retryable && self.auto_retry)
+            .then(|| Instant::now() + reconnect::delay(self.failed_attempts - 1));
+    }
+
+    /// Everything on the form that goes into an attempt, hashed, to tell
+    /// when the user has changed something since it failed.
+    fn form_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (&self.server, &self.user, &self.password, &self.channel, &self.join_override).hash(&mut hasher);
+        (self.tls, self.tls_insecure, &self.proxy, self.line_limit, self.timeouts).hash(&mut hasher);
+        (self.create_account, &self.confirm_password).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The channel this login joins and where the name came from.
+    pub fn join_target(&self) -> (String, &'static str) {
+        let join_override = self.join_override.trim();
+        if !join_override.is_empty() {
+            return (join_override.to_string(), "this session only");
+        }
+        if let Some(last) = self.last_channels.get(&self.server, &self.user) {
+            return (last.to_string(), "where you were last time");
+        }
+        match self.channel.trim() {
+            "" => (FALLBACK_CHANNEL.to_string(), "the server's default"),
+            channel => (channel.to_string(), "this profile's channel"),
+        }
+    }
+
+    /// Checks that need no server: both password entries must match.
+    fn validate(&mut self) -> bool {
+        self.clear_errors();
+        if self.create_account && self.password != self.confirm_password {
+            self.password_error = Some("The passwords do not match".to_string());
+        }
+        if self.proxy.enabled() && self.proxy.host.trim().is_empty() {
+            self.error = Some("Enter the proxy's host, or choose \"No proxy\"".to_string());
+        }
+        self.password_error.is_none() && self.error.is_none()
+    }
+
+    fn proxy_section(&mut self, ui: &mut egui::Ui) {
+        let proxy = &mut self.proxy;
+        egui::CollapsingHeader::new(format!("Proxy: {}", proxy.kind.label()))
+            .id_source("login_proxy")
+            .show(ui, |ui| {
+                egui::ComboBox::from_id_source("proxy_kind")
+                    .selected_text(proxy.kind.label())
+                    .show_ui(ui, |ui| {
+                        for kind in ProxyKind::ALL {
+                            ui.selectable_value(&mut proxy.kind, kind, kind.label());
+                        }
+                    });
+                if !proxy.enabled() {
+                    return;
+                }
+                ui.horizontal(|ui| {
+                    ui.add(TextEdit::singleline(&mut proxy.host).hint_text("proxy host").desired_width(160.0));
+                    ui.add(egui::DragValue::new(&mut proxy.port).clamp_range(1..=65535));
+                });
+                ui.add(TextEdit::singleline(&mut proxy.user).hint_text("proxy user (optional)"));
+                if !proxy.user.is_empty() {
+                    ui.add(TextEdit::singleline(&mut proxy.password).password(true).hint_text("proxy password"));
+                }
+            });
+    }
+
+    fn advanced_section(&mut self, ui: &mut egui::Ui) {
+        let timeouts = &mut self.timeouts;
+        let line_limit = &mut self.line_limit;
+        egui::CollapsingHeader::new("Advanced").id_source("login_advanced").show(ui, |ui| {
+            egui::Grid::new("login_timeouts").num_columns(2).show(ui, |ui| {
+                let range = MIN_TIMEOUT_SECS..=MAX_TIMEOUT_SECS;
+                ui.label("Connect timeout").on_hover_text("Opening the connection, all addresses together");
+                ui.add(egui::DragValue::new(&mut timeouts.connect_secs).clamp_range(range.clone()).suffix(" s"));
+                ui.end_row();
+                ui.label("Prompt timeout").on_hover_text("Each prompt or reply while logging in");
+                ui.add(egui::DragValue::new(&mut timeouts.prompt_secs).clamp_range(range.clone()).suffix(" s"));
+                ui.end_row();
+                ui.label("Login time limit").on_hover_text("The whole login, however many prompts it takes");
+                ui.add(egui::DragValue::new(&mut timeouts.handshake_secs).clamp_range(range).suffix(" s"));
+                ui.end_row();
+                ui.label("Line limit").on_hover_text("Longest message this server passes on whole. Longer ones are split between words");
+                ui.add(egui::DragValue::new(line_limit).clamp_range(MIN_LINE_LIMIT..=MAX_LINE_LIMIT).suffix(" bytes"));
+                ui.end_row();
+            });
+            ui.checkbox(&mut self.auto_retry, "Retry automatically")
+                .on_hover_text("When the server can't be reached, try again after a pause. A refused password is never retried");
+        });
+    }
+
+    /// After a failed attempt: how many in a row, a Retry button and the
+    /// automatic retry's countdown. True when Retry was pressed.
+    fn retry_row(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut retry = false;
+        ui.horizontal(|ui| {
+            ui.weak(match self.failed_attempts {
+                1 => "Attempt failed".to_string(),
+                attempts => format!("{} attempts failed", attempts),
+            });
+            if ui.button("⟳ Retry").on_hover_text("Try again with the details as they are").clicked() {
+                retry = true;
+            }
+            if let Some(retry_at) = self.retry_at {
+                let secs = retry_at.saturating_duration_since(Instant::now()).as_secs() + 1;
+                ui.label(format!("Retrying in {}s…", secs));
+                if ui.button("Cancel").clicked() {
+                    self.retry_at = None;
+                }
+            }
+        });
+        retry
+    }
+
+    pub fn update(&mut self, ctx: &egui::Context) -> LoginAction {
 
-        let mut update = false;
+        let mut action = LoginAction::None;
+        if self.failed_attempts > 0 && self.form_key() != self.failed_form {
+            self.failed_attempts = 0;
+            self.retry_at = None;
+        }
+        if let Some(retry_at) = self.retry_at {
+            if Instant::now() >= retry_at {
+                self.retry_at = None;
+                action = LoginAction::LogIn;
+            } else {
+                ctx.request_repaint_after(Duration::from_secs(1));
+            }
+        }
         egui::CentralPanel::default().show(ctx, |ui| {
 
             ui.vertical_centered(|ui| {
@@ -49,22 +309,75 @@ impl Login {
                     if let Some(ref error) = self.error {
                         ui.colored_label(Color32::from_rgb(255, 0, 0), error);
                     }
+                    let connecting = self.connecting;
+                    if self.failed_attempts > 0 && !connecting && self.retry_row(ui) {
+                        action = LoginAction::LogIn;
+                    }
                     ui.vertical_centered(|ui| {
+                        ui.set_enabled(!connecting);
                         ui.heading("Log in");
-                        ui.label("Server ip and port:");
+                        ui.label("Server (host or ip, port defaults to 6112):");
                         ui.text_edit_singleline(&mut self.server);
+                        ui.checkbox(&mut self.tls, "Use TLS")
+                            .on_hover_text("For servers behind stunnel or another TLS terminator");
+                        if self.tls {
+                            ui.checkbox(&mut self.tls_insecure, "Skip certificate verification (self-signed)");
+                            if self.tls_insecure {
+                                ui.colored_label(
+                                    ui.visuals().warn_fg_color,
+                                    "Anyone between you and the server can read your password",
+                                );
+                            }
+                        }
+                        self.proxy_section(ui);
+                        self.advanced_section(ui);
 
                         ui.label("Username:");
                         ui.add(TextEdit::singleline(&mut self.user).hint_text("alice"));
+                        field_error(ui, &self.name_error);
                         ui.label("Password:");
                         ui.add(TextEdit::singleline(&mut self.password).password(true));
-                        if ui.button("Log in").clicked() {
-                            update = true;
+                        if self.create_account {
+                            ui.label("Confirm password:");
+                            ui.add(TextEdit::singleline(&mut self.confirm_password).password(true));
+                        }
+                        field_error(ui, &self.password_error);
+                        ui.label("Channel:");
+                        ui.add(TextEdit::singleline(&mut self.channel).hint_text(FALLBACK_CHANNEL));
+                        let (target, source) = self.join_target();
+                        ui.weak(format!("Joins {} ({})", target, source));
+                        ui.add(TextEdit::singleline(&mut self.join_override).hint_text("Join another channel this time"));
+                        ui.checkbox(&mut self.remember, "Remember me")
+                            .on_hover_text("Keeps the password in your system keyring");
+                        ui.checkbox(&mut self.create_account, "Create account")
+                            .on_hover_text("Registers this name on the server first, if the server allows new accounts");
+                        let label = if self.create_account { "Create account and log in" } else { "Log in" };
+                        if ui.button(label).clicked() && self.validate() {
+                            action = LoginAction::LogIn;
                         }
-                    })
+                    });
+                    if connecting {
+                        ui.horizontal(|ui| {
+                            if self.reduced_motion {
+                                ui.label("⏳");
+                            } else {
+                                ui.spinner();
+                            }
+                            let phase = self.phase.unwrap_or(ConnectPhase::Connect);
+                            let (step, steps) = phase.step(self.create_account);
+                            ui.vertical(|ui| {
+                                ui.label(format!("Step {} of {} — {}…", step, steps, phase.label()));
+                                ui.add(egui::ProgressBar::new(step as f32 / steps as f32).desired_width(160.0));
+                                ui.weak(self.server.trim());
+                            });
+                            if ui.button("Cancel").clicked() {
+                                action = LoginAction::Cancel;
+                            }
+                        });
+                    }
                 })
             });
         });
-        update
+        action
     }
 }
\ No newline at end of file
@@ -1,124 +1,892 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod login;
+mod capture;
+mod channel_list;
+mod clock;
+mod colors;
+mod commands;
+mod chat_log;
 mod connect;
+mod export;
+mod credential_store;
+mod crypto;
+mod delivery;
+mod emoji;
+mod focus;
+mod idle;
+mod import;
+mod input_state;
+mod keepalive;
+mod latency;
+mod message;
+mod keychain;
+mod last_channel;
+mod lists;
+mod nicknames;
+mod ops;
+mod outgoing;
+mod paths;
+mod prefs;
+mod presence;
+mod protocol;
+mod proxy;
+mod queries;
+mod rate_limit;
+mod reconnect;
+mod roster;
+mod row_layout;
+mod report;
+mod rich_text;
+mod search;
+mod selection;
+mod settings;
+mod slash;
+mod sound;
+mod startup;
+mod stats;
+mod status;
+mod throttle;
+mod theme;
+mod tls;
+mod unread;
+mod watch_list;
 
-use std::collections::{HashMap, HashSet};
-use std::io::Read;
-use std::net::{SocketAddr, TcpStream};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, SendError, Sender, TryRecvError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::connect::Connect;
+use crate::capture::{Capture, Direction};
+use crate::channel_list::ChannelBrowser;
+use crate::chat_log::ChatLog;
+use crate::commands::{Local, HELP};
+use crate::connect::{Connect, ConnectError, ConnectPhase, LoginError, NetEvent, Progress, RegisterError, TimeoutConfig, Wake};
+use crate::credential_store::CredentialStore;
+use crate::crypto::{E2e, PeerState, Received};
+use crate::delivery::PendingWhispers;
+use crate::focus::{is_highlight, FocusMode, Visibility};
+use crate::idle::{format_idle, IdleSupport, IdleTimes};
+use crate::import::{Format, ImportWizard};
+use crate::message::{ChatMessage, Delivery, MessageKind, DELIVERY_WINDOW};
+use crate::outgoing::{outgoing_lines, unsent_text};
+use crate::rate_limit::{Outbox, MAX_BURST, MAX_WINDOW_SECS};
+use crate::selection::Selection;
+use crate::last_channel::LastChannels;
+use crate::watch_list::WatchLists;
+use crate::input_state::{resolve_escape, EscapeAction, EscapeContext, InputHistory, TypingIndicator};
+use crate::keepalive::{Keepalive, KEEPALIVE_COMMAND};
+use crate::latency::{Latency, Quality};
+use crate::lists::{ContactLists, ListKind};
+use crate::login::LoginAction;
+use crate::nicknames::AddressBook;
+use crate::ops::{Capabilities, Confirmation, ModAction, ModCommand, Removal, Role, UserFlags, VOID_CHANNEL};
+use crate::prefs::{ChannelPrefStore, ChannelPrefs, JoinLeaveDisplay, SidebarToggle};
+use crate::presence::{ActivityTracker, Presence};
+use crate::protocol::{ServerMessage, UserInfo};
+use crate::proxy::Proxy;
+use crate::queries::{Friend, QueryResult, QueryRouter, Routed, QUIET_PERIOD};
+use crate::reconnect::Reconnect;
+use crate::report::ReportDraft;
+use crate::rich_text::Segment;
+use crate::roster::Roster;
+use crate::row_layout::RowHeights;
+use crate::search::Search;
+use crate::status::{AwayOnTalk, OwnStatus, Status};
+use crate::settings::{
+    MultilineSend, Settings, Theme, TimestampFormat, UserSort, WindowGeometry, MAX_FONT_SCALE, MAX_MAX_MESSAGES,
+    MIN_FONT_SCALE, MIN_MAX_MESSAGES,
+};
+use crate::slash::{Dispatch, SlashChoice, SlashPrompt};
+use crate::sound::{Alerts, Cue};
+use crate::startup::CheckResult;
+use crate::stats::FrameStats;
+use crate::throttle::{fold_summary, shift_rows, unknown_text, ErrorThrottle, Filed, JoinLeaveFolds, UnknownLines, Verdict};
+use crate::unread::Unread;
 
 use eframe::egui;
 use eframe::egui::{Label, Sense};
 use serde::{Deserialize, Serialize};
 use crate::Connected::Done;
 
+/// Widget id of the sidebar's user filter box.
+const USER_FILTER_ID: &str = "user_filter";
+/// Most network events handled in one frame; a channel join sends one
+/// line per user.
+const EVENTS_PER_FRAME: usize = 200;
+/// How long logging out waits for the server to hang up after `/quit`.
+const LOGOUT_GRACE: Duration = Duration::from_millis(800);
+
 fn main() {
+    clock::init();
     // Log to stdout (if you run with `RUST_LOG=debug`).
     tracing_subscriber::fmt::init();
+    let startup_issues = startup::run_checks()
+        .into_iter()
+        .filter(|check| !check.is_ok())
+        .collect();
+    let args: Vec<String> = std::env::args().collect();
+    let replay = replay_args(&args);
 
+    // Put back where it was; the first frame pulls it onto the monitor if needed.
+    let window = Settings::load().window;
     let options = eframe::NativeOptions {
-        // initial_window_size: Some(egui::vec2(320.0, 240.0)),
+        initial_window_pos: window.map(|window| egui::pos2(window.x, window.y)),
+        initial_window_size: window.map(|window| egui::vec2(window.width, window.height)),
         ..Default::default()
     };
     eframe::run_native(
         "Bnet chat",
         options,
-        Box::new(|_cc| Box::new(MyApp::default())),
+        Box::new(|cc| Box::new(MyApp::new(&cc.egui_ctx, startup_issues, replay))),
     );
 }
 
+/// `--replay <file> [--replay-speed <factor>]` runs the UI against a capture
+/// instead of a server. A speed of 0 delivers every line at once.
+fn replay_args(args: &[String]) -> Option<(PathBuf, f64)> {
+    let value_after = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|index| args.get(index + 1))
+    };
+    let path = PathBuf::from(value_after("--replay")?);
+    let speed = value_after("--replay-speed")
+        .and_then(|speed| speed.parse().ok())
+        .unwrap_or(1.0);
+    Some((path, speed))
+}
+
 /// Which view is currectly open
 #[derive(Debug)]
 pub enum View {
-    Login(login::Login),
-    Main(Main)
+    Login(Box<login::Login>),
+    Main(Box<Main>)
 }
 impl Default for View {
     fn default() -> Self {
-        View::Login(login::Login::default())
+        View::Login(Box::default())
     }
 }
 
 #[derive(Debug, Default)]
 struct MyApp {
     view: View,
+    /// Failed startup checks, shown once until dismissed.
+    startup_issues: Vec<CheckResult>,
+    /// Handed to each session and taken back when it ends.
+    settings: Settings,
+    applied_style: Option<StyleKey>,
+    /// Login in progress on a worker thread, so a dead host never freezes
+    /// the window, and the phases it reports.
+    connecting: Option<(Credentials, ConnectionResult, Receiver<ConnectPhase>)>,
+    /// The window's close button was pressed mid-session; asking first.
+    confirm_close: bool,
+    /// The user said quit; the next close request goes through.
+    close_allowed: bool,
+    /// Latest window position and size, saved on exit.
+    window: Option<WindowGeometry>,
+}
+
+impl MyApp {
+    fn new(ctx: &egui::Context, mut startup_issues: Vec<CheckResult>, replay: Option<(PathBuf, f64)>) -> Self {
+        let settings = Settings::load();
+        let mut view = View::default();
+        if let Some((path, speed)) = replay {
+            match View::replay(&path, speed, settings.clone(), ctx) {
+                Ok(replay) => view = replay,
+                Err(err) => startup_issues.push(CheckResult::failed(
+                    "Replay",
+                    format!("{} could not be loaded: {}", path.display(), err),
+                    "Started normally instead",
+                )),
+            }
+        }
+        MyApp {
+            view,
+            startup_issues,
+            settings,
+            applied_style: None,
+            connecting: None,
+            confirm_close: false,
+            close_allowed: false,
+            window: None,
+        }
+    }
+
+    fn poll_login(&mut self) {
+        let finished = match &self.connecting {
+            Some((cred, result, phases)) => match result.try_recv() {
+                Ok(result) => result,
+                // The worker wakes the UI when it is done.
+                Err(TryRecvError::Empty) => {
+                    if let (Some(phase), View::Login(login)) = (phases.try_iter().last(), &mut self.view) {
+                        login.phase = Some(phase);
+                    }
+                    return;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    Err(ConnectError::Unreachable(cred.server.clone(), std::io::Error::other("login handshake failed")))
+                }
+            },
+            None => return,
+        };
+        let (cred, _, phases) = self.connecting.take().expect("checked above");
+        match finished {
+            Ok(connection) => self.view.make_main(cred, connection, self.settings.clone()),
+            Err(err) => {
+                if let View::Login(login) = &mut self.view {
+                    let retryable = !matches!(err, ConnectError::Register(_) | ConnectError::Login(_));
+                    // Everything the worker reported came before its result.
+                    let phase = phases.try_iter().last().or(login.phase.take());
+                    match err {
+                        ConnectError::Register(RegisterError::Name(text)) => login.name_error = Some(text),
+                        ConnectError::Register(RegisterError::Password(text)) => login.password_error = Some(text),
+                        ConnectError::Login(err) if err.blames_name() => login.name_error = Some(err.to_string()),
+                        ConnectError::Login(LoginError::BadPassword) => login.password_error = Some(LoginError::BadPassword.to_string()),
+                        err => login.error = Some(match phase {
+                            Some(phase) => {
+                                let (step, steps) = phase.step(login.create_account);
+                                format!("{} (step {} of {}, {})", err, step, steps, phase.label())
+                            }
+                            None => err.to_string(),
+                        }),
+                    }
+                    login.failed(retryable);
+                }
+            }
+        }
+    }
+
+    /// Active settings: the session's while logged in.
+    fn settings_mut(&mut self) -> &mut Settings {
+        match &mut self.view {
+            View::Main(main) => &mut main.settings,
+            View::Login(_) => &mut self.settings,
+        }
+    }
+
+    /// Follows the window so its geometry can be saved on exit. On the first
+    /// frame, moves a restored window back onto the monitor if it is off it.
+    fn track_window(&mut self, frame: &mut eframe::Frame) {
+        let info = frame.info().window_info;
+        let position = match info.position {
+            Some(position) if !info.fullscreen => position,
+            _ => return,
+        };
+        let current = WindowGeometry { x: position.x, y: position.y, width: info.size.x, height: info.size.y };
+        if self.window.is_none() {
+            if let Some(monitor) = info.monitor_size {
+                let clamped = current.clamped_to(monitor.x, monitor.y);
+                if clamped != current {
+                    frame.set_window_pos(egui::pos2(clamped.x, clamped.y));
+                    frame.set_window_size(egui::vec2(clamped.width, clamped.height));
+                }
+            }
+        }
+        self.window = Some(current);
+    }
+
+    fn show_close_confirmation(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        if !self.confirm_close {
+            return;
+        }
+        egui::Window::new("Disconnect and quit?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("You are still connected. Quitting logs you out of the server.");
+                ui.horizontal(|ui| {
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_close = false;
+                    }
+                    if ui.button("Disconnect and quit").clicked() {
+                        if let View::Main(main) = &mut self.view {
+                            main.log_out();
+                        }
+                        self.confirm_close = false;
+                        self.close_allowed = true;
+                        frame.close();
+                    }
+                });
+            });
+    }
+
+    fn show_startup_issues(&mut self, ctx: &egui::Context) {
+        if self.startup_issues.is_empty() {
+            return;
+        }
+        let mut dismissed = false;
+        egui::Window::new("Startup issues")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Some things went wrong while starting up. The app is still usable:");
+                ui.add_space(6.0);
+                for issue in &self.startup_issues {
+                    ui.strong(issue.name);
+                    ui.label(issue.problem.as_deref().unwrap_or_default());
+                    ui.weak(format!("→ {}", issue.fallback.unwrap_or_default()));
+                    ui.add_space(4.0);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("📋 Copy report").clicked() {
+                        ui.output().copied_text = startup::report(&self.startup_issues);
+                    }
+                    if ui.button("Dismiss").clicked() {
+                        dismissed = true;
+                    }
+                });
+            });
+        if dismissed {
+            self.startup_issues.clear();
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Main {
     message: String,
-    messages: Vec<String>,
+    messages: Vec<ChatMessage>,
+    /// Id for the next row added.
+    next_message_id: u64,
     stream: Connect,
-    users: HashSet<String>,
-    response: Receiver<String>,
-    message_codes: HashMap<String, String>,
+    users: Roster,
+    /// When each user entered, by lowercase name, for sorting by join order.
+    join_order: HashMap<String, u64>,
+    joins_seen: u64,
+    user_filter: String,
+    response: Receiver<NetEvent>,
+    e2e: E2e,
+    verify_peer: Option<String>,
+    error_throttle: ErrorThrottle,
+    show_diagnostics: bool,
+    server: String,
+    current_channel: String,
+    /// The channel before the current one, to go back to after a kick.
+    previous_channel: Option<String>,
+    /// Set when an operator kicked or banned us; shown until dismissed.
+    removal: Option<Removal>,
+    /// Reason typed in the user menu's Moderation section.
+    mod_reason: String,
+    mod_action: Option<ModAction>,
+    channel_topic: Option<String>,
+    channel_prefs: ChannelPrefStore,
+    last_channels: LastChannels,
+    watch_lists: WatchLists,
+    /// `/watch` answers still to swallow after restoring the list on login.
+    watch_acks_pending: usize,
+    /// Ctrl+U since the last big resize; overrides auto-collapse meanwhile.
+    sidebar_toggle: Option<SidebarToggle>,
+    typing: TypingIndicator,
+    capture: Option<Capture>,
+    capture_outbound: bool,
+    username: String,
+    focus: FocusMode,
+    queries: QueryRouter,
+    /// Structured results shown as cards, keyed by their row in `messages`.
+    cards: HashMap<usize, QueryResult>,
+    /// Server lines we don't recognize, grouped by the row showing them.
+    unknown_lines: UnknownLines,
+    join_leave_folds: JoinLeaveFolds,
+    /// Latest `/users` answer, shown in the status bar.
+    server_totals: Option<String>,
+    activity: ActivityTracker,
+    settings: Settings,
+    nicknames: AddressBook,
+    /// Account being labelled and the label being typed.
+    nickname_edit: Option<(String, String)>,
+    /// `/w` target candidates and which one Tab last inserted.
+    tab_completion: Option<(Vec<String>, usize)>,
+    show_settings: bool,
+    frame_stats: FrameStats,
+    /// Open "send as command or text?" question for an unknown `/` prefix.
+    slash_prompt: Option<SlashPrompt>,
+    /// Our own flags in the current channel, from USER/JOIN lines about us.
+    own_flags: UserFlags,
+    /// Flags, ping and client of everyone in the channel, keyed by lowercased name.
+    user_info: HashMap<String, UserInfo>,
+    /// New topic being typed in the topic bar.
+    topic_edit: Option<String>,
+    confirmation: Option<Confirmation>,
+    lists: ContactLists,
+    import_wizard: Option<ImportWizard>,
+    idle: IdleTimes,
+    /// A "refresh idle times" `/who` is out; its answer updates `idle` instead of showing a card.
+    idle_refresh_pending: bool,
+    report: Option<ReportDraft>,
+    channel_browser: Option<ChannelBrowser>,
+    /// Messages held back from each ignored user this session, keyed by lowercased name.
+    ignored_counts: HashMap<String, usize>,
+    /// Cleared when a send fails or the server drops us.
+    connected: bool,
+    /// When this connection (or the latest reconnect) logged in.
+    connected_at: Instant,
+    /// Chat, whispers, emotes and broadcasts from others this session.
+    received_messages: usize,
+    /// When the user last sent something from the input box, and the error if it failed.
+    last_send: Option<(Instant, Result<(), String>)>,
+    /// Lines held back by the rate limit, oldest first.
+    outbox: Outbox<Queued>,
+    /// Our last typed row to go out, and when, for pinning a server error on it.
+    last_typed: Option<(u64, Instant)>,
+    /// What we logged in with, for reconnecting. `None` when replaying.
+    credentials: Option<Credentials>,
+    reconnect: Option<Reconnect>,
+    /// Result of the reconnect attempt running in the background.
+    reconnect_result: Option<ConnectionResult>,
+    /// Set by "Cancel" on the reconnect banner.
+    back_to_login: bool,
+    /// Channel name typed into the header's channel menu.
+    join_input: String,
+    history: InputHistory,
+    alerts: Alerts,
+    /// Open search bar above the message list.
+    search: Option<Search>,
+    /// Message rows picked for copying.
+    selection: Selection,
+    /// First row in view last frame, kept at the top across a layout switch.
+    top_row: usize,
+    scroll_anchor: Option<usize>,
+    /// What the emoji menu's search box holds.
+    emoji_query: String,
+    unread: Unread,
+    /// Daily log files, while enabled in settings.
+    chat_log: Option<ChatLog>,
+    /// Logging hit a disk error this session; stays off until re-enabled.
+    chat_log_failed: bool,
+    /// Rows trimmed off the top of the buffer this session.
+    discarded: usize,
+    /// Whispers waiting for the server's echo, to pin errors on them.
+    pending_whispers: PendingWhispers,
+    show_help: bool,
+    status: OwnStatus,
+    /// Message typed into the status menu.
+    status_input: String,
+    /// "You're away, clear it?" after talking while away.
+    away_prompt: bool,
+    /// Last `/friends list` answer, shown at the top of the sidebar.
+    friends: Vec<Friend>,
+    /// A `/friends list` we sent ourselves, answered without a card.
+    friends_refresh_pending: bool,
+    row_heights: RowHeights<(i32, bool, bool, StyleKey, TimestampFormat, bool, bool)>,
+    keepalive: Keepalive,
+    latency: Latency,
 }
 
 impl Main {
-    fn new(stream: Connect, req_rx: Receiver<String>) -> Self {
-        // These are fucked up
-        let message_codes = [
-            ("1001", "USER"),
-            ("1007", "CHANNEL"),
-            ("1009", "USER"),
-            ("1018", "INFO"),
-            ("1019", "ERROR"),
-            ("1020", "STATS"),
-            ("1005", "TALK"),
-            ("1022", "LOGGED_IN"),
-            ("1023", "LOGGED_OUT"),
-            ("1002", "JOIN"),
-            ("1003", "LEAVE"),
-            ("1004", "WHISPER"),
-            ("1010", "WHISPER_TO"),
-        ];
-
-        let message_codes_map: HashMap<String, String> = HashMap::from_iter(message_codes.iter().map(|(k,v)| (k.to_string(), v.to_string())));
+    fn new(stream: Connect, req_rx: Receiver<NetEvent>, server: String, username: String, settings: Settings) -> Self {
+        let store = CredentialStore::load().unwrap_or_else(|err| {
+            println!("Could not read credential store: {}", err);
+            CredentialStore::default()
+        });
+        let outbox = Outbox::new(&settings.rate_limit, Instant::now());
         Self {
             message: "".to_string(),
             messages: vec![],
+            next_message_id: 0,
             stream,
-            users: HashSet::new(),
+            users: Roster::default(),
+            join_order: HashMap::new(),
+            joins_seen: 0,
+            user_filter: String::new(),
             response: req_rx,
-            message_codes: message_codes_map,
+            e2e: E2e::load(store),
+            verify_peer: None,
+            error_throttle: ErrorThrottle::default(),
+            show_diagnostics: false,
+            server,
+            current_channel: "".to_string(),
+            previous_channel: None,
+            removal: None,
+            mod_reason: String::new(),
+            mod_action: None,
+            channel_topic: None,
+            channel_prefs: ChannelPrefStore::load(),
+            last_channels: LastChannels::load(),
+            watch_lists: WatchLists::load(),
+            watch_acks_pending: 0,
+            typing: TypingIndicator::default(),
+            capture: None,
+            capture_outbound: false,
+            username,
+            focus: FocusMode::default(),
+            queries: QueryRouter::default(),
+            cards: HashMap::new(),
+            unknown_lines: UnknownLines::default(),
+            join_leave_folds: JoinLeaveFolds::default(),
+            server_totals: None,
+            activity: ActivityTracker::default(),
+            settings,
+            nicknames: AddressBook::load(),
+            nickname_edit: None,
+            tab_completion: None,
+            show_settings: false,
+            frame_stats: FrameStats::default(),
+            slash_prompt: None,
+            own_flags: UserFlags::default(),
+            user_info: HashMap::new(),
+            topic_edit: None,
+            confirmation: None,
+            lists: ContactLists::load(),
+            import_wizard: None,
+            idle: IdleTimes::default(),
+            idle_refresh_pending: false,
+            report: None,
+            channel_browser: None,
+            ignored_counts: HashMap::new(),
+            connected: true,
+            connected_at: Instant::now(),
+            received_messages: 0,
+            last_send: None,
+            outbox,
+            last_typed: None,
+            credentials: None,
+            reconnect: None,
+            reconnect_result: None,
+            back_to_login: false,
+            join_input: String::new(),
+            history: InputHistory::default(),
+            alerts: Alerts::new(),
+            search: None,
+            selection: Selection::default(),
+            top_row: 0,
+            scroll_anchor: None,
+            emoji_query: String::new(),
+            unread: Unread::default(),
+            chat_log: None,
+            chat_log_failed: false,
+            discarded: 0,
+            pending_whispers: PendingWhispers::default(),
+            show_help: false,
+            status: OwnStatus::default(),
+            status_input: String::new(),
+            away_prompt: false,
+            friends: vec![],
+            friends_refresh_pending: false,
+            row_heights: RowHeights::default(),
+            sidebar_toggle: None,
+            keepalive: Keepalive::new(Instant::now()),
+            latency: Latency::default(),
         }
 
     }
 
     fn update(&mut self, ctx: &egui::Context) {
-        if let Ok(response) = self.response.try_recv() {
-            self.parse_message(response);
+        self.frame_stats.record(Instant::now());
+        self.sync_chat_log();
+        if self.settings.low_resource {
+            // Incoming lines still repaint via the queue; this only caps idle redraws.
+            ctx.request_repaint_after(Duration::from_millis(500));
+        }
+        self.drain_events(ctx);
+        self.poll_reconnect(ctx);
+        self.poll_keepalive(ctx);
+        self.poll_latency(ctx);
+        self.poll_outbox(ctx);
+        for result in self.queries.expire(Instant::now()) {
+            self.add_query_result(result);
+        }
+        if self.queries.has_pending() {
+            ctx.request_repaint_after(QUIET_PERIOD);
+        }
+        // Presence dots fade on their own; a coarse repaint keeps them honest.
+        ctx.request_repaint_after(Duration::from_secs(30));
+        let command = ctx.input().modifiers.command;
+        if command && ctx.input().modifiers.shift && ctx.input().key_pressed(egui::Key::F) {
+            self.toggle_focus();
+        } else if ctx.input_mut().consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::M) {
+            self.toggle_compact_messages();
+        } else if ctx.input_mut().consume_key(egui::Modifiers::COMMAND, egui::Key::S) {
+            self.save_log();
+        } else if ctx.input_mut().consume_key(egui::Modifiers::COMMAND, egui::Key::F) {
+            self.search = match self.search.take() {
+                Some(_) => None,
+                None => Some(Search::new()),
+            };
+        }
+        let input_id = egui::Id::new("input_text_id");
+        // End belongs to the input while there is text to move through.
+        let input_busy = ctx.memory().has_focus(input_id) && !self.message.is_empty();
+        if !input_busy && ctx.input_mut().consume_key(egui::Modifiers::NONE, egui::Key::End) {
+            self.unread.jump_pending = true;
+        }
+        if ctx.input().key_pressed(egui::Key::Escape) {
+            self.handle_escape(ctx, input_id);
+        }
+        let layout = self.channel_prefs.resolve(&self.server, &self.current_channel);
+        let caps = self.capabilities();
+        egui::TopBottomPanel::top("header").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if self.connected {
+                    let hover = match self.latency.summary() {
+                        Some(summary) => format!("Connected\nRound trip: {}", summary),
+                        None => "Connected".to_string(),
+                    };
+                    ui.label("🟢").on_hover_text(&hover);
+                    if let Some(round_trip) = self.latency.last() {
+                        let color = match latency::quality(round_trip) {
+                            Quality::Good => egui::Color32::from_rgb(80, 200, 100),
+                            Quality::Fair => egui::Color32::from_rgb(220, 190, 60),
+                            Quality::Poor => egui::Color32::from_rgb(230, 80, 70),
+                        };
+                        ui.colored_label(color, format!("{} ms", round_trip.as_millis())).on_hover_text(hover);
+                    }
+                } else {
+                    ui.label("🔴").on_hover_text("Disconnected");
+                }
+                if !self.username.is_empty() {
+                    avatar(ui, &self.username, 20.0);
+                    ui.label(format!("Logged in as {} @ {}", self.username, self.server));
+                }
+                let status = &self.status.current;
+                let status_button = ui.menu_button(format!("{} {}", status.icon(), status.label()), |ui| self.status_menu(ui));
+                if let Some(message) = self.status.current.message() {
+                    status_button.response.on_hover_text(message);
+                }
+                ui.heading("Bnet chat");
+                let channel_label = if self.current_channel.is_empty() {
+                    "No channel".to_string()
+                } else if caps.is_op() {
+                    format!("🔨 #{}", self.current_channel)
+                } else {
+                    format!("#{}", self.current_channel)
+                };
+                ui.menu_button(channel_label, |ui| self.channel_menu(ui));
+                if self.focus.active {
+                    let pill = egui::Button::new(
+                        egui::RichText::new(format!("🎯 FOCUS MODE · {} held back", self.focus.suppressed))
+                            .color(egui::Color32::BLACK),
+                    )
+                    .fill(egui::Color32::from_rgb(255, 190, 60));
+                    if ui.add(pill).on_hover_text("Only whispers and mentions are shown. Click to turn off.").clicked() {
+                        self.toggle_focus();
+                    }
+                    ui.checkbox(&mut self.focus.hide, "Hide instead of dim");
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.button("⏏ Log out").clicked() {
+                        self.back_to_login = true;
+                    }
+                    ui.toggle_value(&mut self.show_diagnostics, "🩺 Diagnostics");
+                    ui.toggle_value(&mut self.show_settings, "⚙ Settings");
+                    if ui.button("📋 Channels").on_hover_text("Browse the server's public channels").clicked() {
+                        self.list_channels();
+                    }
+                    if ui.button("💾 Save chat log").on_hover_text("Ctrl+S").clicked() {
+                        self.save_log();
+                    }
+                    if ui.selectable_label(self.focus.active, "🎯 Focus")
+                        .on_hover_text("Whisper-only focus mode (Ctrl+Shift+F)")
+                        .clicked()
+                    {
+                        self.toggle_focus();
+                    }
+                    ui.toggle_value(&mut self.selection.mode, "☑ Select")
+                        .on_hover_text("Click rows to select them for copying; Shift+click selects a range anywhere");
+                    if !self.selection.is_empty() && ui.button(format!("📋 Copy selected ({})", self.selection.len())).clicked() {
+                        self.copy_selection(ctx);
+                    }
+                });
+            });
+        });
+        if self.reconnect.is_some() {
+            egui::TopBottomPanel::top("reconnect_banner").show(ctx, |ui| self.reconnect_banner(ui));
+        }
+        if layout.show_topic && (self.channel_topic.is_some() || caps.edit_topic) {
+            egui::TopBottomPanel::top("topic").show(ctx, |ui| self.topic_bar(ui, caps));
+        }
+        if ctx.memory().has_focus(input_id) && ctx.input_mut().consume_key(egui::Modifiers::NONE, egui::Key::Tab) {
+            self.complete_whisper_target(ctx, input_id);
         }
-        egui::CentralPanel::default().show(ctx, |ui| {
-            let input_id = ui.make_persistent_id("input_text_id");
+        if ctx.memory().has_focus(input_id) {
+            self.browse_history(ctx, input_id);
+        }
+        let modal_open = self.modal_open();
+        self.focus_input_shortcuts(ctx, input_id, modal_open);
+        egui::CentralPanel::default().show(ctx, |_ui| {
+            egui::TopBottomPanel::bottom("status_bar").show_animated(ctx, self.settings.show_status_bar, |ui| self.status_bar(ui));
             egui::TopBottomPanel::bottom("actions").show(ctx, |ui| {
+                self.show_slash_prompt(ui);
+                self.show_away_prompt(ui);
+                self.show_command_completions(ui, input_id);
+                self.show_send_preview(ui);
+                self.show_outbox_note(ui);
                 ui.horizontal(|ui| {
 
-                    let input = ui.add(egui::TextEdit::singleline(&mut self.message)
-                        .id(input_id));
+                    // Taken before the text box sees it, or it would insert a newline.
+                    let (send_keys, hint) = if self.settings.send_on_enter {
+                        (egui::Modifiers::NONE, "Enter to send, Shift+Enter for a new line")
+                    } else {
+                        (egui::Modifiers::COMMAND, "Ctrl+Enter to send, Enter for a new line")
+                    };
+                    let send_pressed = ctx.memory().has_focus(input_id) && ctx.input_mut().consume_key(send_keys, egui::Key::Enter);
+                    let input = ui.add(
+                        egui::TextEdit::multiline(&mut self.message)
+                            .id(input_id)
+                            .desired_rows(1)
+                            .hint_text(hint),
+                    );
+                    if input.changed() {
+                        self.typing.keystroke(Instant::now());
+                        self.tab_completion = None;
+                        self.slash_prompt = None;
+                    }
 
-                    if input.lost_focus() && input.ctx.input().key_pressed(egui::Key::Enter) {
-                        input.request_focus();
+                    if send_pressed {
                         self.send_input();
                     }
                     let button = egui::Button::new("Submit");
                     if ui.add(button).clicked() {
                         self.send_input();
                     }
+                    if let Some(emoji) = ui.menu_button("😀", |ui| emoji_picker(ui, &mut self.emoji_query)).inner.flatten() {
+                        self.insert_at_caret(ctx, input_id, emoji);
+                    }
+                    let hint = self.typing.remaining(Instant::now(), modal_open).filter(|_| !self.settings.reduced_motion);
+                    if let Some(remaining) = hint {
+                        ui.weak("💭 typing…");
+                        ctx.request_repaint_after(remaining);
+                    }
                 });
             });
-            egui::SidePanel::right("sidebar_users").show(ctx, |ui| {
+            let width = ctx.input().screen_rect().width();
+            if self.sidebar_toggle.is_some_and(|toggle| !toggle.holds_at(width)) {
+                self.sidebar_toggle = None;
+            }
+            let mut sidebar_shown = layout.sidebar_shown(width, self.sidebar_toggle, self.settings.sidebar_pinned);
+            if ctx.input_mut().consume_key(egui::Modifiers::COMMAND, egui::Key::U) {
+                sidebar_shown = !sidebar_shown;
+                self.sidebar_toggle = Some(SidebarToggle { visible: sidebar_shown, width });
+            }
+            let now = Instant::now();
+            let mut sidebar = egui::SidePanel::right("sidebar_users");
+            if let Some(width) = self.settings.sidebar_width {
+                sidebar = sidebar.default_width(width);
+            }
+            let sidebar = sidebar.show_animated(ctx, sidebar_shown, |ui| {
+                let users = self.sorted_users();
+                ui.horizontal(|ui| {
+                    if self.user_filter.trim().is_empty() {
+                        ui.strong(format!("Users ({})", self.users.len()));
+                    } else {
+                        ui.strong(format!("Users ({}/{})", users.len(), self.users.len()));
+                    }
+                    if self.settings.show_active_count {
+                        let active = self.activity.active_count(now, self.settings.presence);
+                        ui.weak(format!("· {} active now", active));
+                    }
+                    ui.menu_button("⋯", |ui| self.user_list_menu(ui));
+                    if ui
+                        .toggle_value(&mut self.settings.sidebar_pinned, "📌")
+                        .on_hover_text("Keep the user list open at any window width (Ctrl+U shows or hides it for now)")
+                        .changed()
+                    {
+                        self.sidebar_toggle = None;
+                        self.settings.save();
+                    }
+                });
+                ui.separator();
+                if let Some(name) = self.friends_section(ui) {
+                    self.message = protocol::whisper_prefix(&name);
+                    ctx.memory().request_focus(input_id);
+                }
+                ui.separator();
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.user_filter)
+                        .id(egui::Id::new(USER_FILTER_ID))
+                        .hint_text("🔍 Filter users")
+                        .desired_width(f32::INFINITY),
+                );
                 egui::ScrollArea::vertical().show(ui, |ui| {
-                    for x in self.users.clone() {
+                    for x in users {
                         ui.horizontal(|ui| {
                             let user_name = String::from(&x);
-                            let response = ui.add(Label::new(x).sense(Sense::click()));
+                            match self.activity.presence(&user_name, now, self.settings.presence) {
+                                Presence::Active => {
+                                    ui.colored_label(egui::Color32::from_rgb(80, 220, 100), "●")
+                                        .on_hover_text("Chatting right now");
+                                }
+                                Presence::Recent => {
+                                    ui.colored_label(egui::Color32::from_rgb(60, 120, 70), "●")
+                                        .on_hover_text("Spoke recently");
+                                }
+                                Presence::Idle => {
+                                    ui.label(" ");
+                                }
+                            }
+                            let is_self = user_name.eq_ignore_ascii_case(&self.username);
+                            let mut response = ui.add(Label::new(self.nicknames.display(&self.server, &x)).sense(Sense::click()));
+                            if is_self {
+                                ui.weak("(you)");
+                            }
+                            if self.is_friend(&user_name) {
+                                ui.label("♥").on_hover_text("Friend");
+                            }
+                            let flags = self.flags_of(&user_name);
+                            match flags.role() {
+                                Role::Admin => {
+                                    ui.label("🛡").on_hover_text("Server admin");
+                                }
+                                Role::Moderator => {
+                                    ui.label("🔨").on_hover_text("Channel operator");
+                                }
+                                Role::User => {}
+                            }
+                            if flags.is_speaker() {
+                                ui.label("🎤").on_hover_text("Speaker");
+                            }
+                            if flags.is_squelched() {
+                                ui.label("🔇").on_hover_text("Squelched");
+                            }
+                            let mut details = vec![];
+                            if let Some(info) = self.user_info.get(&user_name.to_lowercase()) {
+                                if let Some(client) = &info.client {
+                                    match protocol::product(client) {
+                                        Some((short, full)) => {
+                                            ui.weak(short);
+                                            details.push(format!("Client: {} ({})", full, client));
+                                        }
+                                        None => details.push(format!("Client: {}", client)),
+                                    }
+                                }
+                                if let Some(ping) = info.ping {
+                                    details.push(format!("Ping: {} ms", ping));
+                                }
+                            }
+                            let idle = self.idle.idle(&user_name, now, self.activity.last_spoke(&user_name));
+                            if let Some(idle) = idle.filter(|_| self.idle.is_supported()) {
+                                if self.settings.show_idle_suffix {
+                                    ui.weak(format_idle(idle));
+                                }
+                                details.push(format!("Idle {}", format_idle(idle)));
+                            }
+                            if !details.is_empty() {
+                                response = response.on_hover_text(details.join("\n"));
+                            }
+                            let row = egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), ui.min_rect().y_range());
+                            if caps.kick && !is_self && ui.rect_contains_pointer(row) {
+                                if ui.small_button("👢").on_hover_text("Kick").clicked() {
+                                    self.confirmation = Some(Confirmation::kick(&user_name));
+                                }
+                                if caps.ban && ui.small_button("🔨").on_hover_text("Ban").clicked() {
+                                    self.confirmation = Some(Confirmation::ban(&user_name));
+                                }
+                            }
                             response.context_menu(|ui| {
+                                if is_self {
+                                    if ui.button("📋 Copy name").clicked() {
+                                        ui.output().copied_text = user_name.clone();
+                                        ui.close_menu();
+                                    }
+                                    return;
+                                }
                                 if ui.button("Whisper").clicked() {
-                                    self.message = format!("/w {} ", user_name);
+                                    self.message = protocol::whisper_prefix(&user_name);
                                     ctx.memory().request_focus(input_id);
                                     ui.close_menu();
                                 }
@@ -128,197 +896,3425 @@ impl Main {
                                     self.send_input();
                                     ui.close_menu();
                                 }
-                                if ui.button("Watch").clicked() {
-                                    self.message = format!("/watch {}", user_name);
-                                    ctx.memory().request_focus(input_id);
-                                    self.send_input();
+                                if self.is_friend(&user_name) {
+                                    if ui.button("💔 Remove friend").clicked() {
+                                        self.send_or_report(format!("/friends del {}", user_name));
+                                        self.lists.remove(ListKind::Friends, &user_name);
+                                        self.lists.save();
+                                        self.refresh_friends();
+                                        ui.close_menu();
+                                    }
+                                } else if ui.button("💖 Add friend").clicked() {
+                                    self.send_or_report(format!("/friends add {}", user_name));
+                                    self.lists.insert(ListKind::Friends, &user_name);
+                                    self.lists.save();
+                                    self.refresh_friends();
                                     ui.close_menu();
                                 }
-                                if ui.button("Unwatch").clicked() {
-                                    self.message = format!("/unwatch {}", user_name);
-                                    ctx.memory().request_focus(input_id);
-                                    self.send_input();
+                                if self.is_watched(&user_name) {
+                                    if ui.button("👁 Unwatch").clicked() {
+                                        self.set_watched(&user_name, false);
+                                        ui.close_menu();
+                                    }
+                                } else if ui.button("👁 Watch").on_hover_text("Be told when they log on or off").clicked() {
+                                    self.set_watched(&user_name, true);
                                     ui.close_menu();
                                 }
+                                if self.lists.contains(ListKind::Ignore, &user_name) {
+                                    if ui.button("🙉 Unignore").clicked() {
+                                        self.send_or_report(format!("/unsquelch {}", protocol::quote_name(&user_name)));
+                                        self.lists.remove(ListKind::Ignore, &user_name);
+                                        self.lists.save();
+                                        self.add_message(MessageKind::System, format!("No longer ignoring {}", user_name));
+                                        ui.close_menu();
+                                    }
+                                } else if ui.button("🙈 Ignore…").clicked() {
+                                    self.confirmation = Some(Confirmation::ignore(&user_name, self.settings.squelch_on_ignore));
+                                    ui.close_menu();
+                                }
+                                if ui.button("🚩 Report to ops…").clicked() {
+                                    self.open_report(Some(&user_name), None);
+                                    ui.close_menu();
+                                }
+                                if ui.button("✏ Set nickname…").clicked() {
+                                    let label = self.nicknames.label(&self.server, &user_name).unwrap_or_default().to_string();
+                                    self.nickname_edit = Some((user_name.clone(), label));
+                                    ui.close_menu();
+                                }
+                                if caps.is_op() {
+                                    ui.menu_button("🛡 Moderation", |ui| self.moderation_menu(ui, &user_name));
+                                }
+                                ui.separator();
+                                match self.e2e.state(&user_name) {
+                                    PeerState::Off | PeerState::WaitingForKey => {
+                                        if ui.button("🔒 Encrypt whispers").clicked() {
+                                            self.start_encryption(&user_name);
+                                            ui.close_menu();
+                                        }
+                                    }
+                                    PeerState::NeedsVerification => {
+                                        if ui.button("🔒 Verify encryption…").clicked() {
+                                            self.verify_peer = Some(user_name.clone());
+                                            ui.close_menu();
+                                        }
+                                    }
+                                    PeerState::Enabled => {
+                                        if ui.button("🔓 Stop encrypting whispers").clicked() {
+                                            self.e2e.disable(&user_name);
+                                            self.add_message(MessageKind::System, format!("Whispers to {} are no longer encrypted", user_name));
+                                            ui.close_menu();
+                                        }
+                                    }
+                                }
                             });
                         });
                     }
                 });
             });
+            // Only set while fully open; the open/close animation reports in-between widths.
+            if let Some(sidebar) = sidebar {
+                let width = sidebar.response.rect.width();
+                if !self.settings.sidebar_width.is_some_and(|saved| (saved - width).abs() < 1.0) {
+                    self.settings.sidebar_width = Some(width);
+                }
+            }
 
             egui::CentralPanel::default().show(ctx, |ui| {
-                egui::ScrollArea::vertical().max_width(f32::INFINITY).stick_to_bottom(true).show(ui, |ui| {
-                    for x in self.messages.clone() {
-                        ui.horizontal(|ui| {
-                            ui.label(x);
+                let mut whisper_to = None;
+                let mut row_action = None;
+                let mut rows_drawn = 0;
+                let compact = self.settings.compact_messages;
+                if let Some(search) = &mut self.search {
+                    search.refresh(self.messages.iter().map(ChatMessage::text));
+                    search_bar(ui, search);
+                    ui.separator();
+                }
+                // While searching, jumping to a match must not snap back to the bottom.
+                let stick_to_bottom = self.search.is_none();
+                // Only rows inside the viewport are laid out; the rest are
+                // stood in for by their last measured (or estimated) height.
+                let spacing = if compact { 0.0 } else { ui.spacing().item_spacing.y };
+                let estimate = ui.text_style_height(&egui::TextStyle::Body) + spacing;
+                let marker_height = if self.discarded > 0 { estimate } else { 0.0 };
+                let layout_key = (
+                    ui.available_width().round() as i32,
+                    self.focus.active,
+                    self.focus.hide,
+                    style_key(&self.settings, ctx.input().screen_rect().width()),
+                    self.settings.timestamps,
+                    self.settings.twelve_hour,
+                    compact,
+                );
+                self.row_heights.sync(self.messages.len(), layout_key);
+                let mut scroll_area = egui::ScrollArea::vertical().max_width(f32::INFINITY).stick_to_bottom(stick_to_bottom);
+                if let Some(search) = &mut self.search {
+                    if let Some(row) = search.current_row().filter(|_| std::mem::take(&mut search.scroll_pending)) {
+                        let top = marker_height + self.row_heights.offset_of(row, estimate);
+                        scroll_area = scroll_area.vertical_scroll_offset((top - ui.available_height() / 2.0).max(0.0));
+                    }
+                }
+                if let Some(row) = self.scroll_anchor.take() {
+                    // Rows changed height with the layout; keep the same one at the top.
+                    scroll_area = scroll_area.vertical_scroll_offset(marker_height + self.row_heights.offset_of(row, estimate));
+                }
+                if std::mem::take(&mut self.unread.jump_pending) {
+                    // Clamped to the real bottom, which also re-engages sticking.
+                    scroll_area = scroll_area.vertical_scroll_offset(marker_height + self.row_heights.total(estimate));
+                    ctx.request_repaint();
+                }
+                let divider = self.unread.divider();
+                let mut remeasured = false;
+                let output = scroll_area.show_viewport(ui, |ui, viewport| {
+                    let origin = ui.max_rect().min;
+                    let width = ui.available_width();
+                    ui.set_height(marker_height + self.row_heights.total(estimate));
+                    if self.discarded > 0 && viewport.min.y < marker_height {
+                        let rect = egui::Rect::from_min_size(origin, egui::vec2(width, marker_height));
+                        ui.allocate_ui_at_rect(rect, |ui| {
+                            ui.weak(format!("⋯ {} older messages discarded", self.discarded))
+                                .on_hover_text("The buffer size is set in Settings");
                         });
                     }
+                    let message_style = theme::active(&self.settings).messages;
+                    let (mut index, top) = self.row_heights.row_at(viewport.min.y - marker_height, estimate);
+                    self.top_row = index;
+                    let mut y = marker_height + top;
+                    while index < self.messages.len() && y < viewport.max.y {
+                        let mention = self.is_mention(index);
+                        let message = &self.messages[index];
+                        let (id, content, timestamp) = (message.id, message.text(), &message.timestamp);
+                        let kind = RowKind::of(message.kind);
+                        // Day separators sit between rows from different local days.
+                        let new_day = index > 0 && clock::local_date(self.messages[index - 1].timestamp) != clock::local_date(*timestamp);
+                        let visibility = self.focus.visibility(message.kind, mention);
+                        let stamp = self.settings.timestamps.format(*timestamp, self.settings.twelve_hour).map(|time| format!("[{}] ", time));
+                        let text = format!("{}{}", stamp.as_deref().unwrap_or(""), content);
+                        let search = self.search.as_ref();
+                        let fill = if self.selection.contains(id) {
+                            ui.visuals().selection.bg_fill
+                        } else if search.is_some_and(|search| search.current_row() == Some(index)) {
+                            egui::Color32::from_rgb(110, 90, 20)
+                        } else if search.is_some_and(|search| search.is_match(index)) {
+                            egui::Color32::from_rgb(60, 52, 20)
+                        } else if mention {
+                            egui::Color32::from_rgba_unmultiplied(170, 90, 230, 60)
+                        } else if index % 2 == 1 && !compact {
+                            ui.visuals().faint_bg_color
+                        } else {
+                            egui::Color32::TRANSPARENT
+                        };
+                        let background = ui.painter().add(egui::Shape::Noop);
+                        let rect = egui::Rect::from_min_size(origin + egui::vec2(0.0, y), egui::vec2(width, f32::INFINITY));
+                        let row = ui.allocate_ui_at_rect(rect, |ui| {
+                            if new_day && visibility != Visibility::Hidden {
+                                ui.vertical_centered(|ui| {
+                                    ui.weak(format!("—— {} ——", clock::local_date(*timestamp).map_or_else(String::new, clock::date_label)));
+                                });
+                            }
+                            if divider == Some(index) && visibility != Visibility::Hidden {
+                                ui.vertical_centered(|ui| {
+                                    ui.colored_label(ui.visuals().warn_fg_color, "— new messages —");
+                                });
+                            }
+                            match visibility {
+                                Visibility::Hidden => {}
+                                Visibility::Dimmed => {
+                                    ui.horizontal_wrapped(|ui| {
+                                        ui.weak(text);
+                                    });
+                                }
+                                Visibility::Full => match self.cards.get(&index) {
+                                    Some(card) => {
+                                        if let Some(name) = show_card(ui, index, card) {
+                                            whisper_to = Some(name);
+                                        }
+                                    }
+                                    None if kind == RowKind::Broadcast && !compact => {
+                                        let style = message_style;
+                                        egui::Frame::none().fill(style.broadcast_fill).inner_margin(egui::style::Margin::symmetric(6.0, 3.0)).show(ui, |ui| {
+                                            ui.set_min_width(ui.available_width());
+                                            ui.add(Label::new(egui::RichText::new(text).color(style.broadcast).strong()).wrap(true));
+                                        });
+                                    }
+                                    None if kind == RowKind::Warning => {
+                                        let style = message_style;
+                                        ui.add(Label::new(egui::RichText::new(text).color(style.warning)).wrap(true));
+                                    }
+                                    None if kind == RowKind::Presence => {
+                                        ui.add(Label::new(egui::RichText::new(text).color(message_style.presence)).wrap(true));
+                                    }
+                                    None if kind == RowKind::Error => {
+                                        ui.add(Label::new(egui::RichText::new(text).color(ui.visuals().error_fg_color)).wrap(true));
+                                    }
+                                    None if kind == RowKind::Unknown => match self.unknown_lines.lines(index) {
+                                        Some(lines) if lines.len() > 1 => {
+                                            egui::CollapsingHeader::new(egui::RichText::new(text).weak()).id_source(("unknown_lines", index)).show(ui, |ui| {
+                                                for line in lines {
+                                                    ui.add(Label::new(egui::RichText::new(line).monospace().weak()).wrap(true));
+                                                }
+                                            });
+                                        }
+                                        _ => {
+                                            ui.add(Label::new(egui::RichText::new(text).weak()).wrap(true));
+                                        }
+                                    },
+                                    None if kind == RowKind::Fold => match self.join_leave_folds.events(index) {
+                                        Some(events) if events.len() > 1 => {
+                                            let color = message_style.system;
+                                            egui::CollapsingHeader::new(egui::RichText::new(text).color(color)).id_source(("join_fold", index)).show(ui, |ui| {
+                                                for (name, joined) in events {
+                                                    let arrow = if *joined { "→" } else { "←" };
+                                                    ui.label(egui::RichText::new(format!("{} {}", arrow, name)).color(color));
+                                                }
+                                            });
+                                        }
+                                        _ => {
+                                            ui.add(Label::new(egui::RichText::new(text).color(message_style.system)).wrap(true));
+                                        }
+                                    },
+                                    None if kind == RowKind::Command => {
+                                        ui.horizontal_wrapped(|ui| {
+                                            ui.weak(text);
+                                            if let Some(delivery) = message.delivery {
+                                                show_delivery(ui, delivery);
+                                            }
+                                        });
+                                    }
+                                    None => {
+                                        let italics = message.kind == MessageKind::Emote && !compact;
+                                        let font = egui::TextStyle::Body.resolve(ui.style());
+                                        let plain = if kind == RowKind::System { message_style.system } else { ui.visuals().text_color() };
+                                        let format = |color| egui::TextFormat { font_id: font.clone(), color, italics, ..Default::default() };
+                                        let sender = colored_sender(message, &self.username).filter(|_| self.settings.color_names);
+                                        let mut sender_color = sender.as_ref().map_or(plain, |(account, _)| colors::name_color(account, ui.visuals().dark_mode));
+                                        if self.settings.high_contrast {
+                                            sender_color = theme::ensure_contrast(sender_color, ui.visuals().panel_fill, theme::MIN_CONTRAST);
+                                        }
+                                        let mut lead = egui::text::LayoutJob::default();
+                                        // The sender's name stays out of the markup so `_name_` isn't italicised.
+                                        let body = if compact {
+                                            let mono = egui::TextStyle::Monospace.resolve(ui.style());
+                                            let seconds = self.settings.timestamps == TimestampFormat::HourMinuteSecond;
+                                            let time = clock::time_of_day(*timestamp, seconds, self.settings.twelve_hour).unwrap_or_default();
+                                            let (name, body) = compact_columns(message.kind, &content);
+                                            lead.append(&format!("{}  ", time), 0.0, egui::TextFormat::simple(mono.clone(), ui.visuals().weak_text_color()));
+                                            lead.append(&format!("{:<width$}  ", name, width = COMPACT_NAME_WIDTH), 0.0, egui::TextFormat::simple(mono, sender_color));
+                                            body
+                                        } else {
+                                            lead.append(&text[..text.len() - content.len()], 0.0, format(plain));
+                                            match &sender {
+                                                Some((_, name)) => {
+                                                    lead.append(name, 0.0, format(sender_color));
+                                                    &content[name.len()..]
+                                                }
+                                                None => &content,
+                                            }
+                                        };
+                                        let body_color = (kind == RowKind::System).then_some(plain);
+                                        let response = show_rich_text(ui, lead, body, italics, body_color, self.settings.convert_emoticons, message.delivery);
+                                        response.context_menu(|ui| row_menu(ui, index, message, &content, self.selection.len(), &mut row_action));
+                                    }
+                                },
+                            }
+                        });
+                        let height = if visibility == Visibility::Hidden {
+                            0.0
+                        } else {
+                            rows_drawn += 1;
+                            let row_rect = egui::Rect::from_min_size(row.response.rect.min, egui::vec2(width, row.response.rect.height()));
+                            ui.painter().set(background, egui::Shape::rect_filled(row_rect, 0.0, fill));
+                            if row_clicked(ui, row_rect) {
+                                if ui.input().modifiers.shift {
+                                    self.selection.extend_to(id);
+                                } else if self.selection.mode {
+                                    self.selection.toggle(id);
+                                }
+                            }
+                            row.response.rect.height() + spacing
+                        };
+                        remeasured |= self.row_heights.set(index, height);
+                        y += height;
+                        index += 1;
+                    }
                 });
+                let at_bottom = output.state.offset.y + output.inner_rect.height() >= output.content_size.y - 1.0;
+                // Ctrl+A over the messages, with no text field focused, selects every shown row.
+                let pointer_over = ctx.input().pointer.hover_pos().is_some_and(|pos| output.inner_rect.contains(pos));
+                if pointer_over && ctx.memory().focus().is_none() && ctx.input_mut().consume_key(egui::Modifiers::COMMAND, egui::Key::A) {
+                    let shown: Vec<u64> = (0..self.messages.len())
+                        .filter(|&index| self.focus.visibility(self.messages[index].kind, self.is_mention(index)) != Visibility::Hidden)
+                        .map(|index| self.messages[index].id)
+                        .collect();
+                    self.selection.select_all(shown.into_iter());
+                }
+                self.unread.update(at_bottom, self.messages.len());
+                let new_count = self.unread.new_count(self.messages.len());
+                if new_count > 0 {
+                    let label = if new_count == 1 { "↓ 1 new message".to_string() } else { format!("↓ {} new messages", new_count) };
+                    let jump = egui::Area::new("jump_to_bottom")
+                        .order(egui::Order::Foreground)
+                        .pivot(egui::Align2::RIGHT_BOTTOM)
+                        .fixed_pos(output.inner_rect.right_bottom() - egui::vec2(16.0, 8.0))
+                        .show(ctx, |ui| ui.button(label).on_hover_text("Jump to the latest message (End)"));
+                    if jump.inner.clicked() {
+                        self.unread.jump_pending = true;
+                    }
+                }
+                if remeasured {
+                    // Estimates were corrected; lay out again so the scroll position settles.
+                    ctx.request_repaint();
+                }
+                self.frame_stats.rows_drawn = rows_drawn;
+                match row_action {
+                    Some((index, RowAction::Report)) => self.open_report(None, Some(index)),
+                    Some((_, RowAction::CopySelected)) => self.copy_selection(ctx),
+                    Some((index, RowAction::Quote)) => {
+                        let message = &self.messages[index];
+                        let time = clock::time_of_day(message.timestamp, false, self.settings.twelve_hour);
+                        self.message = message.quote(&self.username, time.as_deref());
+                        ctx.memory().request_focus(input_id);
+                        self.move_caret_to_end(ctx, input_id);
+                    }
+                    Some((index, RowAction::Reply)) => {
+                        if let Some(prefix) = self.messages[index].reply_prefix() {
+                            self.message = prefix;
+                            ctx.memory().request_focus(input_id);
+                            self.move_caret_to_end(ctx, input_id);
+                        }
+                    }
+                    None => {}
+                }
+                if let Some(name) = whisper_to {
+                    self.message = protocol::whisper_prefix(&name);
+                    ctx.memory().request_focus(input_id);
+                }
             });
         });
+        self.show_verify_window(ctx);
+        self.show_removal_window(ctx);
+        self.show_nickname_window(ctx);
+        self.show_diagnostics_window(ctx);
+        self.show_settings_window(ctx);
+        self.show_confirmation(ctx);
+        self.show_import_window(ctx);
+        self.show_report_window(ctx);
+        self.show_channel_browser(ctx);
+        self.show_help_window(ctx);
     }
 
-    fn send_input(&mut self) {
-        self.send(self.message.clone());
-        self.messages.push(format!("You: {}", self.message));
-        self.message.clear();
+    fn show_help_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Commands")
+            .open(&mut self.show_help)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Type these in the message box. Anything else starting with / goes to the server as is.");
+                ui.separator();
+                egui::Grid::new("help_commands").num_columns(2).striped(true).show(ui, |ui| {
+                    for help in HELP {
+                        ui.monospace(format!("{} {}", help.command, help.arguments));
+                        ui.label(help.description);
+                        ui.end_row();
+                    }
+                });
+            });
     }
 
-    pub fn send(&mut self, msg: String) {
-        self.stream.send(msg);
+    /// Matching commands above the input while a bare `/command` is typed.
+    fn show_command_completions(&mut self, ui: &mut egui::Ui, input_id: egui::Id) {
+        let matches = commands::completions(&self.message);
+        if matches.is_empty() {
+            return;
+        }
+        let mut chosen = None;
+        ui.horizontal_wrapped(|ui| {
+            for help in matches {
+                if ui.small_button(help.command).on_hover_text(format!("{} {}\n{}", help.command, help.arguments, help.description)).clicked() {
+                    chosen = Some(help.command);
+                }
+            }
+        });
+        if let Some(command) = chosen {
+            self.message = format!("{} ", command);
+            ui.memory().request_focus(input_id);
+            self.move_caret_to_end(ui.ctx(), input_id);
+        }
     }
 
-    fn parse_message(&mut self, line: String) {
-        let mut parts = line.split(" ");
-        let code = parts.next().unwrap();
-        let x1 = &"UNKNOWN".to_string();
-        let message_type = match self.message_codes.get(code) {
-            Some(x) => x,
-            _ => x1
+    /// Ctrl+L jumps to the message box, and so does typing while nothing
+    /// else has focus; the keystroke then lands in the box.
+    fn focus_input_shortcuts(&mut self, ctx: &egui::Context, input_id: egui::Id, modal_open: bool) {
+        if modal_open {
+            return;
+        }
+        if ctx.input_mut().consume_key(egui::Modifiers::COMMAND, egui::Key::L) {
+            ctx.memory().request_focus(input_id);
+            self.move_caret_to_end(ctx, input_id);
+            return;
+        }
+        let typed = ctx.input().events.iter().any(|event| matches!(event, egui::Event::Text(text) if !text.trim().is_empty()));
+        if typed && ctx.memory().focus().is_none() {
+            ctx.memory().request_focus(input_id);
+            self.move_caret_to_end(ctx, input_id);
+        }
+    }
+
+    /// Opens the report composer for `user`, or for the sender of message `row`.
+    /// The user's recent lines are offered; a chosen row starts out ticked.
+    fn open_report(&mut self, user: Option<&str>, row: Option<usize>) {
+        let offender = match (user, row) {
+            (Some(user), _) => user.to_string(),
+            (None, Some(row)) => {
+                match self.messages[row].other_sender() {
+                    Some(sender) => sender.to_string(),
+                    None => return,
+                }
+            }
+            (None, None) => return,
         };
+        let mut lines: Vec<(u64, String, bool)> = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| message.other_sender().is_some_and(|sender| sender.eq_ignore_ascii_case(&offender)))
+            .map(|(index, message)| (message.timestamp, message.text().into_owned(), row.is_none_or(|row| row == index)))
+            .collect();
+        let keep_from = lines.len().saturating_sub(10);
+        lines.drain(..keep_from);
+        self.report = Some(ReportDraft { offender, lines, note: String::new() });
+    }
 
-        // skip text type as we validate on code
-        parts.next().unwrap_or("");
-        match message_type.as_ref() {
-            "USER" => {
-                let user = parts.next().unwrap();
-                self.users.insert(user.to_string().to_owned());
-            }
-            "JOIN" => {
-                let user = parts.next().unwrap();
-                self.users.insert(user.to_string().to_owned());
-                self.messages.push(format!("{} has joined the channel", user));
-            },
-            "LEAVE" => {
-                let user = parts.next().unwrap();
-                self.users.remove(user);
-                self.messages.push(format!("{} has left the channel", user));
-            },
-            "WHISPER" => {
-                let from = parts.next().unwrap();
-                let _ = parts.next(); // Skip the "to" part
-                self.messages.push(format!("{} whispers: {}", from, parts.collect::<Vec<_>>().join(" ")));
-            }
-            "WHISPER_TO" => {
-                let from = parts.next().unwrap();
-                let _ = parts.next(); // Skip the "to" part
-                self.messages.push(format!("You whisper {}: {}", from, parts.collect::<Vec<_>>().join(" ")));
-            }
-            "TALK" => {
-                let from = parts.next().unwrap();
-                self.messages.push(format!("{}: {}", from, parts.collect::<Vec<_>>().join(" ")));
-            }
-            "BROADCAST" => {
-                self.messages.push(format!("Broadcast: {}", parts.collect::<Vec<_>>().join(" ")));
-            }
-            "ERROR" | "UNKNOWN" | "INFO" => {
-                self.messages.push(format!("{}: {}", message_type, parts.collect::<Vec<_>>().join(" ")));
-                self.messages.push(format!("Unknown: {}", line));
-            },
-            "CHANNEL" => {
-                self.users.clear();
-            },
-            _ => self.messages.push(format!("Unknown: {}", line)),
+    fn report_recipients(&self) -> Vec<String> {
+        let roster = self.users.iter().map(|user| {
+            (user.as_str(), self.flags_of(user))
+        });
+        report::op_recipients(roster, &self.username, &self.settings.report_recipient)
+    }
+
+    fn show_report_window(&mut self, ctx: &egui::Context) {
+        let recipients = self.report_recipients();
+        let draft = match &mut self.report {
+            Some(draft) => draft,
+            None => return,
+        };
+        let mut open = true;
+        let mut send = false;
+        egui::Window::new(format!("Report {} to ops", draft.offender))
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                if draft.lines.is_empty() {
+                    ui.weak("No recent lines from this user in the buffer.");
+                }
+                egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                    for (timestamp, text, include) in &mut draft.lines {
+                        ui.checkbox(include, format!("{} {}", report::format_timestamp(*timestamp), text));
+                    }
+                });
+                ui.label("Note");
+                ui.text_edit_multiline(&mut draft.note);
+                ui.horizontal(|ui| {
+                    ui.label("Report recipient");
+                    ui.text_edit_singleline(&mut self.settings.report_recipient)
+                        .on_hover_text("An ops account or bot. Leave empty to whisper the operators in the channel.");
+                });
+                if recipients.is_empty() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, "No operators in this channel to send to.");
+                } else {
+                    ui.weak(format!("Goes to: {}", recipients.join(", ")));
+                }
+                if ui.add_enabled(!recipients.is_empty(), egui::Button::new("Send report")).clicked() {
+                    send = true;
+                }
+            });
+        if send {
+            self.settings.save();
+            self.send_report(&recipients);
+        } else if !open {
+            self.settings.save();
+            self.report = None;
         }
     }
-}
 
-fn read(mut stream: TcpStream, req_tx: Sender<String>) {
-    let mut buffer = [0; 1024];
-    loop {
-        let n = stream.read(&mut buffer).unwrap();
-        unsafe {
-            let s = std::str::from_utf8_unchecked(&buffer[..n]);
-            // println!("Read {} bytes: {:?}", n, s);
-            let lines = s.split("\r\n");
-            for line in lines {
-                if line.is_empty() {
-                    continue;
+    fn send_report(&mut self, recipients: &[String]) {
+        let draft = match self.report.take() {
+            Some(draft) => draft,
+            None => return,
+        };
+        let lines: Vec<(u64, String)> = draft
+            .lines
+            .into_iter()
+            .filter(|(_, _, include)| *include)
+            .map(|(timestamp, text, _)| (timestamp, text))
+            .collect();
+        let report = report::format_report(&draft.offender, &self.current_channel, &draft.note, &lines);
+        for recipient in recipients {
+            let prefix = protocol::whisper_prefix(recipient);
+            for item in &report {
+                // Sized and marked like a typed whisper, however long the name.
+                let whisper = format!("{}{}", prefix, item);
+                for line in outgoing_lines(&whisper, MultilineSend::Flatten, self.line_limit()) {
+                    self.send_or_report(line);
                 }
-                // println!("{}", line.to_string());
-                let _ = req_tx.send(line.to_string());
             }
         }
+        self.add_message(
+            MessageKind::Whisper,
+            format!("You reported {} to {}: {}", draft.offender, recipients.join(", "), report.join(" ")),
+        );
     }
-}
-
-enum Connected {
-    Done(Credentials),
-    None,
-}
-
-#[derive(Serialize, Deserialize, Default, Debug)]
-struct Credentials {
-    server: String,
-    user: String,
-    password: String,
-}
 
-impl eframe::App for MyApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let credentials = match self.view {
-            View::Login(ref mut login) => {
-                if login.update(ctx)
-                {
-                    let connected = Done(Credentials {
-                        server: login.server.to_string(),
-                        user: login.user.to_string(),
-                        password: login.password.to_string()
+    fn show_import_window(&mut self, ctx: &egui::Context) {
+        let wizard = match &mut self.import_wizard {
+            Some(wizard) => wizard,
+            None => return,
+        };
+        let mut open = true;
+        let mut apply = false;
+        egui::Window::new("Import list")
+            .open(&mut open)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    let field = ui.text_edit_singleline(&mut wizard.path);
+                    let submit = field.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+                    if submit || ui.button("Load").clicked() {
+                        wizard.load(&self.lists);
+                    }
+                });
+                if let Some(err) = &wizard.load_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("Could not read the file: {}", err));
+                }
+                if wizard.text.is_none() {
+                    return;
+                }
+                let (format, target) = (wizard.format, wizard.target);
+                ui.horizontal(|ui| {
+                    ui.label("Format");
+                    egui::ComboBox::from_id_source("import_format")
+                        .selected_text(wizard.format.label())
+                        .show_ui(ui, |ui| {
+                            for format in Format::ALL {
+                                ui.selectable_value(&mut wizard.format, format, format.label());
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Add to");
+                    for kind in ListKind::ALL {
+                        ui.radio_value(&mut wizard.target, kind, kind.label());
+                    }
+                });
+                if (wizard.format, wizard.target) != (format, target) {
+                    wizard.reparse(&self.lists);
+                }
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(260.0).show(ui, |ui| {
+                    egui::Grid::new("import_preview").striped(true).num_columns(3).show(ui, |ui| {
+                        for row in &mut wizard.parsed.rows {
+                            ui.checkbox(&mut row.include, &row.name);
+                            ui.weak(row.reason.as_deref().unwrap_or(""));
+                            if row.duplicate {
+                                ui.weak("duplicate");
+                            } else {
+                                ui.label("");
+                            }
+                            ui.end_row();
+                        }
                     });
-                    connected
-                } else {
-                    Connected::None
+                    if !wizard.parsed.errors.is_empty() {
+                        ui.add_space(6.0);
+                        ui.colored_label(
+                            egui::Color32::LIGHT_RED,
+                            format!("{} rows could not be read:", wizard.parsed.errors.len()),
+                        );
+                        for error in &wizard.parsed.errors {
+                            ui.weak(format!("Line {}: \"{}\" ({})", error.line, error.text, error.reason));
+                        }
+                    }
+                });
+                ui.separator();
+                let count = wizard.selected().count();
+                let label = format!("Add {} to {}", count, wizard.target.label());
+                if ui.add_enabled(count > 0, egui::Button::new(label)).clicked() {
+                    apply = true;
                 }
+            });
+        if apply {
+            self.apply_import();
+        } else if !open {
+            self.import_wizard = None;
+        }
+    }
+
+    /// Adds the ticked rows to the chosen server-side list.
+    fn apply_import(&mut self) {
+        let wizard = match self.import_wizard.take() {
+            Some(wizard) => wizard,
+            None => return,
+        };
+        let kind = wizard.target;
+        let names: Vec<String> = wizard.selected().map(|row| row.name.clone()).collect();
+        let mut added = 0;
+        for name in &names {
+            if self.lists.insert(kind, name) {
+                self.send_or_report(kind.add_command(name));
+                added += 1;
             }
-            View::Main(ref mut view) => {
-                view.update(ctx);
-                Connected::None
+        }
+        self.lists.save();
+        let mut summary = format!("Imported {} names into {}", added, kind.label());
+        if !wizard.parsed.errors.is_empty() {
+            summary.push_str(&format!(", {} unreadable rows skipped", wizard.parsed.errors.len()));
+        }
+        self.add_message(MessageKind::System, summary);
+    }
+
+    /// Sends the keepalive command once we have sent nothing for the set minutes.
+    fn poll_keepalive(&mut self, ctx: &egui::Context) {
+        if !self.connected || self.settings.keepalive_minutes == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(u64::from(self.settings.keepalive_minutes) * 60);
+        match self.keepalive.remaining(Instant::now(), interval) {
+            Some(remaining) => ctx.request_repaint_after(remaining),
+            None => {
+                self.queries.issue(KEEPALIVE_COMMAND, Instant::now());
+                self.send_or_report(KEEPALIVE_COMMAND.to_string());
             }
-        };
-        if let Done(cred) = credentials {
-            self.view.make_main(cred);
         }
     }
-}
 
-impl View {
-    fn make_main(&mut self, cred: Credentials) -> &mut Main {
-        std::fs::write(
-            "credentials.json",
-            serde_json::to_string_pretty(&cred).unwrap(),
-        )
-            .unwrap();
-        let host: SocketAddr = cred.server
-            .parse()
-            .expect("Unable to parse socket address");
-        let timeout = 2;
-        // println!("Connecting chat... {}", host);
-        let timeout_initial = timeout as u64;
-        let timeout = std::time::Duration::from_secs(timeout_initial);
-        let stream = match TcpStream::connect_timeout(&host, timeout) {
-            Ok(s) => s,
-            Err(_) => {
-                // println!("Socket error");
-                panic!("Omg");
+    /// Times a `/users` round trip; the answer never reaches the chat.
+    fn poll_latency(&mut self, ctx: &egui::Context) {
+        if !self.connected || self.settings.latency_probe_seconds == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let interval = Duration::from_secs(u64::from(self.settings.latency_probe_seconds));
+        match self.latency.remaining(now, interval) {
+            Some(remaining) => ctx.request_repaint_after(remaining),
+            None => {
+                self.queries.issue(KEEPALIVE_COMMAND, now);
+                self.latency.probe_sent(now);
+                self.send_or_report(KEEPALIVE_COMMAND.to_string());
+            }
+        }
+    }
+
+    /// Schedules and runs reconnect attempts while we're disconnected.
+    fn poll_reconnect(&mut self, ctx: &egui::Context) {
+        if self.connected {
+            return;
+        }
+        let credentials = match &self.credentials {
+            Some(credentials) => credentials.clone(),
+            None => return,
+        };
+        let now = Instant::now();
+        let reconnect = self.reconnect.get_or_insert_with(|| Reconnect::new(now));
+        if let Some(result) = &self.reconnect_result {
+            let outcome = match result.try_recv() {
+                Ok(outcome) => outcome,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => Err(ConnectError::Unreachable(
+                    credentials.server.clone(),
+                    std::io::Error::other("login handshake failed"),
+                )),
+            };
+            self.reconnect_result = None;
+            match outcome {
+                Ok((stream, response)) => {
+                    let down_for = reconnect.since.elapsed().unwrap_or_default().as_secs();
+                    // The old connection is most likely dead already, but if the
+                    // server still holds it, end it properly rather than leave a ghost.
+                    log_out(&mut self.stream, &self.response);
+                    self.stream = stream;
+                    self.response = response;
+                    self.connected = true;
+                    self.connected_at = Instant::now();
+                    self.reconnect = None;
+                    // A fresh login starts out available.
+                    self.status.apply(status::Change::AwayOff);
+                    self.away_prompt = false;
+                    self.latency.reset();
+                    self.refresh_friends();
+                    self.restore_watches();
+                    self.add_message(
+                        MessageKind::System,
+                        format!("── Reconnected after {}s. Anything said meanwhile was missed. ──", down_for),
+                    );
+                }
+                Err(err) => {
+                    reconnect.failed(now);
+                    let attempt = reconnect.attempt;
+                    self.add_message(MessageKind::Error, format!("Reconnect attempt {} failed: {}", attempt, err));
+                }
             }
+            return;
+        }
+        if reconnect.due(now) {
+            reconnect.in_flight = true;
+            let channel = if self.current_channel.is_empty() { credentials.channel.clone() } else { self.current_channel.clone() };
+            self.reconnect_result = Some(start_background_connection(credentials, channel, false, self.settings.timeouts, None, ctx));
+        }
+        ctx.request_repaint_after(Duration::from_secs(1));
+    }
+
+    /// Ends the session for the logout button, the reconnect banner's Cancel
+    /// and closing the window.
+    fn log_out(&mut self) {
+        log_out(&mut self.stream, &self.response);
+        self.connected = false;
+    }
+
+    fn reconnect_banner(&mut self, ui: &mut egui::Ui) {
+        let reconnect = match &mut self.reconnect {
+            Some(reconnect) => reconnect,
+            None => return,
         };
-        let stream3 = stream.try_clone().unwrap();
-        let mut connection = Connect::new(
-            stream3
-        );
-        connection.connect(&cred.user, &cred.password);
-        let (req_tx, req_rx) = channel();
-        std::thread::spawn(move || {
-            read(stream, req_tx);
+        ui.horizontal(|ui| {
+            if reconnect.in_flight {
+                ui.label("🔴 Connection lost. Reconnecting…");
+            } else {
+                let secs = reconnect.remaining(Instant::now()).as_secs() + 1;
+                ui.label(format!("🔴 Connection lost. Reconnecting in {}s (attempt {})", secs, reconnect.attempt + 1));
+                if ui.button("Reconnect now").clicked() {
+                    reconnect.next_at = Instant::now();
+                }
+            }
+            if ui.button("Cancel").clicked() {
+                self.back_to_login = true;
+            }
         });
+    }
 
-        let view = Main::new(connection, req_rx);
-        *self = View::Main(view);
-        match *self {
-            View::Main(ref mut main) => {
-                main
-            },
-            _ => unreachable!(),
+    /// Kick, ban, unban and designate for `user`, with an optional reason,
+    /// and the server's answer to the last one.
+    fn moderation_menu(&mut self, ui: &mut egui::Ui, user: &str) {
+        ui.add(egui::TextEdit::singleline(&mut self.mod_reason).hint_text("Reason (optional)").desired_width(160.0));
+        for command in ModCommand::ALL {
+            let button = ui.button(command.label());
+            let button = if command.takes_reason() { button } else { button.on_hover_text("Takes no reason") };
+            if button.clicked() {
+                self.moderate(command, user);
+            }
+        }
+        let action = match self.mod_action.as_ref().filter(|action| action.target.eq_ignore_ascii_case(user)) {
+            Some(action) => action,
+            None => return,
+        };
+        ui.separator();
+        match &action.error {
+            Some(error) => {
+                ui.colored_label(ui.visuals().error_fg_color, format!("⚠ {}", error));
+            }
+            None if action.sent.elapsed() < DELIVERY_WINDOW => {
+                ui.weak(format!("⏳ {} sent", action.command.label()));
+                ui.ctx().request_repaint_after(DELIVERY_WINDOW);
+            }
+            None => {
+                ui.weak(format!("✓ {}: no objection from the server", action.command.label()));
+            }
         }
     }
-}
\ No newline at end of file
+
+    /// Sends a moderation command, keeping it so the menu can show what the
+    /// server said back.
+    fn moderate(&mut self, command: ModCommand, user: &str) {
+        let error = self.send(command.command(user, &self.mod_reason)).err().map(|err| format!("Failed to send: {}", err));
+        if let Some(error) = &error {
+            self.push_client_error(error.clone());
+        }
+        if command.takes_reason() {
+            self.mod_reason.clear();
+        }
+        self.mod_action = Some(ModAction { command, target: user.to_string(), sent: Instant::now(), error });
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        ops::capabilities(self.own_flags)
+    }
+
+    fn topic_bar(&mut self, ui: &mut egui::Ui, caps: Capabilities) {
+        if !caps.edit_topic {
+            self.topic_edit = None;
+        }
+        ui.horizontal_wrapped(|ui| {
+            ui.weak("Topic:");
+            if let Some(draft) = &mut self.topic_edit {
+                let field = ui.text_edit_singleline(draft);
+                let submit = field.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+                if submit || ui.button("Set").clicked() {
+                    let topic = draft.trim().to_string();
+                    self.topic_edit = None;
+                    self.send_or_report(format!("/topic {} \"{}\"", self.current_channel, topic));
+                } else if ui.button("Cancel").clicked() {
+                    self.topic_edit = None;
+                }
+                return;
+            }
+            ui.label(self.channel_topic.as_deref().unwrap_or("(none)"));
+            if caps.edit_topic && ui.small_button("✏").on_hover_text("Change topic").clicked() {
+                self.topic_edit = Some(self.channel_topic.clone().unwrap_or_default());
+            }
+        });
+    }
+
+    fn show_confirmation(&mut self, ctx: &egui::Context) {
+        let confirmation = match self.confirmation.clone() {
+            Some(confirmation) => confirmation,
+            None => return,
+        };
+        let mut open = true;
+        let mut answer = None;
+        let mut squelch = confirmation.squelch;
+        egui::Window::new("Confirm")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(&confirmation.prompt);
+                if let Some(squelch) = &mut squelch {
+                    ui.radio_value(squelch, false, "Only in this client");
+                    ui.weak("The server keeps sending their messages; this client hides them.");
+                    ui.radio_value(squelch, true, "Squelch on the server");
+                    ui.weak("The server stops sending their messages and whispers at all (/squelch).");
+                }
+                ui.horizontal(|ui| {
+                    if ui.button(&confirmation.confirm_label).clicked() {
+                        answer = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        answer = Some(false);
+                    }
+                });
+            });
+        if let Some(pending) = &mut self.confirmation {
+            pending.squelch = squelch;
+        }
+        if answer == Some(true) {
+            if let Some(command) = confirmation.command {
+                self.send_or_report(command);
+            }
+            if let (Some(true), Some((_, name))) = (squelch, &confirmation.list_add) {
+                self.send_or_report(format!("/squelch {}", protocol::quote_name(name)));
+            }
+            if confirmation.clear_messages {
+                self.clear_messages();
+            }
+            if let Some((kind, name)) = confirmation.list_add {
+                self.lists.insert(kind, &name);
+                self.lists.save();
+            }
+        }
+        if answer.is_some() || !open {
+            self.confirmation = None;
+        }
+    }
+
+    /// True when `user` is on our ignore list. Their message is then dropped
+    /// and counted; the first one from each user gets a notice so nothing
+    /// disappears without a trace.
+    fn ignoring(&mut self, user: &str) -> bool {
+        if !self.lists.contains(ListKind::Ignore, user) {
+            return false;
+        }
+        let count = self.ignored_counts.entry(user.to_lowercase()).or_default();
+        *count += 1;
+        if *count == 1 {
+            self.add_message(MessageKind::System, format!("Ignoring messages from {} (count in the status bar)", user));
+        }
+        true
+    }
+
+    /// USER lines repeat for people already here; only the first one counts.
+    fn note_join(&mut self, user: &str) {
+        if let Entry::Vacant(entry) = self.join_order.entry(user.to_lowercase()) {
+            entry.insert(self.joins_seen);
+            self.joins_seen += 1;
+        }
+    }
+
+    fn flags_of(&self, user: &str) -> UserFlags {
+        self.user_info.get(&user.to_lowercase()).map_or(UserFlags::default(), |info| info.flags)
+    }
+
+    /// Records a USER or JOIN line. Flag-change lines often leave out ping and
+    /// client, so those keep their earlier values. Lines about ourselves
+    /// carry our current flags.
+    fn note_user(&mut self, mut info: UserInfo) {
+        if let Some(known) = self.user_info.get(&info.name.to_lowercase()) {
+            info.ping = info.ping.or(known.ping);
+            info.client = info.client.take().or_else(|| known.client.clone());
+        }
+        let flags = info.flags;
+        let name = info.name.clone();
+        // The server marks who we squelched; a squelch from another client is an ignore too.
+        if flags.is_squelched() && !name.eq_ignore_ascii_case(&self.username) && self.lists.insert(ListKind::Ignore, &name) {
+            self.lists.save();
+        }
+        let previous = self.user_info.insert(name.to_lowercase(), info).map(|known| known.flags.role());
+        if !name.eq_ignore_ascii_case(&self.username) {
+            if let Some(notice) = ops::op_change_notice(&name, previous, flags.role()) {
+                self.add_message(MessageKind::System, notice);
+            }
+            return;
+        }
+        let was_op = self.capabilities().is_op();
+        self.own_flags = flags;
+        let is_op = self.capabilities().is_op();
+        if is_op != was_op {
+            let notice = if is_op { "🔨 You are now a channel operator" } else { "You are no longer a channel operator" };
+            self.add_message(MessageKind::System, notice.to_string());
+        }
+    }
+
+    fn show_settings_window(&mut self, ctx: &egui::Context) {
+        let before = self.settings.clone();
+        egui::Window::new("Settings")
+            .open(&mut self.show_settings)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.strong("Appearance");
+                egui::Grid::new("appearance_settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Theme");
+                    ui.horizontal_wrapped(|ui| {
+                        for theme in Theme::ALL {
+                            let selected = self.settings.custom_theme.is_none() && self.settings.theme == theme;
+                            if ui.radio(selected, theme.label()).clicked() {
+                                self.settings.theme = theme;
+                                self.settings.custom_theme = None;
+                            }
+                        }
+                        for custom in &theme::custom().themes {
+                            let selected = self.settings.custom_theme.as_ref() == Some(&custom.name);
+                            if ui.radio(selected, &custom.name).clicked() {
+                                self.settings.custom_theme = Some(custom.name.clone());
+                            }
+                        }
+                    });
+                    ui.end_row();
+                    ui.label("");
+                    ui.vertical(|ui| {
+                        for error in &theme::custom().errors {
+                            ui.colored_label(ui.visuals().error_fg_color, error);
+                        }
+                        ui.weak(format!("More themes: JSON files in {}", paths::themes_dir().display()))
+                            .on_hover_text("Read at startup. Copy one of the examples there and change its colors");
+                    });
+                    ui.end_row();
+                    ui.label("Names");
+                    ui.checkbox(&mut self.settings.color_names, "Color each sender's name");
+                    ui.end_row();
+                    ui.label("Timestamps");
+                    egui::ComboBox::from_id_source("timestamp_format")
+                        .selected_text(self.settings.timestamps.label())
+                        .show_ui(ui, |ui| {
+                            for format in TimestampFormat::ALL {
+                                ui.selectable_value(&mut self.settings.timestamps, format, format.label());
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("");
+                    ui.checkbox(&mut self.settings.twelve_hour, "12-hour clock (AM/PM)");
+                    ui.end_row();
+                });
+                ui.separator();
+                ui.strong("Accessibility");
+                ui.checkbox(&mut self.settings.high_contrast, "High contrast")
+                    .on_hover_text("Black or white backgrounds and stronger colors, in the theme's light or dark variant");
+                ui.horizontal(|ui| {
+                    ui.label("Text size");
+                    ui.add(egui::Slider::new(&mut self.settings.font_scale, MIN_FONT_SCALE..=MAX_FONT_SCALE).step_by(0.05).suffix("×"))
+                        .on_hover_text("Also Ctrl+= and Ctrl+-, and Ctrl+0 to reset. Narrow windows use a slightly smaller size on top");
+                });
+                ui.checkbox(&mut self.settings.reduced_motion, "Reduced motion")
+                    .on_hover_text("No sliding panels, no spinner and no typing hint, which also saves redraws");
+                ui.separator();
+                ui.strong("Chat");
+                ui.horizontal(|ui| {
+                    ui.label("Default channel");
+                    ui.add(egui::TextEdit::singleline(&mut self.settings.default_channel).hint_text("w3").desired_width(120.0))
+                        .on_hover_text("Filled in on the login form when no channel was remembered");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Highlight words");
+                    ui.add(egui::TextEdit::singleline(&mut self.settings.highlight_words).hint_text("gg, clan tag").desired_width(160.0))
+                        .on_hover_text("Comma-separated. Lines saying one of these count as mentions, like your name");
+                });
+                ui.checkbox(&mut self.settings.send_on_enter, "Send on Enter")
+                    .on_hover_text("Shift+Enter starts a new line. Off: Ctrl+Enter sends and Enter starts a new line");
+                ui.horizontal(|ui| {
+                    ui.label("Line breaks:");
+                    for choice in MultilineSend::ALL {
+                        ui.radio_value(&mut self.settings.multiline_send, choice, choice.label());
+                    }
+                })
+                .response
+                .on_hover_text("The server ends a message at each line break. Commands other than whispers and /me are always joined");
+                ui.checkbox(&mut self.settings.compact_messages, "Compact messages")
+                    .on_hover_text("One dense line per message: time, name, text. Ctrl+Shift+M switches");
+                ui.checkbox(&mut self.settings.convert_emoticons, "Show smileys as emoji")
+                    .on_hover_text("Received :) <3 and the like appear as pictures. Off: exactly as typed");
+                ui.separator();
+                ui.strong("Connection");
+                ui.horizontal(|ui| {
+                    ui.label("Keepalive after");
+                    ui.add(egui::DragValue::new(&mut self.settings.keepalive_minutes).clamp_range(0..=60));
+                    ui.label("quiet minutes");
+                })
+                .response
+                .on_hover_text("Sends a harmless /users when nothing was sent for this long, so the server and routers keep the connection. 0 turns it off");
+                ui.horizontal(|ui| {
+                    ui.label("Measure latency every");
+                    ui.add(egui::DragValue::new(&mut self.settings.latency_probe_seconds).clamp_range(0..=600));
+                    ui.label("seconds");
+                })
+                .response
+                .on_hover_text("Times a /users round trip for the indicator next to the connection dot. 0 turns it off");
+                ui.horizontal(|ui| {
+                    ui.label("Send at most");
+                    ui.add(egui::DragValue::new(&mut self.settings.rate_limit.messages).clamp_range(1..=MAX_BURST));
+                    ui.label("lines per");
+                    ui.add(egui::DragValue::new(&mut self.settings.rate_limit.seconds).clamp_range(1..=MAX_WINDOW_SECS));
+                    ui.label("seconds");
+                })
+                .response
+                .on_hover_text("Faster lines wait their turn, so a big paste can't trip the server's flood protection");
+                ui.checkbox(&mut self.settings.confirm_close, "Ask before closing the window while connected");
+                ui.checkbox(&mut self.settings.show_status_bar, "Show the status bar")
+                    .on_hover_text("Server, channel, last send, session time and message count along the bottom");
+                ui.separator();
+                ui.strong("Performance");
+                let mut low_resource = self.settings.low_resource;
+                if ui
+                    .checkbox(&mut low_resource, "Low-resource mode")
+                    .on_hover_text("Turns on reduced motion and compact rows, keeps fewer messages and redraws at most twice a second when idle")
+                    .changed()
+                {
+                    self.settings.set_low_resource(low_resource);
+                }
+                ui.indent("performance_options", |ui| {
+                    ui.checkbox(&mut self.settings.compact, "Compact rows");
+                    ui.horizontal(|ui| {
+                        ui.label("Keep the last");
+                        ui.add(egui::DragValue::new(&mut self.settings.max_messages).clamp_range(MIN_MAX_MESSAGES..=MAX_MAX_MESSAGES));
+                        ui.label("messages");
+                    });
+                });
+                ui.separator();
+                ui.strong("Logging");
+                ui.checkbox(&mut self.settings.chat_log, "Write chat logs")
+                    .on_hover_text(format!(
+                        "Appends every message to <server>/<channel>/<date>.log under {}; whispers go under <server>/whispers/<name>/",
+                        paths::logs_dir().display()
+                    ));
+                ui.separator();
+                ui.strong("Lists");
+                if ui.button("📥 Import friends/ignore list…").clicked() {
+                    self.import_wizard = Some(ImportWizard::default());
+                }
+                ui.checkbox(&mut self.settings.squelch_on_ignore, "Ignoring squelches on the server")
+                    .on_hover_text("Preselected in the ignore dialog. A squelch stops the server sending their messages; otherwise only this client hides them");
+                ui.separator();
+                ui.strong("Notifications");
+                if !self.alerts.available() {
+                    ui.weak("Sound is not available in this build or no audio device was found.");
+                }
+                let sounds = &mut self.settings.sounds;
+                ui.checkbox(&mut sounds.mute, "Mute all");
+                ui.add_enabled_ui(!sounds.mute, |ui| {
+                    ui.checkbox(&mut sounds.whisper, "Whispers");
+                    ui.checkbox(&mut sounds.mention, "Mentions of my name");
+                    ui.checkbox(&mut sounds.join_leave, "Joins and leaves");
+                    ui.checkbox(&mut sounds.watch, "Watched users logging on or off");
+                });
+            });
+        if self.settings.compact_messages != before.compact_messages {
+            self.keep_top_row();
+        }
+        if self.settings != before {
+            self.settings.clamp_limits();
+            self.settings.save();
+            self.trim_messages();
+        }
+    }
+
+    fn show_nickname_window(&mut self, ctx: &egui::Context) {
+        let (account, mut label) = match self.nickname_edit.clone() {
+            Some(edit) => edit,
+            None => return,
+        };
+        let mut open = true;
+        let mut done = None;
+        egui::Window::new(format!("Nickname for {}", account))
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label("Only you see this label; whispers still go to the real account.");
+                let field = ui.text_edit_singleline(&mut label);
+                if field.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                    done = Some(label.clone());
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        done = Some(label.clone());
+                    }
+                    if ui.button("Remove").clicked() {
+                        done = Some(String::new());
+                    }
+                });
+            });
+        match done {
+            Some(label) => {
+                let users: Vec<String> = self.users.iter().cloned().collect();
+                if let Some(warning) = self.nicknames.set(&self.server, &account, &label, &users) {
+                    self.add_message(MessageKind::System, warning);
+                }
+                self.nickname_edit = None;
+            }
+            None if !open => self.nickname_edit = None,
+            None => self.nickname_edit = Some((account, label)),
+        }
+    }
+
+    /// Tab after `/w <partial>` completes to an account, matching nicknames
+    /// too. Repeated Tab cycles through the candidates.
+    fn complete_whisper_target(&mut self, ctx: &egui::Context, input_id: egui::Id) {
+        let rest = match self.message.strip_prefix("/w ") {
+            Some(rest) => rest.trim_end(),
+            None => return,
+        };
+        // A quoted name is one we completed before, spaces and all.
+        let partial = match rest.strip_prefix('"').and_then(|quoted| quoted.strip_suffix('"')) {
+            Some(name) => name,
+            None if rest.contains(' ') => return,
+            None => rest,
+        };
+        let next = match &mut self.tab_completion {
+            Some((candidates, index)) if candidates.get(*index).is_some_and(|current| current == partial) => {
+                *index = (*index + 1) % candidates.len();
+                candidates[*index].clone()
+            }
+            _ => {
+                let users: Vec<String> = self.users.iter().cloned().collect();
+                let candidates = self.nicknames.complete(&self.server, partial, &users);
+                match candidates.first() {
+                    Some(first) => {
+                        let first = first.clone();
+                        self.tab_completion = Some((candidates, 0));
+                        first
+                    }
+                    None => return,
+                }
+            }
+        };
+        self.message = protocol::whisper_prefix(&next);
+        self.move_caret_to_end(ctx, input_id);
+    }
+
+    /// Session facts along the bottom edge. Narrow windows drop fields from
+    /// the end of the line, least important first.
+    fn status_bar(&mut self, ui: &mut egui::Ui) {
+        let now = Instant::now();
+        let width = ui.available_width();
+        ui.horizontal(|ui| {
+            if self.connected {
+                ui.weak(format!("🟢 {}", self.server)).on_hover_text("Connected");
+            } else if self.reconnect.is_some() {
+                ui.weak(format!("🟡 {}", self.server)).on_hover_text("Reconnecting");
+            } else {
+                ui.weak(format!("🔴 {}", self.server)).on_hover_text("Disconnected");
+            }
+            if !self.current_channel.is_empty() {
+                ui.weak(format!("#{}", self.current_channel));
+            }
+            match &self.last_send {
+                Some((_, Err(err))) if width >= STATUS_SEND_WIDTH => {
+                    ui.colored_label(ui.visuals().error_fg_color, "✖ Send failed").on_hover_text(err);
+                }
+                Some((at, Ok(()))) if width >= STATUS_SEND_WIDTH => {
+                    ui.weak("✔ Sent").on_hover_text(format!("Last message went out {} ago", format_idle(now.duration_since(*at))));
+                }
+                _ => {}
+            }
+            if self.connected && width >= STATUS_DURATION_WIDTH {
+                ui.weak(format!("⏱ {}", format_idle(now.duration_since(self.connected_at))))
+                    .on_hover_text("Time since logging in");
+            }
+            if width >= STATUS_RECEIVED_WIDTH {
+                ui.weak(format!("✉ {} received", self.received_messages))
+                    .on_hover_text("Chat lines, emotes, whispers and broadcasts from others this session");
+            }
+            if let Some(totals) = self.server_totals.as_ref().filter(|_| width >= STATUS_TOTALS_WIDTH) {
+                ui.weak(format!("👥 {}", totals));
+            }
+            let ignored: usize = self.ignored_counts.values().sum();
+            if ignored > 0 && width >= STATUS_TOTALS_WIDTH {
+                let mut counts: Vec<_> = self.ignored_counts.iter().collect();
+                counts.sort();
+                let detail: Vec<String> = counts.iter().map(|(user, count)| format!("{}: {}", user, count)).collect();
+                ui.weak(format!("🙈 {} ignored", ignored)).on_hover_text(detail.join("\n"));
+            }
+        });
+    }
+
+    /// Switches between the roomy and the one-line message layouts.
+    fn toggle_compact_messages(&mut self) {
+        self.settings.compact_messages = !self.settings.compact_messages;
+        self.settings.save();
+        self.keep_top_row();
+    }
+
+    /// After a layout switch, scrolls so the row at the top of the view
+    /// stays there, unless the view follows the bottom anyway.
+    fn keep_top_row(&mut self) {
+        if !self.unread.at_bottom() {
+            self.scroll_anchor = Some(self.top_row);
+        }
+    }
+
+    /// Puts `text` where the caret was in the message box, or at the end if
+    /// the box was never focused, and gives the box focus again.
+    fn insert_at_caret(&mut self, ctx: &egui::Context, input_id: egui::Id, text: &str) {
+        let mut state = egui::TextEdit::load_state(ctx, input_id).unwrap_or_default();
+        let count = self.message.chars().count();
+        let caret = state.ccursor_range().map_or(count, |range| range.primary.index.min(count));
+        let at = self.message.char_indices().nth(caret).map_or(self.message.len(), |(at, _)| at);
+        self.message.insert_str(at, text);
+        let after = egui::text::CCursor::new(caret + text.chars().count());
+        state.set_ccursor_range(Some(egui::text_edit::CCursorRange::one(after)));
+        state.store(ctx, input_id);
+        ctx.memory().request_focus(input_id);
+        self.typing.keystroke(Instant::now());
+    }
+
+    fn move_caret_to_end(&self, ctx: &egui::Context, input_id: egui::Id) {
+        if let Some(mut state) = egui::TextEdit::load_state(ctx, input_id) {
+            let end = egui::text::CCursor::new(self.message.chars().count());
+            state.set_ccursor_range(Some(egui::text_edit::CCursorRange::one(end)));
+            state.store(ctx, input_id);
+        }
+    }
+
+    /// Up on the first line recalls older input, Down on the last line newer
+    /// input. Elsewhere the keys are left to the editor to move between lines.
+    fn browse_history(&mut self, ctx: &egui::Context, input_id: egui::Id) {
+        let caret = egui::TextEdit::load_state(ctx, input_id)
+            .and_then(|state| state.ccursor_range())
+            .map_or(self.message.chars().count(), |range| range.primary.index);
+        let before: String = self.message.chars().take(caret).collect();
+        let on_first_line = !before.contains('\n');
+        let on_last_line = !self.message.chars().skip(caret).any(|c| c == '\n');
+        let recalled = if on_first_line && ctx.input_mut().consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp) {
+            self.history.previous(&self.message)
+        } else if on_last_line && ctx.input_mut().consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown) {
+            self.history.next()
+        } else {
+            None
+        };
+        if let Some(text) = recalled {
+            self.message = text;
+            self.tab_completion = None;
+            self.move_caret_to_end(ctx, input_id);
+        }
+    }
+
+    /// Users in sidebar order, narrowed to names (or nicknames) containing the filter.
+    fn sorted_users(&self) -> Vec<String> {
+        let filter = self.user_filter.trim().to_lowercase();
+        let mut users: Vec<String> = self
+            .users
+            .iter()
+            .filter(|user| filter.is_empty() || self.nicknames.display(&self.server, user).to_lowercase().contains(&filter))
+            .cloned()
+            .collect();
+        users.sort_by_key(|user| user.to_lowercase());
+        // Stable sorts keep names alphabetical among equally (in)active users.
+        match self.settings.user_sort {
+            UserSort::Name => {}
+            UserSort::Activity => users.sort_by_key(|user| std::cmp::Reverse(self.activity.last_spoke(user))),
+            UserSort::Idle if self.idle.is_supported() => {
+                let now = Instant::now();
+                users.sort_by_key(|user| {
+                    let idle = self.idle.idle(user, now, self.activity.last_spoke(user));
+                    // Unknown idle times go last.
+                    (idle.is_none(), idle)
+                });
+            }
+            UserSort::Idle => {}
+            UserSort::Role => users.sort_by_key(|user| {
+                self.flags_of(user).role()
+            }),
+            UserSort::JoinOrder => users.sort_by_key(|user| self.join_order.get(&user.to_lowercase()).copied()),
+        }
+        // Friends here lead the list, in the order chosen above.
+        users.sort_by_key(|user| !self.is_friend(user));
+        users
+    }
+
+    fn user_list_menu(&mut self, ui: &mut egui::Ui) {
+        let before = self.settings.clone();
+        ui.strong("Sort by");
+        for sort in UserSort::ALL {
+            if sort == UserSort::Idle && !self.idle.is_supported() {
+                continue;
+            }
+            ui.radio_value(&mut self.settings.user_sort, sort, sort.label());
+        }
+        ui.separator();
+        if self.idle.support != IdleSupport::Unsupported && !self.current_channel.is_empty() {
+            let refreshing = self.idle_refresh_pending;
+            if ui.add_enabled(!refreshing, egui::Button::new("⟳ Refresh idle times")).clicked() {
+                let command = format!("/who {}", self.current_channel);
+                self.queries.issue(&command, Instant::now());
+                self.idle_refresh_pending = true;
+                self.send_or_report(command);
+            }
+        }
+        if self.idle.is_supported() {
+            ui.checkbox(&mut self.settings.show_idle_suffix, "Show idle time after names");
+        }
+        ui.checkbox(&mut self.settings.show_active_count, "Show \"active now\" count");
+        ui.horizontal(|ui| {
+            ui.label("Bright dot within");
+            ui.add(egui::DragValue::new(&mut self.settings.presence.active_secs).clamp_range(10..=3600).suffix(" s"));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Dim dot within");
+            ui.add(egui::DragValue::new(&mut self.settings.presence.recent_secs).clamp_range(60..=86400).suffix(" s"));
+        });
+        let presence = &mut self.settings.presence;
+        presence.recent_secs = presence.recent_secs.max(presence.active_secs);
+        if self.settings != before {
+            self.settings.save();
+        }
+    }
+
+    /// Header menu for the current channel's layout and the defaults other channels use.
+    fn channel_menu(&mut self, ui: &mut egui::Ui) {
+        let before = self.channel_prefs.get(&self.server, &self.current_channel);
+        let resolved = self.channel_prefs.resolve(&self.server, &self.current_channel);
+        let defaults_before = self.channel_prefs.defaults;
+        let mut prefs = before;
+
+        ui.strong("Switch channel");
+        ui.horizontal(|ui| {
+            let field = ui.add(egui::TextEdit::singleline(&mut self.join_input).hint_text("Channel name").desired_width(140.0));
+            let submit = field.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+            if (submit || ui.button("Join").clicked()) && !self.join_input.trim().is_empty() {
+                let name = std::mem::take(&mut self.join_input);
+                self.join_channel(name.trim());
+                ui.close_menu();
+            }
+        });
+        ui.separator();
+
+        ui.strong("This channel");
+        let mut sidebar = resolved.show_sidebar;
+        if ui.checkbox(&mut sidebar, "Show user list").changed() {
+            prefs.show_sidebar = Some(sidebar);
+            self.sidebar_toggle = None;
+        }
+        let mut topic = resolved.show_topic;
+        if ui.checkbox(&mut topic, "Show topic").changed() {
+            prefs.show_topic = Some(topic);
+        }
+        for level in JoinLeaveDisplay::ALL {
+            if ui.radio(resolved.join_leave == level, level.label()).clicked() {
+                prefs.join_leave = Some(level);
+            }
+        }
+        if ui.add_enabled(!before.is_empty(), egui::Button::new("Use defaults here")).clicked() {
+            prefs = ChannelPrefs::default();
+        }
+        let customized = self.sidebar_toggle.is_some() || self.settings.sidebar_pinned;
+        if ui
+            .add_enabled(customized, egui::Button::new("Reset user list"))
+            .on_hover_text("Forgets Ctrl+U and the pin; the user list follows the layout and window width again")
+            .clicked()
+        {
+            self.sidebar_toggle = None;
+            self.settings.sidebar_pinned = false;
+            self.settings.save();
+        }
+
+        ui.separator();
+        ui.strong("Defaults for all channels");
+        let defaults = &mut self.channel_prefs.defaults;
+        ui.checkbox(&mut defaults.show_sidebar, "Show user list");
+        ui.checkbox(&mut defaults.show_topic, "Show topic");
+        for level in JoinLeaveDisplay::ALL {
+            ui.radio_value(&mut defaults.join_leave, level, level.label());
+        }
+
+        if prefs != before || self.channel_prefs.defaults != defaults_before {
+            self.channel_prefs.set(&self.server, &self.current_channel, prefs);
+            self.channel_prefs.save();
+        }
+    }
+
+    fn show_diagnostics_window(&mut self, ctx: &egui::Context) {
+        let mut capturing = self.capture.is_some();
+        egui::Window::new("Diagnostics")
+            .open(&mut self.show_diagnostics)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("diagnostics_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Messages in buffer");
+                    ui.label(self.messages.len().to_string());
+                    ui.end_row();
+                    ui.label("Users in channel");
+                    ui.label(self.users.len().to_string());
+                    ui.end_row();
+                    ui.label("Suppressed client errors");
+                    ui.label(self.error_throttle.suppressed.to_string());
+                    ui.end_row();
+                    ui.label("Average FPS (5 s)");
+                    ui.label(format!("{:.1}", self.frame_stats.average_fps()));
+                    ui.end_row();
+                    ui.label("Frames drawn");
+                    ui.label(self.frame_stats.total_frames.to_string());
+                    ui.end_row();
+                    ui.label("Rows drawn last frame");
+                    ui.label(self.frame_stats.rows_drawn.to_string());
+                    ui.end_row();
+                    ui.label("Read threads");
+                    ui.label(connect::live_readers().to_string());
+                    ui.end_row();
+                });
+                ui.separator();
+                ui.checkbox(&mut capturing, "Capture session");
+                ui.add_enabled(
+                    self.capture.is_none(),
+                    egui::Checkbox::new(&mut self.capture_outbound, "Include outbound lines (whispers redacted)"),
+                );
+                if let Some(capture) = &self.capture {
+                    ui.weak(format!("Writing {}", capture.path.display()));
+                }
+            });
+        if capturing != self.capture.is_some() {
+            self.toggle_capture(capturing);
+        }
+    }
+
+    fn toggle_capture(&mut self, enable: bool) {
+        if !enable {
+            if let Some(capture) = self.capture.take() {
+                self.add_message(MessageKind::System, format!("Capture saved to {}", capture.path.display()));
+            }
+            return;
+        }
+        match Capture::start(Path::new("."), self.capture_outbound) {
+            Ok(capture) => self.capture = Some(capture),
+            Err(err) => self.push_client_error(format!("Could not start capture: {}", err)),
+        }
+    }
+
+    fn capture_line(&mut self, direction: Direction, line: &str) {
+        let result = match (&mut self.capture, direction) {
+            (None, _) => return,
+            (Some(capture), Direction::In) => capture.record_inbound(line),
+            (Some(capture), Direction::Out) => capture.record_outbound(line),
+        };
+        if let Err(err) = result {
+            self.capture = None;
+            self.push_client_error(format!("Capture stopped: {}", err));
+        }
+    }
+
+    /// Asks where to export the buffer; `.json` gets JSON, anything else text.
+    fn save_log(&mut self) {
+        let channel = if self.current_channel.is_empty() { "chat" } else { &self.current_channel };
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name(format!("{}.txt", channel))
+            .add_filter("Text", &["txt", "log"])
+            .add_filter("JSON", &["json"])
+            .save_file()
+        else {
+            return;
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        match export::write(&path, &self.messages, &self.current_channel, now) {
+            Ok(()) => self.add_message(MessageKind::System, format!("Chat log saved to {}", path.display())),
+            Err(err) => self.push_client_error(format!("Could not save chat log to {}: {}", path.display(), err)),
+        }
+    }
+
+    /// Starts or stops the logger to match settings, and turns it off with a
+    /// single error row if the writer failed.
+    fn sync_chat_log(&mut self) {
+        if !self.settings.chat_log {
+            self.chat_log = None;
+            self.chat_log_failed = false;
+            return;
+        }
+        if let Some(err) = self.chat_log.as_ref().and_then(ChatLog::failure) {
+            self.chat_log = None;
+            self.chat_log_failed = true;
+            self.push_client_error(format!("Chat logging stopped: {}", err));
+        }
+        if self.chat_log.is_none() && !self.chat_log_failed {
+            self.chat_log = Some(ChatLog::start(&self.server));
+        }
+    }
+
+    fn add_message(&mut self, kind: MessageKind, body: String) {
+        self.push_message(ChatMessage::new(kind, body));
+    }
+
+    /// A row said by or to `user`, with the nickname we have for them.
+    fn said(&self, kind: MessageKind, user: &str, body: String) -> ChatMessage {
+        ChatMessage::said(kind, user, self.nicknames.label(&self.server, user), body)
+    }
+
+    /// Adds a row and returns its id.
+    fn push_message(&mut self, mut message: ChatMessage) -> u64 {
+        message.timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if let Some(chat_log) = &self.chat_log {
+            chat_log.record(&self.current_channel, message.kind.as_str(), &message.text(), message.timestamp);
+        }
+        message.id = self.next_message_id;
+        self.next_message_id += 1;
+        let id = message.id;
+        self.messages.push(message);
+        self.trim_messages();
+        id
+    }
+
+    /// Puts the selected rows on the clipboard, oldest first, one per line.
+    fn copy_selection(&self, ctx: &egui::Context) {
+        let lines: Vec<String> = self
+            .messages
+            .iter()
+            .filter(|message| self.selection.contains(message.id))
+            .map(|message| {
+                let time = clock::time_of_day(message.timestamp, false, self.settings.twelve_hour);
+                message.transcript_line(&self.username, time.as_deref())
+            })
+            .collect();
+        ctx.output().copied_text = lines.join("\n");
+    }
+
+    /// Updates the delivery mark of row `id`, if it's still in the list.
+    fn set_delivery(&mut self, id: u64, delivery: Delivery) {
+        if let Ok(index) = self.messages.binary_search_by_key(&id, |message| message.id) {
+            self.messages[index].delivery = Some(delivery);
+        }
+    }
+
+    /// Empties the message list along with everything pointing into it.
+    fn clear_messages(&mut self) {
+        self.messages.clear();
+        self.cards.clear();
+        self.unknown_lines = UnknownLines::default();
+        self.join_leave_folds = JoinLeaveFolds::default();
+        self.error_throttle = ErrorThrottle::default();
+        self.discarded = 0;
+        self.search = None;
+        self.selection.clear();
+        self.unread.reset(0);
+    }
+
+    /// Drops the oldest rows beyond `max_messages`, keeping row indices held
+    /// elsewhere (cards, repeated errors) pointing at the same rows.
+    fn trim_messages(&mut self) {
+        let excess = self.messages.len().saturating_sub(self.settings.max_messages);
+        if excess == 0 {
+            return;
+        }
+        self.messages.drain(..excess);
+        self.discarded += excess;
+        self.selection.forget_before(self.messages.first().map_or(self.next_message_id, |message| message.id));
+        self.row_heights.trim(excess);
+        self.unread.trim(excess);
+        self.cards = shift_rows(std::mem::take(&mut self.cards), excess);
+        self.error_throttle.shift_rows(excess);
+        self.unknown_lines.shift_rows(excess);
+        self.join_leave_folds.shift_rows(excess);
+    }
+
+    fn add_query_result(&mut self, result: QueryResult) {
+        // The listing fills the browser, opening it if `/channels` was typed.
+        if let QueryResult::Channels { channels } = result {
+            let browser = self.channel_browser.get_or_insert_with(ChannelBrowser::default);
+            browser.channels = channels;
+            browser.requested = None;
+            return;
+        }
+        if let QueryResult::Users { .. } = result {
+            self.latency.answered(Instant::now());
+            self.server_totals = Some(result.summary());
+            return;
+        }
+        if let QueryResult::Friends { friends } = &result {
+            self.friends = friends.clone();
+            // The server's list is the real one: bring our copy in line.
+            for name in self.lists.names(ListKind::Friends) {
+                if !friends.iter().any(|friend| friend.name.eq_ignore_ascii_case(&name)) {
+                    self.lists.remove(ListKind::Friends, &name);
+                }
+            }
+            for friend in friends {
+                self.lists.insert(ListKind::Friends, &friend.name);
+            }
+            self.lists.save();
+            if std::mem::take(&mut self.friends_refresh_pending) {
+                return;
+            }
+        }
+        if let QueryResult::Who { users, idle, .. } = &result {
+            self.idle.record(users.len(), idle, Instant::now());
+            if std::mem::take(&mut self.idle_refresh_pending) {
+                return;
+            }
+        }
+        self.cards.insert(self.messages.len(), result.clone());
+        self.add_message(MessageKind::Card, result.summary());
+    }
+
+    /// Opens the channel browser and asks the server for a fresh listing.
+    fn list_channels(&mut self) {
+        let command = "/channels".to_string();
+        self.queries.issue(&command, Instant::now());
+        self.channel_browser.get_or_insert_with(ChannelBrowser::default).requested = Some(Instant::now());
+        self.send_or_report(command);
+    }
+
+    fn show_channel_browser(&mut self, ctx: &egui::Context) {
+        let browser = match &mut self.channel_browser {
+            Some(browser) => browser,
+            None => return,
+        };
+        let mut open = true;
+        let mut close = false;
+        let mut refresh = false;
+        let mut join = None;
+        let waiting = browser.waiting(Instant::now());
+        egui::Window::new("Channels")
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(320.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut browser.filter).hint_text("Filter").desired_width(160.0));
+                    if ui.add_enabled(!waiting, egui::Button::new("🔄 Refresh")).clicked() {
+                        refresh = true;
+                    }
+                });
+                if waiting {
+                    ui.weak("⏳ Asking the server…");
+                } else if browser.channels.is_empty() {
+                    ui.weak("The server listed no channels.");
+                }
+                egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                    egui::Grid::new("channel_list").num_columns(2).striped(true).show(ui, |ui| {
+                        ui.strong("Channel");
+                        ui.strong("Users");
+                        ui.end_row();
+                        for (name, users) in browser.visible() {
+                            if ui.selectable_label(false, name).on_hover_text("Double-click to join").double_clicked() {
+                                join = Some(name.clone());
+                            }
+                            ui.label(users.to_string());
+                            ui.end_row();
+                        }
+                    });
+                });
+                ui.separator();
+                if ui.button("Close").clicked() {
+                    close = true;
+                }
+            });
+        if waiting {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+        if !open || close || join.is_some() {
+            self.channel_browser = None;
+        } else if refresh {
+            self.list_channels();
+        }
+        if let Some(name) = join {
+            self.join_channel(&name);
+        }
+    }
+
+    /// Asks the server for the friends list without putting a card in the chat.
+    fn refresh_friends(&mut self) {
+        let command = "/friends list".to_string();
+        self.queries.issue(&command, Instant::now());
+        self.friends_refresh_pending = true;
+        self.send_or_report(command);
+    }
+
+    /// Watches everyone on this profile's list again; the server drops
+    /// watches when we log off.
+    fn restore_watches(&mut self) {
+        for name in self.watch_lists.names(&self.server, &self.username) {
+            self.watch_acks_pending += 1;
+            self.send_or_report(format!("/watch {}", protocol::quote_name(&name)));
+        }
+    }
+
+    fn is_watched(&self, user: &str) -> bool {
+        self.watch_lists.contains(&self.server, &self.username, user)
+    }
+
+    /// Adds or drops `user` on the watch list, here and on the server.
+    fn set_watched(&mut self, user: &str, watched: bool) {
+        if watched {
+            self.send_or_report(format!("/watch {}", protocol::quote_name(user)));
+            self.watch_lists.insert(&self.server, &self.username, user);
+            self.lists.insert(ListKind::Watch, user);
+        } else {
+            self.send_or_report(format!("/unwatch {}", protocol::quote_name(user)));
+            self.watch_lists.remove(&self.server, &self.username, user);
+            self.lists.remove(ListKind::Watch, user);
+        }
+        self.lists.save();
+    }
+
+    fn is_friend(&self, user: &str) -> bool {
+        self.lists.contains(ListKind::Friends, user)
+    }
+
+    /// Collapsible friends list at the top of the sidebar. Returns a friend
+    /// that was clicked to whisper.
+    fn friends_section(&mut self, ui: &mut egui::Ui) -> Option<String> {
+        let mut whisper = None;
+        let mut refresh = false;
+        let online = self.friends.iter().filter(|friend| friend.online).count();
+        egui::CollapsingHeader::new(format!("Friends ({}/{})", online, self.friends.len()))
+            .id_source("sidebar_friends")
+            .default_open(true)
+            .show(ui, |ui| {
+                if self.friends.is_empty() {
+                    ui.weak("No friends yet. Right-click a user to add one.");
+                }
+                for friend in &self.friends {
+                    ui.horizontal(|ui| {
+                        if friend.online {
+                            ui.colored_label(egui::Color32::from_rgb(80, 220, 100), "●");
+                        } else {
+                            ui.weak("○");
+                        }
+                        let name = egui::RichText::new(self.nicknames.display(&self.server, &friend.name));
+                        let name = if friend.online { name } else { name.weak() };
+                        if ui.add(Label::new(name).sense(Sense::click())).on_hover_text(&friend.status).clicked() {
+                            whisper = Some(friend.name.clone());
+                        }
+                    });
+                }
+                refresh = ui.small_button("⟳ Refresh").clicked();
+            });
+        if refresh {
+            self.refresh_friends();
+        }
+        whisper
+    }
+
+    /// Someone else's channel chat that names us or says a highlight word.
+    /// These stay visible in focus mode.
+    fn is_mention(&self, index: usize) -> bool {
+        let message = &self.messages[index];
+        message.kind == MessageKind::Chat && !message.outgoing && self.mentions_me(&message.body)
+    }
+
+    fn mentions_me(&self, text: &str) -> bool {
+        is_highlight(text, &self.username, &self.settings.highlight_words)
+    }
+
+    fn toggle_focus(&mut self) {
+        if let Some(summary) = self.focus.toggle() {
+            self.add_message(MessageKind::System, summary);
+        }
+    }
+
+    /// Shows a line we have no handling for, under its code. Lines that
+    /// follow within a minute fold into the same row.
+    fn add_unknown_line(&mut self, line: String) {
+        let text = unknown_text(&line);
+        match self.unknown_lines.add(line, Instant::now(), self.messages.len()) {
+            Filed::NewRow => self.add_message(MessageKind::Unknown, text),
+            Filed::Joined { row, count } => {
+                if let Some(message) = self.messages.get_mut(row) {
+                    message.body = format!("{} unrecognized server lines", count);
+                }
+            }
+        }
+    }
+
+    /// Adds an error row, ours or a server ERROR line's text, collapsing repeats
+    /// and capping the rate so a flapping connection or a chatty server can't
+    /// bury the chat.
+    fn push_client_error(&mut self, text: String) {
+        match self.error_throttle.check(&text, Instant::now(), self.messages.len()) {
+            Verdict::NewRow => self.add_message(MessageKind::Error, text),
+            Verdict::Repeat { row, count } => {
+                if let Some(message) = self.messages.get_mut(row) {
+                    message.body = format!("{} (×{})", text, count);
+                }
+            }
+            Verdict::Suppressed { first } => {
+                println!("Suppressed error: {}", text);
+                if first {
+                    self.add_message(MessageKind::System, "Further errors suppressed for a minute (see Diagnostics)".to_string());
+                }
+            }
+        }
+    }
+
+    /// Whether one of our dialogs is up and should take Escape and hide the typing hint.
+    fn modal_open(&self) -> bool {
+        self.verify_peer.is_some()
+            || self.nickname_edit.is_some()
+            || self.slash_prompt.is_some()
+            || self.confirmation.is_some()
+            || self.away_prompt
+            || self.removal.is_some()
+    }
+
+    fn handle_escape(&mut self, ctx: &egui::Context, input_id: egui::Id) {
+        let focused = ctx.memory().has_focus(input_id);
+        let state = egui::TextEdit::load_state(ctx, input_id);
+        let selection = state
+            .as_ref()
+            .and_then(|state| state.ccursor_range())
+            .filter(|range| focused && range.primary != range.secondary);
+        let action = resolve_escape(EscapeContext {
+            dialog_open: self.modal_open() || self.show_help,
+            user_filter_active: ctx.memory().has_focus(egui::Id::new(USER_FILTER_ID)) && !self.user_filter.is_empty(),
+            search_open: self.search.is_some(),
+            rows_selected: !self.selection.is_empty(),
+            input_has_selection: selection.is_some(),
+            input_empty: self.message.is_empty(),
+        });
+        match action {
+            EscapeAction::CloseDialog => {
+                self.show_help = false;
+                self.verify_peer = None;
+                self.nickname_edit = None;
+                self.slash_prompt = None;
+                self.confirmation = None;
+                self.away_prompt = false;
+                self.removal = None;
+            }
+            EscapeAction::ClearUserFilter => self.user_filter.clear(),
+            EscapeAction::CloseSearch => self.search = None,
+            EscapeAction::DeselectRows => self.selection.clear(),
+            EscapeAction::ClearSelection => {
+                if let (Some(mut state), Some(range)) = (state, selection) {
+                    state.set_ccursor_range(Some(egui::text_edit::CCursorRange::one(range.primary)));
+                    state.store(ctx, input_id);
+                    ctx.memory().request_focus(input_id);
+                }
+            }
+            EscapeAction::ClearInput => {
+                self.message.clear();
+                self.typing.clear();
+            }
+            EscapeAction::Nothing => {}
+        }
+    }
+
+    fn show_verify_window(&mut self, ctx: &egui::Context) {
+        let peer = match self.verify_peer.clone() {
+            Some(peer) => peer,
+            None => return,
+        };
+        let mut open = true;
+        egui::Window::new("Verify encryption")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                match self.e2e.phrase(&peer) {
+                    Some(phrase) => {
+                        ui.label(format!("Compare this phrase with {} over voice or another channel you trust:", peer));
+                        ui.add_space(6.0);
+                        ui.heading(phrase);
+                        ui.add_space(6.0);
+                        ui.horizontal(|ui| {
+                            if ui.button("Phrases match").clicked() {
+                                self.e2e.verify(&peer);
+                                self.add_message(MessageKind::System, format!("🔒 Whispers to {} are now encrypted", peer));
+                                self.verify_peer = None;
+                            }
+                            if ui.button("They differ").clicked() {
+                                self.e2e.disable(&peer);
+                                self.add_message(MessageKind::System, format!("⚠ Encryption with {} cancelled: verification phrases did not match", peer));
+                                self.verify_peer = None;
+                            }
+                        });
+                    }
+                    None => {
+                        ui.label(format!("Waiting for {} to send their key…", peer));
+                    }
+                }
+            });
+        if !open {
+            self.verify_peer = None;
+        }
+    }
+
+    fn start_encryption(&mut self, user: &str) {
+        let offer = self.e2e.start(user);
+        self.send_or_report(format!("{}{}", protocol::whisper_prefix(user), offer));
+        if self.e2e.state(user) == PeerState::NeedsVerification {
+            self.verify_peer = Some(user.to_string());
+        } else {
+            self.add_message(MessageKind::System, format!("Sent your encryption key to {}. Waiting for theirs…", user));
+        }
+    }
+
+    fn join_channel(&mut self, name: &str) {
+        if name.eq_ignore_ascii_case(&self.current_channel) {
+            self.add_message(MessageKind::System, format!("You are already in {}", self.current_channel));
+            return;
+        }
+        self.send_or_report(format!("/join {}", name));
+    }
+
+    /// Notices of our own kick or ban: the channel state is stale from here
+    /// on, whether the server moves us to The Void or closes the socket.
+    fn note_removal(&mut self, text: &str) -> bool {
+        let removal = match ops::parse_removal(text, &self.username, &self.current_channel) {
+            Some(removal) => removal,
+            None => return false,
+        };
+        self.previous_channel = Some(std::mem::replace(&mut self.current_channel, VOID_CHANNEL.to_string()));
+        self.users.clear();
+        self.join_order.clear();
+        self.user_info.clear();
+        self.own_flags = UserFlags::default();
+        self.channel_topic = None;
+        self.idle.clear();
+        self.removal = Some(removal);
+        true
+    }
+
+    fn show_removal_window(&mut self, ctx: &egui::Context) {
+        let removal = match self.removal.clone() {
+            Some(removal) => removal,
+            None => return,
+        };
+        let mut open = true;
+        let mut rejoin = false;
+        let mut dismissed = false;
+        egui::Window::new("Removed from channel")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.heading(removal.summary());
+                if let Some(reason) = &removal.reason {
+                    ui.label(format!("Reason: {}", reason));
+                }
+                ui.horizontal(|ui| {
+                    let label = format!("Rejoin {}", removal.channel);
+                    let button = ui.add_enabled(removal.can_rejoin(), egui::Button::new(label));
+                    if button.on_disabled_hover_text("Banned users cannot rejoin").clicked() {
+                        rejoin = true;
+                    }
+                    if ui.button("OK").clicked() {
+                        dismissed = true;
+                    }
+                });
+            });
+        if rejoin {
+            self.join_channel(&removal.channel);
+        }
+        if rejoin || dismissed || !open {
+            self.removal = None;
+        }
+    }
+
+    /// Checks leading-slash input against the known commands before sending.
+    fn send_input(&mut self) {
+        self.history.push(&self.message);
+        if let Some(local) = commands::local_command(&self.message) {
+            match local {
+                Local::Help => self.show_help = true,
+                Local::Clear => self.confirmation = Some(Confirmation::clear_messages()),
+            }
+            self.message.clear();
+            self.typing.clear();
+            return;
+        }
+        self.message = commands::expand_alias(&self.message);
+        if let Some(name) = join_command(&self.message) {
+            let name = name.to_string();
+            self.join_channel(&name);
+            self.message.clear();
+            self.typing.clear();
+            return;
+        }
+        match slash::dispatch(&self.message, &self.settings.slash_choices) {
+            Dispatch::Send => self.submit_input(),
+            Dispatch::PlainText => {
+                self.message = slash::as_plain_text(&self.message);
+                self.submit_input();
+            }
+            Dispatch::Confirm(prefix) => self.slash_prompt = Some(SlashPrompt { prefix, remember: false }),
+        }
+    }
+
+    /// Sends whatever takes the server from our current status to `wanted`.
+    /// The local state follows once the server confirms.
+    fn set_status(&mut self, wanted: Status) {
+        for command in self.status.current.commands_to(&wanted) {
+            self.status.note_command(&command);
+            self.send_or_report(command);
+        }
+    }
+
+    fn status_menu(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::TextEdit::singleline(&mut self.status_input).hint_text("Message (optional)").desired_width(180.0));
+        let message = self.status_input.trim().to_string();
+        let mut wanted = None;
+        if ui.button("🟢 Available").clicked() {
+            wanted = Some(Status::Available);
+        }
+        if ui.button("🌙 Away").on_hover_text("Whisperers get your message as an automatic reply").clicked() {
+            wanted = Some(Status::Away(message.clone()));
+        }
+        if ui.button("⛔ Do not disturb").on_hover_text("Whispers are refused").clicked() {
+            wanted = Some(Status::DoNotDisturb(message));
+        }
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Talking while away:");
+            for choice in AwayOnTalk::ALL {
+                if ui.radio(self.settings.away_on_talk == choice, choice.label()).clicked() {
+                    self.settings.away_on_talk = choice;
+                    self.settings.save();
+                }
+            }
+        });
+        if let Some(wanted) = wanted {
+            self.set_status(wanted);
+            ui.close_menu();
+        }
+    }
+
+    fn show_away_prompt(&mut self, ui: &mut egui::Ui) {
+        if !self.away_prompt {
+            return;
+        }
+        let mut back = None;
+        ui.horizontal_wrapped(|ui| {
+            ui.label("🌙 You're marked as away.");
+            if ui.button("I'm back").clicked() {
+                back = Some(true);
+            }
+            if ui.button("Stay away").clicked() {
+                back = Some(false);
+            }
+        });
+        ui.separator();
+        if let Some(back) = back {
+            self.away_prompt = false;
+            if back {
+                self.set_status(Status::Available);
+            }
+        }
+    }
+
+    fn show_slash_prompt(&mut self, ui: &mut egui::Ui) {
+        let prompt = match &mut self.slash_prompt {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        let mut preview: String = self.message.chars().take(30).collect();
+        if self.message.chars().count() > 30 {
+            preview.push('…');
+        }
+        let mut choice = None;
+        let mut cancel = false;
+        ui.horizontal_wrapped(|ui| {
+            ui.label(format!("{} isn't a known command. Send '{}'", prompt.prefix, preview));
+            if ui.button("as a server command").clicked() {
+                choice = Some(SlashChoice::Command);
+            }
+            if ui.button("as plain text").clicked() {
+                choice = Some(SlashChoice::PlainText);
+            }
+            if ui.button("Cancel").clicked() {
+                cancel = true;
+            }
+            ui.checkbox(&mut prompt.remember, format!("Remember my choice for {}", prompt.prefix));
+        });
+        ui.separator();
+        if cancel {
+            self.slash_prompt = None;
+        }
+        if let Some(choice) = choice {
+            self.answer_slash_prompt(choice);
+        }
+    }
+
+    fn answer_slash_prompt(&mut self, choice: SlashChoice) {
+        let prompt = match self.slash_prompt.take() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        if prompt.remember {
+            self.settings.slash_choices.insert(prompt.prefix, choice);
+            self.settings.save();
+        }
+        if choice == SlashChoice::PlainText {
+            self.message = slash::as_plain_text(&self.message);
+        }
+        self.submit_input();
+    }
+
+    fn submit_input(&mut self) {
+        self.slash_prompt = None;
+        self.unread.clear_divider();
+        // Nicknames are local: swap a label target for the account it names.
+        if let Some((target, text)) = whisper_command(&self.message) {
+            let in_channel = self.users.contains(target);
+            if !in_channel {
+                if let Some(account) = self.nicknames.resolve_label(&self.server, target) {
+                    self.message = format!("{}{}", protocol::whisper_prefix(account), text);
+                }
+            }
+        }
+        if let Some((target, text)) = whisper_command(&self.message) {
+            // Each armored chunk goes out behind `/w <name> ` on a line of its own.
+            let prefix = protocol::whisper_prefix(target);
+            let room = self.line_limit().saturating_sub(prefix.len());
+            if let Some(sealed) = self.e2e.seal_for(target, text, room) {
+                let lines = match sealed {
+                    Ok(lines) => lines,
+                    Err(err) => {
+                        self.push_client_error(format!("Failed to send: {}", err));
+                        return;
+                    }
+                };
+                let target = target.to_string();
+                let parts = lines.len();
+                for (sent, line) in lines.into_iter().enumerate() {
+                    if let Err(err) = self.send(format!("{}{}", prefix, line)) {
+                        // Parts only decrypt together, so the whole message stays to be resent.
+                        self.last_send = Some((Instant::now(), Err(err.to_string())));
+                        self.push_client_error(format!("Failed to send: {} ({} of {} parts sent)", err, sent, parts));
+                        return;
+                    }
+                    self.pending_whispers.sent(&target, Instant::now());
+                }
+                self.last_send = Some((Instant::now(), Ok(())));
+                // The server's echo of each part shows the whisper once it's through.
+                self.message.clear();
+                self.typing.clear();
+                return;
+            }
+        }
+        let (mode, limit) = (self.settings.multiline_send, self.line_limit());
+        let lines = outgoing_lines(&self.message, mode, limit);
+        let chat = lines.first().is_some_and(|line| !line.starts_with('/'));
+        let total = lines.len();
+        for (sent, line) in lines.into_iter().enumerate() {
+            let row = self.echo_typed(&line);
+            if let Err(err) = self.dispatch(Queued::Typed { line, row }) {
+                // Only what didn't go out stays in the input box, ready to resend.
+                self.message = unsent_text(&self.message, mode, limit, sent);
+                if sent == 0 {
+                    self.push_client_error(format!("Failed to send: {}", err));
+                } else {
+                    self.push_client_error(format!(
+                        "Failed to send: {} ({} of {} lines sent, the rest is still in the input box)",
+                        err, sent, total
+                    ));
+                }
+                return;
+            }
+        }
+        if chat && self.status.is_away() {
+            match self.settings.away_on_talk {
+                AwayOnTalk::Ask => self.away_prompt = true,
+                AwayOnTalk::Clear => self.set_status(Status::Available),
+                AwayOnTalk::Keep => {}
+            }
+        }
+        self.message.clear();
+        self.typing.clear();
+    }
+
+    /// Sends `item` right away when nothing is queued and the rate limit
+    /// allows, otherwise queues it; errors from queued lines show up later.
+    fn dispatch(&mut self, item: Queued) -> std::io::Result<()> {
+        match self.outbox.offer(item, &self.settings.rate_limit, Instant::now()) {
+            Some(item) => self.write(item),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, item: Queued) -> std::io::Result<()> {
+        match item {
+            Queued::Line(line) => self.write_line(line),
+            Queued::Typed { line, row } => {
+                let result = self.write_line(line.clone());
+                self.last_send = Some((Instant::now(), result.as_ref().map(|_| ()).map_err(|err| err.to_string())));
+                if result.is_ok() {
+                    self.note_typed_sent(&line);
+                }
+                if let Some(row) = row {
+                    self.set_delivery(row, if result.is_ok() { Delivery::Sent } else { Delivery::Failed });
+                    self.last_typed = result.is_ok().then(|| (row, Instant::now()));
+                }
+                result
+            }
+        }
+    }
+
+    /// Shows a line the user typed exactly as it will go out, marked pending
+    /// until it's written. Returns the row, if one was added.
+    fn echo_typed(&mut self, line: &str) -> Option<u64> {
+        let kind = match whisper_command(line) {
+            // Echoed by the server on delivery, or answered with an error.
+            Some(_) => return None,
+            // Commands aren't chat: the server's reply is the real feedback.
+            None if line.starts_with('/') => MessageKind::Command,
+            None => MessageKind::Chat,
+        };
+        let message = ChatMessage { outgoing: true, delivery: Some(Delivery::Pending), ..ChatMessage::new(kind, line.to_string()) };
+        Some(self.push_message(message))
+    }
+
+    /// Bookkeeping for a typed line that was just written.
+    fn note_typed_sent(&mut self, line: &str) {
+        self.queries.issue(line, Instant::now());
+        match whisper_command(line) {
+            Some((target, _)) => self.pending_whispers.sent(target, Instant::now()),
+            None if line.starts_with('/') => {
+                self.status.note_command(line);
+                self.note_watch_command(line);
+            }
+            None => {}
+        }
+    }
+
+    /// Keeps the watch list in step with `/watch` and `/unwatch` typed by hand.
+    fn note_watch_command(&mut self, line: &str) {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let watched = match command.to_lowercase().as_str() {
+            "/watch" => true,
+            "/unwatch" => false,
+            _ => return,
+        };
+        if let Some((name, _)) = protocol::split_name(rest.trim()).filter(|(name, _)| !name.is_empty()) {
+            if watched {
+                self.watch_lists.insert(&self.server, &self.username, name);
+            } else {
+                self.watch_lists.remove(&self.server, &self.username, name);
+            }
+        }
+    }
+
+    /// Sends queued lines as the rate limit allows.
+    fn poll_outbox(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        while let Some(item) = self.outbox.next_ready(&self.settings.rate_limit, now) {
+            if let Err(err) = self.write(item) {
+                let unsent = self.drop_outbox();
+                self.push_client_error(format!("Failed to send: {} ({} queued lines dropped)", err, unsent));
+                return;
+            }
+        }
+        if let Some(wait) = self.outbox.wait(&self.settings.rate_limit, now) {
+            ctx.request_repaint_after(wait);
+        }
+    }
+
+    /// "Sending 5 queued…" above the input while the rate limit holds lines back.
+    fn show_outbox_note(&mut self, ui: &mut egui::Ui) {
+        if self.outbox.is_empty() {
+            return;
+        }
+        let mut cancel = false;
+        ui.horizontal(|ui| {
+            ui.weak(format!("⏳ Sending {} queued…", self.outbox.len()));
+            cancel = ui.small_button("Cancel").on_hover_text("Drop the lines not sent yet").clicked();
+        });
+        if cancel {
+            self.drop_outbox();
+        }
+    }
+
+    /// Empties the outbox, marking the typed lines in it as not delivered.
+    /// Returns how many lines were dropped.
+    fn drop_outbox(&mut self) -> usize {
+        let unsent = self.outbox.clear();
+        for item in &unsent {
+            if let Queued::Typed { row: Some(row), .. } = item {
+                self.set_delivery(*row, Delivery::Failed);
+            }
+        }
+        unsent.len()
+    }
+
+    /// Longest line the server passes on whole, from the profile.
+    fn line_limit(&self) -> usize {
+        let limit = self.credentials.as_ref().map_or(protocol::MAX_MESSAGE_LEN, |credentials| credentials.line_limit);
+        limit.clamp(protocol::MIN_LINE_LIMIT, protocol::MAX_LINE_LIMIT)
+    }
+
+    /// Says what the input turns into before it's sent: its length against
+    /// the line limit once it gets close, and how many messages it makes.
+    fn show_send_preview(&self, ui: &mut egui::Ui) {
+        let text = self.message.trim();
+        let multiline = text.contains('\n');
+        let limit = self.line_limit();
+        let length = outgoing::line_length(text);
+        if !multiline && length * 4 < limit * 3 {
+            return;
+        }
+        let count = outgoing_lines(text, self.settings.multiline_send, limit).len();
+        ui.horizontal(|ui| {
+            if !multiline {
+                let counter = format!("{}/{}", length, limit);
+                if length > limit {
+                    ui.colored_label(ui.visuals().warn_fg_color, counter);
+                } else {
+                    ui.weak(counter);
+                }
+            }
+            if count > 1 {
+                ui.weak(format!("📝 Goes out as {} separate messages", count));
+            } else if multiline {
+                ui.weak("📝 Line breaks go out as spaces");
+            }
+        });
+    }
+
+    /// Sends `msg` through the rate limit, which queues it when we have been
+    /// sending too fast. Every command and chat line goes through here.
+    pub fn send(&mut self, msg: String) -> std::io::Result<()> {
+        self.dispatch(Queued::Line(msg))
+    }
+
+    fn write_line(&mut self, msg: String) -> std::io::Result<()> {
+        self.capture_line(Direction::Out, &msg);
+        self.keepalive.sent(Instant::now());
+        let result = self.stream.send(msg);
+        if result.is_err() {
+            self.connected = false;
+        }
+        result
+    }
+
+    /// For sends where telling the user is all we can do about a failure.
+    fn send_or_report(&mut self, msg: String) {
+        if let Err(err) = self.send(msg) {
+            self.push_client_error(format!("Failed to send: {}", err));
+        }
+    }
+
+    /// A join or leave, shown the way the channel's preference asks.
+    fn show_join_leave(&mut self, name: &str, joined: bool) {
+        let display = self.channel_prefs.resolve(&self.server, &self.current_channel).join_leave;
+        if display == JoinLeaveDisplay::Hide || self.lists.contains(ListKind::Ignore, name) {
+            return;
+        }
+        self.alerts.play(Cue::JoinLeave, &self.settings.sounds, Instant::now());
+        let text = roster::join_leave_text(name, joined);
+        // Our own comings and goings always get a row of their own.
+        if display == JoinLeaveDisplay::Show || name.eq_ignore_ascii_case(&self.username) {
+            self.add_message(MessageKind::JoinLeave, text);
+            return;
+        }
+        match self.join_leave_folds.add(name, joined, self.messages.len()) {
+            Filed::NewRow => self.add_message(MessageKind::JoinFold, text),
+            Filed::Joined { row, .. } => {
+                let summary = self.join_leave_folds.events(row).map(fold_summary).unwrap_or_default();
+                if let Some(message) = self.messages.get_mut(row) {
+                    message.body = summary;
+                }
+            }
+        }
+    }
+
+    /// Handles what arrived since the last frame, up to `EVENTS_PER_FRAME`
+    /// so a flood can't stall drawing; the rest wait for the next frame,
+    /// which is requested right away.
+    fn drain_events(&mut self, ctx: &egui::Context) {
+        let (events, closed) = connect::take_events(&self.response, EVENTS_PER_FRAME);
+        let capped = events.len() == EVENTS_PER_FRAME;
+        for event in events {
+            self.handle_event(event);
+        }
+        if closed {
+            // The read thread exits when the server goes away.
+            self.connected = false;
+        } else if capped {
+            ctx.request_repaint();
+        }
+    }
+
+    fn handle_event(&mut self, event: NetEvent) {
+        match event {
+            NetEvent::Line(line) => {
+                self.capture_line(Direction::In, &line);
+                let first_new = self.messages.len();
+                self.parse_message(line);
+                for index in first_new..self.messages.len() {
+                    let mention = self.is_mention(index);
+                    self.focus.note_incoming(self.messages[index].kind, mention);
+                }
+            }
+            // Losing the connection shows the red dot and starts the reconnect loop.
+            NetEvent::Disconnected(reason) => {
+                self.connected = false;
+                self.add_message(MessageKind::Error, reason);
+            }
+            NetEvent::IoError(err) => {
+                self.connected = false;
+                self.add_message(MessageKind::Error, format!("Connection lost: {}", err));
+            }
+        }
+    }
+
+    fn parse_message(&mut self, line: String) {
+        let message = match protocol::parse(&line) {
+            Ok(message) => message,
+            Err(err) => {
+                self.push_client_error(format!("Unknown: {} ({})", line, err));
+                return;
+            }
+        };
+        match message {
+            ServerMessage::User(mut info) => {
+                info.name = info.name.trim().to_string();
+                self.note_join(&info.name);
+                self.users.insert(&info.name);
+                self.note_user(info);
+            }
+            ServerMessage::Join(mut info) => {
+                info.name = info.name.trim().to_string();
+                let name = info.name.clone();
+                self.note_join(&name);
+                self.users.insert(&name);
+                self.note_user(info);
+                self.show_join_leave(&name, true);
+            }
+            ServerMessage::Leave { name } => {
+                let name = name.trim();
+                self.users.remove(name);
+                self.user_info.remove(&roster::key(name));
+                self.join_order.remove(&roster::key(name));
+                self.show_join_leave(name, false);
+            }
+            ServerMessage::Whisper { from, text } => {
+                if self.ignoring(&from) {
+                    return;
+                }
+                match self.e2e.receive(&from, &text) {
+                    Received::Plain => {
+                        self.received_messages += 1;
+                        self.push_message(self.said(MessageKind::Whisper, &from, text));
+                        if self.status.should_alert(&from) {
+                            self.alerts.play(Cue::Whisper, &self.settings.sounds, Instant::now());
+                        }
+                    }
+                    Received::Decrypted(text) => {
+                        self.received_messages += 1;
+                        let message = ChatMessage { encrypted: true, ..self.said(MessageKind::Whisper, &from, text) };
+                        self.push_message(message);
+                        if self.status.should_alert(&from) {
+                            self.alerts.play(Cue::Whisper, &self.settings.sounds, Instant::now());
+                        }
+                    }
+                    Received::Notice(notice) => {
+                        self.add_message(MessageKind::System, notice);
+                        if self.e2e.state(&from) == PeerState::NeedsVerification {
+                            self.verify_peer = Some(from);
+                        }
+                    }
+                    Received::Rejected(err) => self.add_message(MessageKind::Error, format!("⚠ Encrypted whisper from {} could not be read: {}", from, err)),
+                }
+            }
+            ServerMessage::WhisperTo { to, text } => {
+                let to = self.pending_whispers.echoed(to, &self.username, Instant::now());
+                let (encrypted, text) = match self.e2e.open_sent(&to, &text) {
+                    Some(text) => (true, text),
+                    None => (false, text),
+                };
+                let message = ChatMessage { outgoing: true, encrypted, ..self.said(MessageKind::Whisper, &to, text) };
+                self.push_message(message);
+            }
+            ServerMessage::Talk { from, text } => {
+                if self.ignoring(&from) {
+                    return;
+                }
+                self.activity.spoke(&from, Instant::now());
+                if self.mentions_me(&text) {
+                    self.alerts.play(Cue::Mention, &self.settings.sounds, Instant::now());
+                }
+                self.received_messages += 1;
+                self.push_message(self.said(MessageKind::Chat, &from, text));
+            }
+            ServerMessage::Emote { from, text } => {
+                if self.ignoring(&from) {
+                    return;
+                }
+                self.activity.spoke(&from, Instant::now());
+                self.received_messages += 1;
+                self.push_message(self.said(MessageKind::Emote, &from, text));
+            }
+            ServerMessage::Broadcast(text) => {
+                self.received_messages += 1;
+                self.add_message(MessageKind::Broadcast, text);
+            }
+            ServerMessage::ChannelRefused(text) => {
+                self.add_message(MessageKind::Warning, format!("⚠ {}", text));
+            }
+            ServerMessage::Info(text) | ServerMessage::Stats(text) => {
+                match self.queries.route(&text, Instant::now()) {
+                    Routed::Unclaimed => {}
+                    Routed::Consumed => return,
+                    Routed::Completed(result) => {
+                        self.add_query_result(result);
+                        return;
+                    }
+                }
+                if self.watch_acks_pending > 0 && watch_list::is_added_notice(&text) {
+                    self.watch_acks_pending -= 1;
+                    return;
+                }
+                if let Some((name, logon)) = watch_list::parse_notice(&text).filter(|(name, _)| self.is_watched(name)) {
+                    let name = name.to_string();
+                    self.alerts.play(Cue::Watch, &self.settings.sounds, Instant::now());
+                    self.push_message(self.said(MessageKind::Presence, &name, format!("{} {}", logon.icon(), text)));
+                    return;
+                }
+                if let Some(change) = status::parse_change(&text) {
+                    self.status.apply(change);
+                    self.away_prompt &= self.status.is_away();
+                }
+                self.note_removal(&text);
+                if let Some(topic) = topic_from_info(&self.current_channel, &text) {
+                    self.channel_topic = Some(topic.to_string());
+                }
+                self.add_message(MessageKind::Info, text);
+            }
+            ServerMessage::Error(text) => {
+                if self.note_removal(&text) {
+                    self.add_message(MessageKind::Error, text);
+                    return;
+                }
+                if let Some(reason) = delivery::whisper_failure(&text) {
+                    if let Some(target) = self.pending_whispers.take_oldest(Instant::now()) {
+                        self.push_client_error(format!("Could not deliver whisper to {}: {}", target, reason));
+                        return;
+                    }
+                }
+                if let Some(action) = self.mod_action.as_mut().filter(|action| action.error.is_none() && action.sent.elapsed() < DELIVERY_WINDOW) {
+                    action.error = Some(text.clone());
+                }
+                // Errors right after a typed line, such as a squelch or flood notice, answer it.
+                if let Some((row, _)) = self.last_typed.take().filter(|(_, at)| at.elapsed() < DELIVERY_WINDOW) {
+                    self.set_delivery(row, Delivery::Failed);
+                }
+                self.push_client_error(text);
+            }
+            ServerMessage::Channel { name } => {
+                // After a kick we are already in The Void by the time the server says so.
+                if !self.current_channel.is_empty() && !name.eq_ignore_ascii_case(&self.current_channel) {
+                    self.previous_channel = Some(std::mem::take(&mut self.current_channel));
+                }
+                self.users.clear();
+                self.join_order.clear();
+                self.activity.clear();
+                self.current_channel = name;
+                // Replays have no profile to remember a channel for.
+                if self.credentials.is_some() {
+                    self.last_channels.record(&self.server, &self.username, &self.current_channel);
+                }
+                self.unread.reset(self.messages.len());
+                self.add_message(MessageKind::System, format!("── Joined channel {} ──", self.current_channel));
+                self.channel_topic = None;
+                self.own_flags = UserFlags::default();
+                self.user_info.clear();
+                self.idle.clear();
+            }
+            ServerMessage::Unknown(line) => self.add_unknown_line(line),
+        }
+    }
+}
+
+/// Body of the emoji menu: a search box over a grid. Returns the emoji
+/// clicked, which also closes the menu.
+fn emoji_picker(ui: &mut egui::Ui, query: &mut String) -> Option<&'static str> {
+    const COLUMNS: usize = 8;
+    let mut picked = None;
+    ui.add(egui::TextEdit::singleline(query).hint_text("🔍 Search emoji").desired_width(COLUMNS as f32 * 28.0));
+    egui::ScrollArea::vertical().max_height(180.0).show(ui, |ui| {
+        egui::Grid::new("emoji_grid").spacing([2.0, 2.0]).show(ui, |ui| {
+            let mut shown = 0;
+            for emoji in emoji::search(query) {
+                let button = egui::Button::new(egui::RichText::new(emoji.character).size(18.0)).frame(false).min_size(egui::vec2(26.0, 26.0));
+                if ui.add(button).on_hover_text(emoji.name).clicked() {
+                    picked = Some(emoji.character);
+                }
+                shown += 1;
+                if shown % COLUMNS == 0 {
+                    ui.end_row();
+                }
+            }
+            if shown == 0 {
+                ui.weak("No matches");
+            }
+        });
+    });
+    if picked.is_some() {
+        query.clear();
+        ui.close_menu();
+    }
+    picked
+}
+
+/// Query field, match counter and next/previous buttons.
+fn search_bar(ui: &mut egui::Ui, search: &mut Search) {
+    ui.horizontal(|ui| {
+        ui.label("🔍");
+        let field = ui.add(egui::TextEdit::singleline(&mut search.query).hint_text("Search messages"));
+        if std::mem::take(&mut search.focus_pending) {
+            field.request_focus();
+        }
+        if field.changed() {
+            search.jump_to_last();
+        }
+        if field.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+            if ui.input().modifiers.shift {
+                search.previous();
+            } else {
+                search.next();
+            }
+            field.request_focus();
+        }
+        ui.weak(search.counter());
+        if ui.small_button("⏶").on_hover_text("Previous (Shift+Enter)").clicked() {
+            search.previous();
+        }
+        if ui.small_button("⏷").on_hover_text("Next (Enter)").clicked() {
+            search.next();
+        }
+    });
+}
+
+/// What [`apply_style`] last pushed into the egui style.
+type StyleKey = (Theme, Option<String>, bool, bool, bool, u32);
+
+fn style_key(settings: &Settings, width: f32) -> StyleKey {
+    (
+        settings.theme,
+        settings.custom_theme.clone(),
+        settings.high_contrast,
+        settings.compact,
+        settings.reduced_motion,
+        text_scale(settings, width).to_bits(),
+    )
+}
+
+/// Text size relative to egui's defaults: the user's scale times the
+/// window-width step.
+fn text_scale(settings: &Settings, width: f32) -> f32 {
+    settings.font_scale * prefs::responsive_text_factor(width)
+}
+
+/// Ctrl+= and Ctrl+- step the text size, Ctrl+0 puts it back. Returns
+/// whether it changed.
+fn font_scale_shortcuts(ctx: &egui::Context, settings: &mut Settings) -> bool {
+    let before = settings.font_scale;
+    let mut input = ctx.input_mut();
+    // Shift is optional: Ctrl++ on keyboards where + needs it.
+    if input.consume_key(egui::Modifiers::COMMAND, egui::Key::PlusEquals)
+        || input.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::PlusEquals)
+    {
+        settings.step_font_scale(1);
+    }
+    if input.consume_key(egui::Modifiers::COMMAND, egui::Key::Minus) {
+        settings.step_font_scale(-1);
+    }
+    if input.consume_key(egui::Modifiers::COMMAND, egui::Key::Num0) {
+        settings.font_scale = 1.0;
+    }
+    settings.font_scale != before
+}
+
+/// Pushes theme, font size, density and motion settings into the egui style,
+/// only when they change.
+fn apply_style(ctx: &egui::Context, settings: &Settings, applied: &mut Option<StyleKey>) {
+    let width = ctx.input().screen_rect().width();
+    let wanted = style_key(settings, width);
+    if applied.as_ref() == Some(&wanted) {
+        return;
+    }
+    let defaults = egui::Style::default();
+    let mut style = (*ctx.style()).clone();
+    let theme = theme::active(settings);
+    theme.apply_to_style(&mut style);
+    if settings.high_contrast {
+        // Outlined widgets in the text color, so buttons and fields stand out from the surface.
+        let widgets = &mut style.visuals.widgets;
+        for visuals in [&mut widgets.inactive, &mut widgets.hovered, &mut widgets.active, &mut widgets.open] {
+            visuals.fg_stroke.color = theme.text;
+            visuals.bg_stroke = egui::Stroke::new(1.0, theme.text);
+        }
+    }
+    // From egui's sizes each time, never from the current ones, so scales don't compound.
+    let scale = text_scale(settings, width);
+    for (text_style, font) in style.text_styles.iter_mut() {
+        if let Some(default) = defaults.text_styles.get(text_style) {
+            font.size = default.size * scale;
+        }
+    }
+    if settings.compact {
+        style.spacing.item_spacing = egui::vec2(4.0, 1.0);
+        style.spacing.button_padding = egui::vec2(3.0, 0.0);
+    } else {
+        style.spacing.item_spacing = defaults.spacing.item_spacing;
+        style.spacing.button_padding = defaults.spacing.button_padding;
+    }
+    // Zero animation time also makes the sidebar snap open and closed.
+    style.animation_time = if settings.reduced_motion { 0.0 } else { defaults.animation_time };
+    ctx.set_style(style);
+    *applied = Some(wanted);
+}
+
+/// How a row is drawn. Every message kind maps to one here.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RowKind {
+    /// Someone talking: chat, emotes and whispers, in both directions.
+    Chat,
+    /// The client's or the server's own notices: joins, info lines, status changes.
+    System,
+    Command,
+    Broadcast,
+    Warning,
+    Error,
+    Unknown,
+    /// Joins and leaves folded into one expandable row.
+    Fold,
+    /// A query result drawn as a card.
+    Card,
+    /// A watched user logging on or off.
+    Presence,
+}
+
+impl RowKind {
+    fn of(kind: MessageKind) -> RowKind {
+        match kind {
+            MessageKind::Chat | MessageKind::Emote | MessageKind::Whisper => RowKind::Chat,
+            MessageKind::System | MessageKind::Info | MessageKind::JoinLeave => RowKind::System,
+            MessageKind::Command => RowKind::Command,
+            MessageKind::Broadcast => RowKind::Broadcast,
+            MessageKind::Warning => RowKind::Warning,
+            MessageKind::Error => RowKind::Error,
+            MessageKind::Unknown => RowKind::Unknown,
+            MessageKind::JoinFold => RowKind::Fold,
+            MessageKind::Presence => RowKind::Presence,
+            MessageKind::Card => RowKind::Card,
+        }
+    }
+}
+
+/// For a chat line, the account whose color the name takes and the name as
+/// shown, which starts the row's text. Our own lines use our name.
+fn colored_sender<'a>(message: &'a ChatMessage, own_name: &'a str) -> Option<(&'a str, Cow<'a, str>)> {
+    if message.kind != MessageKind::Chat {
+        return None;
+    }
+    let account = if message.outgoing { own_name } else { message.sender.as_deref()? };
+    Some((account, message.name()?))
+}
+
+/// Window widths below which the status bar drops a field, least important
+/// first: server totals and ignores, then the received count, the session
+/// time and the last send. Server and channel always stay.
+const STATUS_TOTALS_WIDTH: f32 = 760.0;
+const STATUS_RECEIVED_WIDTH: f32 = 620.0;
+const STATUS_DURATION_WIDTH: f32 = 500.0;
+const STATUS_SEND_WIDTH: f32 = 400.0;
+
+/// Width of the name column in compact rows, in characters.
+const COMPACT_NAME_WIDTH: usize = 12;
+
+/// Name and text of a row for the compact layout's columns. Only chat rows
+/// have a name; the rest keep their whole text.
+fn compact_columns(kind: MessageKind, content: &str) -> (&str, &str) {
+    match kind {
+        MessageKind::Chat => content.split_once(": ").unwrap_or(("", content)),
+        MessageKind::Emote => ("*", content.strip_prefix("* ").unwrap_or(content)),
+        _ => ("", content),
+    }
+}
+
+/// A line waiting for the rate limit.
+#[derive(Debug)]
+enum Queued {
+    /// Commands from buttons, keepalives and the like, sent as they are.
+    Line(String),
+    /// Typed by the user and already echoed in the chat as `row`, which is
+    /// marked once the line goes out.
+    Typed { line: String, row: Option<u64> },
+}
+
+/// What was picked from a message row's context menu.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RowAction {
+    CopySelected,
+    Quote,
+    Reply,
+    Report,
+}
+
+/// Right-click menu of a message row. Copying happens right away; the rest
+/// needs `Main` and is handed back through `action`.
+fn row_menu(ui: &mut egui::Ui, index: usize, message: &ChatMessage, content: &str, selected: usize, action: &mut Option<(usize, RowAction)>) {
+    if ui.button("📋 Copy").clicked() {
+        ui.output().copied_text = content.to_string();
+        ui.close_menu();
+    }
+    if selected > 0 && ui.button(format!("📋 Copy selected ({})", selected)).clicked() {
+        *action = Some((index, RowAction::CopySelected));
+        ui.close_menu();
+    }
+    if ui.button("💬 Quote").clicked() {
+        *action = Some((index, RowAction::Quote));
+        ui.close_menu();
+    }
+    if message.other_sender().is_some() {
+        let reply = if message.kind == MessageKind::Whisper { "↩ Whisper back" } else { "↩ Reply" };
+        if ui.button(reply).clicked() {
+            *action = Some((index, RowAction::Reply));
+            ui.close_menu();
+        }
+        if ui.button("🚩 Report to ops…").clicked() {
+            *action = Some((index, RowAction::Report));
+            ui.close_menu();
+        }
+    }
+}
+
+/// Whether a left click landed on `rect` in this layer, and not on a menu
+/// or window above it.
+fn row_clicked(ui: &egui::Ui, rect: egui::Rect) -> bool {
+    let (pos, clicked) = {
+        let pointer = &ui.input().pointer;
+        (pointer.interact_pos(), pointer.primary_clicked())
+    };
+    let pos = match pos {
+        Some(pos) if clicked => pos,
+        _ => return false,
+    };
+    rect.contains(pos) && ui.clip_rect().contains(pos) && ui.ctx().layer_id_at(pos) == Some(ui.layer_id())
+}
+
+/// A message row: `lead` as laid out by the caller, then `body` split into
+/// rich-text segments, all flowing and wrapping as one paragraph.
+fn show_rich_text(
+    ui: &mut egui::Ui,
+    lead: egui::text::LayoutJob,
+    body: &str,
+    italics: bool,
+    color: Option<egui::Color32>,
+    emoticons: bool,
+    delivery: Option<Delivery>,
+) -> egui::Response {
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        let styled = |text: egui::RichText| {
+            let text = if italics { text.italics() } else { text };
+            match color {
+                Some(color) => text.color(color),
+                None => text,
+            }
+        };
+        let mut response = ui.add(Label::new(lead).sense(Sense::click()));
+        for segment in rich_text::tokenize(body, emoticons) {
+            let piece = match segment {
+                Segment::Plain(text) => ui.add(Label::new(styled(egui::RichText::new(text))).sense(Sense::click())),
+                Segment::Bold(text) => ui.add(Label::new(styled(egui::RichText::new(text).strong())).sense(Sense::click())),
+                Segment::Italic(text) => ui.add(Label::new(styled(egui::RichText::new(text)).italics()).sense(Sense::click())),
+                Segment::Emoticon(picture) => ui.add(Label::new(picture).sense(Sense::click())),
+                // The full address shows on hover.
+                Segment::Url(url) => ui.hyperlink_to(
+                    styled(egui::RichText::new(rich_text::elide(url, rich_text::URL_DISPLAY_CHARS))),
+                    rich_text::url_target(url),
+                ),
+            };
+            response = response.union(piece);
+        }
+        // Outside the clickable pieces, so it never lands in copied text.
+        if let Some(delivery) = delivery {
+            show_delivery(ui, delivery);
+        }
+        response
+    })
+    .inner
+}
+
+/// The mark after a row we typed, explained on hover.
+fn show_delivery(ui: &mut egui::Ui, delivery: Delivery) {
+    let color = match delivery {
+        Delivery::Failed => ui.visuals().error_fg_color,
+        Delivery::Pending | Delivery::Sent => ui.visuals().weak_text_color(),
+    };
+    ui.add_space(4.0);
+    ui.label(egui::RichText::new(delivery.glyph()).small().color(color)).on_hover_text(delivery.description());
+}
+
+/// A circle in the name's color with its initials, for telling accounts apart.
+fn avatar(ui: &mut egui::Ui, name: &str, size: f32) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(size, size), Sense::hover());
+    let painter = ui.painter();
+    painter.circle_filled(rect.center(), size / 2.0, colors::name_color(name, false));
+    painter.text(
+        rect.center(),
+        egui::Align2::CENTER_CENTER,
+        colors::initials(name),
+        egui::FontId::proportional(size * 0.45),
+        egui::Color32::WHITE,
+    );
+    response.on_hover_text(name)
+}
+
+/// Inline card for a structured command result. Returns a name the user
+/// clicked to whisper.
+fn show_card(ui: &mut egui::Ui, index: usize, card: &QueryResult) -> Option<String> {
+    let mut clicked = None;
+    egui::Frame::group(ui.style()).show(ui, |ui| match card {
+        QueryResult::Who { channel, users, .. } => {
+            ui.vertical(|ui| {
+                ui.strong(format!("👥 {} · {} users", channel, users.len()));
+                egui::Grid::new(("who_card", index)).show(ui, |ui| {
+                    for (position, name) in users.iter().enumerate() {
+                        if ui.link(name).on_hover_text("Whisper").clicked() {
+                            clicked = Some(name.clone());
+                        }
+                        if position % 4 == 3 {
+                            ui.end_row();
+                        }
+                    }
+                });
+            });
+        }
+        QueryResult::Time { .. } | QueryResult::Users { .. } => {
+            ui.label(format!("🕒 {}", card.summary()));
+        }
+        QueryResult::Channels { .. } => {
+            ui.label(format!("📋 {}", card.summary()));
+        }
+        QueryResult::Stats { user, fields } => {
+            ui.vertical(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label("📊");
+                    if ui.link(egui::RichText::new(user).strong()).on_hover_text("Whisper").clicked() {
+                        clicked = Some(user.clone());
+                    }
+                });
+                egui::Grid::new(("stats_card", index)).num_columns(2).striped(true).show(ui, |ui| {
+                    for (label, value) in fields {
+                        ui.weak(label);
+                        ui.label(value);
+                        ui.end_row();
+                    }
+                });
+            });
+        }
+        QueryResult::Friends { friends } => {
+            ui.vertical(|ui| {
+                let online = friends.iter().filter(|friend| friend.online).count();
+                ui.strong(format!("💖 Friends · {} of {} online", online, friends.len()));
+                for friend in friends {
+                    ui.horizontal(|ui| {
+                        if ui.link(&friend.name).on_hover_text("Whisper").clicked() {
+                            clicked = Some(friend.name.clone());
+                        }
+                        ui.weak(&friend.status);
+                    });
+                }
+            });
+        }
+    });
+    clicked
+}
+
+/// The channel name from `/join <channel>` or `/j <channel>`.
+fn join_command(msg: &str) -> Option<&str> {
+    let (command, name) = msg.split_once(' ')?;
+    match command {
+        "/join" | "/j" if !name.trim().is_empty() => Some(name.trim()),
+        _ => None,
+    }
+}
+
+/// Splits `/w name text` (and its aliases) into target and text. The name
+/// may be quoted: `/w "[Clan] Bob" hi`.
+fn whisper_command(msg: &str) -> Option<(&str, &str)> {
+    let (command, rest) = msg.split_once(' ')?;
+    match command {
+        "/w" | "/whisper" | "/m" | "/msg" => {}
+        _ => return None,
+    }
+    let (target, text) = protocol::split_name(rest)?;
+    Some((target, text)).filter(|_| !text.is_empty())
+}
+
+/// PvPGN announces the topic as an INFO line like `w3 topic: Be nice`.
+fn topic_from_info<'a>(channel: &str, text: &'a str) -> Option<&'a str> {
+    let (prefix, topic) = text.split_once(" topic: ")?;
+    if prefix.eq_ignore_ascii_case(channel) && !topic.is_empty() {
+        Some(topic)
+    } else {
+        None
+    }
+}
+
+/// A logged-in connection and the lines its read thread delivers.
+type Connection = (Connect, Receiver<NetEvent>);
+
+/// Where a background login hands over its connection or why it failed.
+type ConnectionResult = Receiver<Result<Connection, ConnectError>>;
+
+/// Connects, logs in, joins `join` and starts the read thread. If the join
+/// fails we stay in whatever channel the server put us in; the CHANNEL line
+/// that follows tells `Main` which one that is. Each phase goes to
+/// `progress`, when given, as it starts.
+fn open_connection(
+    cred: &Credentials,
+    join: &str,
+    register: bool,
+    timeouts: TimeoutConfig,
+    wake: Wake,
+    progress: Option<Sender<ConnectPhase>>,
+) -> Result<Connection, ConnectError> {
+    let progress = progress.map(|sender| Progress { sender, wake: wake.clone() });
+    if let Some(progress) = &progress {
+        progress.report(ConnectPhase::Connect);
+    }
+    let open = || connect::open_transport(&cred.server, timeouts.connect(), &cred.proxy, cred.tls, !cred.tls_insecure);
+    if register {
+        let mut connection = Connect::new(open()?, timeouts);
+        connection.report_to(progress.clone());
+        connection.create_account(&cred.user, &cred.password).map_err(ConnectError::Register)?;
+    }
+    let mut connection = Connect::new(open()?, timeouts);
+    connection.report_to(progress);
+    connection
+        .connect(&cred.user, &cred.password, join)
+        .map_err(|err| match err {
+            LoginError::Io(err) => ConnectError::Handshake(cred.server.clone(), err),
+            err => ConnectError::Login(err),
+        })?;
+    let req_rx = connection
+        .start_reading(wake)
+        .map_err(|err| ConnectError::Unreachable(cred.server.clone(), err))?;
+    Ok((connection, req_rx))
+}
+
+/// Runs `open_connection` on a worker thread so the UI keeps drawing.
+fn start_background_connection(
+    cred: Credentials,
+    join: String,
+    register: bool,
+    timeouts: TimeoutConfig,
+    progress: Option<Sender<ConnectPhase>>,
+    ctx: &egui::Context,
+) -> ConnectionResult {
+    let (result_tx, result_rx) = channel();
+    let wake = waker(ctx);
+    std::thread::spawn(move || {
+        let result = open_connection(&cred, &join, register, timeouts, wake.clone(), progress);
+        // Nobody is waiting any more (the login was cancelled): don't leave
+        // the session we just opened logged in.
+        if let Err(SendError(Ok((mut connection, response)))) = result_tx.send(result) {
+            log_out(&mut connection, &response);
+        }
+        wake();
+    });
+    result_rx
+}
+
+/// Repaints as soon as a background thread has news, so an idle window
+/// shows incoming chat without polling.
+fn waker(ctx: &egui::Context) -> Wake {
+    let ctx = ctx.clone();
+    Arc::new(move || ctx.request_repaint())
+}
+
+/// Says goodbye, gives the server `LOGOUT_GRACE` to close the connection,
+/// then closes the socket ourselves, which also ends the read thread.
+fn log_out(stream: &mut Connect, response: &Receiver<NetEvent>) {
+    if stream.quit() {
+        let deadline = Instant::now() + LOGOUT_GRACE;
+        // The read thread drops its sender once the server hangs up.
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            if response.recv_timeout(left).is_err() {
+                break;
+            }
+        }
+    }
+    let _ = stream.shutdown();
+}
+
+enum Connected {
+    Done(Credentials),
+    LoggedOut,
+    None,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+struct Credentials {
+    server: String,
+    user: String,
+    /// Only ever read, to migrate files written before passwords moved to the keyring.
+    #[serde(default, skip_serializing)]
+    password: String,
+    /// Keep the password in the OS keyring between sessions.
+    #[serde(default)]
+    remember: bool,
+    /// Channel joined after login.
+    #[serde(default = "default_channel")]
+    channel: String,
+    #[serde(default)]
+    proxy: Proxy,
+    /// Longest line, in bytes, before messages are split.
+    #[serde(default = "default_line_limit")]
+    line_limit: usize,
+    #[serde(default)]
+    tls: bool,
+    /// Accept self-signed certificates.
+    #[serde(default)]
+    tls_insecure: bool,
+}
+
+fn default_channel() -> String {
+    "w3".to_string()
+}
+
+fn default_line_limit() -> usize {
+    protocol::MAX_MESSAGE_LEN
+}
+
+impl Credentials {
+    /// Writes server and user to credentials.json in the config directory and puts the password in the
+    /// keyring, or removes it from there when "Remember me" is off.
+    fn save(&self) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        std::fs::write(paths::config_file(paths::CREDENTIALS), text).map_err(|err| err.to_string())?;
+        let proxy_key = format!("proxy:{}", self.proxy.address());
+        if self.remember {
+            if self.proxy.enabled() && !self.proxy.user.is_empty() {
+                keychain::save_password(&proxy_key, &self.proxy.user, &self.proxy.password)
+                    .map_err(|err| format!("Could not save the proxy password to the system keyring: {}", err))?;
+            }
+            keychain::save_password(&self.server, &self.user, &self.password)
+                .map_err(|err| format!("Could not save the password to the system keyring: {}", err))
+        } else {
+            keychain::forget_password(&proxy_key, &self.proxy.user);
+            keychain::forget_password(&self.server, &self.user);
+            Ok(())
+        }
+    }
+}
+
+impl eframe::App for MyApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.track_window(frame);
+        let settings = self.settings_mut();
+        if font_scale_shortcuts(ctx, settings) {
+            settings.save();
+        }
+        let settings = match &self.view {
+            View::Main(main) => &main.settings,
+            View::Login(_) => &self.settings,
+        };
+        apply_style(ctx, settings, &mut self.applied_style);
+        let credentials = match self.view {
+            View::Login(ref mut login) => match login.update(ctx) {
+                LoginAction::LogIn => Done(Credentials {
+                    server: login.server.to_string(),
+                    user: login.user.to_string(),
+                    password: login.password.to_string(),
+                    remember: login.remember,
+                    channel: login.channel.trim().to_string(),
+                    proxy: login.proxy.clone(),
+                    line_limit: login.line_limit,
+                    tls: login.tls,
+                    tls_insecure: login.tls_insecure,
+                }),
+                LoginAction::Cancel => {
+                    // The worker finishes on its own; its connection is dropped unused.
+                    self.connecting = None;
+                    login.connecting = false;
+                    login.phase = None;
+                    Connected::None
+                }
+                LoginAction::None => Connected::None,
+            },
+            View::Main(ref mut view) => {
+                view.update(ctx);
+                if view.back_to_login {
+                    Connected::LoggedOut
+                } else {
+                    Connected::None
+                }
+            }
+        };
+        if let Connected::LoggedOut = credentials {
+            if let View::Main(main) = &mut self.view {
+                main.log_out();
+                self.settings = main.settings.clone();
+            }
+            self.view = View::default();
+        }
+        if let Done(cred) = credentials {
+            let mut register = false;
+            let mut join = cred.channel.clone();
+            if let View::Login(login) = &mut self.view {
+                join = login.join_target().0;
+                login.clear_errors();
+                login.connecting = true;
+                login.phase = None;
+                register = login.create_account;
+                if self.settings.timeouts != login.timeouts || self.settings.auto_retry_login != login.auto_retry {
+                    self.settings.timeouts = login.timeouts;
+                    self.settings.auto_retry_login = login.auto_retry;
+                    self.settings.save();
+                }
+            }
+            let (progress, phases) = channel();
+            let result = start_background_connection(cred.clone(), join, register, self.settings.timeouts, Some(progress), ctx);
+            self.connecting = Some((cred, result, phases));
+        }
+        self.poll_login();
+        self.show_startup_issues(ctx);
+        self.show_close_confirmation(ctx, frame);
+    }
+
+    fn on_close_event(&mut self) -> bool {
+        let ask = match &self.view {
+            View::Main(main) => main.connected && main.settings.confirm_close,
+            View::Login(_) => false,
+        };
+        if ask && !self.close_allowed {
+            self.confirm_close = true;
+            return false;
+        }
+        true
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Some(window) = self.window {
+            let settings = self.settings_mut();
+            settings.window = Some(window);
+            settings.save();
+        }
+        // Dropping the logger flushes it before the process ends.
+        if let View::Main(main) = &mut self.view {
+            main.log_out();
+            main.chat_log = None;
+        }
+    }
+}
+
+impl View {
+    fn replay(path: &Path, speed: f64, settings: Settings, ctx: &egui::Context) -> std::io::Result<View> {
+        let records = capture::load(path)?;
+        let (req_tx, req_rx) = channel();
+        capture::spawn_replay(records, speed, req_tx, waker(ctx));
+        let server = format!("replay:{}", path.display());
+        Ok(View::Main(Box::new(Main::new(Connect::offline(), req_rx, server, "".to_string(), settings))))
+    }
+
+    fn make_main(&mut self, cred: Credentials, (connection, req_rx): Connection, settings: Settings) {
+        let saved = cred.save();
+        let mut view = Main::new(connection, req_rx, cred.server.clone(), cred.user.clone(), settings);
+        view.credentials = Some(cred);
+        view.refresh_friends();
+        view.restore_watches();
+        if let Err(err) = saved {
+            view.add_message(MessageKind::Error, err);
+        }
+        *self = View::Main(Box::new(view));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::sound::SoundSettings;
+
+    /// A channel view logged in as Me, fed by the returned sender instead of
+    /// a read thread. Sounds are off.
+    fn session() -> (Main, Sender<NetEvent>) {
+        let (sender, events) = channel();
+        let settings = Settings { sounds: SoundSettings { mute: true, ..SoundSettings::default() }, ..Settings::default() };
+        (Main::new(Connect::offline(), events, "test".into(), "Me".into(), settings), sender)
+    }
+
+    fn kinds_and_bodies(rows: &[ChatMessage]) -> Vec<(MessageKind, &str)> {
+        rows.iter().map(|row| (row.kind, row.body.as_str())).collect()
+    }
+
+    #[test]
+    fn a_server_error_makes_one_row_with_its_text() {
+        let (mut main, _sender) = session();
+        main.handle_event(NetEvent::Line("1019 ERROR \"That user is not logged on.\"".into()));
+        assert_eq!(kinds_and_bodies(&main.messages), [(MessageKind::Error, "That user is not logged on.")]);
+        // It doesn't count as losing the connection.
+        assert!(main.connected);
+    }
+
+    #[test]
+    fn a_burst_of_500_lines_drains_in_three_frames() {
+        let (mut main, sender) = session();
+        let ctx = egui::Context::default();
+        let mut lines = vec!["1007 CHANNEL \"Chat\"".to_string()];
+        lines.extend((0..300).map(|n| format!("1001 USER user{} 0010 [CHAT]", n)));
+        lines.extend((0..198).map(|n| format!("1005 TALK user{} 0010 \"line {}\"", n, n)));
+        lines.push("1002 JOIN Latecomer 0010 [W3XP]".to_string());
+        assert_eq!(lines.len(), 500);
+        for line in lines {
+            sender.send(NetEvent::Line(line)).unwrap();
+        }
+        for _ in 0..3 {
+            main.drain_events(&ctx);
+        }
+        assert!(main.response.try_recv().is_err(), "lines left after three frames");
+        assert_eq!(main.users.len(), 301);
+        assert!(main.users.contains("user0") && main.users.contains("user299") && main.users.contains("latecomer"));
+        assert_eq!(main.current_channel, "Chat");
+        assert!(main.connected);
+    }
+
+    #[test]
+    fn an_unknown_code_makes_one_row_under_its_code() {
+        let (mut main, _sender) = session();
+        main.handle_event(NetEvent::Line("1011 SOMETHING x".into()));
+        main.handle_event(NetEvent::Line("1012 OTHER y".into()));
+        assert_eq!(kinds_and_bodies(&main.messages), [(MessageKind::Unknown, "2 unrecognized server lines")]);
+    }
+
+    #[test]
+    fn server_notices_draw_as_system_rows() {
+        assert_eq!(RowKind::of(MessageKind::Info), RowKind::System);
+        assert_eq!(RowKind::of(MessageKind::JoinLeave), RowKind::System);
+        assert_eq!(RowKind::of(MessageKind::System), RowKind::System);
+        assert_eq!(RowKind::of(MessageKind::Emote), RowKind::Chat);
+        assert_eq!(RowKind::of(MessageKind::Whisper), RowKind::Chat);
+        assert_eq!(RowKind::of(MessageKind::Error), RowKind::Error);
+        assert_eq!(RowKind::of(MessageKind::Warning), RowKind::Warning);
+        assert_eq!(RowKind::of(MessageKind::JoinFold), RowKind::Fold);
+    }
+
+    #[test]
+    fn whisper_commands_read_quoted_targets() {
+        assert_eq!(whisper_command("/w Bob hi there"), Some(("Bob", "hi there")));
+        assert_eq!(whisper_command("/msg \"[Clan] Bob\" hi"), Some(("[Clan] Bob", "hi")));
+        assert_eq!(whisper_command("/whisper Bob#2 gg"), Some(("Bob#2", "gg")));
+        // Nothing to say, or not a whisper at all.
+        assert_eq!(whisper_command("/w Bob"), None);
+        assert_eq!(whisper_command("/w \"[Clan] Bob\""), None);
+        assert_eq!(whisper_command("/who Bob hi"), None);
+        // What the Whisper action prefills reads back as the same target.
+        let line = format!("{}hello", protocol::whisper_prefix("[Clan] Bob"));
+        assert_eq!(whisper_command(&line), Some(("[Clan] Bob", "hello")));
+    }
+
+    #[test]
+    fn every_kind_has_its_own_log_name() {
+        let kinds = [
+            MessageKind::Chat,
+            MessageKind::Emote,
+            MessageKind::Whisper,
+            MessageKind::Command,
+            MessageKind::System,
+            MessageKind::Info,
+            MessageKind::JoinLeave,
+            MessageKind::JoinFold,
+            MessageKind::Broadcast,
+            MessageKind::Warning,
+            MessageKind::Error,
+            MessageKind::Unknown,
+            MessageKind::Card,
+            MessageKind::Presence,
+        ];
+        let names: HashSet<_> = kinds.iter().map(MessageKind::as_str).collect();
+        assert_eq!(names.len(), kinds.len());
+        assert_eq!(MessageKind::Chat.as_str(), "user");
+        assert_eq!(MessageKind::Info.as_str(), "info");
+    }
+}
@@ -0,0 +1,329 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use crate::protocol;
+
+/// An error this soon after one of our lines is taken as the server's answer
+/// to it, such as "You are squelched".
+pub const DELIVERY_WINDOW: Duration = Duration::from_secs(2);
+
+/// How far a line we typed got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// Waiting for the rate limit.
+    Pending,
+    /// Written to the connection without an error coming back.
+    Sent,
+    /// The write failed or the server refused it.
+    Failed,
+}
+
+impl Delivery {
+    pub fn glyph(&self) -> &'static str {
+        match self {
+            Delivery::Pending => "⏳",
+            Delivery::Sent => "✓",
+            Delivery::Failed => "⚠",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Delivery::Pending => "Queued to stay under the server's flood limit",
+            Delivery::Sent => "Sent to the server",
+            Delivery::Failed => "Not delivered",
+        }
+    }
+}
+
+/// What a row is, which decides how it's drawn and filtered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    /// Channel chat, ours and others'.
+    Chat,
+    Emote,
+    /// Whispers in both directions.
+    Whisper,
+    /// A command we typed, echoed as it went out.
+    Command,
+    /// The client's own notices.
+    System,
+    /// The server's info lines.
+    Info,
+    JoinLeave,
+    /// Joins and leaves folded into one expandable row.
+    JoinFold,
+    Broadcast,
+    Warning,
+    Error,
+    /// Server lines we have no handling for.
+    Unknown,
+    /// A query result drawn as a card.
+    Card,
+    /// A watched user logging on or off; the sender is who.
+    Presence,
+}
+
+impl MessageKind {
+    /// The name used in chat logs and exports.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageKind::Chat => "user",
+            MessageKind::Emote => "emote",
+            MessageKind::Whisper => "whisper",
+            MessageKind::Command => "command",
+            MessageKind::System => "system",
+            MessageKind::Info => "info",
+            MessageKind::JoinLeave => "join_leave",
+            MessageKind::JoinFold => "join_fold",
+            MessageKind::Broadcast => "broadcast",
+            MessageKind::Warning => "warning",
+            MessageKind::Error => "error",
+            MessageKind::Unknown => "unknown",
+            MessageKind::Card => "card",
+            MessageKind::Presence => "presence",
+        }
+    }
+}
+
+/// One row of the message list.
+#[derive(Clone, Debug)]
+pub struct ChatMessage {
+    /// Counts up over the session, so a row can be found again after older
+    /// ones were trimmed.
+    pub id: u64,
+    pub kind: MessageKind,
+    /// The other user on chat, emote and whisper rows: who spoke, or who a
+    /// whisper we sent went to. `None` on our own channel lines.
+    pub sender: Option<String>,
+    /// The sender's nickname when the row was added.
+    pub nickname: Option<String>,
+    /// What was said, or the whole text of rows without a sender.
+    pub body: String,
+    /// Unix seconds.
+    pub timestamp: u64,
+    /// Said by us.
+    pub outgoing: bool,
+    /// A whisper that was end-to-end encrypted.
+    pub encrypted: bool,
+    /// Only set on rows echoing what we typed.
+    pub delivery: Option<Delivery>,
+}
+
+impl ChatMessage {
+    /// A row without a sender; id and timestamp are filled in when it's added.
+    pub fn new(kind: MessageKind, body: String) -> ChatMessage {
+        ChatMessage {
+            id: 0,
+            kind,
+            sender: None,
+            nickname: None,
+            body,
+            timestamp: 0,
+            outgoing: false,
+            encrypted: false,
+            delivery: None,
+        }
+    }
+
+    /// A row said by or to `sender`, shown with their nickname if they have one.
+    pub fn said(kind: MessageKind, sender: &str, nickname: Option<&str>, body: String) -> ChatMessage {
+        ChatMessage {
+            sender: Some(sender.to_string()),
+            nickname: nickname.map(str::to_string),
+            ..ChatMessage::new(kind, body)
+        }
+    }
+
+    /// The sender as shown: `Account (Nickname)`, or `You` for our own chat.
+    pub fn name(&self) -> Option<Cow<'_, str>> {
+        if self.outgoing && self.kind == MessageKind::Chat {
+            return Some(Cow::Borrowed("You"));
+        }
+        let sender = self.sender.as_deref()?;
+        Some(match &self.nickname {
+            Some(nickname) => Cow::Owned(format!("{} ({})", sender, nickname)),
+            None => Cow::Borrowed(sender),
+        })
+    }
+
+    /// The row as displayed, copied, searched and exported. Rows without a
+    /// name, like our own report notes, are just their body.
+    pub fn text(&self) -> Cow<'_, str> {
+        let lock = if self.encrypted { "🔒 " } else { "" };
+        let text = match (self.kind, self.name()) {
+            (MessageKind::Chat, Some(name)) => format!("{}: {}", name, self.body),
+            (MessageKind::Emote, Some(name)) => format!("* {} {}", name, self.body),
+            (MessageKind::Whisper, Some(name)) if self.outgoing => format!("{}To {}: {}", lock, name, self.body),
+            (MessageKind::Whisper, Some(name)) => format!("{}{} whispers: {}", lock, name, self.body),
+            (MessageKind::Broadcast, _) => format!("📢 {}", self.body),
+            (MessageKind::Info, _) => format!("INFO: {}", self.body),
+            _ => return Cow::Borrowed(&self.body),
+        };
+        Cow::Owned(text)
+    }
+
+    /// Who a chat, emote or whisper row came from, when it wasn't us.
+    pub fn other_sender(&self) -> Option<&str> {
+        self.sender.as_deref().filter(|_| self.is_spoken() && !self.outgoing)
+    }
+
+    /// Who said the row: `own_name` for ours, the sender for others'. Notices
+    /// have no author.
+    pub fn author<'a>(&'a self, own_name: &'a str) -> Option<&'a str> {
+        match self.is_spoken() {
+            true if self.outgoing => Some(own_name),
+            true => self.sender.as_deref(),
+            false => None,
+        }
+    }
+
+    /// What Quote puts in the input: `Quoting Bob at 12:04: "text" `, or just
+    /// the quoted row when nobody said it. `time` is the row's time of day.
+    pub fn quote(&self, own_name: &str, time: Option<&str>) -> String {
+        match (self.author(own_name), time) {
+            (Some(author), Some(time)) => format!("Quoting {} at {}: \"{}\" ", author, time, self.body),
+            (Some(author), None) => format!("Quoting {}: \"{}\" ", author, self.body),
+            (None, _) => format!("\"{}\" ", self.text()),
+        }
+    }
+
+    /// What Reply puts in the input: a whisper back for whispers, `@Bob `
+    /// for channel rows. Nothing for our own rows or notices.
+    pub fn reply_prefix(&self) -> Option<String> {
+        let sender = self.other_sender()?;
+        Some(match self.kind {
+            MessageKind::Whisper => protocol::whisper_prefix(sender),
+            _ => format!("@{} ", protocol::quote_name(sender)),
+        })
+    }
+
+    /// The row as one line of a copied selection: `[12:04] Bob: text`, or
+    /// `[12:04] text` when nobody said it.
+    pub fn transcript_line(&self, own_name: &str, time: Option<&str>) -> String {
+        let stamp = time.map(|time| format!("[{}] ", time)).unwrap_or_default();
+        match self.author(own_name) {
+            Some(author) => format!("{}{}: {}", stamp, author, self.body),
+            None => format!("{}{}", stamp, self.text()),
+        }
+    }
+
+    fn is_spoken(&self) -> bool {
+        matches!(self.kind, MessageKind::Chat | MessageKind::Emote | MessageKind::Whisper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn said(kind: MessageKind, sender: &str, body: &str) -> ChatMessage {
+        ChatMessage::said(kind, sender, None, body.to_string())
+    }
+
+    fn notice(kind: MessageKind, body: &str) -> ChatMessage {
+        ChatMessage::new(kind, body.to_string())
+    }
+
+    #[test]
+    fn rows_render_as_before_the_refactor() {
+        let rows = [
+            said(MessageKind::Chat, "Bob", "hi all"),
+            ChatMessage::said(MessageKind::Chat, "bob2", Some("Bobby"), "me too".into()),
+            ChatMessage { outgoing: true, ..notice(MessageKind::Chat, "hello") },
+            said(MessageKind::Emote, "Bob", "waves"),
+            said(MessageKind::Whisper, "[Clan] Bob", "psst"),
+            ChatMessage { encrypted: true, ..said(MessageKind::Whisper, "Bob", "secret") },
+            ChatMessage { outgoing: true, encrypted: true, ..said(MessageKind::Whisper, "Bob", "secret") },
+            notice(MessageKind::Broadcast, "Restart in 5 minutes"),
+            notice(MessageKind::Info, "Welcome to PvPGN!"),
+            notice(MessageKind::System, "── Joined channel Chat ──"),
+            notice(MessageKind::JoinLeave, "Bob joined the channel"),
+            notice(MessageKind::Warning, "Channel Clan Y is restricted"),
+            notice(MessageKind::Error, "That user is not logged on."),
+            notice(MessageKind::Unknown, "Unrecognized 1011: SOMETHING x"),
+            ChatMessage { outgoing: true, ..notice(MessageKind::Command, "/whois Bob") },
+            notice(MessageKind::Presence, "Bob logged on"),
+        ];
+        let rendered: Vec<_> = rows.iter().map(|row| row.text()).collect();
+        assert_eq!(
+            rendered,
+            [
+                "Bob: hi all",
+                "bob2 (Bobby): me too",
+                "You: hello",
+                "* Bob waves",
+                "[Clan] Bob whispers: psst",
+                "🔒 Bob whispers: secret",
+                "🔒 To Bob: secret",
+                "📢 Restart in 5 minutes",
+                "INFO: Welcome to PvPGN!",
+                "── Joined channel Chat ──",
+                "Bob joined the channel",
+                "Channel Clan Y is restricted",
+                "That user is not logged on.",
+                "Unrecognized 1011: SOMETHING x",
+                "/whois Bob",
+                "Bob logged on",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_spoken_rows_have_an_author() {
+        let own = ChatMessage { outgoing: true, ..notice(MessageKind::Chat, "hello") };
+        assert_eq!(own.author("Me"), Some("Me"));
+        assert_eq!(own.other_sender(), None);
+        let theirs = said(MessageKind::Emote, "Bob", "waves");
+        assert_eq!(theirs.author("Me"), Some("Bob"));
+        assert_eq!(theirs.other_sender(), Some("Bob"));
+        // Presence rows carry who logged on, but nobody said them.
+        let presence = ChatMessage { sender: Some("Bob".into()), ..notice(MessageKind::Presence, "Bob logged on") };
+        assert_eq!(presence.author("Me"), None);
+        assert_eq!(presence.other_sender(), None);
+    }
+
+    #[test]
+    fn quotes_take_the_sender_from_the_row() {
+        let colons = said(MessageKind::Chat, "Bob", "look: this [x]: y");
+        assert_eq!(colons.quote("Me", Some("12:04")), "Quoting Bob at 12:04: \"look: this [x]: y\" ");
+        let whisper = said(MessageKind::Whisper, "[Clan] Bob", "hi: there");
+        assert_eq!(whisper.quote("Me", None), "Quoting [Clan] Bob: \"hi: there\" ");
+        let own = ChatMessage { outgoing: true, ..notice(MessageKind::Chat, "a: b") };
+        assert_eq!(own.quote("Me", Some("08:00")), "Quoting Me at 08:00: \"a: b\" ");
+    }
+
+    #[test]
+    fn quoting_a_notice_invents_no_author() {
+        let error = notice(MessageKind::Error, "Bob: no such user");
+        assert_eq!(error.quote("Me", Some("12:04")), "\"Bob: no such user\" ");
+        assert_eq!(notice(MessageKind::Info, "a: b").quote("Me", None), "\"INFO: a: b\" ");
+    }
+
+    #[test]
+    fn replies_address_the_sender() {
+        assert_eq!(said(MessageKind::Chat, "Bob", "x: y").reply_prefix().as_deref(), Some("@Bob "));
+        assert_eq!(said(MessageKind::Emote, "[Clan] Bob", "waves").reply_prefix().as_deref(), Some("@\"[Clan] Bob\" "));
+        assert_eq!(said(MessageKind::Whisper, "Bob", "hi").reply_prefix().as_deref(), Some("/w Bob "));
+        let own = ChatMessage { outgoing: true, ..said(MessageKind::Whisper, "Bob", "hi") };
+        assert_eq!(own.reply_prefix(), None);
+        assert_eq!(notice(MessageKind::System, "Bob: hi").reply_prefix(), None);
+    }
+
+    #[test]
+    fn transcript_lines_name_the_author() {
+        let theirs = said(MessageKind::Chat, "Bob", "hi");
+        assert_eq!(theirs.transcript_line("Me", Some("12:04")), "[12:04] Bob: hi");
+        let own = ChatMessage { outgoing: true, ..notice(MessageKind::Chat, "hello") };
+        assert_eq!(own.transcript_line("Me", None), "Me: hello");
+        assert_eq!(notice(MessageKind::Info, "Welcome").transcript_line("Me", Some("09:00")), "[09:00] INFO: Welcome");
+    }
+
+    #[test]
+    fn echoed_whispers_name_the_recipient() {
+        let sent = ChatMessage { outgoing: true, ..ChatMessage::said(MessageKind::Whisper, "Bob", None, "on my way".into()) };
+        assert_eq!(sent.text(), "To Bob: on my way");
+        let received = ChatMessage::said(MessageKind::Whisper, "Bob", None, "thanks".into());
+        assert_eq!(received.text(), "Bob whispers: thanks");
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Entry {
+    /// Account name with its original casing.
+    account: String,
+    label: String,
+}
+
+/// Friendly labels for account names, kept per server. Labels are local only:
+/// anything that goes over the wire uses the real account name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AddressBook {
+    servers: HashMap<String, HashMap<String, Entry>>,
+}
+
+impl AddressBook {
+    pub fn load() -> AddressBook {
+        std::fs::read_to_string(paths::config_file(paths::NICKNAMES))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)
+            .and_then(|text| std::fs::write(paths::config_file(paths::NICKNAMES), text));
+        if let Err(err) = result {
+            println!("Could not save nicknames: {}", err);
+        }
+    }
+
+    pub fn label(&self, server: &str, account: &str) -> Option<&str> {
+        self.servers
+            .get(&server.to_lowercase())?
+            .get(&account.to_lowercase())
+            .map(|entry| entry.label.as_str())
+    }
+
+    /// `Account (Label)` when a label is set, otherwise just the account.
+    pub fn display(&self, server: &str, account: &str) -> String {
+        match self.label(server, account) {
+            Some(label) => format!("{} ({})", account, label),
+            None => account.to_string(),
+        }
+    }
+
+    /// Sets or (with an empty label) clears a label. Returns a warning when the
+    /// label reads like one of `known_accounts`, since that invites whispering
+    /// the wrong person.
+    pub fn set(&mut self, server: &str, account: &str, label: &str, known_accounts: &[String]) -> Option<String> {
+        let warning = self.update(server, account, label, known_accounts);
+        self.save();
+        warning
+    }
+
+    /// `set` without writing the file.
+    fn update(&mut self, server: &str, account: &str, label: &str, known_accounts: &[String]) -> Option<String> {
+        let label = label.trim();
+        let entries = self.servers.entry(server.to_lowercase()).or_default();
+        if label.is_empty() {
+            entries.remove(&account.to_lowercase());
+            return None;
+        }
+        entries.insert(
+            account.to_lowercase(),
+            Entry { account: account.to_string(), label: label.to_string() },
+        );
+        let book_accounts: Vec<String> = entries.values().map(|entry| entry.account.clone()).collect();
+
+        let clashes_with_account = known_accounts
+            .iter()
+            .chain(book_accounts.iter())
+            .any(|known| known.eq_ignore_ascii_case(label) && !known.eq_ignore_ascii_case(account));
+        if clashes_with_account {
+            Some(format!(
+                "⚠ The nickname \"{}\" is also a real account name. /w {} will still go to {}.",
+                label, label, account
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Account whose label is exactly `label` (ignoring case).
+    pub fn resolve_label(&self, server: &str, label: &str) -> Option<&str> {
+        self.servers
+            .get(&server.to_lowercase())?
+            .values()
+            .find(|entry| entry.label.eq_ignore_ascii_case(label))
+            .map(|entry| entry.account.as_str())
+    }
+
+    /// Accounts whose label or name starts with `prefix`, for `/w` completion.
+    pub fn complete(&self, server: &str, prefix: &str, channel_users: &[String]) -> Vec<String> {
+        let prefix = prefix.to_lowercase();
+        let mut matches: Vec<String> = vec![];
+        if let Some(entries) = self.servers.get(&server.to_lowercase()) {
+            for entry in entries.values() {
+                if entry.label.to_lowercase().starts_with(&prefix)
+                    || entry.account.to_lowercase().starts_with(&prefix)
+                {
+                    matches.push(entry.account.clone());
+                }
+            }
+        }
+        for user in channel_users {
+            if user.to_lowercase().starts_with(&prefix) && !matches.iter().any(|m| m.eq_ignore_ascii_case(user)) {
+                matches.push(user.clone());
+            }
+        }
+        matches.sort_by_key(|account| account.to_lowercase());
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> AddressBook {
+        let mut book = AddressBook::default();
+        book.update("Realm.Example", "xXSniperXx", "Dave", &[]);
+        book
+    }
+
+    #[test]
+    fn labels_are_per_server_and_ignore_case() {
+        let book = book();
+        assert_eq!(book.label("realm.example", "XXSNIPERXX"), Some("Dave"));
+        assert_eq!(book.label("other.example", "xXSniperXx"), None);
+        assert_eq!(book.display("realm.example", "xXSniperXx"), "xXSniperXx (Dave)");
+        assert_eq!(book.display("realm.example", "Bob"), "Bob");
+    }
+
+    #[test]
+    fn labels_resolve_to_the_account_with_its_casing() {
+        let book = book();
+        assert_eq!(book.resolve_label("realm.example", "dave"), Some("xXSniperXx"));
+        assert_eq!(book.resolve_label("realm.example", "Dav"), None);
+        assert_eq!(book.resolve_label("other.example", "Dave"), None);
+    }
+
+    #[test]
+    fn an_empty_label_clears_it() {
+        let mut book = book();
+        assert_eq!(book.update("realm.example", "xxsniperxx", "  ", &[]), None);
+        assert_eq!(book.label("realm.example", "xXSniperXx"), None);
+        assert_eq!(book.resolve_label("realm.example", "Dave"), None);
+    }
+
+    #[test]
+    fn labels_that_are_real_accounts_warn() {
+        let mut book = book();
+        let warning = book.update("realm.example", "Bob", "Alice", &["alice".to_string()]);
+        assert!(warning.unwrap().contains("/w Alice will still go to Bob"));
+        // Another account already in the book counts too.
+        assert!(book.update("realm.example", "Carol", "xxsniperxx", &[]).is_some());
+        // Labelling an account with its own name is harmless.
+        assert_eq!(book.update("realm.example", "Bob", "BOB", &["Bob".to_string()]), None);
+    }
+
+    #[test]
+    fn completion_matches_labels_and_channel_users_once() {
+        let book = book();
+        let users = vec!["david".to_string(), "XXSNIPERXX".to_string(), "Eve".to_string()];
+        assert_eq!(book.complete("realm.example", "da", &users), vec!["david", "xXSniperXx"]);
+        assert_eq!(book.complete("realm.example", "xx", &users), vec!["xXSniperXx"]);
+        assert_eq!(book.complete("other.example", "da", &users), vec!["david"]);
+    }
+}
@@ -0,0 +1,353 @@
+use std::time::Instant;
+
+use crate::lists::ListKind;
+use crate::protocol;
+
+/// The flags field on USER/JOIN lines, a hex bitmask such as `0012`
+/// (channel operator, plus a bit we have no use for).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UserFlags(u32);
+
+impl UserFlags {
+    pub const BLIZZARD_REP: UserFlags = UserFlags(0x01);
+    pub const CHANNEL_OP: UserFlags = UserFlags(0x02);
+    pub const SPEAKER: UserFlags = UserFlags(0x04);
+    pub const SERVER_ADMIN: UserFlags = UserFlags(0x08);
+    pub const SQUELCHED: UserFlags = UserFlags(0x20);
+
+    /// `None` when the field is not hex.
+    pub fn parse(text: &str) -> Option<UserFlags> {
+        u32::from_str_radix(text, 16).ok().map(UserFlags)
+    }
+
+    pub fn contains(self, other: UserFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn role(self) -> Role {
+        if self.contains(UserFlags::SERVER_ADMIN) || self.contains(UserFlags::BLIZZARD_REP) {
+            Role::Admin
+        } else if self.contains(UserFlags::CHANNEL_OP) {
+            Role::Moderator
+        } else {
+            Role::User
+        }
+    }
+
+    pub fn is_speaker(self) -> bool {
+        self.contains(UserFlags::SPEAKER)
+    }
+
+    pub fn is_squelched(self) -> bool {
+        self.contains(UserFlags::SQUELCHED)
+    }
+}
+
+/// Standing in the channel, highest first so it doubles as a sort key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Server admin or Blizzard representative.
+    Admin,
+    /// Channel operator.
+    Moderator,
+    User,
+}
+
+/// The notice for someone whose role went from `before` to `after`, when
+/// that made or unmade a channel operator. Only someone we already knew
+/// (`before` set) can change role; the USER lines listing a channel on
+/// entry are not news.
+pub fn op_change_notice(name: &str, before: Option<Role>, after: Role) -> Option<String> {
+    match (before?, after) {
+        (before, Role::Moderator) if before != Role::Moderator => Some(format!("{} is now a channel operator", name)),
+        (Role::Moderator, Role::User) => Some(format!("{} is no longer a channel operator", name)),
+        _ => None,
+    }
+}
+
+/// What our own flags let us do in the current channel. Every op-only
+/// control checks this one function, so gaining or losing op changes all of
+/// them on the same frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub edit_topic: bool,
+    pub kick: bool,
+    pub ban: bool,
+}
+
+impl Capabilities {
+    pub fn is_op(&self) -> bool {
+        self.kick
+    }
+}
+
+pub fn capabilities(own_flags: UserFlags) -> Capabilities {
+    let op = own_flags.role() != Role::User;
+    Capabilities {
+        edit_topic: op,
+        kick: op,
+        ban: op,
+    }
+}
+
+/// What the Moderation section of the user menu can do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModCommand {
+    Kick,
+    Ban,
+    Unban,
+    /// Hands operator status to them when we leave.
+    Designate,
+}
+
+impl ModCommand {
+    pub const ALL: [ModCommand; 4] = [ModCommand::Kick, ModCommand::Ban, ModCommand::Unban, ModCommand::Designate];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ModCommand::Kick => "👢 Kick",
+            ModCommand::Ban => "🔨 Ban",
+            ModCommand::Unban => "Unban",
+            ModCommand::Designate => "Designate as next op",
+        }
+    }
+
+    /// Only kicks and bans carry a reason; the server ignores one elsewhere.
+    pub fn takes_reason(&self) -> bool {
+        matches!(self, ModCommand::Kick | ModCommand::Ban)
+    }
+
+    pub fn command(&self, user: &str, reason: &str) -> String {
+        let verb = match self {
+            ModCommand::Kick => "/kick",
+            ModCommand::Ban => "/ban",
+            ModCommand::Unban => "/unban",
+            ModCommand::Designate => "/designate",
+        };
+        let user = protocol::quote_name(user);
+        let reason = reason.trim();
+        if self.takes_reason() && !reason.is_empty() {
+            format!("{} {} {}", verb, user, reason)
+        } else {
+            format!("{} {}", verb, user)
+        }
+    }
+}
+
+/// The last moderation command sent from the user menu, shown there with
+/// the server's answer.
+#[derive(Clone, Debug)]
+pub struct ModAction {
+    pub command: ModCommand,
+    pub target: String,
+    pub sent: Instant,
+    /// An error that came back, or why it could not be sent.
+    pub error: Option<String>,
+}
+
+/// Where the server parks users it kicks or bans from a channel.
+pub const VOID_CHANNEL: &str = "The Void";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RemovalKind {
+    Kicked,
+    Banned,
+}
+
+/// An operator put us out of `channel`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Removal {
+    pub kind: RemovalKind,
+    pub by: String,
+    pub reason: Option<String>,
+    pub channel: String,
+}
+
+impl Removal {
+    /// A kick only lasts until we come back; a ban keeps us out.
+    pub fn can_rejoin(&self) -> bool {
+        self.kind == RemovalKind::Kicked
+    }
+
+    pub fn summary(&self) -> String {
+        let verb = match self.kind {
+            RemovalKind::Kicked => "kicked",
+            RemovalKind::Banned => "banned",
+        };
+        format!("You were {} from {} by {}", verb, self.channel, self.by)
+    }
+}
+
+/// Reads the notice the server sends before moving us out of the channel,
+/// `Me has been kicked by Op (reason).`, when it is about `own_name`.
+pub fn parse_removal(text: &str, own_name: &str, channel: &str) -> Option<Removal> {
+    let (name, rest) = text.trim_end_matches('.').split_once(" has been ")?;
+    if !name.eq_ignore_ascii_case(own_name) {
+        return None;
+    }
+    let (kind, rest) = if let Some(rest) = rest.strip_prefix("kicked by ") {
+        (RemovalKind::Kicked, rest)
+    } else {
+        (RemovalKind::Banned, rest.strip_prefix("banned by ")?)
+    };
+    let (by, reason) = match rest.split_once(" (") {
+        Some((by, reason)) => (by, Some(reason.trim_end_matches(')').to_string()).filter(|reason| !reason.is_empty())),
+        None => (rest, None),
+    };
+    Some(Removal { kind, by: by.trim().to_string(), reason, channel: channel.to_string() })
+}
+
+/// An action that waits for the user to confirm it in a small dialog.
+#[derive(Clone, Debug)]
+pub struct Confirmation {
+    pub prompt: String,
+    pub confirm_label: String,
+    /// Line sent to the server once confirmed.
+    pub command: Option<String>,
+    /// Name added to one of our local lists once confirmed.
+    pub list_add: Option<(ListKind, String)>,
+    /// Empty the local message list once confirmed.
+    pub clear_messages: bool,
+    /// For ignores: whether to squelch on the server too. The dialog offers
+    /// the choice when this is set.
+    pub squelch: Option<bool>,
+}
+
+impl Confirmation {
+    pub fn kick(user: &str) -> Confirmation {
+        Confirmation {
+            prompt: format!("Kick {} from the channel?", user),
+            confirm_label: "Kick".to_string(),
+            command: Some(format!("/kick {}", user)),
+            list_add: None,
+            clear_messages: false,
+            squelch: None,
+        }
+    }
+
+    pub fn ban(user: &str) -> Confirmation {
+        Confirmation {
+            prompt: format!("Ban {} from the channel?", user),
+            confirm_label: "Ban".to_string(),
+            command: Some(format!("/ban {}", user)),
+            list_add: None,
+            clear_messages: false,
+            squelch: None,
+        }
+    }
+
+    /// The squelch command is sent on confirmation if `squelch` is still
+    /// chosen then.
+    pub fn ignore(user: &str, squelch: bool) -> Confirmation {
+        Confirmation {
+            prompt: format!("Ignore {}? Their messages and whispers will be hidden.", user),
+            confirm_label: "Ignore".to_string(),
+            command: None,
+            list_add: Some((ListKind::Ignore, user.to_string())),
+            clear_messages: false,
+            squelch: Some(squelch),
+        }
+    }
+
+    pub fn clear_messages() -> Confirmation {
+        Confirmation {
+            prompt: "Clear all messages from the list? This only affects this window.".to_string(),
+            confirm_label: "Clear".to_string(),
+            command: None,
+            list_add: None,
+            clear_messages: true,
+            squelch: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(text: &str) -> UserFlags {
+        UserFlags::parse(text).unwrap()
+    }
+
+    #[test]
+    fn flags_parse_as_hex() {
+        assert_eq!(UserFlags::parse("0012"), Some(UserFlags(0x12)));
+        assert_eq!(UserFlags::parse("0000"), Some(UserFlags::default()));
+        assert_eq!(UserFlags::parse("zz"), None);
+        assert_eq!(UserFlags::parse(""), None);
+    }
+
+    #[test]
+    fn roles_from_flags() {
+        assert_eq!(flags("0000").role(), Role::User);
+        assert_eq!(flags("0002").role(), Role::Moderator);
+        assert_eq!(flags("0012").role(), Role::Moderator);
+        assert_eq!(flags("0008").role(), Role::Admin);
+        assert_eq!(flags("0001").role(), Role::Admin);
+        assert!(flags("0004").is_speaker());
+        assert!(flags("0020").is_squelched());
+        assert!(!flags("0002").is_squelched());
+        assert!(Role::Admin < Role::Moderator && Role::Moderator < Role::User);
+    }
+
+    #[test]
+    fn several_bits_at_once() {
+        let bits = flags("0026");
+        assert!(bits.contains(UserFlags::CHANNEL_OP));
+        assert!(bits.is_speaker());
+        assert!(bits.is_squelched());
+        assert!(!bits.contains(UserFlags::SERVER_ADMIN));
+        assert_eq!(bits.role(), Role::Moderator);
+        // Admin outranks op when both bits are set.
+        assert_eq!(flags("000A").role(), Role::Admin);
+        let mut roster = [("Carol", flags("0000")), ("Bob", flags("0002")), ("Ann", flags("0008"))];
+        roster.sort_by_key(|(_, flags)| flags.role());
+        assert_eq!(roster.iter().map(|(name, _)| *name).collect::<Vec<_>>(), vec!["Ann", "Bob", "Carol"]);
+    }
+
+    #[test]
+    fn op_changes_are_announced_for_people_we_knew() {
+        assert_eq!(op_change_notice("Bob", Some(Role::User), Role::Moderator).as_deref(), Some("Bob is now a channel operator"));
+        assert_eq!(op_change_notice("Bob", Some(Role::Admin), Role::Moderator).as_deref(), Some("Bob is now a channel operator"));
+        assert_eq!(op_change_notice("Bob", Some(Role::Moderator), Role::User).as_deref(), Some("Bob is no longer a channel operator"));
+        // Listed on entry, or no change that concerns op.
+        assert_eq!(op_change_notice("Bob", None, Role::Moderator), None);
+        assert_eq!(op_change_notice("Bob", Some(Role::Moderator), Role::Moderator), None);
+        assert_eq!(op_change_notice("Bob", Some(Role::User), Role::Admin), None);
+    }
+
+    #[test]
+    fn op_controls_follow_our_flags() {
+        let none = Capabilities::default();
+        let all = Capabilities { edit_topic: true, kick: true, ban: true };
+        assert_eq!(capabilities(flags("0010")), none);
+        // Gaining op turns every control on at once, losing it turns them off.
+        assert_eq!(capabilities(flags("0012")), all);
+        assert!(capabilities(flags("0012")).is_op());
+        assert_eq!(capabilities(flags("0010")), none);
+        assert_eq!(capabilities(flags("0008")), all);
+    }
+
+    #[test]
+    fn mod_commands_quote_names_and_keep_reasons_where_they_count() {
+        assert_eq!(ModCommand::Kick.command("Bob", " spam "), "/kick Bob spam");
+        assert_eq!(ModCommand::Ban.command("Bob Smith", ""), "/ban \"Bob Smith\"");
+        assert_eq!(ModCommand::Unban.command("Bob", "sorry"), "/unban Bob");
+        assert_eq!(ModCommand::Designate.command("Bob", "next"), "/designate Bob");
+    }
+
+    #[test]
+    fn removal_notices_about_us() {
+        assert_eq!(
+            parse_removal("Me has been kicked by Op (spam).", "me", "w3"),
+            Some(Removal { kind: RemovalKind::Kicked, by: "Op".into(), reason: Some("spam".into()), channel: "w3".into() })
+        );
+        let ban = parse_removal("Me has been banned by Op.", "Me", "w3").unwrap();
+        assert_eq!(ban.kind, RemovalKind::Banned);
+        assert_eq!(ban.reason, None);
+        assert!(!ban.can_rejoin());
+        assert_eq!(ban.summary(), "You were banned from w3 by Op");
+        assert_eq!(parse_removal("Bob has been kicked by Op.", "Me", "w3"), None);
+        assert_eq!(parse_removal("Me has been away.", "Me", "w3"), None);
+    }
+}
@@ -0,0 +1,176 @@
+//! What the input box turns into on the wire. The server ends a message at
+//! every line break, so a second line starting with `/` would run as a
+//! command if the text went out as typed.
+
+use crate::protocol::{split_message, whisper_prefix, CONTINUATION};
+use crate::settings::MultilineSend;
+use crate::slash::as_plain_text;
+use crate::whisper_command;
+
+/// The lines to send for `text`, each at most `limit` bytes with its
+/// command. Whispers and emotes keep their command on every line; other
+/// commands are always joined into one line, and chat lines that start with
+/// `/` are sent as text. A line too long for one message is split between
+/// words, and its pieces are marked with `…` where they join.
+pub fn outgoing_lines(text: &str, mode: MultilineSend, limit: usize) -> Vec<String> {
+    let (prefix, lines) = match body_lines(text, mode) {
+        Some(split) => split,
+        None => return vec![flatten(text)],
+    };
+    let room = room(&prefix, limit);
+    let mut outgoing = vec![];
+    for line in lines {
+        let pieces = split_message(&line, room);
+        let last = pieces.len().saturating_sub(1);
+        for (index, piece) in pieces.into_iter().enumerate() {
+            let piece = match (index > 0, index < last) {
+                (false, false) => piece,
+                (false, true) => format!("{}{}", piece, CONTINUATION),
+                (true, false) => format!("{}{}", CONTINUATION, piece),
+                (true, true) => format!("{}{}{}", CONTINUATION, piece, CONTINUATION),
+            };
+            outgoing.push(if prefix.is_empty() && piece.starts_with('/') {
+                as_plain_text(&piece)
+            } else {
+                format!("{}{}", prefix, piece)
+            });
+        }
+    }
+    outgoing
+}
+
+/// What is left of `text` once the first `sent` of its [`outgoing_lines`]
+/// went out: the command, then the rest of a part-sent line and every line
+/// after it. Empty when nothing is left.
+pub fn unsent_text(text: &str, mode: MultilineSend, limit: usize, sent: usize) -> String {
+    if sent == 0 {
+        return text.to_string();
+    }
+    let (prefix, lines) = match body_lines(text, mode) {
+        Some(split) => split,
+        // A command is a single line, so it went out whole.
+        None => return String::new(),
+    };
+    let room = room(&prefix, limit);
+    let mut skip = sent;
+    let mut left = vec![];
+    for line in lines {
+        let pieces = split_message(&line, room);
+        if skip >= pieces.len() {
+            skip -= pieces.len();
+            continue;
+        }
+        // Pieces were cut between words, so a space puts them back together.
+        left.push(if skip == 0 { line } else { pieces[skip..].join(" ") });
+        skip = 0;
+    }
+    match left.first_mut() {
+        None => return String::new(),
+        // Leading the box now, a chat line starting with `/` would run as a command.
+        Some(first) if prefix.is_empty() && first.starts_with('/') => *first = as_plain_text(first),
+        Some(_) => {}
+    }
+    format!("{}{}", prefix, left.join("\n"))
+}
+
+/// The command every line repeats and the lines of text after it, or `None`
+/// for other commands, which go out as one line.
+fn body_lines(text: &str, mode: MultilineSend) -> Option<(String, Vec<String>)> {
+    let emote = ["/me ", "/emote "].into_iter().find(|command| text.starts_with(command));
+    let (prefix, body) = match (whisper_command(text), emote) {
+        (Some((target, body)), _) => (whisper_prefix(target), body),
+        (None, Some(command)) => (command.to_string(), &text[command.len()..]),
+        (None, None) if text.starts_with('/') => return None,
+        (None, None) => (String::new(), text),
+    };
+    let lines = match mode {
+        MultilineSend::SplitLines => body.lines().map(str::to_string).collect(),
+        MultilineSend::Flatten => vec![flatten(body)],
+    };
+    Some((prefix, lines))
+}
+
+/// Bytes left for text on each line. Leaves room for the space that keeps
+/// a leading `/` as text, and for markers at both ends of a middle piece.
+fn room(prefix: &str, limit: usize) -> usize {
+    limit.saturating_sub(prefix.len() + 1 + 2 * CONTINUATION.len()).max(1)
+}
+
+/// How many bytes `text` takes on the wire as one line, command included.
+pub fn line_length(text: &str) -> usize {
+    match whisper_command(text) {
+        Some((target, body)) => whisper_prefix(target).len() + flatten(body).len(),
+        None => flatten(text).len(),
+    }
+}
+
+/// `text` on one line, each line break and the blanks around it now a space.
+fn flatten(text: &str) -> String {
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASTE: &str = "first\none two three four five six\n/last";
+
+    #[test]
+    fn lines_are_split_and_marked() {
+        assert_eq!(
+            outgoing_lines(PASTE, MultilineSend::SplitLines, 20),
+            vec!["first", "one two three…", "…four five six", " /last"]
+        );
+        assert_eq!(
+            outgoing_lines("/w Bob a\nb", MultilineSend::SplitLines, 200),
+            vec!["/w Bob a", "/w Bob b"]
+        );
+        assert_eq!(outgoing_lines("a\n  b ", MultilineSend::Flatten, 200), vec!["a b"]);
+        assert_eq!(outgoing_lines("/join Clan\nX", MultilineSend::SplitLines, 200), vec!["/join Clan X"]);
+    }
+
+    #[test]
+    fn a_name_longer_than_the_limit_still_sends() {
+        // Room for the text runs out, so it goes a character at a time.
+        let lines = outgoing_lines("/w \"[Clan] A very long name\" hi", MultilineSend::Flatten, 20);
+        assert_eq!(lines, ["/w \"[Clan] A very long name\" h…", "/w \"[Clan] A very long name\" …i"]);
+    }
+
+    #[test]
+    fn only_the_unsent_part_of_a_paste_is_left() {
+        let unsent = |sent| unsent_text(PASTE, MultilineSend::SplitLines, 20, sent);
+        assert_eq!(unsent(0), PASTE);
+        assert_eq!(unsent(1), "one two three four five six\n/last");
+        // Part of a long line went out: the rest of it leads.
+        assert_eq!(unsent(2), "four five six\n/last");
+        // Still chat, not a command, when it comes first.
+        assert_eq!(unsent(3), " /last");
+        assert_eq!(unsent(4), "");
+    }
+
+    #[test]
+    fn the_unsent_part_keeps_its_command() {
+        assert_eq!(unsent_text("/w Bob a\nb\nc", MultilineSend::SplitLines, 200, 1), "/w Bob b\nc");
+        assert_eq!(unsent_text("/me waves\nand bows", MultilineSend::SplitLines, 200, 1), "/me and bows");
+        assert_eq!(unsent_text("/join Clan X", MultilineSend::SplitLines, 200, 1), "");
+    }
+
+    #[test]
+    fn the_unsent_part_resends_as_the_same_lines() {
+        let lines = outgoing_lines(PASTE, MultilineSend::SplitLines, 20);
+        for sent in 0..lines.len() {
+            let left = unsent_text(PASTE, MultilineSend::SplitLines, 20, sent);
+            let resent = outgoing_lines(&left, MultilineSend::SplitLines, 20);
+            let mut expected = lines[sent..].to_vec();
+            // The rest of a part-sent line now leads, so it loses its marker.
+            expected[0] = expected[0].trim_start_matches(CONTINUATION).to_string();
+            assert_eq!(resent, expected);
+        }
+    }
+
+    #[test]
+    fn wire_length_includes_the_command() {
+        assert_eq!(line_length("/w Bob hi\nthere"), "/w Bob hi there".len());
+        assert_eq!(line_length("hi\n\nthere"), "hi there".len());
+    }
+}
@@ -0,0 +1,94 @@
+//! Where state files live: a per-user config directory (`~/.config/bnetchat`,
+//! `%APPDATA%\bnetchat`, `~/Library/Application Support/bnetchat`) rather than
+//! wherever the binary was launched from.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CREDENTIALS: &str = "credentials.json";
+pub const SECRETS: &str = "secrets.json";
+pub const SETTINGS: &str = "settings.json";
+pub const CHANNEL_PREFS: &str = "channel_prefs.json";
+pub const NICKNAMES: &str = "nicknames.json";
+pub const LISTS: &str = "lists.json";
+pub const LAST_CHANNELS: &str = "last_channels.json";
+pub const WATCH_LISTS: &str = "watch_lists.json";
+/// Only read once, to carry the old dark/light flag into settings.
+pub const LEGACY_THEME: &str = "theme.json";
+
+/// Files older versions wrote to the working directory.
+const LEGACY_FILES: [&str; 7] = [CREDENTIALS, SECRETS, SETTINGS, CHANNEL_PREFS, NICKNAMES, LISTS, LEGACY_THEME];
+
+fn project_dirs() -> Option<directories::ProjectDirs> {
+    directories::ProjectDirs::from("", "", "bnetchat")
+}
+
+/// Unit tests keep whatever they save in a scratch directory of their own,
+/// away from the real config and logs.
+fn test_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("bnetchat-test-{}", std::process::id()))
+}
+
+/// The per-user config directory as the platform names it, not created
+/// and without the fallback; `None` when there is no home directory.
+pub fn user_config_dir() -> Option<PathBuf> {
+    if cfg!(test) {
+        return Some(test_dir().join("config"));
+    }
+    project_dirs().map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+/// The config directory, created on demand. Falls back to the working
+/// directory when the platform has no home directory to offer.
+pub fn config_dir() -> PathBuf {
+    match user_config_dir() {
+        Some(dir) => {
+            // A failure here shows up as soon as something is written.
+            let _ = std::fs::create_dir_all(&dir);
+            dir
+        }
+        None => PathBuf::from("."),
+    }
+}
+
+pub fn config_file(name: &str) -> PathBuf {
+    config_dir().join(name)
+}
+
+/// User-made themes, one JSON file each.
+pub fn themes_dir() -> PathBuf {
+    config_dir().join("themes")
+}
+
+/// Root of the chat logs, under the per-user data directory.
+pub fn logs_dir() -> PathBuf {
+    if cfg!(test) {
+        return test_dir().join("logs");
+    }
+    match project_dirs() {
+        Some(dirs) => dirs.data_dir().join("logs"),
+        None => PathBuf::from("logs"),
+    }
+}
+
+/// Moves state files left in the working directory by older versions into
+/// the config directory. A file already present there wins and the old copy
+/// is left alone. Returns the files that could not be moved.
+pub fn migrate_legacy_files() -> Vec<(String, io::Error)> {
+    let dir = config_dir();
+    if dir == Path::new(".") {
+        return vec![];
+    }
+    let mut failures = vec![];
+    for name in LEGACY_FILES {
+        let old = Path::new(name);
+        let new = dir.join(name);
+        if !old.is_file() || new.exists() {
+            continue;
+        }
+        if let Err(err) = std::fs::copy(old, &new).and_then(|_| std::fs::remove_file(old)) {
+            failures.push((name.to_string(), err));
+        }
+    }
+    failures
+}
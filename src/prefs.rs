@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+/// Windows narrower than this hide the user list unless the channel says otherwise.
+pub const SIDEBAR_COLLAPSE_WIDTH: f32 = 700.0;
+/// Below this window width text drops a size so rows keep more words.
+pub const NARROW_TEXT_WIDTH: f32 = 520.0;
+
+/// What the window width does to text size, on top of the user's scale.
+/// A step at one breakpoint rather than a curve, so sizes don't drift while
+/// the window is dragged.
+pub fn responsive_text_factor(width: f32) -> f32 {
+    if width < NARROW_TEXT_WIDTH {
+        0.9
+    } else {
+        1.0
+    }
+}
+
+/// A window resize at least this large ends a Ctrl+U toggle, handing the
+/// user list back to the responsive default.
+pub const SIGNIFICANT_RESIZE: f32 = 150.0;
+
+/// The user list shown or hidden by hand, and the window width at the time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SidebarToggle {
+    pub visible: bool,
+    pub width: f32,
+}
+
+impl SidebarToggle {
+    /// Still in effect at window `width`.
+    pub fn holds_at(&self, width: f32) -> bool {
+        (width - self.width).abs() < SIGNIFICANT_RESIZE
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinLeaveDisplay {
+    Show,
+    /// Runs of joins and leaves share one expandable row.
+    Fold,
+    /// Only the user list changes.
+    Hide,
+}
+
+impl JoinLeaveDisplay {
+    pub const ALL: [JoinLeaveDisplay; 3] = [JoinLeaveDisplay::Show, JoinLeaveDisplay::Fold, JoinLeaveDisplay::Hide];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            JoinLeaveDisplay::Show => "Show joins/leaves",
+            JoinLeaveDisplay::Fold => "Fold joins/leaves",
+            JoinLeaveDisplay::Hide => "Hide joins/leaves",
+        }
+    }
+}
+
+/// Layout used by channels that have no override of their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutDefaults {
+    pub show_sidebar: bool,
+    pub show_topic: bool,
+    pub join_leave: JoinLeaveDisplay,
+}
+
+impl Default for LayoutDefaults {
+    fn default() -> Self {
+        LayoutDefaults {
+            show_sidebar: true,
+            show_topic: true,
+            join_leave: JoinLeaveDisplay::Show,
+        }
+    }
+}
+
+/// Per-channel overrides. `None` means "use the global default".
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelPrefs {
+    pub show_sidebar: Option<bool>,
+    pub show_topic: Option<bool>,
+    pub join_leave: Option<JoinLeaveDisplay>,
+}
+
+impl ChannelPrefs {
+    pub fn is_empty(&self) -> bool {
+        *self == ChannelPrefs::default()
+    }
+}
+
+/// The layout actually in effect for a channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolved {
+    pub show_sidebar: bool,
+    /// True when the channel set the sidebar explicitly, so auto-collapse must not touch it.
+    pub sidebar_explicit: bool,
+    pub show_topic: bool,
+    pub join_leave: JoinLeaveDisplay,
+}
+
+impl Resolved {
+    /// Sidebar visibility after the responsive auto-collapse for a window of `width`.
+    pub fn sidebar_visible(&self, width: f32) -> bool {
+        if self.sidebar_explicit {
+            self.show_sidebar
+        } else {
+            self.show_sidebar && width >= SIDEBAR_COLLAPSE_WIDTH
+        }
+    }
+
+    /// Visibility with the user's own choices on top: a Ctrl+U toggle that
+    /// still holds wins, then the pin keeps the list open, then the layout
+    /// and auto-collapse decide.
+    pub fn sidebar_shown(&self, width: f32, toggle: Option<SidebarToggle>, pinned: bool) -> bool {
+        match toggle {
+            Some(toggle) if toggle.holds_at(width) => toggle.visible,
+            _ => pinned || self.sidebar_visible(width),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelPrefStore {
+    pub defaults: LayoutDefaults,
+    channels: HashMap<String, ChannelPrefs>,
+}
+
+/// Channel prefs are keyed per server so "Clan X" on two realms stay separate.
+fn key(server: &str, channel: &str) -> String {
+    format!("{}|{}", server.to_lowercase(), channel.to_lowercase())
+}
+
+impl ChannelPrefStore {
+    pub fn load() -> ChannelPrefStore {
+        std::fs::read_to_string(paths::config_file(paths::CHANNEL_PREFS))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)
+            .and_then(|text| std::fs::write(paths::config_file(paths::CHANNEL_PREFS), text));
+        if let Err(err) = result {
+            println!("Could not save channel preferences: {}", err);
+        }
+    }
+
+    pub fn get(&self, server: &str, channel: &str) -> ChannelPrefs {
+        self.channels
+            .get(&key(server, channel))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set(&mut self, server: &str, channel: &str, prefs: ChannelPrefs) {
+        if prefs.is_empty() {
+            self.channels.remove(&key(server, channel));
+        } else {
+            self.channels.insert(key(server, channel), prefs);
+        }
+    }
+
+    pub fn resolve(&self, server: &str, channel: &str) -> Resolved {
+        let prefs = self.get(server, channel);
+        Resolved {
+            show_sidebar: prefs.show_sidebar.unwrap_or(self.defaults.show_sidebar),
+            sidebar_explicit: prefs.show_sidebar.is_some(),
+            show_topic: prefs.show_topic.unwrap_or(self.defaults.show_topic),
+            join_leave: prefs.join_leave.unwrap_or(self.defaults.join_leave),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> ChannelPrefStore {
+        ChannelPrefStore {
+            defaults: LayoutDefaults { show_sidebar: true, show_topic: false, join_leave: JoinLeaveDisplay::Fold },
+            ..ChannelPrefStore::default()
+        }
+    }
+
+    #[test]
+    fn channels_without_overrides_use_the_defaults() {
+        let resolved = store().resolve("realm.example", "Chat");
+        assert_eq!(
+            resolved,
+            Resolved { show_sidebar: true, sidebar_explicit: false, show_topic: false, join_leave: JoinLeaveDisplay::Fold }
+        );
+    }
+
+    #[test]
+    fn overrides_win_field_by_field() {
+        let mut store = store();
+        store.set("realm.example", "Clan X", ChannelPrefs { show_topic: Some(true), ..ChannelPrefs::default() });
+        let resolved = store.resolve("realm.example", "Clan X");
+        assert!(resolved.show_topic);
+        assert!(resolved.show_sidebar);
+        assert!(!resolved.sidebar_explicit);
+        assert_eq!(resolved.join_leave, JoinLeaveDisplay::Fold);
+    }
+
+    #[test]
+    fn keyed_per_server_ignoring_case() {
+        let mut store = store();
+        store.set("Realm.Example", "Clan X", ChannelPrefs { join_leave: Some(JoinLeaveDisplay::Hide), ..ChannelPrefs::default() });
+        assert_eq!(store.resolve("realm.example", "clan x").join_leave, JoinLeaveDisplay::Hide);
+        assert_eq!(store.resolve("other.example", "Clan X").join_leave, JoinLeaveDisplay::Fold);
+    }
+
+    #[test]
+    fn clearing_every_override_drops_the_entry() {
+        let mut store = store();
+        store.set("realm", "Chat", ChannelPrefs { show_sidebar: Some(false), ..ChannelPrefs::default() });
+        store.set("realm", "Chat", ChannelPrefs::default());
+        assert!(store.channels.is_empty());
+    }
+
+    #[test]
+    fn survives_a_save_and_load() {
+        let mut store = store();
+        store.set("realm", "Chat", ChannelPrefs { show_sidebar: Some(false), show_topic: None, join_leave: Some(JoinLeaveDisplay::Show) });
+        let text = serde_json::to_string_pretty(&store).unwrap();
+        let loaded: ChannelPrefStore = serde_json::from_str(&text).unwrap();
+        assert_eq!(loaded.defaults, store.defaults);
+        assert_eq!(loaded.get("realm", "Chat"), store.get("realm", "Chat"));
+        // Older files without some fields still load.
+        let partial: ChannelPrefStore = serde_json::from_str(r#"{"defaults": {"show_topic": false}}"#).unwrap();
+        assert!(partial.defaults.show_sidebar);
+        assert!(!partial.defaults.show_topic);
+    }
+
+    #[test]
+    fn auto_collapse_respects_an_explicit_choice() {
+        let narrow = SIDEBAR_COLLAPSE_WIDTH - 1.0;
+        let mut store = store();
+        assert!(!store.resolve("realm", "Chat").sidebar_visible(narrow));
+        assert!(store.resolve("realm", "Chat").sidebar_visible(SIDEBAR_COLLAPSE_WIDTH));
+        store.set("realm", "Chat", ChannelPrefs { show_sidebar: Some(true), ..ChannelPrefs::default() });
+        assert!(store.resolve("realm", "Chat").sidebar_visible(narrow));
+        store.set("realm", "Chat", ChannelPrefs { show_sidebar: Some(false), ..ChannelPrefs::default() });
+        assert!(!store.resolve("realm", "Chat").sidebar_visible(2000.0));
+    }
+
+    #[test]
+    fn a_recent_toggle_beats_the_pin_and_the_layout() {
+        let resolved = store().resolve("realm", "Chat");
+        let toggle = SidebarToggle { visible: false, width: 900.0 };
+        assert!(!resolved.sidebar_shown(900.0 + SIGNIFICANT_RESIZE - 1.0, Some(toggle), true));
+        // A big resize hands control back.
+        assert!(resolved.sidebar_shown(900.0 + SIGNIFICANT_RESIZE, Some(toggle), false));
+        assert!(resolved.sidebar_shown(300.0, None, true));
+    }
+
+    #[test]
+    fn text_steps_down_below_the_breakpoint() {
+        assert_eq!(responsive_text_factor(NARROW_TEXT_WIDTH), 1.0);
+        assert_eq!(responsive_text_factor(NARROW_TEXT_WIDTH - 1.0), 0.9);
+    }
+}
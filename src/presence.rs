@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// The "active now" count is recounted at most this often.
+pub const COUNT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresenceThresholds {
+    /// Spoke within this many seconds: bright dot.
+    pub active_secs: u64,
+    /// Spoke within this many seconds: dim dot.
+    pub recent_secs: u64,
+}
+
+impl Default for PresenceThresholds {
+    fn default() -> Self {
+        PresenceThresholds {
+            active_secs: 2 * 60,
+            recent_secs: 15 * 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presence {
+    Active,
+    Recent,
+    Idle,
+}
+
+pub fn bucket(last_spoke: Option<Instant>, now: Instant, thresholds: PresenceThresholds) -> Presence {
+    let elapsed = match last_spoke {
+        Some(last) => now.duration_since(last),
+        None => return Presence::Idle,
+    };
+    if elapsed < Duration::from_secs(thresholds.active_secs) {
+        Presence::Active
+    } else if elapsed < Duration::from_secs(thresholds.recent_secs) {
+        Presence::Recent
+    } else {
+        Presence::Idle
+    }
+}
+
+/// When each user in the channel last said something.
+#[derive(Debug, Default)]
+pub struct ActivityTracker {
+    last_spoke: HashMap<String, Instant>,
+    active_count: usize,
+    counted_at: Option<Instant>,
+}
+
+impl ActivityTracker {
+    pub fn spoke(&mut self, user: &str, now: Instant) {
+        self.last_spoke.insert(user.to_lowercase(), now);
+        // Recount on the next read so the header picks up the new speaker.
+        self.counted_at = None;
+    }
+
+    pub fn clear(&mut self) {
+        *self = ActivityTracker::default();
+    }
+
+    pub fn last_spoke(&self, user: &str) -> Option<Instant> {
+        self.last_spoke.get(&user.to_lowercase()).copied()
+    }
+
+    pub fn presence(&self, user: &str, now: Instant, thresholds: PresenceThresholds) -> Presence {
+        bucket(self.last_spoke(user), now, thresholds)
+    }
+
+    /// Users in the active bucket, recounted lazily on a coarse timer rather
+    /// than every frame.
+    pub fn active_count(&mut self, now: Instant, thresholds: PresenceThresholds) -> usize {
+        let stale = self
+            .counted_at
+            .is_none_or(|counted| now.duration_since(counted) >= COUNT_INTERVAL);
+        if stale {
+            self.active_count = self
+                .last_spoke
+                .values()
+                .filter(|last| bucket(Some(**last), now, thresholds) == Presence::Active)
+                .count();
+            self.counted_at = Some(now);
+        }
+        self.active_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: PresenceThresholds = PresenceThresholds { active_secs: 60, recent_secs: 600 };
+
+    #[test]
+    fn buckets_by_time_since_the_last_message() {
+        let now = Instant::now() + Duration::from_secs(3600);
+        let ago = |secs| Some(now - Duration::from_secs(secs));
+        assert_eq!(bucket(None, now, THRESHOLDS), Presence::Idle);
+        assert_eq!(bucket(ago(0), now, THRESHOLDS), Presence::Active);
+        assert_eq!(bucket(ago(59), now, THRESHOLDS), Presence::Active);
+        assert_eq!(bucket(ago(60), now, THRESHOLDS), Presence::Recent);
+        assert_eq!(bucket(ago(599), now, THRESHOLDS), Presence::Recent);
+        assert_eq!(bucket(ago(600), now, THRESHOLDS), Presence::Idle);
+    }
+
+    #[test]
+    fn tracks_users_ignoring_case() {
+        let start = Instant::now();
+        let mut tracker = ActivityTracker::default();
+        tracker.spoke("Bob", start);
+        assert_eq!(tracker.last_spoke("BOB"), Some(start));
+        assert_eq!(tracker.presence("bob", start + Duration::from_secs(120), THRESHOLDS), Presence::Recent);
+        assert_eq!(tracker.presence("Alice", start, THRESHOLDS), Presence::Idle);
+        tracker.clear();
+        assert_eq!(tracker.last_spoke("Bob"), None);
+    }
+
+    #[test]
+    fn the_active_count_is_recounted_on_a_timer() {
+        let start = Instant::now();
+        let mut tracker = ActivityTracker::default();
+        tracker.spoke("Bob", start);
+        tracker.spoke("Alice", start);
+        assert_eq!(tracker.active_count(start, THRESHOLDS), 2);
+        // Both have gone quiet, but the count waits for the interval.
+        let later = start + Duration::from_secs(61);
+        assert_eq!(tracker.active_count(start + COUNT_INTERVAL - Duration::from_millis(1), THRESHOLDS), 2);
+        assert_eq!(tracker.active_count(later, THRESHOLDS), 0);
+        // A new speaker forces a recount straight away.
+        tracker.spoke("Carol", later);
+        assert_eq!(tracker.active_count(later, THRESHOLDS), 1);
+    }
+}
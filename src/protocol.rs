@@ -0,0 +1,485 @@
+//! Protocol facts shared by the rest of the client, and the parser for the
+//! numbered lines the PvPGN chat gateway sends.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::ops::UserFlags;
+
+/// Slash commands the PvPGN server understands. Anything else starting with
+/// `/` gets an "unknown command" error back, so the input box asks first.
+pub const SERVER_COMMANDS: &[&str] = &[
+    "/?", "/admins", "/away", "/ban", "/beep", "/channels", "/chs", "/clan", "/con", "/connections",
+    "/designate", "/dnd", "/emote", "/f", "/finger", "/friends", "/games", "/help", "/ignore", "/j",
+    "/join", "/kick", "/logout", "/m", "/mail", "/me", "/motd", "/msg", "/news", "/nobeep", "/ops",
+    "/p", "/ping", "/quit", "/r", "/rejoin", "/reply", "/squelch", "/stats", "/time", "/topic",
+    "/unban", "/unignore", "/unsquelch", "/unwatch", "/users", "/version", "/w", "/watch",
+    "/where", "/whereis", "/whisper", "/who", "/whoami", "/whois",
+];
+
+pub fn is_server_command(token: &str) -> bool {
+    SERVER_COMMANDS.contains(&token.to_lowercase().as_str())
+}
+
+/// Longest line, in bytes, that stock PvPGN passes on whole; longer ones get
+/// cut off. Each profile can set its own under Advanced on the login form.
+pub const MAX_MESSAGE_LEN: usize = 200;
+/// Bounds for a profile's own line limit.
+pub const MIN_LINE_LIMIT: usize = 60;
+pub const MAX_LINE_LIMIT: usize = 500;
+/// Ends a piece of a split line and starts the next one.
+pub const CONTINUATION: &str = "…";
+
+/// Splits `text` into pieces of at most `max` bytes, preferring to break at spaces.
+pub fn split_message(text: &str, max: usize) -> Vec<String> {
+    let mut pieces = vec![];
+    let mut rest = text.trim();
+    while rest.len() > max {
+        let mut cut = max;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if cut == 0 {
+            // `max` is narrower than the first character: it goes out whole.
+            cut = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        // A space right at the limit is already a clean break.
+        let at_space = rest[cut..].starts_with(' ');
+        if let Some(space) = rest[..cut].rfind(' ').filter(|space| !at_space && *space > 0) {
+            cut = space;
+        }
+        pieces.push(rest[..cut].trim_end().to_string());
+        rest = rest[cut..].trim_start();
+    }
+    if !rest.is_empty() {
+        pieces.push(rest.to_string());
+    }
+    pieces
+}
+
+/// `name` as a command argument: quoted when it has spaces, as some realms
+/// allow (`[Clan] Bob`), so the server reads it as one name.
+pub fn quote_name(name: &str) -> Cow<'_, str> {
+    if name.contains(char::is_whitespace) {
+        Cow::Owned(format!("\"{}\"", name))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+/// `/w name ` ready for the text, with the name quoted when it needs to be.
+pub fn whisper_prefix(name: &str) -> String {
+    format!("/w {} ", quote_name(name))
+}
+
+/// A name at the front of `text`, quoted or a single word, and what follows
+/// it after one space.
+pub fn split_name(text: &str) -> Option<(&str, &str)> {
+    let (name, rest) = match text.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"')?,
+        None => text.split_once(' ').unwrap_or((text, "")),
+    };
+    if name.trim().is_empty() {
+        return None;
+    }
+    Some((name, rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+/// Product tags the server puts on USER/JOIN lines: tag, short label, full name.
+const PRODUCTS: &[(&str, &str, &str)] = &[
+    ("CHAT", "Chat", "Chat client"),
+    ("STAR", "SC", "StarCraft"),
+    ("SEXP", "BW", "StarCraft: Brood War"),
+    ("SSHR", "SC", "StarCraft Shareware"),
+    ("JSTR", "SCJ", "StarCraft Japanese"),
+    ("W2BN", "W2", "Warcraft II Battle.net Edition"),
+    ("DRTL", "D1", "Diablo"),
+    ("DSHR", "D1", "Diablo Shareware"),
+    ("D2DV", "D2", "Diablo II"),
+    ("D2XP", "LoD", "Diablo II: Lord of Destruction"),
+    ("WAR3", "W3", "Warcraft III"),
+    ("W3XP", "TFT", "Warcraft III: The Frozen Throne"),
+];
+
+/// Short label and full name for a product tag. Some servers send the tag
+/// reversed (`PX3W`), as it travels in the binary protocol.
+pub fn product(tag: &str) -> Option<(&'static str, &'static str)> {
+    let reversed: String = tag.chars().rev().collect();
+    PRODUCTS
+        .iter()
+        .find(|(known, _, _)| known.eq_ignore_ascii_case(tag) || known.eq_ignore_ascii_case(&reversed))
+        .map(|(_, short, full)| (*short, *full))
+}
+
+/// A user as described by a USER or JOIN line. Ping and client are extras
+/// not every server sends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserInfo {
+    pub name: String,
+    pub flags: UserFlags,
+    pub ping: Option<u32>,
+    /// Product tag such as `W3XP`.
+    pub client: Option<String>,
+}
+
+/// One line from the server, e.g. `1005 TALK Bob 0010 "hello"`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerMessage {
+    /// 1001 (already in channel) and 1009 (flags changed).
+    User(UserInfo),
+    Join(UserInfo),
+    Leave { name: String },
+    Whisper { from: String, text: String },
+    Talk { from: String, text: String },
+    /// 1023: `/me` from someone in the channel.
+    Emote { from: String, text: String },
+    /// 1006: an announcement to everyone on the server.
+    Broadcast(String),
+    Channel { name: String },
+    /// 1010: echo of a whisper we sent. `to` is the recipient on some
+    /// servers, while PvPGN puts our own name there.
+    WhisperTo { to: String, text: String },
+    Info(String),
+    Error(String),
+    /// 1020: a line of a `/stats` answer.
+    Stats(String),
+    /// 1021: a channel we asked for does not exist or is restricted.
+    ChannelRefused(String),
+    /// A well-formed line we have no handling for.
+    Unknown(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    /// The line stopped before a field its code requires.
+    MissingField { code: &'static str, field: &'static str },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty line"),
+            ParseError::MissingField { code, field } => write!(f, "{} line without {}", code, field),
+        }
+    }
+}
+
+/// Flags are printed as hex digits with leading zeros, as in `0010`, which
+/// tells them apart from the words of a name.
+fn looks_like_flags(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_digit()) && word.len() >= 4 && word.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn unquote(text: &str) -> &str {
+    text.strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .unwrap_or(text)
+}
+
+/// Fields after the code and type word: name, flags, then free text.
+struct Fields<'a> {
+    code: &'static str,
+    rest: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    fn word(&mut self, field: &'static str) -> Result<&'a str, ParseError> {
+        let rest = self.rest.trim_start_matches(' ');
+        let (word, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if word.is_empty() {
+            return Err(ParseError::MissingField { code: self.code, field });
+        }
+        self.rest = rest;
+        Ok(word)
+    }
+
+    /// A user name. Realms that allow spaces in names quote them. Without
+    /// quotes, every word up to the flags or the quoted text belongs to the
+    /// name, so `[Clan] Bob 0010 "hi"` is from `[Clan] Bob`; when neither
+    /// follows, the name is one word, or the whole rest if `alone`.
+    fn name(&mut self, field: &'static str, alone: bool) -> Result<String, ParseError> {
+        let rest = self.rest.trim_start_matches(' ');
+        if rest.starts_with('"') {
+            if let Some((name, after)) = split_name(rest) {
+                self.rest = after;
+                return Ok(name.to_string());
+            }
+        }
+        let words: Vec<&str> = rest.split(' ').filter(|word| !word.is_empty()).collect();
+        let count = match words.iter().position(|word| word.starts_with('"') || looks_like_flags(word)) {
+            Some(end) => end.max(1),
+            None if alone => words.len().max(1),
+            None => 1,
+        };
+        let name = (0..count).map(|_| self.word(field)).collect::<Result<Vec<_>, _>>()?;
+        Ok(name.join(" "))
+    }
+
+    /// Name and flags, then whatever extras follow: a number is the ping and a
+    /// short tag (bracketed or not) the client. Anything else is ignored.
+    fn user(mut self) -> Result<UserInfo, ParseError> {
+        let name = self.name("name", false)?;
+        // Flags we cannot read leave the user a plain user rather than dropping the line.
+        let flags = UserFlags::parse(self.word("flags")?).unwrap_or_default();
+        let mut ping = None;
+        let mut client = None;
+        for word in self.rest.split_whitespace() {
+            let tag = word.trim_matches(|c| c == '[' || c == ']');
+            if let Ok(value) = tag.parse::<u32>() {
+                ping = ping.or(Some(value));
+            } else if (3..=4).contains(&tag.len()) && tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+                client = client.or_else(|| Some(tag.to_uppercase()));
+            }
+        }
+        Ok(UserInfo { name, flags, ping, client })
+    }
+
+    /// Flags are optional on LEAVE lines, and some servers leave them off;
+    /// a quoted word is already the text.
+    fn skip_optional(&mut self) {
+        if !self.rest.trim_start_matches(' ').starts_with('"') {
+            let _ = self.word("");
+        }
+    }
+
+    fn text(self) -> String {
+        unquote(self.rest.trim_start_matches(' ')).to_string()
+    }
+}
+
+pub fn parse(line: &str) -> Result<ServerMessage, ParseError> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (code, rest) = line.split_once(' ').unwrap_or((line, ""));
+    if code.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    // Skip the type word: the code alone decides what the line is.
+    let rest = rest.split_once(' ').map_or("", |(_, rest)| rest);
+    let fields = |code| Fields { code, rest };
+    let message = match code {
+        "1001" | "1009" => ServerMessage::User(fields("USER").user()?),
+        "1002" => ServerMessage::Join(fields("JOIN").user()?),
+        "1003" => {
+            let mut fields = fields("LEAVE");
+            ServerMessage::Leave { name: fields.name("name", true)? }
+        }
+        "1004" => {
+            let mut fields = fields("WHISPER");
+            let from = fields.name("sender", false)?;
+            fields.skip_optional();
+            ServerMessage::Whisper { from, text: fields.text() }
+        }
+        "1005" => {
+            let mut fields = fields("TALK");
+            let from = fields.name("sender", false)?;
+            fields.skip_optional();
+            ServerMessage::Talk { from, text: fields.text() }
+        }
+        "1006" => ServerMessage::Broadcast(fields("BROADCAST").text()),
+        "1007" => {
+            let name = fields("CHANNEL").text();
+            if name.is_empty() {
+                return Err(ParseError::MissingField { code: "CHANNEL", field: "name" });
+            }
+            ServerMessage::Channel { name }
+        }
+        "1010" => {
+            let mut fields = fields("WHISPER");
+            let to = fields.name("recipient", false)?;
+            fields.skip_optional();
+            ServerMessage::WhisperTo { to, text: fields.text() }
+        }
+        "1023" => {
+            let mut fields = fields("EMOTE");
+            let from = fields.name("sender", false)?;
+            fields.skip_optional();
+            ServerMessage::Emote { from, text: fields.text() }
+        }
+        "1018" => ServerMessage::Info(fields("INFO").text()),
+        "1019" => ServerMessage::Error(fields("ERROR").text()),
+        "1020" => ServerMessage::Stats(fields("STATS").text()),
+        "1021" => ServerMessage::ChannelRefused(fields("CHANNEL").text()),
+        _ => ServerMessage::Unknown(line.to_string()),
+    };
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(name: &str, flags: &str, ping: Option<u32>, client: Option<&str>) -> UserInfo {
+        UserInfo {
+            name: name.to_string(),
+            flags: UserFlags::parse(flags).unwrap(),
+            ping,
+            client: client.map(str::to_string),
+        }
+    }
+
+    fn missing(code: &'static str, field: &'static str) -> Result<ServerMessage, ParseError> {
+        Err(ParseError::MissingField { code, field })
+    }
+
+    #[test]
+    fn user_lines() {
+        assert_eq!(parse("1001 USER Bob 0010 [CHAT]"), Ok(ServerMessage::User(user("Bob", "0010", None, Some("CHAT")))));
+        assert_eq!(parse("1009 USER Bob 0012 [W3XP] 120"), Ok(ServerMessage::User(user("Bob", "0012", Some(120), Some("W3XP")))));
+        assert_eq!(parse("1002 JOIN Alice 0010 PX3W\r\n"), Ok(ServerMessage::Join(user("Alice", "0010", None, Some("PX3W")))));
+        // Flags we cannot read leave a plain user.
+        assert_eq!(parse("1002 JOIN Alice 00zz"), Ok(ServerMessage::Join(user("Alice", "0000", None, None))));
+    }
+
+    #[test]
+    fn leave_lines_with_and_without_flags() {
+        assert_eq!(parse("1003 LEAVE Bob 0010"), Ok(ServerMessage::Leave { name: "Bob".into() }));
+        assert_eq!(parse("1003 LEAVE Bob"), Ok(ServerMessage::Leave { name: "Bob".into() }));
+        assert_eq!(parse("1003 LEAVE [Clan] Bob"), Ok(ServerMessage::Leave { name: "[Clan] Bob".into() }));
+        assert_eq!(parse("1003 LEAVE \"Bob Smith\" 0010"), Ok(ServerMessage::Leave { name: "Bob Smith".into() }));
+    }
+
+    #[test]
+    fn chat_lines() {
+        assert_eq!(
+            parse("1004 WHISPER Bob 0010 \"psst\""),
+            Ok(ServerMessage::Whisper { from: "Bob".into(), text: "psst".into() })
+        );
+        assert_eq!(
+            parse("1005 TALK Bob 0010 \"hello there\""),
+            Ok(ServerMessage::Talk { from: "Bob".into(), text: "hello there".into() })
+        );
+        assert_eq!(
+            parse("1010 WHISPER Me 0010 \"on my way\""),
+            Ok(ServerMessage::WhisperTo { to: "Me".into(), text: "on my way".into() })
+        );
+        assert_eq!(
+            parse("1023 EMOTE Bob 0010 \"waves\""),
+            Ok(ServerMessage::Emote { from: "Bob".into(), text: "waves".into() })
+        );
+        // A captured echo of "/w Bob on my way": the recipient is named.
+        assert_eq!(
+            parse("1010 WHISPER Bob 0010 \"on my way\""),
+            Ok(ServerMessage::WhisperTo { to: "Bob".into(), text: "on my way".into() })
+        );
+        // Quotes inside the text survive; only the outer pair goes.
+        assert_eq!(
+            parse("1005 TALK Bob 0010 \"say \"hi\"\""),
+            Ok(ServerMessage::Talk { from: "Bob".into(), text: "say \"hi\"".into() })
+        );
+        // Some servers leave the flags off.
+        assert_eq!(
+            parse("1004 WHISPER Bob \"psst\""),
+            Ok(ServerMessage::Whisper { from: "Bob".into(), text: "psst".into() })
+        );
+    }
+
+    #[test]
+    fn names_with_spaces() {
+        assert_eq!(
+            parse("1005 TALK [Clan] Bob 0010 \"hi\""),
+            Ok(ServerMessage::Talk { from: "[Clan] Bob".into(), text: "hi".into() })
+        );
+        assert_eq!(
+            parse("1005 TALK \"Bob Smith\" 0010 \"hi\""),
+            Ok(ServerMessage::Talk { from: "Bob Smith".into(), text: "hi".into() })
+        );
+        assert_eq!(
+            parse("1004 WHISPER [Clan] Bob \"hi\""),
+            Ok(ServerMessage::Whisper { from: "[Clan] Bob".into(), text: "hi".into() })
+        );
+        assert_eq!(parse("1001 USER [Clan] Bob 0002"), Ok(ServerMessage::User(user("[Clan] Bob", "0002", None, None))));
+        assert_eq!(parse("1002 JOIN \"Bob Smith\" 0010 [CHAT]"), Ok(ServerMessage::Join(user("Bob Smith", "0010", None, Some("CHAT")))));
+        // Numbered accounts keep their suffix.
+        assert_eq!(
+            parse("1005 TALK Bob#2 0010 \"hi\""),
+            Ok(ServerMessage::Talk { from: "Bob#2".into(), text: "hi".into() })
+        );
+        assert_eq!(
+            parse("1010 WHISPER \"[Clan] Bob\" 0010 \"hi\""),
+            Ok(ServerMessage::WhisperTo { to: "[Clan] Bob".into(), text: "hi".into() })
+        );
+    }
+
+    #[test]
+    fn server_text_lines() {
+        assert_eq!(parse("1006 BROADCAST \"Restart in 5 minutes\""), Ok(ServerMessage::Broadcast("Restart in 5 minutes".into())));
+        assert_eq!(parse("1007 CHANNEL \"Clan X\""), Ok(ServerMessage::Channel { name: "Clan X".into() }));
+        assert_eq!(parse("1018 INFO \"Welcome to PvPGN!\""), Ok(ServerMessage::Info("Welcome to PvPGN!".into())));
+        assert_eq!(parse("1019 ERROR \"That user is not logged on.\""), Ok(ServerMessage::Error("That user is not logged on.".into())));
+        assert_eq!(parse("1020 STATS \"Wins: 3\""), Ok(ServerMessage::Stats("Wins: 3".into())));
+        assert_eq!(parse("1021 CHANNEL \"Clan Y\""), Ok(ServerMessage::ChannelRefused("Clan Y".into())));
+    }
+
+    #[test]
+    fn codes_we_do_not_handle_are_kept_whole() {
+        for line in ["1008 UNUSED", "1011 SOMETHING x", "1016 INFO \"x\"", "1022 SOMETHING", "2010 NAME Bob", "hello"] {
+            assert_eq!(parse(line), Ok(ServerMessage::Unknown(line.to_string())));
+        }
+    }
+
+    #[test]
+    fn truncated_lines() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("\r\n"), Err(ParseError::Empty));
+        assert_eq!(parse("1001 USER"), missing("USER", "name"));
+        assert_eq!(parse("1001 USER Bob"), missing("USER", "flags"));
+        assert_eq!(parse("1002"), missing("JOIN", "name"));
+        assert_eq!(parse("1003 LEAVE"), missing("LEAVE", "name"));
+        assert_eq!(parse("1004 WHISPER"), missing("WHISPER", "sender"));
+        assert_eq!(parse("1005 TALK "), missing("TALK", "sender"));
+        assert_eq!(parse("1007 CHANNEL"), missing("CHANNEL", "name"));
+        assert_eq!(parse("1010 WHISPER"), missing("WHISPER", "recipient"));
+        assert_eq!(parse("1023 EMOTE"), missing("EMOTE", "sender"));
+        // A sender with nothing after it still makes a (blank) line.
+        assert_eq!(parse("1005 TALK Bob"), Ok(ServerMessage::Talk { from: "Bob".into(), text: String::new() }));
+        assert_eq!(ParseError::MissingField { code: "USER", field: "flags" }.to_string(), "USER line without flags");
+    }
+
+    #[test]
+    fn splits_at_spaces_within_the_limit() {
+        assert_eq!(split_message("  hello there world ", 11), vec!["hello there", "world"]);
+        assert_eq!(split_message("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(split_message("short", 200), vec!["short"]);
+        assert!(split_message("   ", 10).is_empty());
+    }
+
+    #[test]
+    fn never_splits_inside_a_character() {
+        // "é" is two bytes: a cut at byte 3 would land inside the second one.
+        assert_eq!(split_message("ééé", 3), vec!["é", "é", "é"]);
+        assert_eq!(split_message("aé😀b", 4), vec!["aé", "😀", "b"]);
+        for piece in split_message("日本語のテキスト", 7) {
+            assert!(piece.len() <= 7);
+        }
+        // A character wider than the limit still goes out rather than looping.
+        assert_eq!(split_message("😀😀", 2), vec!["😀", "😀"]);
+    }
+
+    #[test]
+    fn names_are_quoted_only_when_they_need_it() {
+        assert_eq!(quote_name("Bob"), "Bob");
+        assert_eq!(quote_name("[Clan] Bob"), "\"[Clan] Bob\"");
+        assert_eq!(whisper_prefix("[Clan] Bob"), "/w \"[Clan] Bob\" ");
+        assert_eq!(whisper_prefix("Bob"), "/w Bob ");
+        assert_eq!(whisper_prefix("Bob#2"), "/w Bob#2 ");
+    }
+
+    #[test]
+    fn split_name_reads_quoted_and_plain_names() {
+        assert_eq!(split_name("\"[Clan] Bob\" hi there"), Some(("[Clan] Bob", "hi there")));
+        assert_eq!(split_name("Bob hi there"), Some(("Bob", "hi there")));
+        assert_eq!(split_name("Bob"), Some(("Bob", "")));
+        assert_eq!(split_name("\"unterminated name"), None);
+        assert_eq!(split_name("\"\" hi"), None);
+        assert_eq!(split_name(""), None);
+    }
+
+    #[test]
+    fn product_tags_either_way_round() {
+        assert_eq!(product("W3XP"), Some(("TFT", "Warcraft III: The Frozen Throne")));
+        assert_eq!(product("PX3W"), Some(("TFT", "Warcraft III: The Frozen Throne")));
+        assert_eq!(product("chat"), Some(("Chat", "Chat client")));
+        assert_eq!(product("XXXX"), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! SOCKS5 and HTTP CONNECT tunnels to the server. Both are small enough to
+//! speak by hand; the server's name is sent to the proxy unresolved, so
+//! hosts only the proxy can resolve still work.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::connect;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProxyKind {
+    #[default]
+    None,
+    Socks5,
+    Http,
+}
+
+impl ProxyKind {
+    pub const ALL: [ProxyKind; 3] = [ProxyKind::None, ProxyKind::Socks5, ProxyKind::Http];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProxyKind::None => "No proxy",
+            ProxyKind::Socks5 => "SOCKS5",
+            ProxyKind::Http => "HTTP (CONNECT)",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    /// Leave empty when the proxy needs no login.
+    pub user: String,
+    /// Kept in the keyring next to the account password, never in the file.
+    #[serde(skip)]
+    pub password: String,
+}
+
+impl Default for Proxy {
+    fn default() -> Self {
+        Proxy { kind: ProxyKind::None, host: "".to_string(), port: 1080, user: "".to_string(), password: "".to_string() }
+    }
+}
+
+/// What went wrong on the way through the proxy, as opposed to at the server.
+#[derive(Debug)]
+pub enum ProxyError {
+    /// The proxy itself could not be reached.
+    Connect(Box<connect::ConnectError>),
+    /// The proxy wants a login we did not have or it did not accept.
+    Auth(String),
+    /// The proxy could not or would not reach the server; its reason.
+    Refused(String),
+    /// The proxy answered something we don't understand.
+    Protocol(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::Connect(err) => write!(f, "{}", err),
+            ProxyError::Auth(text) => write!(f, "authentication failed: {}", text),
+            ProxyError::Refused(text) => write!(f, "could not reach the server: {}", text),
+            ProxyError::Protocol(text) => write!(f, "unexpected answer: {}", text),
+            ProxyError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for ProxyError {
+    fn from(err: io::Error) -> Self {
+        ProxyError::Io(err)
+    }
+}
+
+impl Proxy {
+    pub fn enabled(&self) -> bool {
+        self.kind != ProxyKind::None
+    }
+
+    /// `host:port` of the proxy, for messages and the keyring.
+    pub fn address(&self) -> String {
+        match self.host.trim().parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, self.port),
+            _ => format!("{}:{}", self.host.trim(), self.port),
+        }
+    }
+
+    /// Connects to the proxy and asks it for a tunnel to `server`.
+    pub fn open(&self, server: &str, timeout: Duration) -> Result<TcpStream, ProxyError> {
+        let mut stream = connect::open(&self.address(), timeout).map_err(|err| ProxyError::Connect(Box::new(err)))?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let (host, port) = connect::split_host_port(server);
+        match self.kind {
+            ProxyKind::None => {}
+            ProxyKind::Socks5 => self.socks5(&mut stream, host, port)?,
+            ProxyKind::Http => self.http(&mut stream, host, port)?,
+        }
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        Ok(stream)
+    }
+
+    /// RFC 1928 CONNECT, with RFC 1929 username/password when one is set.
+    fn socks5(&self, stream: &mut TcpStream, host: &str, port: u16) -> Result<(), ProxyError> {
+        let methods: &[u8] = if self.user.is_empty() { &[0x00] } else { &[0x00, 0x02] };
+        let mut greeting = vec![0x05, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting)?;
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice)?;
+        if choice[0] != 0x05 {
+            return Err(ProxyError::Protocol("not a SOCKS5 proxy".to_string()));
+        }
+        match choice[1] {
+            0x00 => {}
+            0x02 => {
+                let user = self.user.as_bytes();
+                let password = self.password.as_bytes();
+                if user.len() > 255 || password.len() > 255 {
+                    return Err(ProxyError::Auth("user name or password longer than 255 bytes".to_string()));
+                }
+                let mut login = vec![0x01, user.len() as u8];
+                login.extend_from_slice(user);
+                login.push(password.len() as u8);
+                login.extend_from_slice(password);
+                stream.write_all(&login)?;
+                let mut status = [0; 2];
+                stream.read_exact(&mut status)?;
+                if status[1] != 0x00 {
+                    return Err(ProxyError::Auth("user name or password rejected".to_string()));
+                }
+            }
+            0xFF if self.user.is_empty() => return Err(ProxyError::Auth("the proxy requires a login".to_string())),
+            0xFF => return Err(ProxyError::Auth("no login method the proxy accepts".to_string())),
+            other => return Err(ProxyError::Protocol(format!("login method {:#04x}", other))),
+        }
+
+        let mut request = vec![0x05, 0x01, 0x00];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(0x01);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(0x04);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                if host.len() > 255 {
+                    return Err(ProxyError::Protocol("host name longer than 255 bytes".to_string()));
+                }
+                request.push(0x03);
+                request.push(host.len() as u8);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request)?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0x00 {
+            return Err(ProxyError::Refused(socks5_reply(reply[1]).to_string()));
+        }
+        // The address the proxy bound; we have no use for it.
+        let skip = match reply[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => {
+                let mut len = [0; 1];
+                stream.read_exact(&mut len)?;
+                usize::from(len[0])
+            }
+            other => return Err(ProxyError::Protocol(format!("address type {:#04x}", other))),
+        };
+        let mut bound = vec![0; skip + 2];
+        stream.read_exact(&mut bound)?;
+        Ok(())
+    }
+
+    fn http(&self, stream: &mut TcpStream, host: &str, port: u16) -> Result<(), ProxyError> {
+        let target = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+        let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+        if !self.user.is_empty() {
+            let login = STANDARD.encode(format!("{}:{}", self.user, self.password));
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", login));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        // Byte by byte, so nothing the server sends after the headers is eaten.
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() > 8192 {
+                return Err(ProxyError::Protocol("response headers too long".to_string()));
+            }
+            stream.read_exact(&mut byte)?;
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status_line = head.lines().next().unwrap_or_default();
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next().unwrap_or_default().starts_with("HTTP/") {
+            return Err(ProxyError::Protocol(status_line.to_string()));
+        }
+        let code = parts.next().unwrap_or_default();
+        let reason = parts.next().unwrap_or_default();
+        match code {
+            "200" => Ok(()),
+            "407" => Err(ProxyError::Auth(format!("407 {}", reason))),
+            _ => Err(ProxyError::Refused(format!("{} {}", code, reason))),
+        }
+    }
+}
+
+fn socks5_reply(code: u8) -> &'static str {
+    match code {
+        0x01 => "general proxy failure",
+        0x02 => "not allowed by the proxy's rules",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "timed out",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
@@ -0,0 +1,612 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::idle::parse_idle;
+
+/// Give up on a query whose first response line never arrives.
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Multi-line answers have no terminator: finish once the server goes quiet.
+pub const QUIET_PERIOD: Duration = Duration::from_millis(700);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryKind {
+    Who,
+    Time,
+    Users,
+    Friends,
+    Stats,
+    Channels,
+}
+
+impl QueryKind {
+    /// Which outgoing commands we know how to parse the answer for.
+    pub fn from_command(command: &str) -> Option<QueryKind> {
+        let mut words = command.split_whitespace();
+        match (words.next()?, words.next()) {
+            ("/who", _) => Some(QueryKind::Who),
+            ("/time", _) => Some(QueryKind::Time),
+            ("/users", _) => Some(QueryKind::Users),
+            ("/stats" | "/astat", _) => Some(QueryKind::Stats),
+            ("/channels" | "/chs", _) => Some(QueryKind::Channels),
+            ("/friends" | "/f", Some("list" | "l")) => Some(QueryKind::Friends),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryResult {
+    /// `idle` only has entries on servers that report idle times.
+    Who { channel: String, users: Vec<String>, idle: HashMap<String, Duration> },
+    Time { server: String, local: Option<String> },
+    Users { users: u32, games: Option<u32>, channels: Option<u32> },
+    Friends { friends: Vec<Friend> },
+    /// `fields` are the `Label: value` lines in the order the server sent them.
+    Stats { user: String, fields: Vec<(String, String)> },
+    /// Public channels and how many users each has, in the server's order.
+    Channels { channels: Vec<(String, u32)> },
+}
+
+/// One entry of `/friends list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Friend {
+    pub name: String,
+    pub online: bool,
+    /// What the server says they're doing: `offline`, `in channel "w3"`, ...
+    pub status: String,
+}
+
+impl QueryResult {
+    /// Plain text version, used for copying and as the row text behind the card.
+    pub fn summary(&self) -> String {
+        match self {
+            QueryResult::Who { channel, users, .. } => {
+                format!("Users in {} ({}): {}", channel, users.len(), users.join(", "))
+            }
+            QueryResult::Time { server, local: Some(local) } => {
+                format!("Server time: {} (your time: {})", server, local)
+            }
+            QueryResult::Time { server, local: None } => format!("Server time: {}", server),
+            QueryResult::Users { users, games, channels } => {
+                let mut summary = format!("{} users online", users);
+                if let Some(games) = games {
+                    summary.push_str(&format!(", {} games", games));
+                }
+                if let Some(channels) = channels {
+                    summary.push_str(&format!(", {} channels", channels));
+                }
+                summary
+            }
+            QueryResult::Friends { friends } => {
+                let online = friends.iter().filter(|friend| friend.online).count();
+                let names: Vec<&str> = friends.iter().map(|friend| friend.name.as_str()).collect();
+                format!("Friends ({} online of {}): {}", online, friends.len(), names.join(", "))
+            }
+            QueryResult::Stats { user, fields } => {
+                let fields: Vec<String> = fields.iter().map(|(label, value)| format!("{}: {}", label, value)).collect();
+                format!("Stats for {}: {}", user, fields.join("; "))
+            }
+            QueryResult::Channels { channels } => {
+                let names: Vec<String> = channels.iter().map(|(name, users)| format!("{} ({})", name, users)).collect();
+                format!("Channels ({}): {}", channels.len(), names.join(", "))
+            }
+        }
+    }
+}
+
+/// `Users in channel w3:`
+pub fn parse_who_header(text: &str) -> Option<String> {
+    let rest = text.strip_prefix("Users in channel ")?;
+    Some(rest.trim_end_matches(':').trim().to_string())
+}
+
+/// Name lines under the header: `Bob, [Alice], Carol`. Some builds append
+/// an idle field to each name: `Bob (idle 5m), Carol (idle 00:01:10)`.
+pub fn parse_who_names(text: &str) -> Vec<(String, Option<Duration>)> {
+    text.split(',')
+        .filter_map(|entry| {
+            let entry = entry.trim();
+            let (name, rest) = entry.split_once(' ').unwrap_or((entry, ""));
+            let name = name.trim_start_matches('[').trim_end_matches(']').trim();
+            if name.is_empty() {
+                return None;
+            }
+            let rest = rest.trim().trim_start_matches('-').trim();
+            if rest.is_empty() {
+                return Some((name.to_string(), None));
+            }
+            // Anything else after the name means this isn't a name line.
+            parse_idle(rest).map(|idle| (name.to_string(), Some(idle)))
+        })
+        .collect()
+}
+
+/// `Your PvPGN - Friends List` (the server name varies). Not to be confused
+/// with `Added Bob to your friends list.`
+pub fn is_friends_header(text: &str) -> bool {
+    let text = text.trim().to_lowercase();
+    text.starts_with("your ") && text.contains(" - ") && text.ends_with("friends list")
+}
+
+/// `1: Bob, offline` or `2: *Alice, using Warcraft III in channel "w3".`
+/// A leading `*` marks a mutual friend.
+pub fn parse_friend_line(text: &str) -> Option<Friend> {
+    let (number, rest) = text.split_once(':')?;
+    number.trim().parse::<u32>().ok()?;
+    let (name, status) = rest.split_once(',').unwrap_or((rest, ""));
+    let name = name.trim().trim_start_matches('*').trim();
+    if name.is_empty() || name.contains(' ') {
+        return None;
+    }
+    let status = status.trim().trim_end_matches('.').to_string();
+    Some(Friend {
+        name: name.to_string(),
+        online: !status.is_empty() && !status.to_lowercase().starts_with("offline"),
+        status,
+    })
+}
+
+/// `Bob's record:` or `Bob's Ladder Record:`
+pub fn parse_stats_header(text: &str) -> Option<String> {
+    let lower = text.to_lowercase();
+    let at = lower.find("'s ")?;
+    if !lower[at..].trim_end_matches(':').trim_end().ends_with("record") {
+        return None;
+    }
+    let user = text[..at].trim();
+    if user.is_empty() || user.contains(' ') {
+        return None;
+    }
+    Some(user.to_string())
+}
+
+/// `Normal games: 10-5-0` under a stats header.
+pub fn parse_stats_field(text: &str) -> Option<(String, String)> {
+    let (label, value) = text.split_once(':')?;
+    let (label, value) = (label.trim(), value.trim());
+    if label.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some((label.to_string(), value.to_string()))
+}
+
+/// `Currently accessible channels:`, or the column header under it:
+/// `-----------name----------- users ----admin/operator----`.
+pub fn is_channels_header(text: &str) -> bool {
+    let text = text.trim().to_lowercase();
+    text.starts_with("currently accessible channels")
+        || text.starts_with("current channels")
+        || (text.starts_with('-') && text.contains("name") && text.contains("users"))
+}
+
+/// `w3                          12 - Bob, Alice`: the name is padded to a
+/// column and may contain spaces, then the user count, then the operators.
+pub fn parse_channel_line(text: &str) -> Option<(String, u32)> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    // The count is the last number that ends the line or comes before the
+    // operators' dash, so a name like `Clan 1337` keeps its number.
+    let at = (1..words.len()).rev().find(|&at| {
+        words[at].parse::<u32>().is_ok() && words.get(at + 1).is_none_or(|next| *next == "-")
+    })?;
+    Some((words[..at].join(" "), words[at].parse().ok()?))
+}
+
+/// `Server Time: Thu Oct 16 12:00:00` / `Your local time: ...`
+pub fn parse_time_line(text: &str) -> Option<(bool, String)> {
+    let (label, value) = text.split_once(':')?;
+    let value = value.trim().to_string();
+    match label.trim().to_lowercase().as_str() {
+        "server time" => Some((true, value)),
+        "your local time" | "local time" => Some((false, value)),
+        _ => None,
+    }
+}
+
+/// `There are currently 120 users online, in 14 games, and in 9 channels.`
+pub fn parse_users_line(text: &str) -> Option<QueryResult> {
+    if !text.starts_with("There are currently") {
+        return None;
+    }
+    let mut numbers = text
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|word| word.parse::<u32>().ok());
+    Some(QueryResult::Users {
+        users: numbers.next()?,
+        games: numbers.next(),
+        channels: numbers.next(),
+    })
+}
+
+#[derive(Debug)]
+enum Partial {
+    Waiting,
+    Who { channel: String, users: Vec<String>, idle: HashMap<String, Duration> },
+    Time { server: Option<String>, local: Option<String> },
+    Friends { friends: Vec<Friend> },
+    Stats { user: String, fields: Vec<(String, String)> },
+    Channels { channels: Vec<(String, u32)> },
+}
+
+#[derive(Debug)]
+struct PendingQuery {
+    kind: QueryKind,
+    issued: Instant,
+    last_line: Option<Instant>,
+    partial: Partial,
+}
+
+enum Feed {
+    NotMine,
+    Consumed,
+    Done(QueryResult),
+}
+
+impl PendingQuery {
+    fn feed(&mut self, text: &str, now: Instant) -> Feed {
+        let feed = match (self.kind, &mut self.partial) {
+            (QueryKind::Users, _) => match parse_users_line(text) {
+                Some(result) => Feed::Done(result),
+                None => Feed::NotMine,
+            },
+            (QueryKind::Who, Partial::Waiting) => match parse_who_header(text) {
+                Some(channel) => {
+                    self.partial = Partial::Who { channel, users: vec![], idle: HashMap::new() };
+                    Feed::Consumed
+                }
+                None => Feed::NotMine,
+            },
+            (QueryKind::Who, Partial::Who { users, idle, .. }) => {
+                let names = parse_who_names(text);
+                if names.is_empty() {
+                    Feed::NotMine
+                } else {
+                    for (name, name_idle) in names {
+                        if let Some(name_idle) = name_idle {
+                            idle.insert(name.clone(), name_idle);
+                        }
+                        users.push(name);
+                    }
+                    Feed::Consumed
+                }
+            }
+            (QueryKind::Friends, Partial::Waiting) => {
+                if is_friends_header(text) {
+                    self.partial = Partial::Friends { friends: vec![] };
+                    Feed::Consumed
+                } else {
+                    Feed::NotMine
+                }
+            }
+            (QueryKind::Friends, Partial::Friends { friends }) => match parse_friend_line(text) {
+                Some(friend) => {
+                    friends.push(friend);
+                    Feed::Consumed
+                }
+                None => Feed::NotMine,
+            },
+            (QueryKind::Stats, Partial::Waiting) => match parse_stats_header(text) {
+                Some(user) => {
+                    self.partial = Partial::Stats { user, fields: vec![] };
+                    Feed::Consumed
+                }
+                None => Feed::NotMine,
+            },
+            (QueryKind::Stats, Partial::Stats { fields, .. }) => match parse_stats_field(text) {
+                Some(field) => {
+                    fields.push(field);
+                    Feed::Consumed
+                }
+                None => Feed::NotMine,
+            },
+            (QueryKind::Channels, Partial::Waiting) => {
+                if is_channels_header(text) {
+                    self.partial = Partial::Channels { channels: vec![] };
+                    Feed::Consumed
+                } else {
+                    Feed::NotMine
+                }
+            }
+            (QueryKind::Channels, Partial::Channels { channels }) => {
+                if is_channels_header(text) {
+                    Feed::Consumed
+                } else if let Some(channel) = parse_channel_line(text) {
+                    channels.push(channel);
+                    Feed::Consumed
+                } else {
+                    Feed::NotMine
+                }
+            }
+            (QueryKind::Time, Partial::Waiting) => match parse_time_line(text) {
+                Some((is_server, value)) => {
+                    self.partial = if is_server {
+                        Partial::Time { server: Some(value), local: None }
+                    } else {
+                        Partial::Time { server: None, local: Some(value) }
+                    };
+                    Feed::Consumed
+                }
+                None => Feed::NotMine,
+            },
+            (QueryKind::Time, Partial::Time { server, local }) => match parse_time_line(text) {
+                Some((true, value)) if server.is_none() => {
+                    *server = Some(value);
+                    Feed::Consumed
+                }
+                Some((false, value)) if local.is_none() => {
+                    *local = Some(value);
+                    Feed::Consumed
+                }
+                _ => Feed::NotMine,
+            },
+            _ => Feed::NotMine,
+        };
+        if matches!(feed, Feed::Consumed) {
+            self.last_line = Some(now);
+        }
+        feed
+    }
+
+    fn finish(self) -> Option<QueryResult> {
+        match self.partial {
+            Partial::Waiting => None,
+            Partial::Who { channel, users, idle } => Some(QueryResult::Who { channel, users, idle }),
+            Partial::Time { server: Some(server), local } => Some(QueryResult::Time { server, local }),
+            Partial::Time { server: None, .. } => None,
+            Partial::Friends { friends } => Some(QueryResult::Friends { friends }),
+            Partial::Stats { user, fields } => Some(QueryResult::Stats { user, fields }),
+            Partial::Channels { channels } => Some(QueryResult::Channels { channels }),
+        }
+    }
+
+    fn is_finished(&self, now: Instant) -> bool {
+        match self.last_line {
+            Some(last) => now.duration_since(last) >= QUIET_PERIOD,
+            None => now.duration_since(self.issued) >= QUERY_TIMEOUT,
+        }
+    }
+}
+
+/// What happened to an INFO line offered to the router.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Routed {
+    /// Not part of any pending answer: display it normally.
+    Unclaimed,
+    /// Swallowed into a result that isn't complete yet.
+    Consumed,
+    Completed(QueryResult),
+}
+
+/// Routes the answers of commands we issued into structured results.
+#[derive(Debug, Default)]
+pub struct QueryRouter {
+    pending: VecDeque<PendingQuery>,
+}
+
+impl QueryRouter {
+    /// Registers `command` if we know its answer grammar.
+    pub fn issue(&mut self, command: &str, now: Instant) -> bool {
+        match QueryKind::from_command(command) {
+            Some(kind) => {
+                self.pending.push_back(PendingQuery {
+                    kind,
+                    issued: now,
+                    last_line: None,
+                    partial: Partial::Waiting,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn route(&mut self, text: &str, now: Instant) -> Routed {
+        for index in 0..self.pending.len() {
+            match self.pending[index].feed(text, now) {
+                Feed::NotMine => continue,
+                Feed::Consumed => return Routed::Consumed,
+                Feed::Done(result) => {
+                    self.pending.remove(index);
+                    return Routed::Completed(result);
+                }
+            }
+        }
+        Routed::Unclaimed
+    }
+
+    /// Finishes queries that went quiet and drops ones that never got an answer.
+    pub fn expire(&mut self, now: Instant) -> Vec<QueryResult> {
+        let mut results = vec![];
+        let mut index = 0;
+        while index < self.pending.len() {
+            if self.pending[index].is_finished(now) {
+                if let Some(result) = self.pending.remove(index).and_then(PendingQuery::finish) {
+                    results.push(result);
+                }
+            } else {
+                index += 1;
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::throttle::shift_rows;
+
+    fn after(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn known_commands() {
+        assert_eq!(QueryKind::from_command("/who Clan X"), Some(QueryKind::Who));
+        assert_eq!(QueryKind::from_command("/time"), Some(QueryKind::Time));
+        assert_eq!(QueryKind::from_command("/users"), Some(QueryKind::Users));
+        assert_eq!(QueryKind::from_command("/astat Bob"), Some(QueryKind::Stats));
+        assert_eq!(QueryKind::from_command("/chs"), Some(QueryKind::Channels));
+        assert_eq!(QueryKind::from_command("/f l"), Some(QueryKind::Friends));
+        assert_eq!(QueryKind::from_command("/friends add Bob"), None);
+        assert_eq!(QueryKind::from_command("/join w3"), None);
+        assert_eq!(QueryKind::from_command("hello"), None);
+    }
+
+    #[test]
+    fn who_grammar() {
+        assert_eq!(parse_who_header("Users in channel Clan X:").as_deref(), Some("Clan X"));
+        assert_eq!(parse_who_header("Users in game w3"), None);
+        assert_eq!(
+            parse_who_names("Bob, [Alice], Carol"),
+            vec![("Bob".to_string(), None), ("Alice".to_string(), None), ("Carol".to_string(), None)]
+        );
+        assert_eq!(
+            parse_who_names("Bob (idle 5m), Carol (idle 00:01:10)"),
+            vec![("Bob".to_string(), Some(Duration::from_secs(300))), ("Carol".to_string(), Some(Duration::from_secs(70)))]
+        );
+        assert!(parse_who_names("Bob is away right now").is_empty());
+    }
+
+    #[test]
+    fn friends_grammar() {
+        assert!(is_friends_header("Your PvPGN - Friends List"));
+        assert!(!is_friends_header("Added Bob to your friends list."));
+        assert_eq!(
+            parse_friend_line("2: *Alice, using Warcraft III in channel \"w3\"."),
+            Some(Friend { name: "Alice".into(), online: true, status: "using Warcraft III in channel \"w3\"".into() })
+        );
+        assert_eq!(parse_friend_line("1: Bob, offline").map(|friend| friend.online), Some(false));
+        assert_eq!(parse_friend_line("Bob, offline"), None);
+    }
+
+    #[test]
+    fn stats_grammar() {
+        assert_eq!(parse_stats_header("Bob's record:").as_deref(), Some("Bob"));
+        assert_eq!(parse_stats_header("Bob's Ladder Record:").as_deref(), Some("Bob"));
+        assert_eq!(parse_stats_header("Bob's here"), None);
+        assert_eq!(parse_stats_field("Normal games: 10-5-0"), Some(("Normal games".into(), "10-5-0".into())));
+        assert_eq!(parse_stats_field("Empty:"), None);
+    }
+
+    #[test]
+    fn channels_grammar() {
+        assert!(is_channels_header("Currently accessible channels:"));
+        assert!(is_channels_header("-----------name----------- users ----admin/operator----"));
+        assert_eq!(parse_channel_line("w3                          12 - Bob, Alice"), Some(("w3".into(), 12)));
+        assert_eq!(parse_channel_line("Clan 1337 12 - Bob"), Some(("Clan 1337".into(), 12)));
+        assert_eq!(parse_channel_line("Clan 1337     4"), Some(("Clan 1337".into(), 4)));
+        assert_eq!(parse_channel_line("Chat"), None);
+    }
+
+    #[test]
+    fn time_and_users_grammar() {
+        assert_eq!(parse_time_line("Server Time: Thu Oct 16 12:00:00"), Some((true, "Thu Oct 16 12:00:00".into())));
+        assert_eq!(parse_time_line("Your local time: 14:00"), Some((false, "14:00".into())));
+        assert_eq!(parse_time_line("Uptime: 3 days"), None);
+        assert_eq!(
+            parse_users_line("There are currently 120 users online, in 14 games, and in 9 channels."),
+            Some(QueryResult::Users { users: 120, games: Some(14), channels: Some(9) })
+        );
+        assert_eq!(
+            parse_users_line("There are currently 3 users online."),
+            Some(QueryResult::Users { users: 3, games: None, channels: None })
+        );
+        assert_eq!(parse_users_line("3 users"), None);
+    }
+
+    #[test]
+    fn users_completes_on_its_line() {
+        let start = Instant::now();
+        let mut router = QueryRouter::default();
+        assert!(router.issue("/users", start));
+        assert_eq!(router.route("Welcome back", start), Routed::Unclaimed);
+        assert_eq!(
+            router.route("There are currently 5 users online.", start),
+            Routed::Completed(QueryResult::Users { users: 5, games: None, channels: None })
+        );
+        assert!(!router.has_pending());
+    }
+
+    #[test]
+    fn who_collects_until_the_server_goes_quiet() {
+        let start = Instant::now();
+        let mut router = QueryRouter::default();
+        router.issue("/who w3", start);
+        assert_eq!(router.route("Users in channel w3:", after(start, 10)), Routed::Consumed);
+        assert_eq!(router.route("Bob, Alice", after(start, 20)), Routed::Consumed);
+        assert_eq!(router.route("Carol", after(start, 30)), Routed::Consumed);
+        assert_eq!(router.route("Bob says hi there!", after(start, 40)), Routed::Unclaimed);
+        assert!(router.expire(after(start, 30) + QUIET_PERIOD - Duration::from_millis(1)).is_empty());
+        let results = router.expire(after(start, 30) + QUIET_PERIOD);
+        assert_eq!(
+            results,
+            vec![QueryResult::Who { channel: "w3".into(), users: vec!["Bob".into(), "Alice".into(), "Carol".into()], idle: HashMap::new() }]
+        );
+        assert!(!router.has_pending());
+    }
+
+    #[test]
+    fn unanswered_queries_time_out_and_late_lines_show_normally() {
+        let start = Instant::now();
+        let mut router = QueryRouter::default();
+        router.issue("/time", start);
+        assert!(router.expire(start + QUERY_TIMEOUT - Duration::from_millis(1)).is_empty());
+        assert!(router.has_pending());
+        assert!(router.expire(start + QUERY_TIMEOUT).is_empty());
+        assert!(!router.has_pending());
+        assert_eq!(router.route("Server Time: 12:00", start + QUERY_TIMEOUT), Routed::Unclaimed);
+    }
+
+    #[test]
+    fn time_needs_the_server_line() {
+        let start = Instant::now();
+        let mut router = QueryRouter::default();
+        router.issue("/time", start);
+        assert_eq!(router.route("Your local time: 14:00", start), Routed::Consumed);
+        assert!(router.expire(start + QUIET_PERIOD).is_empty());
+
+        router.issue("/time", start);
+        router.route("Server Time: 12:00", start);
+        router.route("Your local time: 14:00", start);
+        assert_eq!(router.route("Your local time: again", start), Routed::Unclaimed);
+        assert_eq!(
+            router.expire(start + QUIET_PERIOD),
+            vec![QueryResult::Time { server: "12:00".into(), local: Some("14:00".into()) }]
+        );
+    }
+
+    #[test]
+    fn each_line_goes_to_the_query_whose_grammar_fits() {
+        let start = Instant::now();
+        let mut router = QueryRouter::default();
+        router.issue("/who w3", start);
+        router.issue("/stats Bob", start);
+        assert_eq!(router.route("Bob's record:", start), Routed::Consumed);
+        assert_eq!(router.route("Users in channel w3:", start), Routed::Consumed);
+        assert_eq!(router.route("Wins: 3", start), Routed::Consumed);
+        let results = router.expire(start + QUIET_PERIOD);
+        assert_eq!(results.len(), 2);
+        assert!(results.contains(&QueryResult::Stats { user: "Bob".into(), fields: vec![("Wins".into(), "3".into())] }));
+    }
+
+    #[test]
+    fn summaries() {
+        let who = QueryResult::Who { channel: "w3".into(), users: vec!["Bob".into(), "Alice".into()], idle: HashMap::new() };
+        assert_eq!(who.summary(), "Users in w3 (2): Bob, Alice");
+        assert_eq!(QueryResult::Time { server: "12:00".into(), local: None }.summary(), "Server time: 12:00");
+        assert_eq!(QueryResult::Users { users: 9, games: Some(2), channels: None }.summary(), "9 users online, 2 games");
+        assert_eq!(QueryResult::Channels { channels: vec![("w3".into(), 4)] }.summary(), "Channels (1): w3 (4)");
+    }
+
+    #[test]
+    fn cards_follow_their_rows_when_old_rows_are_trimmed() {
+        let time = QueryResult::Time { server: "12:00".into(), local: None };
+        let users = QueryResult::Users { users: 1, games: None, channels: None };
+        let cards = HashMap::from([(2, time), (7, users.clone())]);
+        assert_eq!(shift_rows(cards, 3), HashMap::from([(4, users)]));
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Bounds for hand-edited or typed-in limits.
+pub const MAX_BURST: u32 = 20;
+pub const MAX_WINDOW_SECS: u32 = 30;
+
+/// How fast we may send: `messages` lines per `seconds`. PvPGN squelches or
+/// drops clients that go faster than its flood limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub messages: u32,
+    pub seconds: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit { messages: 4, seconds: 2 }
+    }
+}
+
+impl RateLimit {
+    pub fn clamp(&mut self) {
+        self.messages = self.messages.clamp(1, MAX_BURST);
+        self.seconds = self.seconds.clamp(1, MAX_WINDOW_SECS);
+    }
+
+    fn per_second(&self) -> f32 {
+        self.messages.max(1) as f32 / self.seconds.max(1) as f32
+    }
+}
+
+/// Token bucket: a full `messages` burst goes out at once, so a single line
+/// is never held back; after that lines trickle out at the limit's rate.
+#[derive(Debug)]
+pub struct RateLimiter {
+    tokens: f32,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: &RateLimit, now: Instant) -> RateLimiter {
+        RateLimiter { tokens: limit.messages as f32, refilled: now }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let earned = now.duration_since(self.refilled).as_secs_f32() * limit.per_second();
+        self.tokens = (self.tokens + earned).min(limit.messages as f32);
+        self.refilled = now;
+    }
+
+    /// Uses up one line's allowance, if there is one left.
+    pub fn take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How long until the next line may go out.
+    pub fn wait(&mut self, limit: &RateLimit, now: Instant) -> Duration {
+        self.refill(limit, now);
+        Duration::from_secs_f32(((1.0 - self.tokens) / limit.per_second()).max(0.0))
+    }
+}
+
+/// Lines held back by the rate limit, sent in the order they were offered:
+/// a new line never overtakes queued ones, even when a token is free.
+#[derive(Debug)]
+pub struct Outbox<T> {
+    queue: VecDeque<T>,
+    limiter: RateLimiter,
+}
+
+impl<T> Outbox<T> {
+    pub fn new(limit: &RateLimit, now: Instant) -> Outbox<T> {
+        Outbox { queue: VecDeque::new(), limiter: RateLimiter::new(limit, now) }
+    }
+
+    /// Hands `item` back when it may go out now, otherwise queues it.
+    pub fn offer(&mut self, item: T, limit: &RateLimit, now: Instant) -> Option<T> {
+        if self.queue.is_empty() && self.limiter.take(limit, now) {
+            Some(item)
+        } else {
+            self.queue.push_back(item);
+            None
+        }
+    }
+
+    /// The oldest queued item, once the limit lets it go.
+    pub fn next_ready(&mut self, limit: &RateLimit, now: Instant) -> Option<T> {
+        if self.queue.is_empty() || !self.limiter.take(limit, now) {
+            return None;
+        }
+        self.queue.pop_front()
+    }
+
+    /// How long until the next queued item may go, when any are queued.
+    pub fn wait(&mut self, limit: &RateLimit, now: Instant) -> Option<Duration> {
+        (!self.queue.is_empty()).then(|| self.limiter.wait(limit, now))
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Empties the queue, returning what was still in it.
+    pub fn clear(&mut self) -> VecDeque<T> {
+        std::mem::take(&mut self.queue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn a_full_burst_goes_out_at_once() {
+        let limit = RateLimit::default();
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(&limit, start);
+        for _ in 0..limit.messages {
+            assert!(limiter.take(&limit, start));
+        }
+        assert!(!limiter.take(&limit, start));
+    }
+
+    #[test]
+    fn tokens_come_back_at_the_limit_rate() {
+        // 4 lines per 2 seconds: one every half second.
+        let limit = RateLimit::default();
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(&limit, start);
+        while limiter.take(&limit, start) {}
+        assert_eq!(limiter.wait(&limit, start), Duration::from_millis(500));
+        assert!(!limiter.take(&limit, at(start, 400)));
+        assert!(limiter.take(&limit, at(start, 500)));
+        assert!(!limiter.take(&limit, at(start, 500)));
+        assert!(limiter.wait(&limit, at(start, 750)) <= Duration::from_millis(250));
+    }
+
+    #[test]
+    fn an_idle_bucket_holds_no_more_than_one_burst() {
+        let limit = RateLimit { messages: 2, seconds: 1 };
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(&limit, start);
+        let later = at(start, 60_000);
+        assert_eq!(limiter.wait(&limit, later), Duration::ZERO);
+        assert!(limiter.take(&limit, later));
+        assert!(limiter.take(&limit, later));
+        assert!(!limiter.take(&limit, later));
+    }
+
+    #[test]
+    fn limits_are_clamped_to_sane_bounds() {
+        let mut limit = RateLimit { messages: 0, seconds: 0 };
+        limit.clamp();
+        assert_eq!(limit, RateLimit { messages: 1, seconds: 1 });
+        let mut limit = RateLimit { messages: 500, seconds: 500 };
+        limit.clamp();
+        assert_eq!(limit, RateLimit { messages: MAX_BURST, seconds: MAX_WINDOW_SECS });
+    }
+
+    #[test]
+    fn queued_lines_keep_their_order() {
+        let limit = RateLimit { messages: 2, seconds: 1 };
+        let start = Instant::now();
+        let mut outbox = Outbox::new(&limit, start);
+        assert_eq!(outbox.offer("a", &limit, start), Some("a"));
+        assert_eq!(outbox.offer("b", &limit, start), Some("b"));
+        assert_eq!(outbox.offer("c", &limit, start), None);
+        assert_eq!(outbox.offer("d", &limit, start), None);
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(outbox.next_ready(&limit, start), None);
+        assert_eq!(outbox.wait(&limit, start), Some(Duration::from_millis(500)));
+
+        // A token is free again, but "e" still waits behind "c" and "d".
+        let later = at(start, 500);
+        assert_eq!(outbox.offer("e", &limit, later), None);
+        assert_eq!(outbox.next_ready(&limit, later), Some("c"));
+        assert_eq!(outbox.next_ready(&limit, at(start, 1000)), Some("d"));
+        assert_eq!(outbox.next_ready(&limit, at(start, 1500)), Some("e"));
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.wait(&limit, at(start, 1500)), None);
+    }
+
+    #[test]
+    fn clearing_hands_back_the_unsent_lines() {
+        let limit = RateLimit { messages: 1, seconds: 1 };
+        let start = Instant::now();
+        let mut outbox = Outbox::new(&limit, start);
+        outbox.offer(1, &limit, start);
+        outbox.offer(2, &limit, start);
+        outbox.offer(3, &limit, start);
+        assert_eq!(outbox.clear(), [2, 3]);
+        assert!(outbox.is_empty());
+        // The token spent on 1 is still spent.
+        assert_eq!(outbox.offer(4, &limit, start), None);
+    }
+}
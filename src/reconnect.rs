@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant, SystemTime};
+
+/// Wait before each reconnect attempt; the last step repeats.
+pub const BACKOFF: [Duration; 4] = [
+    Duration::from_secs(2),
+    Duration::from_secs(5),
+    Duration::from_secs(15),
+    Duration::from_secs(30),
+];
+
+pub fn delay(attempt: usize) -> Duration {
+    BACKOFF[attempt.min(BACKOFF.len() - 1)]
+}
+
+/// Where a dropped connection is in getting back.
+#[derive(Debug)]
+pub struct Reconnect {
+    /// Failed attempts so far.
+    pub attempt: usize,
+    pub next_at: Instant,
+    /// When the connection dropped, for the gap marker.
+    pub since: SystemTime,
+    /// Set while an attempt is running in the background.
+    pub in_flight: bool,
+}
+
+impl Reconnect {
+    pub fn new(now: Instant) -> Reconnect {
+        Reconnect {
+            attempt: 0,
+            next_at: now + delay(0),
+            since: SystemTime::now(),
+            in_flight: false,
+        }
+    }
+
+    pub fn failed(&mut self, now: Instant) {
+        self.attempt += 1;
+        self.in_flight = false;
+        self.next_at = now + delay(self.attempt);
+    }
+
+    pub fn due(&self, now: Instant) -> bool {
+        !self.in_flight && now >= self.next_at
+    }
+
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.next_at.saturating_duration_since(now)
+    }
+}
@@ -0,0 +1,102 @@
+//! "Report to ops": who gets the report and what it says.
+
+use time::OffsetDateTime;
+
+use crate::ops::UserFlags;
+
+/// Never whisper a report to more than this many operators.
+pub const MAX_RECIPIENTS: usize = 5;
+
+/// Channel operators present in `roster`, deduplicated and capped, or the
+/// configured ops account when there is one.
+pub fn op_recipients<'a>(
+    roster: impl IntoIterator<Item = (&'a str, UserFlags)>,
+    own_name: &str,
+    configured: &str,
+) -> Vec<String> {
+    let configured = configured.trim();
+    if !configured.is_empty() {
+        return vec![configured.to_string()];
+    }
+    let mut ops: Vec<String> = roster
+        .into_iter()
+        .filter(|(name, flags)| flags.contains(UserFlags::CHANNEL_OP) && !name.eq_ignore_ascii_case(own_name))
+        .map(|(name, _)| name.to_string())
+        .collect();
+    ops.sort_by_key(|name| name.to_lowercase());
+    ops.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+    ops.truncate(MAX_RECIPIENTS);
+    ops
+}
+
+/// `12:03:04 UTC` for a unix timestamp.
+pub fn format_timestamp(unix: u64) -> String {
+    match OffsetDateTime::from_unix_timestamp(unix as i64) {
+        Ok(at) => format!("{:02}:{:02}:{:02} UTC", at.hour(), at.minute(), at.second()),
+        Err(_) => "??:??:?? UTC".to_string(),
+    }
+}
+
+/// Report text, one whisper-sized item per entry before splitting:
+/// a header naming the user and channel, the note, then each quoted line.
+pub fn format_report(offender: &str, channel: &str, note: &str, lines: &[(u64, String)]) -> Vec<String> {
+    let mut report = vec![format!("[Report] {} in #{} ({} lines)", offender, channel, lines.len())];
+    let note = note.trim();
+    if !note.is_empty() {
+        report.push(format!("[Report] Note: {}", note));
+    }
+    for (timestamp, text) in lines {
+        report.push(format!("[Report] {} {}", format_timestamp(*timestamp), text));
+    }
+    report
+}
+
+/// Draft shown in the composer window.
+#[derive(Debug)]
+pub struct ReportDraft {
+    pub offender: String,
+    /// Candidate lines with whether they go into the report.
+    pub lines: Vec<(u64, String, bool)>,
+    pub note: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OP: UserFlags = UserFlags::CHANNEL_OP;
+
+    #[test]
+    fn reports_go_to_the_channel_ops_but_not_us() {
+        let plain = UserFlags::default();
+        let roster = [("Zed", OP), ("Bob", plain), ("Me", OP), ("alice", OP), ("ALICE", OP)];
+        assert_eq!(op_recipients(roster, "me", ""), vec!["alice", "Zed"]);
+    }
+
+    #[test]
+    fn a_configured_account_replaces_the_roster() {
+        assert_eq!(op_recipients([("Zed", OP)], "Me", "  OpsBot "), vec!["OpsBot"]);
+    }
+
+    #[test]
+    fn recipients_are_capped() {
+        let names: Vec<String> = (0..MAX_RECIPIENTS + 3).map(|n| format!("Op{}", n)).collect();
+        let recipients = op_recipients(names.iter().map(|name| (name.as_str(), OP)), "Me", "");
+        assert_eq!(recipients.len(), MAX_RECIPIENTS);
+    }
+
+    #[test]
+    fn report_layout() {
+        let lines = [(3723, "buy gold at spam.example".to_string()), (3724, "SPAM".to_string())];
+        assert_eq!(
+            format_report("Bob", "w3", " spamming links ", &lines),
+            vec![
+                "[Report] Bob in #w3 (2 lines)",
+                "[Report] Note: spamming links",
+                "[Report] 01:02:03 UTC buy gold at spam.example",
+                "[Report] 01:02:04 UTC SPAM",
+            ]
+        );
+        assert_eq!(format_report("Bob", "w3", "", &[]), vec!["[Report] Bob in #w3 (0 lines)"]);
+    }
+}
@@ -0,0 +1,212 @@
+use std::borrow::Cow;
+
+use crate::emoji::EMOTICONS;
+
+/// One run of a message body with a single look. Segments borrow from the
+/// message, so splitting never copies it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Plain(&'a str),
+    /// Between `**` markers, which are dropped.
+    Bold(&'a str),
+    /// Between single `*` or `_` markers, which are dropped.
+    Italic(&'a str),
+    /// As written, without trailing punctuation; see [`url_target`].
+    Url(&'a str),
+    /// The picture standing in for a text smiley.
+    Emoticon(&'static str),
+}
+
+const SCHEMES: [&str; 2] = ["http://", "https://"];
+
+/// Links without a scheme are recognised by this prefix and opened over https.
+const WWW: &str = "www.";
+
+/// Characters that end a sentence rather than a link when they come last.
+const TRAILING: &str = ".,;:!?'\"";
+
+/// Displayed links longer than this many characters lose their middle.
+pub const URL_DISPLAY_CHARS: usize = 60;
+
+/// Splits `text` into segments in one left-to-right pass. Markup only counts
+/// at word boundaries, so `snake_case_names` and `2*3*4` stay plain, and
+/// nothing inside a URL is taken for markup or a smiley. Smileys stay text
+/// unless `emoticons` is set.
+pub fn tokenize(text: &str, emoticons: bool) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut plain_start = 0;
+    let mut at = 0;
+    while at < text.len() {
+        let rest = &text[at..];
+        let word_start = text[..at].chars().next_back().is_none_or(opens_word);
+        let found = if word_start { markup_at(rest, emoticons) } else { None };
+        match found {
+            Some((segment, len)) => {
+                if plain_start < at {
+                    segments.push(Segment::Plain(&text[plain_start..at]));
+                }
+                segments.push(segment);
+                at += len;
+                plain_start = at;
+            }
+            None => at += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    if plain_start < text.len() {
+        segments.push(Segment::Plain(&text[plain_start..]));
+    }
+    segments
+}
+
+/// Whether markup may start right after `c`.
+fn opens_word(c: char) -> bool {
+    c.is_whitespace() || "([{<\"'".contains(c)
+}
+
+/// Whether markup may end right before `c`.
+fn closes_word(c: char) -> bool {
+    !c.is_alphanumeric() && c != '_' && c != '*'
+}
+
+/// The segment starting at the front of `rest`, if any, and how many bytes
+/// of `rest` it covers.
+fn markup_at(rest: &str, emoticons: bool) -> Option<(Segment<'_>, usize)> {
+    if let Some(prefix) = SCHEMES.iter().chain([&WWW]).find(|prefix| starts_with_ignore_case(rest, prefix)) {
+        let url = link_end(&rest[..rest.find(char::is_whitespace).unwrap_or(rest.len())]);
+        // A bare scheme is not a link.
+        if url.len() > prefix.len() {
+            return Some((Segment::Url(url), url.len()));
+        }
+    }
+    if let Some(inner) = delimited(rest, "**") {
+        return Some((Segment::Bold(inner), inner.len() + 4));
+    }
+    for marker in ["*", "_"] {
+        if let Some(inner) = delimited(rest, marker) {
+            return Some((Segment::Italic(inner), inner.len() + 2));
+        }
+    }
+    if !emoticons {
+        return None;
+    }
+    EMOTICONS.iter().find_map(|(smiley, picture)| {
+        let after = rest.strip_prefix(smiley)?;
+        after.chars().next().is_none_or(closes_word).then_some((Segment::Emoticon(picture), smiley.len()))
+    })
+}
+
+/// The text between `marker` at the front of `rest` and the nearest closing
+/// `marker` on a word boundary. Empty or space-padded spans don't count.
+fn delimited<'a>(rest: &'a str, marker: &str) -> Option<&'a str> {
+    let body = rest.strip_prefix(marker)?;
+    if body.starts_with(char::is_whitespace) || body.starts_with(marker) {
+        return None;
+    }
+    body.match_indices(marker).map(|(end, _)| end).find(|&end| {
+        let inner = &body[..end];
+        !inner.is_empty()
+            && !inner.ends_with(char::is_whitespace)
+            && body[end + marker.len()..].chars().next().is_none_or(closes_word)
+    }).map(|end| &body[..end])
+}
+
+/// `word` without the punctuation that follows a link in prose: `,` or `.`
+/// at the end of a sentence, and a `)` that closes a parenthesis opened
+/// before the link rather than inside it.
+fn link_end(word: &str) -> &str {
+    let mut url = word;
+    loop {
+        let trimmed = url.trim_end_matches(|c| TRAILING.contains(c));
+        url = match trimmed.strip_suffix(')') {
+            Some(inner) if inner.matches('(').count() < trimmed.matches(')').count() => inner,
+            _ => return trimmed,
+        };
+    }
+}
+
+/// Where a link segment leads: `www.` links get the https scheme.
+pub fn url_target(url: &str) -> Cow<'_, str> {
+    if starts_with_ignore_case(url, WWW) {
+        Cow::Owned(format!("https://{}", url))
+    } else {
+        Cow::Borrowed(url)
+    }
+}
+
+/// `url` cut down to `max` characters by replacing its middle with `…`, so
+/// both the host and the end of the path stay visible.
+pub fn elide(url: &str, max: usize) -> Cow<'_, str> {
+    let count = url.chars().count();
+    if count <= max {
+        return Cow::Borrowed(url);
+    }
+    let head = (max - 1) * 2 / 3;
+    let tail = max - 1 - head;
+    let tail_start = url.char_indices().nth(count - tail).map_or(url.len(), |(at, _)| at);
+    let head_end = url.char_indices().nth(head).map_or(url.len(), |(at, _)| at);
+    Cow::Owned(format!("{}…{}", &url[..head_end], &url[tail_start..]))
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Segment::*;
+
+    #[test]
+    fn bold_and_italic_drop_their_markers() {
+        assert_eq!(
+            tokenize("hello **world** and *you* or _them_", false),
+            vec![Plain("hello "), Bold("world"), Plain(" and "), Italic("you"), Plain(" or "), Italic("them")]
+        );
+        assert_eq!(tokenize("é *ü*", false), vec![Plain("é "), Italic("ü")]);
+    }
+
+    #[test]
+    fn markup_only_counts_at_word_boundaries() {
+        for text in ["snake_case_names", "2*3*4", "** not bold**", "* spaced *", "**", "a**b**"] {
+            assert_eq!(tokenize(text, false), vec![Plain(text)], "{}", text);
+        }
+        assert_eq!(tokenize("(*aside*)", false), vec![Plain("("), Italic("aside"), Plain(")")]);
+    }
+
+    #[test]
+    fn links_leave_out_trailing_punctuation() {
+        assert_eq!(
+            tokenize("see https://example.com/a_b*c*, ok", false),
+            vec![Plain("see "), Url("https://example.com/a_b*c*"), Plain(", ok")]
+        );
+        assert_eq!(tokenize("(www.example.com/x)", false), vec![Plain("("), Url("www.example.com/x"), Plain(")")]);
+        assert_eq!(
+            tokenize("HTTPS://en.wikipedia.org/wiki/Rust_(language).", false),
+            vec![Url("HTTPS://en.wikipedia.org/wiki/Rust_(language)"), Plain(".")]
+        );
+        assert_eq!(tokenize("https:// alone", false), vec![Plain("https:// alone")]);
+    }
+
+    #[test]
+    fn smileys_only_when_asked_for() {
+        assert_eq!(tokenize("hi :) there", true), vec![Plain("hi "), Emoticon("🙂"), Plain(" there")]);
+        assert_eq!(tokenize("hi :) there", false), vec![Plain("hi :) there")]);
+        // Not inside a word or a link.
+        assert_eq!(tokenize("a:)b", true), vec![Plain("a:)b")]);
+        assert_eq!(tokenize("http://x.org/a:)b", true), vec![Url("http://x.org/a:)b")]);
+    }
+
+    #[test]
+    fn link_targets_and_display() {
+        assert_eq!(url_target("www.example.com"), "https://www.example.com");
+        assert_eq!(url_target("http://example.com"), "http://example.com");
+        assert_eq!(elide("https://example.com/very/long/path", 20), "https://exam…ng/path");
+        assert_eq!(elide("https://example.com", URL_DISPLAY_CHARS), "https://example.com");
+    }
+
+    #[test]
+    fn plain_text_comes_back_whole() {
+        assert_eq!(tokenize("", true), vec![]);
+        assert_eq!(tokenize("just words", true), vec![Plain("just words")]);
+    }
+}
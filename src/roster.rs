@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+/// Who is in the channel. Names are trimmed and compared without regard to
+/// case, so `Bob` and `bob ` are one user; the spelling from the latest
+/// line about them is the one shown.
+#[derive(Debug, Default)]
+pub struct Roster {
+    /// Display name by [`key`].
+    users: HashMap<String, String>,
+}
+
+/// How a name is looked up: trimmed and lowercased.
+pub fn key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// The row for someone joining or leaving, before any folding.
+pub fn join_leave_text(name: &str, joined: bool) -> String {
+    if joined {
+        format!("{} has joined the channel", name)
+    } else {
+        format!("{} has left the channel", name)
+    }
+}
+
+impl Roster {
+    pub fn insert(&mut self, name: &str) {
+        let name = name.trim();
+        if !name.is_empty() {
+            self.users.insert(key(name), name.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.users.remove(&key(name)).is_some()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.users.contains_key(&key(name))
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.users.clear();
+    }
+
+    /// Display names, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.users.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{parse, ServerMessage};
+
+    /// Feeds server lines through the roster as the channel view does,
+    /// returning the join and leave rows they make.
+    fn feed(roster: &mut Roster, lines: &[&str]) -> Vec<String> {
+        let mut rows = Vec::new();
+        for line in lines {
+            match parse(line).unwrap() {
+                ServerMessage::User(info) => roster.insert(&info.name),
+                ServerMessage::Join(info) => {
+                    roster.insert(&info.name);
+                    rows.push(join_leave_text(info.name.trim(), true));
+                }
+                ServerMessage::Leave { name } => {
+                    roster.remove(&name);
+                    rows.push(join_leave_text(name.trim(), false));
+                }
+                other => panic!("not a roster line: {:?}", other),
+            }
+        }
+        rows
+    }
+
+    fn names(roster: &Roster) -> Vec<&str> {
+        let mut names: Vec<_> = roster.iter().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn case_and_whitespace_variants_are_one_user() {
+        let mut roster = Roster::default();
+        roster.insert("Bob");
+        roster.insert(" bob ");
+        roster.insert("BOB");
+        assert_eq!(roster.len(), 1);
+        assert!(roster.contains("bob\t"));
+        // The latest spelling is the one shown.
+        assert_eq!(names(&roster), ["BOB"]);
+        assert!(roster.remove("Bob "));
+        assert!(!roster.remove("Bob"));
+        roster.insert("   ");
+        assert_eq!(roster.len(), 0);
+    }
+
+    #[test]
+    fn a_quick_leave_and_rejoin_keeps_the_user() {
+        let mut roster = Roster::default();
+        let rows = feed(
+            &mut roster,
+            &["1001 USER Bob 0010 [W3XP]", "1001 USER Alice 0010 [CHAT]", "1003 LEAVE Bob 0010", "1002 JOIN bob 0010 [W3XP]"],
+        );
+        assert_eq!(names(&roster), ["Alice", "bob"]);
+        assert_eq!(rows, ["Bob has left the channel", "bob has joined the channel"]);
+    }
+
+    #[test]
+    fn a_join_for_someone_listed_does_not_duplicate_them() {
+        let mut roster = Roster::default();
+        let rows = feed(&mut roster, &["1001 USER Bob 0010", "1002 JOIN Bob 0010 [W3XP]", "1001 USER bob 0010"]);
+        assert_eq!(names(&roster), ["bob"]);
+        assert_eq!(rows, ["Bob has joined the channel"]);
+    }
+
+    #[test]
+    fn interleaved_joins_and_leaves_settle_on_who_stayed() {
+        let mut roster = Roster::default();
+        let rows = feed(
+            &mut roster,
+            &[
+                "1002 JOIN Alice 0010",
+                "1002 JOIN [Clan] Bob 0010",
+                "1003 LEAVE Alice 0010",
+                "1002 JOIN Carol 0010",
+                "1003 LEAVE [clan] bob",
+                "1002 JOIN Alice 0010",
+            ],
+        );
+        assert_eq!(names(&roster), ["Alice", "Carol"]);
+        assert!(!roster.contains("[Clan] Bob"));
+        assert_eq!(rows.len(), 6);
+        assert_eq!(rows[4], "[clan] bob has left the channel");
+    }
+
+    #[test]
+    fn keys_are_trimmed_and_lowercased() {
+        assert_eq!(key("  [Clan] Bob "), "[clan] bob");
+        assert_eq!(key("Bob#2"), "bob#2");
+    }
+}
@@ -0,0 +1,69 @@
+/// Remembered heights of message rows, so the list can lay out only the rows
+/// inside the viewport and still know where every other row sits.
+///
+/// Rows not yet drawn count as `estimate`; `key` captures what changes row
+/// heights (width, focus mode, density) and resets the measurements.
+#[derive(Debug)]
+pub struct RowHeights<K> {
+    heights: Vec<Option<f32>>,
+    key: Option<K>,
+}
+
+impl<K> Default for RowHeights<K> {
+    fn default() -> Self {
+        RowHeights { heights: vec![], key: None }
+    }
+}
+
+impl<K: PartialEq> RowHeights<K> {
+    pub fn sync(&mut self, rows: usize, key: K) {
+        if self.key.as_ref() != Some(&key) {
+            self.heights.clear();
+            self.key = Some(key);
+        }
+        self.heights.resize(rows, None);
+    }
+
+    /// Mirrors dropping the oldest `excess` messages.
+    pub fn trim(&mut self, excess: usize) {
+        let excess = excess.min(self.heights.len());
+        self.heights.drain(..excess);
+    }
+
+    /// Records a measured height and says whether it differed from before.
+    pub fn set(&mut self, row: usize, height: f32) -> bool {
+        match self.heights.get_mut(row) {
+            Some(slot) if *slot != Some(height) => {
+                *slot = Some(height);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn height(&self, row: usize, estimate: f32) -> f32 {
+        self.heights.get(row).copied().flatten().unwrap_or(estimate)
+    }
+
+    pub fn total(&self, estimate: f32) -> f32 {
+        (0..self.heights.len()).map(|row| self.height(row, estimate)).sum()
+    }
+
+    /// Top of `row`, measured from the top of the first row.
+    pub fn offset_of(&self, row: usize, estimate: f32) -> f32 {
+        (0..row.min(self.heights.len())).map(|row| self.height(row, estimate)).sum()
+    }
+
+    /// The row containing `y` and where that row starts.
+    pub fn row_at(&self, y: f32, estimate: f32) -> (usize, f32) {
+        let mut top = 0.0;
+        for row in 0..self.heights.len() {
+            let height = self.height(row, estimate);
+            if top + height > y {
+                return (row, top);
+            }
+            top += height;
+        }
+        (self.heights.len(), top)
+    }
+}
@@ -0,0 +1,72 @@
+/// Find-in-buffer state: the query, which rows match and which one is current.
+#[derive(Debug, Default)]
+pub struct Search {
+    pub query: String,
+    matches: Vec<usize>,
+    current: usize,
+    /// Set when the current match changed and the view should scroll to it.
+    pub scroll_pending: bool,
+    /// Set when the bar opens so the query field takes keyboard focus.
+    pub focus_pending: bool,
+}
+
+impl Search {
+    pub fn new() -> Search {
+        Search {
+            focus_pending: true,
+            ..Search::default()
+        }
+    }
+
+    /// Recomputes matches against `rows`. Case-insensitive; rows include the
+    /// sender prefix, so searching for a name finds what they said.
+    pub fn refresh(&mut self, rows: impl Iterator<Item = impl AsRef<str>>) {
+        let query = self.query.to_lowercase();
+        self.matches = if query.is_empty() {
+            vec![]
+        } else {
+            rows.enumerate()
+                .filter(|(_, content)| content.as_ref().to_lowercase().contains(&query))
+                .map(|(row, _)| row)
+                .collect()
+        };
+        self.current = self.current.min(self.matches.len().saturating_sub(1));
+    }
+
+    /// Starts at the newest match, like scrolling up from the bottom.
+    pub fn jump_to_last(&mut self) {
+        self.current = self.matches.len().saturating_sub(1);
+        self.scroll_pending = !self.matches.is_empty();
+    }
+
+    pub fn next(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + 1) % self.matches.len();
+            self.scroll_pending = true;
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + self.matches.len() - 1) % self.matches.len();
+            self.scroll_pending = true;
+        }
+    }
+
+    pub fn current_row(&self) -> Option<usize> {
+        self.matches.get(self.current).copied()
+    }
+
+    pub fn is_match(&self, row: usize) -> bool {
+        self.matches.binary_search(&row).is_ok()
+    }
+
+    /// `3/17`, or `0/0` with no matches.
+    pub fn counter(&self) -> String {
+        if self.matches.is_empty() {
+            "0/0".to_string()
+        } else {
+            format!("{}/{}", self.current + 1, self.matches.len())
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::BTreeSet;
+
+/// Message rows picked for copying. Rows are held by message id, so the
+/// selection stays on the same rows as new ones arrive and old ones are
+/// trimmed.
+#[derive(Debug, Default)]
+pub struct Selection {
+    ids: BTreeSet<u64>,
+    /// The last row clicked; Shift+click selects from here.
+    anchor: Option<u64>,
+    /// Plain clicks select rows, set from the toolbar.
+    pub mode: bool,
+}
+
+impl Selection {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// Adds or removes one row and makes it the anchor.
+    pub fn toggle(&mut self, id: u64) {
+        if !self.ids.remove(&id) {
+            self.ids.insert(id);
+        }
+        self.anchor = Some(id);
+    }
+
+    /// Selects every row from the anchor to `id`, both included. Without an
+    /// anchor it's the same as toggling `id`.
+    pub fn extend_to(&mut self, id: u64) {
+        match self.anchor {
+            Some(anchor) => self.ids.extend(anchor.min(id)..=anchor.max(id)),
+            None => self.toggle(id),
+        }
+    }
+
+    pub fn select_all(&mut self, ids: impl Iterator<Item = u64>) {
+        self.ids.extend(ids);
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.anchor = None;
+    }
+
+    /// Forgets rows older than `first`, which were trimmed from the buffer.
+    pub fn forget_before(&mut self, first: u64) {
+        self.ids = self.ids.split_off(&first);
+        self.anchor = self.anchor.filter(|&anchor| anchor >= first);
+    }
+}
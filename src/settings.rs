@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::connect::TimeoutConfig;
+use crate::paths;
+use crate::presence::PresenceThresholds;
+use crate::rate_limit::RateLimit;
+use crate::slash::SlashChoice;
+use crate::status::AwayOnTalk;
+use crate::sound::SoundSettings;
+
+
+pub const DEFAULT_MAX_MESSAGES: usize = 1000;
+pub const LOW_RESOURCE_MAX_MESSAGES: usize = 300;
+pub const MIN_MAX_MESSAGES: usize = 100;
+pub const MAX_MAX_MESSAGES: usize = 50_000;
+pub const MIN_FONT_SCALE: f32 = 1.0;
+pub const MAX_FONT_SCALE: f32 = 1.75;
+/// How far Ctrl+= and Ctrl+- move the text size.
+pub const FONT_SCALE_STEP: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 2] = [Theme::Dark, Theme::Light];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+}
+
+/// Time shown in front of each message row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimestampFormat {
+    Off,
+    HourMinute,
+    HourMinuteSecond,
+}
+
+impl TimestampFormat {
+    pub const ALL: [TimestampFormat; 3] = [TimestampFormat::Off, TimestampFormat::HourMinute, TimestampFormat::HourMinuteSecond];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TimestampFormat::Off => "Off",
+            TimestampFormat::HourMinute => "12:04",
+            TimestampFormat::HourMinuteSecond => "12:04:05",
+        }
+    }
+
+    /// Local time of day, or `None` when timestamps are off.
+    pub fn format(&self, unix: u64, twelve_hour: bool) -> Option<String> {
+        match self {
+            TimestampFormat::Off => None,
+            TimestampFormat::HourMinute => clock::time_of_day(unix, false, twelve_hour),
+            TimestampFormat::HourMinuteSecond => clock::time_of_day(unix, true, twelve_hour),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UserSort {
+    Name,
+    Activity,
+    /// Least idle first; only offered when the server reports idle times.
+    Idle,
+    /// Admins and operators first.
+    Role,
+    /// In the order people joined; those already present keep the server's order.
+    JoinOrder,
+}
+
+impl UserSort {
+    pub const ALL: [UserSort; 5] = [UserSort::Name, UserSort::Role, UserSort::JoinOrder, UserSort::Activity, UserSort::Idle];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            UserSort::Name => "Name",
+            UserSort::Activity => "Recent activity",
+            UserSort::Idle => "Idle time",
+            UserSort::Role => "Role",
+            UserSort::JoinOrder => "Join order",
+        }
+    }
+}
+
+/// What happens to the line breaks in a message: the server ends a message
+/// at each one, so they can't go out as typed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MultilineSend {
+    /// Every line is a message of its own, sent a moment apart.
+    SplitLines,
+    /// Line breaks become spaces and it all goes out as one message.
+    Flatten,
+}
+
+impl MultilineSend {
+    pub const ALL: [MultilineSend; 2] = [MultilineSend::SplitLines, MultilineSend::Flatten];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MultilineSend::SplitLines => "One message per line",
+            MultilineSend::Flatten => "Join the lines",
+        }
+    }
+}
+
+/// Smallest window we restore, in points.
+const MIN_WINDOW_SIZE: (f32, f32) = (320.0, 240.0);
+
+/// Where the window was and how big, in points; the outer top-left corner
+/// and the inner size, as eframe reports them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl WindowGeometry {
+    /// Shrinks the window to fit a `monitor_width` × `monitor_height`
+    /// monitor and moves it fully onto it, so a window saved on a monitor
+    /// that is gone does not open off-screen.
+    pub fn clamped_to(&self, monitor_width: f32, monitor_height: f32) -> WindowGeometry {
+        let width = self.width.min(monitor_width).max(MIN_WINDOW_SIZE.0);
+        let height = self.height.min(monitor_height).max(MIN_WINDOW_SIZE.1);
+        WindowGeometry {
+            x: self.x.min(monitor_width - width).max(0.0),
+            y: self.y.min(monitor_height - height).max(0.0),
+            width,
+            height,
+        }
+    }
+}
+
+/// App-wide preferences persisted between sessions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub theme: Theme,
+    /// Name of a theme from the themes directory, used instead of `theme`
+    /// while that file loads.
+    pub custom_theme: Option<String>,
+    /// Black or white surfaces and strong colors, whatever the theme.
+    pub high_contrast: bool,
+    /// Multiplies every text size.
+    pub font_scale: f32,
+    pub timestamps: TimestampFormat,
+    /// 12-hour clock with AM/PM instead of 24-hour.
+    pub twelve_hour: bool,
+    /// Sender names in a color of their own.
+    pub color_names: bool,
+    /// Prefilled on the login form when no channel was remembered.
+    pub default_channel: String,
+    /// Comma-separated words that count as mentions besides our name.
+    pub highlight_words: String,
+    /// Enter sends and Shift+Enter starts a new line; off, Ctrl+Enter sends.
+    pub send_on_enter: bool,
+    pub multiline_send: MultilineSend,
+    /// One dense line per message, names in a column.
+    pub compact_messages: bool,
+    /// Show `:)` and friends in received messages as pictures.
+    pub convert_emoticons: bool,
+    /// Minutes without sending anything before a keepalive goes out; 0 turns it off.
+    pub keepalive_minutes: u32,
+    /// Seconds between latency probes; 0 turns measuring off.
+    pub latency_probe_seconds: u32,
+    /// Connect and login time limits, edited under Advanced on the login form.
+    pub timeouts: TimeoutConfig,
+    /// Try a login that failed on the network again after a pause.
+    pub auto_retry_login: bool,
+    /// Flood protection for everything we send.
+    pub rate_limit: RateLimit,
+    /// Ask before closing the window during a session.
+    pub confirm_close: bool,
+    pub show_status_bar: bool,
+    pub presence: PresenceThresholds,
+    pub show_active_count: bool,
+    pub user_sort: UserSort,
+    /// Dimmed idle time after each name in the user list.
+    pub show_idle_suffix: bool,
+    pub low_resource: bool,
+    /// No panel animations, spinner or typing hint.
+    pub reduced_motion: bool,
+    /// Tighter spacing between rows and widgets.
+    pub compact: bool,
+    pub max_messages: usize,
+    /// Remembered answers for unknown `/prefix` commands.
+    pub slash_choices: HashMap<String, SlashChoice>,
+    /// Ops account or bot that receives reports; empty means the channel's operators.
+    pub report_recipient: String,
+    /// Ignoring someone also squelches them on the server, which then
+    /// stops sending their messages at all.
+    pub squelch_on_ignore: bool,
+    pub sounds: SoundSettings,
+    /// Talking in the channel while marked away.
+    pub away_on_talk: AwayOnTalk,
+    /// Append every message to daily files under `logs/`.
+    pub chat_log: bool,
+    /// Window position and size when the app last closed.
+    pub window: Option<WindowGeometry>,
+    /// Width the user dragged the user list to.
+    pub sidebar_width: Option<f32>,
+    /// Keep the user list open however narrow the window.
+    pub sidebar_pinned: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            theme: Theme::Dark,
+            custom_theme: None,
+            high_contrast: false,
+            font_scale: 1.0,
+            timestamps: TimestampFormat::Off,
+            twelve_hour: false,
+            color_names: true,
+            default_channel: "w3".to_string(),
+            highlight_words: String::new(),
+            send_on_enter: true,
+            multiline_send: MultilineSend::SplitLines,
+            convert_emoticons: true,
+            compact_messages: false,
+            keepalive_minutes: 3,
+            latency_probe_seconds: 60,
+            timeouts: TimeoutConfig::default(),
+            auto_retry_login: false,
+            rate_limit: RateLimit::default(),
+            confirm_close: true,
+            show_status_bar: true,
+            presence: PresenceThresholds::default(),
+            show_active_count: true,
+            user_sort: UserSort::Name,
+            show_idle_suffix: false,
+            low_resource: false,
+            reduced_motion: false,
+            compact: false,
+            max_messages: DEFAULT_MAX_MESSAGES,
+            slash_choices: HashMap::new(),
+            report_recipient: String::new(),
+            squelch_on_ignore: true,
+            sounds: SoundSettings::default(),
+            away_on_talk: AwayOnTalk::Ask,
+            chat_log: false,
+            window: None,
+            sidebar_width: None,
+            sidebar_pinned: false,
+        }
+    }
+}
+
+impl Settings {
+    /// The low-resource profile sets exactly: reduced motion, compact density
+    /// and the smaller message cap (plus the 2 Hz repaint heartbeat keyed off
+    /// `low_resource` itself). Each option can still be tuned afterwards.
+    pub fn set_low_resource(&mut self, enabled: bool) {
+        self.low_resource = enabled;
+        self.reduced_motion = enabled;
+        self.compact = enabled;
+        self.max_messages = if enabled {
+            LOW_RESOURCE_MAX_MESSAGES
+        } else {
+            DEFAULT_MAX_MESSAGES
+        };
+    }
+
+    /// Moves the text size by `steps` keyboard steps, within its bounds.
+    /// Lands on whole steps so repeated presses come back to 1.0 exactly.
+    pub fn step_font_scale(&mut self, steps: i32) {
+        let scale = ((self.font_scale / FONT_SCALE_STEP).round() + steps as f32) * FONT_SCALE_STEP;
+        self.font_scale = scale.clamp(MIN_FONT_SCALE, MAX_FONT_SCALE);
+    }
+
+    /// Pulls hand-edited or out-of-range values back into their bounds.
+    pub fn clamp_limits(&mut self) {
+        self.max_messages = self.max_messages.clamp(MIN_MAX_MESSAGES, MAX_MAX_MESSAGES);
+        self.font_scale = self.font_scale.clamp(MIN_FONT_SCALE, MAX_FONT_SCALE);
+        self.timeouts.clamp();
+        self.rate_limit.clamp();
+    }
+
+    /// Reads the settings, carrying over the dark/light flag older versions
+    /// kept in `theme.json` and removing that file once it is folded in.
+    pub fn load() -> Settings {
+        let mut settings: Settings = std::fs::read_to_string(paths::config_file(paths::SETTINGS))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        let legacy_theme = paths::config_file(paths::LEGACY_THEME);
+        if let Some(dark) = std::fs::read_to_string(&legacy_theme)
+            .ok()
+            .and_then(|text| serde_json::from_str::<bool>(&text).ok())
+        {
+            settings.theme = if dark { Theme::Dark } else { Theme::Light };
+            settings.save();
+            let _ = std::fs::remove_file(legacy_theme);
+        }
+        settings.clamp_limits();
+        settings
+    }
+
+    pub fn save(&self) {
+        let path = paths::config_file(paths::SETTINGS);
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)
+            .and_then(|text| std::fs::write(&path, text));
+        if let Err(err) = result {
+            println!("Could not save settings to {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_resource_sets_exactly_its_options() {
+        let mut settings = Settings::default();
+        settings.set_low_resource(true);
+        assert_eq!(
+            settings,
+            Settings {
+                low_resource: true,
+                reduced_motion: true,
+                compact: true,
+                max_messages: LOW_RESOURCE_MAX_MESSAGES,
+                ..Settings::default()
+            }
+        );
+        settings.set_low_resource(false);
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn font_scale_steps_stay_in_bounds_and_come_back_to_one() {
+        let mut settings = Settings::default();
+        settings.step_font_scale(3);
+        settings.step_font_scale(-3);
+        assert_eq!(settings.font_scale, 1.0);
+        settings.step_font_scale(1000);
+        assert_eq!(settings.font_scale, MAX_FONT_SCALE);
+        settings.step_font_scale(-1000);
+        assert_eq!(settings.font_scale, MIN_FONT_SCALE);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let mut settings = Settings { max_messages: 0, font_scale: 40.0, ..Settings::default() };
+        settings.clamp_limits();
+        assert_eq!(settings.max_messages, MIN_MAX_MESSAGES);
+        assert_eq!(settings.font_scale, MAX_FONT_SCALE);
+    }
+
+    #[test]
+    fn survives_a_save_and_load() {
+        let mut settings = Settings { theme: Theme::Light, custom_theme: Some("Night".into()), ..Settings::default() };
+        settings.set_low_resource(true);
+        let text = serde_json::to_string_pretty(&settings).unwrap();
+        let loaded: Settings = serde_json::from_str(&text).unwrap();
+        assert_eq!(loaded, settings);
+        // Files from older versions miss fields; those take their defaults.
+        let partial: Settings = serde_json::from_str(r#"{"compact": true}"#).unwrap();
+        assert_eq!(partial, Settings { compact: true, ..Settings::default() });
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::is_server_command;
+
+/// What to do with input starting with an unknown `/` token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlashChoice {
+    Command,
+    PlainText,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Send the input as typed.
+    Send,
+    /// Send it as chat text instead of a command.
+    PlainText,
+    /// Unknown command and no remembered choice: ask about this prefix.
+    Confirm(String),
+}
+
+/// The leading `/token`, lowercased, if the input looks like a command.
+pub fn command_prefix(input: &str) -> Option<String> {
+    let token = input.split_whitespace().next()?;
+    if token.starts_with('/') && token.len() > 1 {
+        Some(token.to_lowercase())
+    } else {
+        None
+    }
+}
+
+pub fn dispatch(input: &str, remembered: &HashMap<String, SlashChoice>) -> Dispatch {
+    let prefix = match command_prefix(input) {
+        Some(prefix) => prefix,
+        None => return Dispatch::Send,
+    };
+    if is_server_command(&prefix) {
+        return Dispatch::Send;
+    }
+    match remembered.get(&prefix) {
+        Some(SlashChoice::Command) => Dispatch::Send,
+        Some(SlashChoice::PlainText) => Dispatch::PlainText,
+        None => Dispatch::Confirm(prefix),
+    }
+}
+
+/// The server only treats a line as a command when `/` is the first byte,
+/// so a leading space sends it as ordinary chat.
+pub fn as_plain_text(input: &str) -> String {
+    format!(" {}", input)
+}
+
+/// The inline "send as command or text?" question above the input.
+#[derive(Debug)]
+pub struct SlashPrompt {
+    pub prefix: String,
+    pub remember: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_leading_slash_token_is_a_prefix() {
+        assert_eq!(command_prefix("/Shrug oh well").as_deref(), Some("/shrug"));
+        assert_eq!(command_prefix("  /shrug").as_deref(), Some("/shrug"));
+        assert_eq!(command_prefix("/"), None);
+        assert_eq!(command_prefix("and/or"), None);
+        assert_eq!(command_prefix(""), None);
+    }
+
+    #[test]
+    fn server_commands_and_chat_go_out_as_typed() {
+        let remembered = HashMap::new();
+        assert_eq!(dispatch("/w Bob hi", &remembered), Dispatch::Send);
+        assert_eq!(dispatch("/WHO w3", &remembered), Dispatch::Send);
+        assert_eq!(dispatch("hello /there", &remembered), Dispatch::Send);
+    }
+
+    #[test]
+    fn unknown_commands_ask_until_a_choice_is_remembered() {
+        let mut remembered = HashMap::new();
+        assert_eq!(dispatch("/shrug oh well", &remembered), Dispatch::Confirm("/shrug".into()));
+        remembered.insert("/shrug".to_string(), SlashChoice::PlainText);
+        assert_eq!(dispatch("/Shrug again", &remembered), Dispatch::PlainText);
+        remembered.insert("/mycmd".to_string(), SlashChoice::Command);
+        assert_eq!(dispatch("/mycmd", &remembered), Dispatch::Send);
+        assert_eq!(dispatch("/other", &remembered), Dispatch::Confirm("/other".into()));
+    }
+
+    #[test]
+    fn plain_text_loses_the_leading_slash_position() {
+        let text = as_plain_text("/shrug");
+        assert_eq!(text, " /shrug");
+        assert!(!text.starts_with('/'));
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Rapid-fire messages play at most one cue per category in this window.
+pub const COALESCE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Cue {
+    Whisper,
+    Mention,
+    JoinLeave,
+    /// A watched user logged on or off.
+    Watch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoundSettings {
+    pub mute: bool,
+    pub whisper: bool,
+    pub mention: bool,
+    pub join_leave: bool,
+    pub watch: bool,
+}
+
+impl Default for SoundSettings {
+    fn default() -> Self {
+        SoundSettings {
+            mute: false,
+            whisper: true,
+            mention: true,
+            join_leave: false,
+            watch: true,
+        }
+    }
+}
+
+impl SoundSettings {
+    pub fn enabled(&self, cue: Cue) -> bool {
+        !self.mute
+            && match cue {
+                Cue::Whisper => self.whisper,
+                Cue::Mention => self.mention,
+                Cue::JoinLeave => self.join_leave,
+                Cue::Watch => self.watch,
+            }
+    }
+}
+
+/// Hands cues to the player thread, dropping ones that come too fast.
+#[derive(Debug, Default)]
+pub struct Alerts {
+    player: Option<Sender<Cue>>,
+    last_played: HashMap<Cue, Instant>,
+}
+
+impl Alerts {
+    pub fn new() -> Alerts {
+        Alerts {
+            player: player::spawn(),
+            last_played: HashMap::new(),
+        }
+    }
+
+    /// False when this build has no audio support or no output device was found.
+    pub fn available(&self) -> bool {
+        self.player.is_some()
+    }
+
+    pub fn play(&mut self, cue: Cue, settings: &SoundSettings, now: Instant) {
+        if !settings.enabled(cue) {
+            return;
+        }
+        let recent = self
+            .last_played
+            .get(&cue)
+            .is_some_and(|last| now.duration_since(*last) < COALESCE_WINDOW);
+        if recent {
+            return;
+        }
+        self.last_played.insert(cue, now);
+        if let Some(player) = &self.player {
+            if player.send(cue).is_err() {
+                self.player = None;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "sound")]
+mod player {
+    use std::io::Cursor;
+    use std::sync::mpsc::{channel, Sender};
+
+    use rodio::{Decoder, OutputStream, Source};
+
+    use super::Cue;
+
+    fn asset(cue: Cue) -> &'static [u8] {
+        match cue {
+            Cue::Whisper => include_bytes!("../assets/sounds/whisper.wav"),
+            Cue::Mention => include_bytes!("../assets/sounds/mention.wav"),
+            Cue::JoinLeave | Cue::Watch => include_bytes!("../assets/sounds/join.wav"),
+        }
+    }
+
+    /// The output stream has to live on the thread that opened it, so the
+    /// player owns it and plays cues as they arrive.
+    pub fn spawn() -> Option<Sender<Cue>> {
+        let (tx, rx) = channel::<Cue>();
+        let (ready_tx, ready_rx) = channel();
+        std::thread::spawn(move || {
+            let (_stream, handle) = match OutputStream::try_default() {
+                Ok(output) => {
+                    let _ = ready_tx.send(true);
+                    output
+                }
+                Err(err) => {
+                    println!("No audio output, sound alerts disabled: {}", err);
+                    let _ = ready_tx.send(false);
+                    return;
+                }
+            };
+            for cue in rx {
+                match Decoder::new(Cursor::new(asset(cue))) {
+                    Ok(source) => {
+                        if let Err(err) = handle.play_raw(source.convert_samples()) {
+                            println!("Could not play sound: {}", err);
+                        }
+                    }
+                    Err(err) => println!("Could not decode sound: {}", err),
+                }
+            }
+        });
+        match ready_rx.recv() {
+            Ok(true) => Some(tx),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "sound"))]
+mod player {
+    use std::sync::mpsc::Sender;
+
+    use super::Cue;
+
+    pub fn spawn() -> Option<Sender<Cue>> {
+        None
+    }
+}
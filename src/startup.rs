@@ -0,0 +1,270 @@
+use std::path::Path;
+
+use eframe::egui::FontDefinitions;
+
+use crate::credential_store::CredentialStore;
+use crate::paths;
+use crate::settings::Settings;
+use crate::theme::{self, CustomThemes};
+use crate::Credentials;
+
+/// Fonts egui bundles that the UI's icons and emoji come from.
+const BUNDLED_FONTS: [&str; 4] = ["Hack", "Ubuntu-Light", "NotoEmoji-Regular", "emoji-icon-font"];
+
+/// Outcome of one startup probe. `problem` is `None` when the probe passed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub problem: Option<String>,
+    /// What we did about the problem, shown next to it.
+    pub fallback: Option<&'static str>,
+}
+
+impl CheckResult {
+    fn ok(name: &'static str) -> CheckResult {
+        CheckResult { name, problem: None, fallback: None }
+    }
+
+    pub fn failed(name: &'static str, problem: String, fallback: &'static str) -> CheckResult {
+        CheckResult { name, problem: Some(problem), fallback: Some(fallback) }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+/// The per-user config directory must exist, or be creatable, and accept a
+/// new file, or nothing we save survives a restart. `None` is a platform
+/// without one, where saving falls back to the working directory.
+pub fn check_config_dir(dir: Option<&Path>) -> CheckResult {
+    let Some(dir) = dir else {
+        return CheckResult::failed(
+            "Config directory",
+            "this system has no per-user config directory".to_string(),
+            "Saving to the working directory instead",
+        );
+    };
+    let probe = dir.join(".bnetchat-write-test");
+    let written = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&probe, b"ok"))
+        .and_then(|_| std::fs::remove_file(&probe));
+    match written {
+        Ok(()) => CheckResult::ok("Config directory"),
+        Err(err) => CheckResult::failed(
+            "Config directory",
+            format!("{} is not writable: {}", dir.display(), err),
+            "Running without saving logins or keys",
+        ),
+    }
+}
+
+pub fn check_saved_login(path: &Path) -> CheckResult {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return CheckResult::ok("Saved login"),
+    };
+    match serde_json::from_str::<Credentials>(&text) {
+        Ok(_) => CheckResult::ok("Saved login"),
+        Err(err) => CheckResult::failed(
+            "Saved login",
+            format!("{} could not be read: {}", path.display(), err),
+            "Login form starts empty; the file is replaced on next login",
+        ),
+    }
+}
+
+pub fn check_credential_store() -> CheckResult {
+    match CredentialStore::load() {
+        Ok(_) => CheckResult::ok("Credential store"),
+        Err(err) => CheckResult::failed(
+            "Credential store",
+            format!("could not be opened: {}", err),
+            "Started with an empty store; encryption keys will be regenerated",
+        ),
+    }
+}
+
+/// Theme files that did not load, and the chosen custom theme if it is
+/// among them.
+pub fn check_themes(chosen: Option<&str>, custom: &CustomThemes) -> CheckResult {
+    let missing = chosen.filter(|name| !custom.themes.iter().any(|theme| theme.name == *name));
+    if custom.errors.is_empty() && missing.is_none() {
+        return CheckResult::ok("Theme files");
+    }
+    let mut problems = custom.errors.clone();
+    if let Some(name) = missing {
+        problems.push(format!("the chosen theme \"{}\" is not in {}", name, paths::themes_dir().display()));
+    }
+    let fallback = match missing {
+        Some(_) => "Using the built-in theme",
+        None => "Those files were skipped",
+    };
+    CheckResult::failed("Theme files", problems.join("; "), fallback)
+}
+
+/// The fonts egui ships with must be there; without them icons and emoji
+/// draw as boxes.
+pub fn check_fonts(fonts: &FontDefinitions) -> CheckResult {
+    let missing: Vec<&str> = BUNDLED_FONTS.into_iter().filter(|name| !fonts.font_data.contains_key(*name)).collect();
+    if missing.is_empty() {
+        return CheckResult::ok("Bundled fonts");
+    }
+    CheckResult::failed(
+        "Bundled fonts",
+        format!("missing {}", missing.join(", ")),
+        "Using whatever fonts are left; some icons may show as boxes",
+    )
+}
+
+/// Old files that stayed in the working directory after the move to the config directory.
+pub fn check_migration(failures: Vec<(String, std::io::Error)>) -> CheckResult {
+    if failures.is_empty() {
+        return CheckResult::ok("Moving old files");
+    }
+    let problem = failures
+        .iter()
+        .map(|(name, err)| format!("{}: {}", name, err))
+        .collect::<Vec<_>>()
+        .join(", ");
+    CheckResult::failed(
+        "Moving old files",
+        format!("could not move to {}: {}", paths::config_dir().display(), problem),
+        "The old copies were left in the working directory",
+    )
+}
+
+pub fn run_checks() -> Vec<CheckResult> {
+    vec![
+        check_migration(paths::migrate_legacy_files()),
+        check_config_dir(paths::user_config_dir().as_deref()),
+        check_saved_login(&paths::config_file(paths::CREDENTIALS)),
+        check_credential_store(),
+        check_themes(Settings::load().custom_theme.as_deref(), theme::custom()),
+        check_fonts(&FontDefinitions::default()),
+    ]
+}
+
+/// Plain text report of the failed checks, for pasting into a bug report.
+pub fn report(results: &[CheckResult]) -> String {
+    let mut report = format!("bnetchat {} startup issues\n", env!("CARGO_PKG_VERSION"));
+    for result in results.iter().filter(|result| !result.is_ok()) {
+        report.push_str(&format!(
+            "- {}: {}\n  -> {}\n",
+            result.name,
+            result.problem.as_deref().unwrap_or_default(),
+            result.fallback.unwrap_or_default(),
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::theme::AppTheme;
+
+    /// A fresh directory under the system temp dir, removed first.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bnetchat-startup-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writable_config_dir_passes_and_leaves_no_probe() {
+        let dir = scratch_dir("writable");
+        assert_eq!(check_config_dir(Some(&dir)), CheckResult::ok("Config directory"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn missing_config_dir_is_created() {
+        let dir = scratch_dir("created").join("nested").join("bnetchat");
+        assert!(check_config_dir(Some(&dir)).is_ok());
+        assert!(dir.is_dir());
+    }
+
+    #[test]
+    fn config_dir_under_a_file_fails() {
+        let file = scratch_dir("blocked").join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+        let result = check_config_dir(Some(&file.join("bnetchat")));
+        assert!(!result.is_ok());
+        assert_eq!(result.fallback, Some("Running without saving logins or keys"));
+        assert!(result.problem.unwrap().contains("is not writable"));
+    }
+
+    #[test]
+    fn no_config_dir_falls_back_to_the_working_directory() {
+        let result = check_config_dir(None);
+        assert!(!result.is_ok());
+        assert_eq!(result.fallback, Some("Saving to the working directory instead"));
+    }
+
+    #[test]
+    fn saved_login() {
+        let dir = scratch_dir("login");
+        let path = dir.join(paths::CREDENTIALS);
+        assert!(check_saved_login(&path).is_ok(), "no file is fine");
+        std::fs::write(&path, r#"{"server": "example.net", "user": "bob"}"#).unwrap();
+        assert!(check_saved_login(&path).is_ok());
+        std::fs::write(&path, "{ not json").unwrap();
+        let result = check_saved_login(&path);
+        assert_eq!(result.name, "Saved login");
+        assert!(!result.is_ok());
+    }
+
+    #[test]
+    fn migration_lists_each_failed_file() {
+        assert!(check_migration(vec![]).is_ok());
+        let failures = vec![
+            ("settings.json".to_string(), std::io::Error::other("denied")),
+            ("lists.json".to_string(), std::io::Error::other("busy")),
+        ];
+        let problem = check_migration(failures).problem.unwrap();
+        assert!(problem.ends_with("settings.json: denied, lists.json: busy"), "{}", problem);
+    }
+
+    #[test]
+    fn themes() {
+        let mine = AppTheme { name: "Mine".to_string(), ..AppTheme::dark() };
+        let loaded = CustomThemes { themes: vec![mine], errors: vec![] };
+        assert!(check_themes(None, &loaded).is_ok());
+        assert!(check_themes(Some("Mine"), &loaded).is_ok());
+
+        let missing = check_themes(Some("Gone"), &loaded);
+        assert_eq!(missing.fallback, Some("Using the built-in theme"));
+        assert!(missing.problem.unwrap().contains("\"Gone\""));
+
+        let broken = CustomThemes { themes: vec![], errors: vec!["bad.json: expected value".to_string()] };
+        let result = check_themes(None, &broken);
+        assert_eq!(result.problem.as_deref(), Some("bad.json: expected value"));
+        assert_eq!(result.fallback, Some("Those files were skipped"));
+    }
+
+    #[test]
+    fn fonts() {
+        assert!(check_fonts(&FontDefinitions::default()).is_ok());
+        let mut fonts = FontDefinitions::default();
+        fonts.font_data.remove("NotoEmoji-Regular");
+        fonts.font_data.remove("emoji-icon-font");
+        let result = check_fonts(&fonts);
+        assert_eq!(result.problem.as_deref(), Some("missing NotoEmoji-Regular, emoji-icon-font"));
+    }
+
+    #[test]
+    fn report_lists_only_failures() {
+        let results = [
+            CheckResult::ok("Config directory"),
+            CheckResult::failed("Saved login", "broken".to_string(), "Login form starts empty"),
+        ];
+        let report = report(&results);
+        assert!(report.starts_with("bnetchat "));
+        assert!(report.ends_with("- Saved login: broken\n  -> Login form starts empty\n"));
+        assert!(!report.contains("Config directory"));
+    }
+}
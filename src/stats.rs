@@ -0,0 +1,43 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// FPS is averaged over this trailing window.
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// Frame counters shown in Diagnostics, so the effect of low-resource mode
+/// can be checked instead of guessed.
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    recent: VecDeque<Instant>,
+    pub total_frames: u64,
+    /// Messages laid out in the most recent frame.
+    pub rows_drawn: usize,
+}
+
+impl FrameStats {
+    pub fn record(&mut self, now: Instant) {
+        self.total_frames += 1;
+        self.recent.push_back(now);
+        while self
+            .recent
+            .front()
+            .is_some_and(|first| now.duration_since(*first) > WINDOW)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn average_fps(&self) -> f32 {
+        match (self.recent.front(), self.recent.back()) {
+            (Some(first), Some(last)) if self.recent.len() > 1 => {
+                let span = last.duration_since(*first).as_secs_f32();
+                if span > 0.0 {
+                    (self.recent.len() - 1) as f32 / span
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+}
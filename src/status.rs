@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+/// Our own away/do-not-disturb state as the server last confirmed it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Status {
+    #[default]
+    Available,
+    Away(String),
+    DoNotDisturb(String),
+}
+
+impl Status {
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Status::Available => "🟢",
+            Status::Away(_) => "🌙",
+            Status::DoNotDisturb(_) => "⛔",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Available => "Available",
+            Status::Away(_) => "Away",
+            Status::DoNotDisturb(_) => "Do not disturb",
+        }
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            Status::Available => None,
+            Status::Away(message) | Status::DoNotDisturb(message) if !message.is_empty() => Some(message),
+            Status::Away(_) | Status::DoNotDisturb(_) => None,
+        }
+    }
+
+    /// Lines that move the server from `self` to `wanted`. Both commands
+    /// toggle when sent without a message, so leaving one state comes first.
+    pub fn commands_to(&self, wanted: &Status) -> Vec<String> {
+        let mut commands = vec![];
+        match (self, wanted) {
+            (Status::Away(_), Status::Away(_)) | (Status::DoNotDisturb(_), Status::DoNotDisturb(_)) => {}
+            (Status::Away(_), _) => commands.push("/away".to_string()),
+            (Status::DoNotDisturb(_), _) => commands.push("/dnd".to_string()),
+            (Status::Available, _) => {}
+        }
+        match wanted {
+            Status::Available => {}
+            Status::Away(message) => commands.push(with_message("/away", message, "Away")),
+            Status::DoNotDisturb(message) => commands.push(with_message("/dnd", message, "Busy")),
+        }
+        commands
+    }
+}
+
+fn with_message(command: &str, message: &str, fallback: &str) -> String {
+    let message = message.trim();
+    format!("{} {}", command, if message.is_empty() { fallback } else { message })
+}
+
+/// A status confirmation recognised in an INFO line.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    AwayOn,
+    AwayOff,
+    DndOn,
+    DndOff,
+}
+
+/// PvPGN's wording varies a little between versions, so match loosely.
+pub fn parse_change(info: &str) -> Option<Change> {
+    let info = info.to_lowercase();
+    if info.contains("no longer marked as away") || info.contains("no longer away") {
+        Some(Change::AwayOff)
+    } else if info.contains("marked as being away") || info.contains("marked as away") {
+        Some(Change::AwayOn)
+    } else if info.contains("do not disturb") && (info.contains("cancel") || info.contains("disengaged") || info.contains("off")) {
+        Some(Change::DndOff)
+    } else if info.contains("do not disturb") && (info.contains("engaged") || info.contains("now")) {
+        Some(Change::DndOn)
+    } else {
+        None
+    }
+}
+
+/// The message we asked for with `/away` or `/dnd`; the confirmations don't repeat it.
+pub fn requested_message(command: &str) -> Option<(bool, String)> {
+    let (name, message) = command.split_once(' ').unwrap_or((command, ""));
+    match name.to_lowercase().as_str() {
+        "/away" => Some((true, message.trim().to_string())),
+        "/dnd" => Some((false, message.trim().to_string())),
+        _ => None,
+    }
+}
+
+/// What to do about being away when we talk in the channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AwayOnTalk {
+    Ask,
+    Clear,
+    Keep,
+}
+
+impl AwayOnTalk {
+    pub const ALL: [AwayOnTalk; 3] = [AwayOnTalk::Ask, AwayOnTalk::Clear, AwayOnTalk::Keep];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AwayOnTalk::Ask => "Ask",
+            AwayOnTalk::Clear => "Clear away",
+            AwayOnTalk::Keep => "Stay away",
+        }
+    }
+}
+
+/// Tracks our status and the messages that set it.
+#[derive(Debug, Default)]
+pub struct OwnStatus {
+    pub current: Status,
+    away_message: String,
+    dnd_message: String,
+    /// Whisperers already announced with a sound during this away spell.
+    alerted: HashSet<String>,
+}
+
+impl OwnStatus {
+    /// Remembers the message of an outgoing `/away` or `/dnd`.
+    pub fn note_command(&mut self, command: &str) {
+        match requested_message(command) {
+            Some((true, message)) => self.away_message = message,
+            Some((false, message)) => self.dnd_message = message,
+            None => {}
+        }
+    }
+
+    pub fn apply(&mut self, change: Change) {
+        self.current = match change {
+            Change::AwayOn => Status::Away(self.away_message.clone()),
+            Change::DndOn => Status::DoNotDisturb(self.dnd_message.clone()),
+            Change::AwayOff | Change::DndOff => Status::Available,
+        };
+        self.alerted.clear();
+    }
+
+    /// While away, only the first whisper from each person makes a sound;
+    /// the server has already told them we're away.
+    pub fn should_alert(&mut self, from: &str) -> bool {
+        match self.current {
+            Status::Away(_) => self.alerted.insert(from.to_lowercase()),
+            _ => true,
+        }
+    }
+
+    pub fn is_away(&self) -> bool {
+        matches!(self.current, Status::Away(_))
+    }
+}
@@ -0,0 +1,390 @@
+//! Colors of the whole window. Besides the built-in dark and light themes,
+//! JSON files in the `themes` config directory each describe one, listing
+//! only the fields that differ from the built-in they start from.
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use eframe::egui::{self, Color32};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::paths;
+use crate::settings::{Settings, Theme};
+
+/// Copied into an empty themes directory, as starting points.
+const EXAMPLES: [(&str, &str); 2] = [
+    ("solarized-dark.json", include_str!("../themes/solarized-dark.json")),
+    ("high-contrast.json", include_str!("../themes/high-contrast.json")),
+];
+
+static CUSTOM: OnceLock<CustomThemes> = OnceLock::new();
+
+/// WCAG's minimum contrast for body text, held by every color of the
+/// high-contrast themes.
+pub const MIN_CONTRAST: f32 = 4.5;
+
+/// WCAG contrast ratio of two opaque colors, from 1 (same) to 21 (black on white).
+pub fn contrast_ratio(a: Color32, b: Color32) -> f32 {
+    let luminance = |color: Color32| {
+        let linear = egui::Rgba::from(color);
+        0.2126 * linear.r() + 0.7152 * linear.g() + 0.0722 * linear.b()
+    };
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// `color`, pushed toward black or white (whichever `background` contrasts
+/// with more) until it reaches `min` against `background`.
+pub fn ensure_contrast(color: Color32, background: Color32, min: f32) -> Color32 {
+    let target = if contrast_ratio(Color32::BLACK, background) > contrast_ratio(Color32::WHITE, background) {
+        Color32::BLACK
+    } else {
+        Color32::WHITE
+    };
+    let mix = |amount: f32| {
+        let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount).round() as u8;
+        Color32::from_rgb(channel(color.r(), target.r()), channel(color.g(), target.g()), channel(color.b(), target.b()))
+    };
+    (0..=10).map(|step| mix(step as f32 / 10.0)).find(|mixed| contrast_ratio(*mixed, background) >= min).unwrap_or(target)
+}
+
+/// Colors for message rows that stand out from ordinary chat.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageStyle {
+    /// Text of a server-wide announcement.
+    #[serde(with = "hex")]
+    pub broadcast: Color32,
+    /// Banner behind a server-wide announcement.
+    #[serde(with = "hex")]
+    pub broadcast_fill: Color32,
+    /// Text of a server answer that is a refusal rather than a failure.
+    #[serde(with = "hex")]
+    pub warning: Color32,
+    /// Notices from the client or server rather than from people.
+    #[serde(with = "hex")]
+    pub system: Color32,
+    /// A watched user logging on or off. Themes saved before it existed get
+    /// the default.
+    #[serde(with = "hex", default = "default_presence")]
+    pub presence: Color32,
+}
+
+fn default_presence() -> Color32 {
+    Color32::from_rgb(90, 170, 110)
+}
+
+impl MessageStyle {
+    pub fn dark() -> MessageStyle {
+        MessageStyle {
+            broadcast: Color32::from_rgb(255, 214, 120),
+            broadcast_fill: Color32::from_rgb(70, 52, 20),
+            warning: Color32::from_rgb(240, 170, 70),
+            system: Color32::from_rgb(140, 175, 215),
+            presence: Color32::from_rgb(120, 205, 140),
+        }
+    }
+
+    pub fn light() -> MessageStyle {
+        MessageStyle {
+            broadcast: Color32::from_rgb(110, 70, 0),
+            broadcast_fill: Color32::from_rgb(255, 236, 190),
+            warning: Color32::from_rgb(170, 90, 0),
+            system: Color32::from_rgb(45, 85, 140),
+            presence: Color32::from_rgb(30, 115, 55),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppTheme {
+    pub name: String,
+    /// Starts from the dark built-in when set, the light one when not.
+    pub dark: bool,
+    /// Panels and windows.
+    #[serde(with = "hex")]
+    pub background: Color32,
+    /// Text boxes and scroll area backgrounds.
+    #[serde(with = "hex")]
+    pub input_background: Color32,
+    /// Every other message row.
+    #[serde(with = "hex")]
+    pub stripe: Color32,
+    #[serde(with = "hex")]
+    pub text: Color32,
+    /// Selected text and toggled buttons.
+    #[serde(with = "hex")]
+    pub accent: Color32,
+    #[serde(with = "hex")]
+    pub link: Color32,
+    #[serde(with = "hex")]
+    pub warning: Color32,
+    #[serde(with = "hex")]
+    pub error: Color32,
+    pub messages: MessageStyle,
+}
+
+impl AppTheme {
+    pub fn dark() -> AppTheme {
+        AppTheme::from_visuals("Dark", &egui::Visuals::dark(), MessageStyle::dark())
+    }
+
+    pub fn light() -> AppTheme {
+        AppTheme::from_visuals("Light", &egui::Visuals::light(), MessageStyle::light())
+    }
+
+    /// Black or white surfaces with every color at [`MIN_CONTRAST`] or more
+    /// against what it is drawn on.
+    pub fn high_contrast(dark: bool) -> AppTheme {
+        let theme = if dark {
+            AppTheme {
+                name: "High contrast".to_string(),
+                dark,
+                background: Color32::BLACK,
+                input_background: Color32::BLACK,
+                stripe: Color32::from_gray(24),
+                text: Color32::WHITE,
+                accent: Color32::from_rgb(0, 80, 200),
+                link: Color32::from_rgb(0, 229, 255),
+                warning: Color32::from_rgb(255, 216, 0),
+                error: Color32::from_rgb(255, 90, 90),
+                messages: MessageStyle {
+                    broadcast: Color32::BLACK,
+                    broadcast_fill: Color32::from_rgb(255, 216, 0),
+                    warning: Color32::from_rgb(255, 176, 0),
+                    system: Color32::from_rgb(150, 210, 255),
+                    presence: Color32::from_rgb(0, 230, 118),
+                },
+            }
+        } else {
+            AppTheme {
+                name: "High contrast".to_string(),
+                dark,
+                background: Color32::WHITE,
+                input_background: Color32::WHITE,
+                stripe: Color32::from_gray(236),
+                text: Color32::BLACK,
+                accent: Color32::from_rgb(150, 190, 255),
+                link: Color32::from_rgb(0, 0, 192),
+                warning: Color32::from_rgb(122, 77, 0),
+                error: Color32::from_rgb(176, 0, 0),
+                messages: MessageStyle {
+                    broadcast: Color32::WHITE,
+                    broadcast_fill: Color32::from_rgb(0, 0, 128),
+                    warning: Color32::from_rgb(122, 77, 0),
+                    system: Color32::from_rgb(0, 60, 130),
+                    presence: Color32::from_rgb(0, 100, 0),
+                },
+            }
+        };
+        // Rows are striped, so hold the minimum against the darker or lighter of the two.
+        let row = |color| ensure_contrast(ensure_contrast(color, theme.background, MIN_CONTRAST), theme.stripe, MIN_CONTRAST);
+        AppTheme {
+            text: row(theme.text),
+            link: row(theme.link),
+            warning: row(theme.warning),
+            error: row(theme.error),
+            messages: MessageStyle {
+                broadcast: ensure_contrast(theme.messages.broadcast, theme.messages.broadcast_fill, MIN_CONTRAST),
+                warning: row(theme.messages.warning),
+                system: row(theme.messages.system),
+                presence: row(theme.messages.presence),
+                ..theme.messages
+            },
+            ..theme
+        }
+    }
+
+    fn from_visuals(name: &str, visuals: &egui::Visuals, messages: MessageStyle) -> AppTheme {
+        AppTheme {
+            name: name.to_string(),
+            dark: visuals.dark_mode,
+            background: visuals.panel_fill,
+            input_background: visuals.extreme_bg_color,
+            stripe: visuals.faint_bg_color,
+            text: visuals.widgets.noninteractive.fg_stroke.color,
+            accent: visuals.selection.bg_fill,
+            link: visuals.hyperlink_color,
+            warning: visuals.warn_fg_color,
+            error: visuals.error_fg_color,
+            messages,
+        }
+    }
+
+    pub fn apply_to_style(&self, style: &mut egui::Style) {
+        let visuals = &mut style.visuals;
+        *visuals = if self.dark { egui::Visuals::dark() } else { egui::Visuals::light() };
+        visuals.panel_fill = self.background;
+        visuals.window_fill = self.background;
+        visuals.extreme_bg_color = self.input_background;
+        visuals.faint_bg_color = self.stripe;
+        visuals.widgets.noninteractive.fg_stroke.color = self.text;
+        visuals.selection.bg_fill = self.accent;
+        visuals.hyperlink_color = self.link;
+        visuals.warn_fg_color = self.warning;
+        visuals.error_fg_color = self.error;
+    }
+
+    /// Reads a theme file: its fields laid over the built-in it names with
+    /// `dark`. Fields it leaves out keep the built-in's colors, and the name
+    /// defaults to the file's.
+    fn parse(file_name: &str, text: &str) -> Result<AppTheme, String> {
+        let Value::Object(fields) = serde_json::from_str(text).map_err(|err| err.to_string())? else {
+            return Err("expected an object of theme fields".to_string());
+        };
+        let dark = fields.get("dark").and_then(Value::as_bool).unwrap_or(true);
+        let mut base = if dark { AppTheme::dark() } else { AppTheme::light() };
+        base.name = file_name.to_string();
+        let Ok(Value::Object(mut merged)) = serde_json::to_value(base) else {
+            unreachable!("a theme serializes to an object");
+        };
+        for (key, value) in fields {
+            match (merged.get_mut(&key), value) {
+                (Some(Value::Object(inner)), Value::Object(overrides)) => inner.extend(overrides),
+                (_, value) => {
+                    merged.insert(key, value);
+                }
+            }
+        }
+        serde_json::from_value(Value::Object(merged)).map_err(|err| err.to_string())
+    }
+}
+
+/// Themes found in the themes directory at startup, and why others were skipped.
+#[derive(Debug, Default)]
+pub struct CustomThemes {
+    pub themes: Vec<AppTheme>,
+    pub errors: Vec<String>,
+}
+
+/// The custom themes, read on first use. Puts the examples in place when
+/// the directory does not exist yet.
+pub fn custom() -> &'static CustomThemes {
+    CUSTOM.get_or_init(|| {
+        let dir = paths::themes_dir();
+        if !dir.exists() && std::fs::create_dir_all(&dir).is_ok() {
+            for (name, text) in EXAMPLES {
+                let _ = std::fs::write(dir.join(name), text);
+            }
+        }
+        load_dir(&dir)
+    })
+}
+
+fn load_dir(dir: &Path) -> CustomThemes {
+    let mut custom = CustomThemes::default();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return custom;
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let file_name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| AppTheme::parse(&file_name, &text));
+        match parsed {
+            Ok(theme) if custom.themes.iter().any(|other| other.name == theme.name) => {
+                custom.errors.push(format!("{}: another theme is already called \"{}\"", path.display(), theme.name));
+            }
+            Ok(theme) => custom.themes.push(theme),
+            Err(err) => custom.errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    custom
+}
+
+/// The theme `settings` ask for: the custom one by name when it loaded,
+/// otherwise the built-in. High contrast keeps only whether it is dark.
+pub fn active(settings: &Settings) -> AppTheme {
+    let custom = settings
+        .custom_theme
+        .as_ref()
+        .and_then(|name| custom().themes.iter().find(|theme| &theme.name == name));
+    let theme = match (custom, settings.theme) {
+        (Some(theme), _) => theme.clone(),
+        (None, Theme::Dark) => AppTheme::dark(),
+        (None, Theme::Light) => AppTheme::light(),
+    };
+    if settings.high_contrast {
+        AppTheme::high_contrast(theme.dark)
+    } else {
+        theme
+    }
+}
+
+/// Colors as `#rrggbb`, or `#rrggbbaa` for transparency with the color
+/// already multiplied by alpha, as egui keeps it. The built-ins use alpha 0
+/// for colors that brighten what is under them.
+mod hex {
+    use eframe::egui::Color32;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Color32, serializer: S) -> Result<S::Ok, S::Error> {
+        let [r, g, b, a] = color.to_array();
+        if a == 255 {
+            serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}", r, g, b))
+        } else {
+            serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a))
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color32, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let invalid = || de::Error::custom(format!("invalid color \"{}\", expected #rrggbb or #rrggbbaa", text));
+        let digits = text.strip_prefix('#').filter(|digits| digits.is_ascii()).ok_or_else(invalid)?;
+        let channel = |at: usize| u8::from_str_radix(&digits[at..at + 2], 16).map_err(|_| invalid());
+        match digits.len() {
+            6 => Ok(Color32::from_rgb(channel(0)?, channel(2)?, channel(4)?)),
+            8 => Ok(Color32::from_rgba_premultiplied(channel(0)?, channel(2)?, channel(4)?, channel(6)?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broadcast_text_stands_out_on_its_banner() {
+        let themes = [AppTheme::dark(), AppTheme::light(), AppTheme::high_contrast(true), AppTheme::high_contrast(false)];
+        for theme in themes {
+            let messages = theme.messages;
+            assert!(
+                contrast_ratio(messages.broadcast, messages.broadcast_fill) >= MIN_CONTRAST,
+                "{} broadcast banner",
+                theme.name
+            );
+        }
+    }
+
+    #[test]
+    fn message_colors_round_trip_as_hex() {
+        let style = MessageStyle::light();
+        let text = serde_json::to_string(&style).unwrap();
+        assert!(text.contains("\"broadcast\":\"#6e4600\""), "{}", text);
+        assert_eq!(serde_json::from_str::<MessageStyle>(&text).unwrap(), style);
+    }
+
+    #[test]
+    fn styles_saved_before_presence_get_the_default() {
+        let text = r##"{"broadcast":"#000000","broadcast_fill":"#ffd800","warning":"#ffb000","system":"#9fd8ff"}"##;
+        let style: MessageStyle = serde_json::from_str(text).unwrap();
+        assert_eq!(style.broadcast_fill, Color32::from_rgb(255, 216, 0));
+        assert_eq!(style.presence, default_presence());
+        assert!(serde_json::from_str::<MessageStyle>(r##"{"broadcast":"yellow"}"##).is_err());
+    }
+
+    #[test]
+    fn a_theme_file_can_restyle_one_message_color() {
+        let theme = AppTheme::parse("mine", r##"{"dark": false, "messages": {"broadcast_fill": "#102030"}}"##).unwrap();
+        assert_eq!(theme.name, "mine");
+        assert_eq!(theme.messages, MessageStyle { broadcast_fill: Color32::from_rgb(16, 32, 48), ..MessageStyle::light() });
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Identical errors closer together than this collapse into one row.
+pub const REPEAT_WINDOW: Duration = Duration::from_secs(10);
+/// New error rows allowed per minute before further ones are only logged.
+pub const MAX_ROWS_PER_MINUTE: usize = 20;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// `rows` keyed by message index, re-keyed after `removed` rows were dropped
+/// from the front. Entries for the dropped rows go with them.
+pub fn shift_rows<T>(rows: HashMap<usize, T>, removed: usize) -> HashMap<usize, T> {
+    rows.into_iter().filter_map(|(row, value)| Some((row.checked_sub(removed)?, value))).collect()
+}
+
+/// What to do with a client-generated error row.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Show it as a new row.
+    NewRow,
+    /// Same text as a recent row: bump the counter shown on that row.
+    Repeat { row: usize, count: u32 },
+    /// Over the per-minute cap. `first` is true for the first one in this minute,
+    /// which gets a single "further errors suppressed" row.
+    Suppressed { first: bool },
+}
+
+#[derive(Debug)]
+struct Recent {
+    row: usize,
+    count: u32,
+    last_seen: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct ErrorThrottle {
+    recent: HashMap<String, Recent>,
+    minute_start: Option<Instant>,
+    rows_this_minute: usize,
+    noted_this_minute: bool,
+    /// Errors that only went to the log, for the diagnostics window.
+    pub suppressed: u64,
+}
+
+impl ErrorThrottle {
+    /// Keeps row indices valid after `removed` rows were dropped from the front.
+    pub fn shift_rows(&mut self, removed: usize) {
+        self.recent.retain(|_, recent| recent.row >= removed);
+        for recent in self.recent.values_mut() {
+            recent.row -= removed;
+        }
+    }
+
+    /// `next_row` is the index the row will get if it is shown.
+    pub fn check(&mut self, text: &str, now: Instant, next_row: usize) -> Verdict {
+        self.recent
+            .retain(|_, recent| now.duration_since(recent.last_seen) < REPEAT_WINDOW);
+        if let Some(recent) = self.recent.get_mut(text) {
+            recent.count += 1;
+            recent.last_seen = now;
+            return Verdict::Repeat { row: recent.row, count: recent.count };
+        }
+
+        let minute_over = self
+            .minute_start
+            .is_none_or(|start| now.duration_since(start) >= MINUTE);
+        if minute_over {
+            self.minute_start = Some(now);
+            self.rows_this_minute = 0;
+            self.noted_this_minute = false;
+        }
+        if self.rows_this_minute >= MAX_ROWS_PER_MINUTE {
+            self.suppressed += 1;
+            let first = !self.noted_this_minute;
+            self.noted_this_minute = true;
+            return Verdict::Suppressed { first };
+        }
+
+        self.rows_this_minute += 1;
+        self.recent.insert(
+            text.to_string(),
+            Recent { row: next_row, count: 1, last_seen: now },
+        );
+        Verdict::NewRow
+    }
+}
+
+/// Unrecognized server lines closer together than this share one row.
+pub const UNKNOWN_WINDOW: Duration = Duration::from_secs(60);
+
+/// Where a line that can share a row went.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Filed {
+    /// It starts a row of its own, at the `next_row` given.
+    NewRow,
+    /// It joined an earlier row, which now holds `count` lines.
+    Joined { row: usize, count: usize },
+}
+
+/// Groups bursts of unrecognized lines into one expandable row each, so a
+/// chatty server feature we don't know can't flood the view.
+#[derive(Debug, Default)]
+pub struct UnknownLines {
+    /// The lines behind each row that holds unrecognized lines.
+    rows: HashMap<usize, Vec<String>>,
+    /// The row still taking lines, and when it started.
+    open: Option<(usize, Instant)>,
+}
+
+impl UnknownLines {
+    pub fn add(&mut self, line: String, now: Instant, next_row: usize) -> Filed {
+        let open = self.open.filter(|(_, started)| now.duration_since(*started) < UNKNOWN_WINDOW);
+        if let Some((row, _)) = open {
+            if let Some(lines) = self.rows.get_mut(&row) {
+                lines.push(line);
+                return Filed::Joined { row, count: lines.len() };
+            }
+        }
+        self.rows.insert(next_row, vec![line]);
+        self.open = Some((next_row, now));
+        Filed::NewRow
+    }
+
+    pub fn lines(&self, row: usize) -> Option<&[String]> {
+        self.rows.get(&row).map(Vec::as_slice)
+    }
+
+    /// Keeps row indices valid after `removed` rows were dropped from the front.
+    pub fn shift_rows(&mut self, removed: usize) {
+        self.rows = shift_rows(std::mem::take(&mut self.rows), removed);
+        self.open = self.open.and_then(|(row, started)| Some((row.checked_sub(removed)?, started)));
+    }
+}
+
+/// An unrecognized line as its row shows it, under its code:
+/// `Unrecognized 1011: SOMETHING x`.
+pub fn unknown_text(line: &str) -> String {
+    let (code, rest) = line.split_once(' ').unwrap_or((line, ""));
+    format!("Unrecognized {}: {}", code, rest)
+}
+
+/// Folds runs of joins and leaves into one expandable row each. A run only
+/// grows while its row is the newest, so any other row ends it.
+#[derive(Debug, Default)]
+pub struct JoinLeaveFolds {
+    /// Who joined (`true`) or left behind each folded row, in order.
+    rows: HashMap<usize, Vec<(String, bool)>>,
+}
+
+impl JoinLeaveFolds {
+    pub fn add(&mut self, name: &str, joined: bool, next_row: usize) -> Filed {
+        let last_row = next_row.checked_sub(1);
+        if let Some((row, events)) = last_row.and_then(|row| Some((row, self.rows.get_mut(&row)?))) {
+            events.push((name.to_string(), joined));
+            return Filed::Joined { row, count: events.len() };
+        }
+        self.rows.insert(next_row, vec![(name.to_string(), joined)]);
+        Filed::NewRow
+    }
+
+    pub fn events(&self, row: usize) -> Option<&[(String, bool)]> {
+        self.rows.get(&row).map(Vec::as_slice)
+    }
+
+    /// Keeps row indices valid after `removed` rows were dropped from the front.
+    pub fn shift_rows(&mut self, removed: usize) {
+        self.rows = shift_rows(std::mem::take(&mut self.rows), removed);
+    }
+}
+
+/// `7 users joined, 3 left` for a folded row.
+pub fn fold_summary(events: &[(String, bool)]) -> String {
+    let joined = events.iter().filter(|(_, joined)| *joined).count();
+    let left = events.len() - joined;
+    let users = |count| if count == 1 { "1 user".to_string() } else { format!("{} users", count) };
+    match (joined, left) {
+        (joined, 0) => format!("{} joined", users(joined)),
+        (0, left) => format!("{} left", users(left)),
+        (joined, left) => format!("{} joined, {} left", users(joined), left),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn repeats_inside_the_window_bump_the_first_row() {
+        let start = Instant::now();
+        let mut throttle = ErrorThrottle::default();
+        assert_eq!(throttle.check("Failed to send: broken pipe", start, 4), Verdict::NewRow);
+        assert_eq!(throttle.check("Failed to send: broken pipe", secs(start, 3), 9), Verdict::Repeat { row: 4, count: 2 });
+        // Each repeat restarts the window.
+        assert_eq!(throttle.check("Failed to send: broken pipe", secs(start, 12), 9), Verdict::Repeat { row: 4, count: 3 });
+    }
+
+    #[test]
+    fn keyed_by_exact_text() {
+        let start = Instant::now();
+        let mut throttle = ErrorThrottle::default();
+        assert_eq!(throttle.check("Failed to send: a", start, 0), Verdict::NewRow);
+        assert_eq!(throttle.check("Failed to send: b", start, 1), Verdict::NewRow);
+        assert_eq!(throttle.check("failed to send: a", start, 2), Verdict::NewRow);
+    }
+
+    #[test]
+    fn a_repeat_after_the_window_gets_a_new_row() {
+        let start = Instant::now();
+        let mut throttle = ErrorThrottle::default();
+        throttle.check("oops", start, 0);
+        assert_eq!(throttle.check("oops", start + REPEAT_WINDOW, 5), Verdict::NewRow);
+        assert_eq!(throttle.check("oops", secs(start, 11), 9), Verdict::Repeat { row: 5, count: 2 });
+    }
+
+    #[test]
+    fn cap_suppresses_with_one_note_per_minute() {
+        let start = Instant::now();
+        let mut throttle = ErrorThrottle::default();
+        for row in 0..MAX_ROWS_PER_MINUTE {
+            assert_eq!(throttle.check(&format!("error {}", row), start, row), Verdict::NewRow);
+        }
+        assert_eq!(throttle.check("one more", secs(start, 1), 99), Verdict::Suppressed { first: true });
+        assert_eq!(throttle.check("and another", secs(start, 2), 99), Verdict::Suppressed { first: false });
+        assert_eq!(throttle.suppressed, 2);
+        // Repeats of rows already shown still count up, cap or not.
+        assert_eq!(throttle.check("error 0", secs(start, 3), 99), Verdict::Repeat { row: 0, count: 2 });
+
+        let next_minute = start + MINUTE;
+        assert_eq!(throttle.check("fresh", next_minute, 30), Verdict::NewRow);
+        assert_eq!(throttle.suppressed, 2);
+    }
+
+    #[test]
+    fn shifting_rows_forgets_trimmed_ones() {
+        let start = Instant::now();
+        let mut throttle = ErrorThrottle::default();
+        throttle.check("old", start, 2);
+        throttle.check("new", start, 10);
+        throttle.shift_rows(5);
+        assert_eq!(throttle.check("new", start, 20), Verdict::Repeat { row: 5, count: 2 });
+        assert_eq!(throttle.check("old", start, 20), Verdict::NewRow);
+    }
+
+    #[test]
+    fn unknown_lines_within_a_minute_share_a_row() {
+        let start = Instant::now();
+        let mut unknown = UnknownLines::default();
+        assert_eq!(unknown.add("1011 A".into(), start, 3), Filed::NewRow);
+        assert_eq!(unknown.add("1012 B".into(), secs(start, 30), 4), Filed::Joined { row: 3, count: 2 });
+        assert_eq!(unknown.add("1013 C".into(), secs(start, 59), 4), Filed::Joined { row: 3, count: 3 });
+        assert_eq!(unknown.lines(3), Some(&["1011 A".to_string(), "1012 B".to_string(), "1013 C".to_string()][..]));
+        assert_eq!(unknown.lines(4), None);
+    }
+
+    #[test]
+    fn unknown_lines_after_the_window_start_a_new_row() {
+        let start = Instant::now();
+        let mut unknown = UnknownLines::default();
+        unknown.add("1011 A".into(), start, 0);
+        assert_eq!(unknown.add("1011 B".into(), start + UNKNOWN_WINDOW, 7), Filed::NewRow);
+        assert_eq!(unknown.add("1011 C".into(), secs(start, 61), 8), Filed::Joined { row: 7, count: 2 });
+        assert_eq!(unknown.lines(0).map(<[String]>::len), Some(1));
+    }
+
+    #[test]
+    fn unknown_rows_follow_trimming() {
+        let start = Instant::now();
+        let mut unknown = UnknownLines::default();
+        unknown.add("1011 A".into(), start, 2);
+        unknown.shift_rows(2);
+        assert_eq!(unknown.add("1011 B".into(), start, 5), Filed::Joined { row: 0, count: 2 });
+        // Once its row is trimmed away, the next line starts over.
+        unknown.shift_rows(1);
+        assert_eq!(unknown.lines(0), None);
+        assert_eq!(unknown.add("1011 C".into(), start, 4), Filed::NewRow);
+    }
+
+    #[test]
+    fn unknown_rows_name_the_code() {
+        assert_eq!(unknown_text("1011 SOMETHING x"), "Unrecognized 1011: SOMETHING x");
+        assert_eq!(unknown_text("hello"), "Unrecognized hello: ");
+    }
+}
@@ -0,0 +1,166 @@
+//! TLS for servers behind stunnel or a similar terminator.
+//!
+//! A TLS session cannot be cloned like a socket, so both handles share it
+//! behind a lock. The read thread holds the lock only for short polls of the
+//! socket, which leaves room for sends in between.
+
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned};
+
+use crate::connect::{self, Transport};
+
+/// How long one read holds the session before letting a writer in.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Accepts whatever certificate the server shows. Only for self-signed
+/// setups the user opted into; the signatures are still checked.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn client_config(verify: bool) -> Result<ClientConfig, String> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?;
+    let config = if verify {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        builder.with_root_certificates(roots).with_no_client_auth()
+    } else {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth()
+    };
+    Ok(config)
+}
+
+#[derive(Debug)]
+pub struct TlsTransport {
+    session: Arc<Mutex<StreamOwned<ClientConnection, TcpStream>>>,
+    /// The socket under the session, for shutdown.
+    socket: TcpStream,
+    /// Per handle, like a socket's; reads poll until it runs out.
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl TlsTransport {
+    /// Runs the TLS handshake over `socket` within `timeout`. Errors are
+    /// the handshake's own, told apart from TCP failures by the caller.
+    pub fn wrap(socket: TcpStream, server: &str, verify: bool, timeout: Duration) -> Result<TlsTransport, String> {
+        let config = client_config(verify)?;
+        let name = ServerName::try_from(connect::split_host_port(server).0.to_string()).map_err(|err| err.to_string())?;
+        let mut connection = ClientConnection::new(Arc::new(config), name).map_err(|err| err.to_string())?;
+        let mut handshake_socket = socket.try_clone().map_err(|err| err.to_string())?;
+        handshake_socket.set_read_timeout(Some(timeout)).map_err(|err| err.to_string())?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut handshake_socket).map_err(|err| match err.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => "the server did not finish the handshake in time".to_string(),
+                _ => err.to_string(),
+            })?;
+        }
+        handshake_socket.set_read_timeout(None).map_err(|err| err.to_string())?;
+        let stream = socket.try_clone().map_err(|err| err.to_string())?;
+        Ok(TlsTransport {
+            session: Arc::new(Mutex::new(StreamOwned::new(connection, stream))),
+            socket,
+            read_timeout: Cell::new(None),
+        })
+    }
+
+    fn session(&self) -> MutexGuard<'_, StreamOwned<ClientConnection, TcpStream>> {
+        // A panic mid-read leaves the session no worse than the socket.
+        self.session.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Read for TlsTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let deadline = self.read_timeout.get().map(|timeout| Instant::now() + timeout);
+        loop {
+            {
+                let mut session = self.session();
+                session.sock.set_read_timeout(Some(POLL_INTERVAL))?;
+                match session.read(buffer) {
+                    Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                    result => return result,
+                }
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+            }
+        }
+    }
+}
+
+impl Write for TlsTransport {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.session().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session().flush()
+    }
+}
+
+impl Transport for TlsTransport {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(TlsTransport {
+            session: self.session.clone(),
+            socket: self.socket.try_clone()?,
+            read_timeout: Cell::new(None),
+        }))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.socket.shutdown(std::net::Shutdown::Both)
+    }
+}
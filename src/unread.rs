@@ -0,0 +1,65 @@
+/// Tracks rows that arrived while the message list was scrolled away from
+/// the bottom, for the "new messages" button and divider.
+#[derive(Debug)]
+pub struct Unread {
+    /// Rows before this index have been seen at the bottom of the list.
+    last_read: usize,
+    /// Row the "new messages" divider is drawn above.
+    divider: Option<usize>,
+    at_bottom: bool,
+    /// Set by the button or End; the list scrolls down on the next frame.
+    pub jump_pending: bool,
+}
+
+impl Default for Unread {
+    fn default() -> Self {
+        Unread { last_read: 0, divider: None, at_bottom: true, jump_pending: false }
+    }
+}
+
+impl Unread {
+    /// Follows the scroll position once per frame. Coming back to the bottom
+    /// with unseen rows puts the divider where reading left off.
+    pub fn update(&mut self, at_bottom: bool, rows: usize) {
+        if at_bottom {
+            if !self.at_bottom && self.last_read < rows {
+                self.divider = Some(self.last_read);
+            }
+            self.last_read = rows;
+        }
+        self.at_bottom = at_bottom;
+    }
+
+    pub fn at_bottom(&self) -> bool {
+        self.at_bottom
+    }
+
+    /// Rows below the viewport that have not been seen yet.
+    pub fn new_count(&self, rows: usize) -> usize {
+        if self.at_bottom {
+            0
+        } else {
+            rows.saturating_sub(self.last_read)
+        }
+    }
+
+    pub fn divider(&self) -> Option<usize> {
+        self.divider
+    }
+
+    pub fn clear_divider(&mut self) {
+        self.divider = None;
+    }
+
+    /// Forgets what was read, e.g. on a channel change: the `rows` already
+    /// there count as seen.
+    pub fn reset(&mut self, rows: usize) {
+        *self = Unread { last_read: rows, jump_pending: true, ..Unread::default() };
+    }
+
+    /// Mirrors dropping the oldest `excess` messages.
+    pub fn trim(&mut self, excess: usize) {
+        self.last_read = self.last_read.saturating_sub(excess);
+        self.divider = self.divider.and_then(|row| row.checked_sub(excess));
+    }
+}
@@ -0,0 +1,102 @@
+//! Who each saved profile watches. The server forgets `/watch` entries when
+//! we log off, so they are sent again after every login.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchLists {
+    /// Lowercased account names, keyed by lowercased `user@server`.
+    profiles: HashMap<String, BTreeSet<String>>,
+}
+
+fn key(server: &str, user: &str) -> String {
+    format!("{}@{}", user.trim().to_lowercase(), server.trim().to_lowercase())
+}
+
+impl WatchLists {
+    pub fn load() -> WatchLists {
+        std::fs::read_to_string(paths::config_file(paths::WATCH_LISTS))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)
+            .and_then(|text| std::fs::write(paths::config_file(paths::WATCH_LISTS), text));
+        if let Err(err) = result {
+            println!("Could not save the watch lists: {}", err);
+        }
+    }
+
+    pub fn names(&self, server: &str, user: &str) -> Vec<String> {
+        self.profiles.get(&key(server, user)).map_or_else(Vec::new, |names| names.iter().cloned().collect())
+    }
+
+    pub fn contains(&self, server: &str, user: &str, name: &str) -> bool {
+        self.profiles.get(&key(server, user)).is_some_and(|names| names.contains(&name.to_lowercase()))
+    }
+
+    pub fn insert(&mut self, server: &str, user: &str, name: &str) {
+        if self.profiles.entry(key(server, user)).or_default().insert(name.to_lowercase()) {
+            self.save();
+        }
+    }
+
+    pub fn remove(&mut self, server: &str, user: &str, name: &str) {
+        if self.profiles.get_mut(&key(server, user)).is_some_and(|names| names.remove(&name.to_lowercase())) {
+            self.save();
+        }
+    }
+}
+
+/// The server's answer to `/watch`, swallowed while the list is restored.
+pub fn is_added_notice(text: &str) -> bool {
+    text.contains("added to your watch list")
+}
+
+/// Whether a watched user came or went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Logon {
+    On,
+    Off,
+}
+
+impl Logon {
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Logon::On => "👁",
+            Logon::Off => "💤",
+        }
+    }
+}
+
+/// Endings of the server's watch notices, after the name.
+const NOTICES: [(&str, Logon); 9] = [
+    (" has logged on", Logon::On),
+    (" has logged in", Logon::On),
+    (" has logged into Battle.net", Logon::On),
+    (" has entered Battle.net", Logon::On),
+    (" has entered the game", Logon::On),
+    (" has logged off", Logon::Off),
+    (" has logged out", Logon::Off),
+    (" has left Battle.net", Logon::Off),
+    (" has exited Battle.net", Logon::Off),
+];
+
+/// The user named by a logon or logoff notice such as `Bob has logged on.`
+/// or `Watched user Bob has left Battle.net.`, and which of the two it is.
+pub fn parse_notice(text: &str) -> Option<(&str, Logon)> {
+    let text = text.trim().trim_end_matches(['.', '!']);
+    let text = ["Watched user ", "Your friend "].iter().find_map(|prefix| text.strip_prefix(prefix)).unwrap_or(text);
+    NOTICES.iter().find_map(|(ending, logon)| {
+        let name = text.strip_suffix(ending)?;
+        (!name.is_empty()).then_some((name, *logon))
+    })
+}